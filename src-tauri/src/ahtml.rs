@@ -0,0 +1,206 @@
+use std::{
+    path::Path,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{entity, outline, storage};
+
+/// Format version written by this build.
+pub const VERSION: i32 = 1;
+/// Editor that produces `.ahtml` bodies.
+pub const EDITOR: &str = "wangeditor/5";
+
+const OPEN: &str = "<!--ahtml";
+const CLOSE: &str = "-->";
+
+/// An `.ahtml` document: a JSON header inside a leading `<!--ahtml ... -->` comment,
+/// followed by the HTML body. Keeping the header in a comment leaves the file valid HTML.
+#[derive(Debug, Clone)]
+pub struct Document {
+    pub header: entity::AhtmlHeader,
+    pub body: String,
+    /// Whether the file had no header and one was made up for it.
+    pub legacy: bool,
+    pub errors: Vec<String>,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_millis() as i64)
+}
+
+/// Splits `content` into its raw header and body, or `None` for a headerless file.
+pub fn split(content: &str) -> Option<(&str, &str)> {
+    let text = content.trim_start_matches('\u{feff}').trim_start();
+    let rest = text.strip_prefix(OPEN)?;
+    let end = rest.find(CLOSE)?;
+    let body = &rest[end + CLOSE.len()..];
+    let body = body
+        .strip_prefix("\r\n")
+        .or_else(|| body.strip_prefix('\n'))
+        .unwrap_or(body);
+    Some((rest[..end].trim(), body))
+}
+
+/// The HTML body of `content`, without the header.
+pub fn body(content: &str) -> &str {
+    match split(content) {
+        Some((_, body)) => body,
+        None => content,
+    }
+}
+
+/// The header of `content` when it has one that parses.
+pub fn header(content: &str) -> Option<entity::AhtmlHeader> {
+    serde_json::from_str(split(content)?.0).ok()
+}
+
+/// A header for a document that has none, titled after its first heading or file name.
+pub fn legacy(path: &Path, body: &str) -> entity::AhtmlHeader {
+    let title = outline::ahtml(body)
+        .into_iter()
+        .find(|h| h.level == 1)
+        .map(|h| h.text)
+        .or_else(|| {
+            path.file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(|stem| stem.to_string())
+        })
+        .unwrap_or_default();
+    // storages only know when a document last changed, which stands in for its creation
+    let updated = storage::for_path(path)
+        .stat(path)
+        .ok()
+        .and_then(|stat| stat.modified.duration_since(UNIX_EPOCH).ok())
+        .map_or_else(now, |d| d.as_millis() as i64);
+    entity::AhtmlHeader {
+        version: VERSION,
+        title,
+        tags: Vec::new(),
+        created: updated,
+        updated,
+        editor: EDITOR.to_string(),
+    }
+}
+
+/// Problems with a header that can still be read.
+pub fn validate(header: &entity::AhtmlHeader) -> Vec<String> {
+    let mut errors = Vec::new();
+    if header.version < 1 {
+        errors.push(format!("invalid format version {}", header.version));
+    } else if header.version > VERSION {
+        errors.push(format!(
+            "format version {} is newer than the supported version {}",
+            header.version, VERSION
+        ));
+    }
+    if header.title.trim().is_empty() {
+        errors.push("title is empty".to_string());
+    }
+    if header.tags.iter().any(|tag| tag.trim().is_empty()) {
+        errors.push("tags contain an empty tag".to_string());
+    }
+    if header.created > header.updated {
+        errors.push("created is later than updated".to_string());
+    }
+    errors
+}
+
+/// Parses the document at `path`. Headerless files are upgraded in memory, and a header
+/// that fails to parse is replaced the same way with the error kept in `errors`.
+pub fn parse(path: &Path, content: &str) -> Document {
+    match split(content) {
+        Some((raw, body)) => match serde_json::from_str::<entity::AhtmlHeader>(raw) {
+            Ok(header) => Document {
+                errors: validate(&header),
+                header,
+                body: body.to_string(),
+                legacy: false,
+            },
+            Err(e) => Document {
+                header: legacy(path, body),
+                body: body.to_string(),
+                legacy: false,
+                errors: vec![format!("invalid header: {}", e)],
+            },
+        },
+        None => Document {
+            header: legacy(path, content),
+            body: content.to_string(),
+            legacy: true,
+            errors: Vec::new(),
+        },
+    }
+}
+
+pub fn to_string(header: &entity::AhtmlHeader, body: &str) -> String {
+    let header = serde_json::to_string_pretty(header).unwrap();
+    // `-->` inside a string would end the comment early
+    let header = header.replace("-->", "--\\u003e");
+    format!("{}\n{}\n{}\n{}", OPEN, header, CLOSE, body)
+}
+
+/// The file content to store when the editor saves `content` to `path`: the existing
+/// header is kept, or a new one made for legacy files, and its `updated` time bumped.
+/// Content that already carries a header is stored as given.
+pub fn save(path: &Path, content: &str) -> String {
+    if split(content).is_some() {
+        return content.to_string();
+    }
+    let mut header = match storage::for_path(path).read_to_string(path) {
+        Ok(existing) => parse(path, &existing).header,
+        Err(_) => legacy(path, content),
+    };
+    header.version = VERSION;
+    header.editor = EDITOR.to_string();
+    header.updated = now().max(header.created);
+    to_string(&header, content)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let header = entity::AhtmlHeader {
+            version: VERSION,
+            title: "A --> B".to_string(),
+            tags: vec!["x".to_string()],
+            created: 1,
+            updated: 2,
+            editor: EDITOR.to_string(),
+        };
+        let content = to_string(&header, "<p>body</p>");
+        assert!(content.starts_with("<!--ahtml\n{\n  \"version\": 1,"));
+        let document = parse(Path::new("a.ahtml"), &content);
+        assert_eq!(document.header.title, "A --> B");
+        assert_eq!(document.body, "<p>body</p>");
+        assert!(!document.legacy);
+        assert!(document.errors.is_empty());
+    }
+
+    #[test]
+    fn test_legacy_and_errors() {
+        let document = parse(Path::new("/nope/note.ahtml"), "<p>x</p><h1>Title</h1>");
+        assert!(document.legacy);
+        assert_eq!(document.header.title, "Title");
+        assert_eq!(document.body, "<p>x</p><h1>Title</h1>");
+
+        let content = "<!--ahtml\n{\"version\": 9, \"title\": \"\"}\n-->\n<p>x</p>";
+        let document = parse(Path::new("/nope/note.ahtml"), content);
+        assert_eq!(document.body, "<p>x</p>");
+        assert_eq!(
+            document.errors,
+            vec![
+                "format version 9 is newer than the supported version 1",
+                "title is empty"
+            ]
+        );
+
+        let document = parse(Path::new("/nope/note.ahtml"), "<!--ahtml {x -->\n<p>x</p>");
+        assert_eq!(document.header.title, "note");
+        assert!(document.errors[0].starts_with("invalid header"));
+    }
+}
@@ -1,67 +1,142 @@
-use std::{fs, path::Path};
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::SystemTime,
+};
+
+use once_cell::sync::Lazy;
 
 use tauri::api::dialog::FileDialogBuilder;
 
-use crate::entity;
+use crate::{
+    ahtml, archive, backup, convert, docx, embed, entity, epub, export, frontmatter, generator,
+    highlight, history, links, migrate, opml, outline, pdf, query, render, s3, sanitize, section,
+    site, storage, tags, webdav,
+};
+
+/// Front matter read from a document, with the modification time and size it had then.
+type CachedFrontMatter = (SystemTime, u64, Option<entity::FrontMatter>);
+
+/// Front matter of the documents listed so far, so that listing a folder again only reads
+/// the documents that changed. Entries go when the watcher reports a change, since a quick
+/// edit may keep both the size and the modification time.
+static FRONT_MATTER: Lazy<Mutex<HashMap<PathBuf, CachedFrontMatter>>> =
+    Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn front_matter(
+    storage: &dyn storage::Storage,
+    entry: &storage::Entry,
+) -> Option<entity::FrontMatter> {
+    let stat = &entry.stat;
+    if let Some((modified, size, front_matter)) = FRONT_MATTER.lock().unwrap().get(&entry.path) {
+        if *modified == stat.modified && *size == stat.size {
+            return front_matter.clone();
+        }
+    }
+    let front_matter = match storage.read_to_string(&entry.path) {
+        Ok(content) => frontmatter::parse(&content),
+        Err(_) => return None,
+    };
+    FRONT_MATTER.lock().unwrap().insert(
+        entry.path.clone(),
+        (stat.modified, stat.size, front_matter.clone()),
+    );
+    front_matter
+}
+
+/// Drops the cached front matter of a changed document, or of every document below a folder.
+pub fn forget_front_matter(path: &Path) {
+    FRONT_MATTER
+        .lock()
+        .unwrap()
+        .retain(|cached, _| !cached.starts_with(path));
+}
 
 pub fn read_dir(path: &Path, depth: i32) -> Result<Vec<entity::FileTree>, std::io::Error> {
+    let storage = storage::for_path(path);
     let mut result = Vec::new();
-    match std::fs::read_dir(path) {
-        Ok(dir) => {
-            for entry in dir {
-                if depth == 0 && entry.as_ref().unwrap().file_name() == ".ahriknow" {
-                    continue;
-                }
-                let entry = entry.unwrap();
-                let path = entry.path();
-                let type_;
-                if path.is_dir() {
-                    type_ = 0
-                } else {
-                    match path.extension() {
-                        None => continue,
-                        Some(ext) => {
-                            let ext = ext.to_str().unwrap();
-                            if ext == "md" {
-                                type_ = 1
-                            } else if ext == "ahtml" {
-                                type_ = 2
-                            } else {
-                                continue;
-                            }
-                        }
-                    }
-                }
-                let metadata = std::fs::metadata(&path).unwrap();
-                let file_tree = entity::FileTree {
-                    type_,
-                    name: entry.file_name().to_str().unwrap().to_string(),
-                    path: path.to_str().unwrap().to_string(),
-                    updated: metadata.modified().unwrap().elapsed().unwrap().as_micros() as i64,
-                    children: if path.is_dir() {
-                        match read_dir(&path, depth + 1) {
-                            Ok(children) => Some(children),
-                            Err(e) => return Err(e),
-                        }
-                    } else {
-                        None
-                    },
-                };
-                result.push(file_tree);
-            }
+    for entry in storage.list(path)? {
+        if depth == 0 && entry.name == ".ahriknow" {
+            continue;
         }
-        Err(e) => return Err(e),
+        let type_ = if entry.stat.is_dir {
+            0
+        } else {
+            match entry.path.extension().and_then(|ext| ext.to_str()) {
+                Some("md") => 1,
+                Some("ahtml") => 2,
+                _ => continue,
+            }
+        };
+        let front_matter = if type_ == 1 {
+            front_matter(storage.as_ref(), &entry)
+        } else {
+            None
+        };
+        let file_tree = entity::FileTree {
+            type_,
+            name: entry.name,
+            path: entry.path.to_str().unwrap().to_string(),
+            updated: micros(entry.stat.modified),
+            front_matter,
+            children: if entry.stat.is_dir {
+                Some(read_dir(&entry.path, depth + 1)?)
+            } else {
+                None
+            },
+        };
+        result.push(file_tree);
     }
 
-    result.sort_by(|a, b| a.type_.cmp(&b.type_));
+    result.sort_by_key(|node| node.type_);
 
     Ok(result)
 }
 
+/// Collects every `.md` and `.ahtml` document below `path`, skipping `.ahriknow`.
+pub fn walk(path: &Path) -> Vec<std::path::PathBuf> {
+    if archive::split(path).is_some() {
+        return archive::documents(path);
+    }
+    let mut result = Vec::new();
+    walk_dir(path, 0, &mut result);
+    result.sort();
+    result
+}
+
+fn walk_dir(path: &Path, depth: i32, result: &mut Vec<std::path::PathBuf>) {
+    if let Ok(entries) = storage::for_path(path).list(path) {
+        for entry in entries {
+            if depth == 0 && entry.name == ".ahriknow" {
+                continue;
+            }
+            if entry.stat.is_dir {
+                walk_dir(&entry.path, depth + 1, result);
+            } else if let Some(ext) = entry.path.extension() {
+                if ext == "md" || ext == "ahtml" {
+                    result.push(entry.path);
+                }
+            }
+        }
+    }
+}
+
 #[tauri::command]
 pub fn open(path: String) -> Option<Vec<entity::FileTree>> {
     let path = Path::new(&path);
-    match read_dir(path, 0) {
+    // only the open workspace stays cached
+    FRONT_MATTER
+        .lock()
+        .unwrap()
+        .retain(|cached, _| cached.starts_with(path));
+    let tree = if archive::split(path).is_some() {
+        archive::tree(path)
+    } else {
+        read_dir(path, 0).map_err(|e| e.to_string())
+    };
+    match tree {
         Ok(filetree) => Some(filetree),
         Err(e) => {
             println!("{:?}", e);
@@ -104,96 +179,78 @@ pub fn create(
     is_dir: bool,
 ) -> entity::Response<Option<entity::OpenFile>> {
     let path = Path::new(&path);
+    if archive::split(path).is_some() {
+        return entity::Response {
+            code: 50000,
+            msg: archive::READ_ONLY.to_string(),
+            data: None,
+        };
+    }
     let path = path.join(name);
-    if is_dir {
-        match std::fs::create_dir(&path) {
-            Ok(_) => {
-                let metadata = fs::metadata(&path).unwrap();
-                let open_file = entity::OpenFile {
-                    type_: 0,
-                    path: path.to_str().unwrap().to_string(),
-                    content: "".to_string(),
-                    updated: metadata.modified().unwrap().elapsed().unwrap().as_micros() as i64,
-                };
-                return entity::Response {
-                    code: 10000,
-                    msg: "success".to_string(),
-                    data: Some(open_file),
-                };
-            }
-            Err(e) => {
-                return entity::Response {
-                    code: 50000,
-                    msg: e.to_string(),
-                    data: None,
-                };
-            }
-        }
+    let storage = storage::for_path(&path);
+    let created = if is_dir {
+        storage.create_dir(&path)
+    } else if path.extension().map_or(false, |ext| ext == "ahtml") {
+        storage.write(&path, ahtml::save(&path, "").as_bytes())
     } else {
-        std::fs::File::create(&path).unwrap();
-        match std::fs::File::create(&path) {
-            Ok(_) => {
-                let metadata = fs::metadata(&path).unwrap();
-                let open_file = entity::OpenFile {
-                    type_: 0,
-                    path: path.to_str().unwrap().to_string(),
-                    content: "".to_string(),
-                    updated: metadata.modified().unwrap().elapsed().unwrap().as_micros() as i64,
-                };
-                return entity::Response {
-                    code: 10000,
-                    msg: "success".to_string(),
-                    data: Some(open_file),
-                };
-            }
-            Err(e) => {
-                return entity::Response {
-                    code: 50000,
-                    msg: e.to_string(),
-                    data: None,
-                };
-            }
-        }
+        storage.write(&path, b"")
+    };
+    match created.and_then(|_| storage.stat(&path)) {
+        Ok(stat) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(entity::OpenFile {
+                type_: 0,
+                path: path.to_str().unwrap().to_string(),
+                content: "".to_string(),
+                updated: micros(stat.modified),
+                front_matter: None,
+                header: None,
+                errors: Vec::new(),
+                stripped: Vec::new(),
+            }),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: None,
+        },
     }
 }
 
 #[tauri::command]
 pub fn delete(path: String, is_dir: bool) -> entity::Response<bool> {
     let path = Path::new(&path);
-    if is_dir {
-        match std::fs::remove_dir_all(&path) {
-            Ok(_) => {
-                return entity::Response {
-                    code: 10000,
-                    msg: "success".to_string(),
-                    data: true,
-                };
-            }
-            Err(e) => {
-                return entity::Response {
-                    code: 50000,
-                    msg: e.to_string(),
-                    data: false,
-                };
-            }
-        }
-    } else {
-        match std::fs::remove_file(&path) {
-            Ok(_) => {
-                return entity::Response {
-                    code: 10000,
-                    msg: "success".to_string(),
-                    data: true,
-                };
-            }
-            Err(e) => {
-                return entity::Response {
-                    code: 50000,
-                    msg: e.to_string(),
-                    data: false,
-                };
-            }
-        }
+    if archive::split(path).is_some() {
+        return entity::Response {
+            code: 50000,
+            msg: archive::READ_ONLY.to_string(),
+            data: false,
+        };
+    }
+    let storage = storage::for_path(path);
+    if storage.is_dir(path) != is_dir {
+        return entity::Response {
+            code: 50000,
+            msg: format!(
+                "{} is not a {}",
+                path.display(),
+                if is_dir { "folder" } else { "file" }
+            ),
+            data: false,
+        };
+    }
+    match storage.delete(path) {
+        Ok(_) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: true,
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: false,
+        },
     }
 }
 
@@ -201,164 +258,1743 @@ pub fn delete(path: String, is_dir: bool) -> entity::Response<bool> {
 #[tauri::command]
 pub fn rename(path: String, name: String) -> bool {
     let old_path = Path::new(&path);
+    if archive::split(old_path).is_some() {
+        return false;
+    }
     let new_path = old_path.with_file_name(name);
-    std::fs::rename(&old_path, &new_path).is_ok()
+    storage::for_path(old_path)
+        .rename(old_path, &new_path)
+        .is_ok()
 }
 
-#[tauri::command]
-pub fn read(path: String) -> Option<entity::OpenFile> {
-    let path = Path::new(&path);
-    if path.exists() && path.is_file() {
-        let metadata = std::fs::metadata(&path).unwrap();
-        let content = std::fs::read_to_string(&path).unwrap();
-        let open_file = entity::OpenFile {
-            type_: 0,
-            path: path.to_str().unwrap().to_string(),
-            content,
-            updated: metadata.modified().unwrap().elapsed().unwrap().as_micros() as i64,
-        };
-        Some(open_file)
+/// The editable content of a file with the `.ahtml` header split off and checked, and its
+/// body sanitized. Also returns the header problems and what the sanitizer stripped.
+fn document(
+    path: &Path,
+    content: String,
+) -> (
+    String,
+    Option<entity::AhtmlHeader>,
+    Vec<String>,
+    Vec<String>,
+) {
+    if path.extension().map_or(false, |ext| ext == "ahtml") {
+        let document = ahtml::parse(path, &content);
+        let (body, stripped) = sanitize::sanitize(&document.body, &sanitize::allowlist(path));
+        (body, Some(document.header), document.errors, stripped)
     } else {
-        None
+        (content, None, Vec::new(), Vec::new())
     }
 }
 
+/// Microseconds since `time`, zero for a time in the future.
+fn micros(time: SystemTime) -> i64 {
+    time.elapsed()
+        .map_or(0, |elapsed| elapsed.as_micros() as i64)
+}
+
+/// A document on disk or inside an opened zip archive.
+fn open_file(path: &Path) -> Option<entity::OpenFile> {
+    let modified = match archive::split(path) {
+        Some((zip, _)) => fs::metadata(zip).ok()?.modified().ok()?,
+        None => {
+            let stat = storage::for_path(path).stat(path).ok()?;
+            if stat.is_dir {
+                return None;
+            }
+            stat.modified
+        }
+    };
+    let content = archive::read_to_string(path).ok()?;
+    let (content, header, errors, stripped) = document(path, content);
+    Some(entity::OpenFile {
+        type_: 0,
+        path: path.to_string_lossy().to_string(),
+        front_matter: frontmatter::parse(&content),
+        content,
+        updated: micros(modified),
+        header,
+        errors,
+        stripped,
+    })
+}
+
+#[tauri::command]
+pub fn read(path: String) -> Option<entity::OpenFile> {
+    open_file(Path::new(&path))
+}
+
 #[tauri::command]
 pub fn reads(paths: Vec<String>) -> Option<Vec<entity::OpenFile>> {
-    let mut result = Vec::new();
-    for path in paths {
-        let path = Path::new(&path);
-        if path.exists() && path.is_file() {
-            let metadata = std::fs::metadata(&path).unwrap();
-            let content = std::fs::read_to_string(&path).unwrap();
-            let open_file = entity::OpenFile {
-                type_: 0,
-                path: path.to_str().unwrap().to_string(),
-                content,
-                updated: metadata.modified().unwrap().elapsed().unwrap().as_micros() as i64,
-            };
-            result.push(open_file);
-        }
-    }
-    Some(result)
+    Some(
+        paths
+            .iter()
+            .filter_map(|path| open_file(Path::new(path)))
+            .collect(),
+    )
 }
 
 #[tauri::command]
-pub fn write(path: String, content: String) -> Option<entity::OpenFile> {
+pub fn write(path: String, content: String) -> entity::Response<Option<entity::OpenFile>> {
     let path = Path::new(&path);
-    if path.exists() && path.is_file() {
-        std::fs::write(&path, content).unwrap();
-        let metadata = std::fs::metadata(&path).unwrap();
-        let content = std::fs::read_to_string(&path).unwrap();
-        let open_file = entity::OpenFile {
-            type_: 0,
-            path: path.to_str().unwrap().to_string(),
-            content,
-            updated: metadata.modified().unwrap().elapsed().unwrap().as_micros() as i64,
-        };
-        Some(open_file)
+    let storage = storage::for_path(path);
+    let refused = if archive::split(path).is_some() {
+        Some(archive::READ_ONLY.to_string())
+    } else if !storage.is_file(path) {
+        Some(format!("{} is not a document", path.display()))
     } else {
         None
+    };
+    if let Some(msg) = refused {
+        return entity::Response {
+            code: 50000,
+            msg,
+            data: None,
+        };
+    }
+    let (content, stripped) = if path.extension().map_or(false, |ext| ext == "ahtml") {
+        // the header of a legacy file is made from the cleaned body
+        let body = ahtml::body(&content);
+        let (clean, stripped) = sanitize::sanitize(body, &sanitize::allowlist(path));
+        let content = format!("{}{}", &content[..content.len() - body.len()], clean);
+        (ahtml::save(path, &content), stripped)
+    } else {
+        (content, Vec::new())
+    };
+    let written = storage
+        .write(path, content.as_bytes())
+        .and_then(|_| Ok((storage.stat(path)?, storage.read_to_string(path)?)));
+    match written {
+        Ok((metadata, content)) => {
+            let (content, header, errors, _) = document(path, content);
+            entity::Response {
+                code: 10000,
+                msg: "success".to_string(),
+                data: Some(entity::OpenFile {
+                    type_: 0,
+                    path: path.to_str().unwrap().to_string(),
+                    front_matter: frontmatter::parse(&content),
+                    content,
+                    updated: micros(metadata.modified),
+                    header,
+                    errors,
+                    stripped,
+                }),
+            }
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: None,
+        },
     }
 }
 
 #[tauri::command]
-pub fn get_config(path: String) -> entity::Response<entity::Config> {
-    // path + ".ahriknow" + "config.json"
+pub fn update_front_matter(
+    path: String,
+    fields: serde_json::Map<String, serde_json::Value>,
+) -> entity::Response<Option<entity::OpenFile>> {
     let path = Path::new(&path);
-    let config_path = path.join(".ahriknow").join("config.json");
-    if !config_path.exists() {
-        match config_path.parent() {
-            Some(parent) => match std::fs::create_dir_all(parent) {
-                Ok(_) => (),
-                Err(e) => {
-                    return entity::Response {
-                        code: 50000,
-                        msg: e.to_string(),
-                        data: entity::Config {
-                            token: "".to_string(),
-                            project: "".to_string(),
-                        },
-                    }
-                }
-            },
-            None => {
-                return entity::Response {
-                    code: 50000,
-                    msg: "config path not found".to_string(),
-                    data: entity::Config {
-                        token: "".to_string(),
-                        project: "".to_string(),
-                    },
-                }
-            }
-        }
+    // an .ahtml document keeps its metadata in the ahtml header instead
+    if path.extension().map_or(true, |ext| ext != "md") {
+        return entity::Response {
+            code: 50000,
+            msg: format!(
+                "{}: front matter is only kept in Markdown documents",
+                path.display()
+            ),
+            data: None,
+        };
     }
-    match std::fs::write(
-        &config_path,
-        "{\n  \"token\": \"\",\n  \"project\": \"\"\n}",
-    ) {
-        Ok(_) => (),
+    let content = match storage::for_path(path).read_to_string(path) {
+        Ok(content) => content,
         Err(e) => {
             return entity::Response {
                 code: 50000,
                 msg: e.to_string(),
-                data: entity::Config {
-                    token: "".to_string(),
-                    project: "".to_string(),
-                },
+                data: None,
             }
         }
-    }
-    match std::fs::read_to_string(&config_path) {
-        Ok(content) => match serde_json::from_str(&content) {
-            Ok(config) => entity::Response {
-                code: 10000,
-                msg: "success".to_string(),
-                data: config,
-            },
-            Err(e) => entity::Response {
+    };
+    let content = match frontmatter::update(&content, fields) {
+        Ok(content) => content,
+        Err(e) => {
+            return entity::Response {
                 code: 50000,
-                msg: e.to_string(),
-                data: entity::Config {
-                    token: "".to_string(),
-                    project: "".to_string(),
-                },
-            },
+                msg: e,
+                data: None,
+            }
+        }
+    };
+    let storage = storage::for_path(path);
+    match storage
+        .write(path, content.as_bytes())
+        .and_then(|_| storage.stat(path))
+    {
+        Ok(metadata) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(entity::OpenFile {
+                type_: 0,
+                path: path.to_str().unwrap().to_string(),
+                front_matter: frontmatter::parse(&content),
+                content,
+                updated: micros(metadata.modified),
+                header: None,
+                errors: Vec::new(),
+                stripped: Vec::new(),
+            }),
         },
         Err(e) => entity::Response {
             code: 50000,
             msg: e.to_string(),
-            data: entity::Config {
-                token: "".to_string(),
-                project: "".to_string(),
-            },
+            data: None,
         },
     }
 }
 
+pub fn flatten(tree: Vec<entity::FileTree>, result: &mut Vec<entity::FileTree>) {
+    for mut node in tree {
+        if let Some(children) = node.children.take() {
+            flatten(children, result);
+        }
+        result.push(node);
+    }
+}
+
 #[tauri::command]
-pub fn set_config(path: String, config: entity::Config) -> entity::Response<bool> {
-    // path + ".ahriknow" + "config.json"
-    let path = Path::new(&path);
-    let config_path = path.join(".ahriknow").join("config.json");
-    match serde_json::to_string_pretty(&config) {
-        Ok(content) => match std::fs::write(&config_path, content) {
-            Ok(_) => entity::Response {
-                code: 10000,
-                msg: "success".to_string(),
-                data: true,
-            },
-            Err(e) => entity::Response {
+pub fn query(path: String, query: String) -> entity::Response<Vec<entity::FileTree>> {
+    let expr = match query::parse(&query) {
+        Ok(expr) => expr,
+        Err(e) => {
+            return entity::Response {
+                code: 50000,
+                msg: e,
+                data: Vec::new(),
+            }
+        }
+    };
+    let tree = match read_dir(Path::new(&path), 0) {
+        Ok(tree) => tree,
+        Err(e) => {
+            return entity::Response {
                 code: 50000,
                 msg: e.to_string(),
-                data: false,
-            },
+                data: Vec::new(),
+            }
+        }
+    };
+    let mut files = Vec::new();
+    flatten(tree, &mut files);
+    files.retain(|file| match &file.front_matter {
+        Some(front_matter) => expr.matches(&front_matter.data),
+        None => false,
+    });
+    files.sort_by(|a, b| a.path.cmp(&b.path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: files,
+    }
+}
+
+#[tauri::command]
+pub fn list_tags(
+    path: String,
+    index: tauri::State<'_, tags::TagIndex>,
+) -> entity::Response<Vec<entity::Tag>> {
+    index.ensure(Path::new(&path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: index.tags(),
+    }
+}
+
+#[tauri::command]
+pub fn tag_files(
+    path: String,
+    tag: String,
+    index: tauri::State<'_, tags::TagIndex>,
+) -> entity::Response<Vec<String>> {
+    index.ensure(Path::new(&path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: index.files(&tag),
+    }
+}
+
+#[tauri::command]
+pub fn rename_tag(
+    path: String,
+    from: String,
+    to: String,
+    index: tauri::State<'_, tags::TagIndex>,
+) -> entity::Response<Vec<String>> {
+    index.ensure(Path::new(&path));
+    match index.rename(&from, &to) {
+        Ok(files) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: files,
         },
         Err(e) => entity::Response {
             code: 50000,
-            msg: e.to_string(),
-            data: false,
+            msg: e,
+            data: Vec::new(),
+        },
+    }
+}
+
+#[tauri::command]
+pub fn merge_tags(
+    path: String,
+    tags: Vec<String>,
+    into: String,
+    index: tauri::State<'_, tags::TagIndex>,
+) -> entity::Response<Vec<String>> {
+    index.ensure(Path::new(&path));
+    let mut result: Vec<String> = Vec::new();
+    for tag in tags.iter().filter(|tag| **tag != into) {
+        match index.rename(tag, &into) {
+            Ok(files) => result.extend(files),
+            Err(e) => {
+                return entity::Response {
+                    code: 50000,
+                    msg: e,
+                    data: result,
+                }
+            }
+        }
+    }
+    result.sort();
+    result.dedup();
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: result,
+    }
+}
+
+#[tauri::command]
+pub fn outline(path: String) -> entity::Response<Vec<entity::Heading>> {
+    match archive::read_to_string(Path::new(&path)) {
+        Ok(content) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: outline::nest(outline::headings(&path, &content)),
         },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: Vec::new(),
+        },
+    }
+}
+
+/// Writes a restructured document, keeping the previous version in the history.
+fn save(path: &Path, content: String) -> entity::Response<Option<entity::OpenFile>> {
+    match history::write(path, &content).and_then(|_| storage::for_path(path).stat(path)) {
+        Ok(metadata) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(entity::OpenFile {
+                type_: 0,
+                path: path.to_str().unwrap().to_string(),
+                front_matter: frontmatter::parse(&content),
+                content,
+                updated: micros(metadata.modified),
+                header: None,
+                errors: Vec::new(),
+                stripped: Vec::new(),
+            }),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: None,
+        },
+    }
+}
+
+fn read_markdown(path: &Path) -> Result<String, String> {
+    if path.extension().map_or(true, |ext| ext != "md") {
+        return Err("sections can only be restructured in Markdown documents".to_string());
+    }
+    storage::for_path(path)
+        .read_to_string(path)
+        .map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+pub fn move_section(
+    path: String,
+    line: i32,
+    offset: i32,
+) -> entity::Response<Option<entity::OpenFile>> {
+    let path = Path::new(&path);
+    match read_markdown(path).and_then(|content| section::move_by(&content, line, offset)) {
+        Ok(content) => save(path, content),
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+#[tauri::command]
+pub fn move_section_to(
+    path: String,
+    line: i32,
+    target: String,
+    target_line: Option<i32>,
+) -> entity::Response<Option<entity::OpenFile>> {
+    let path = Path::new(&path);
+    let target = Path::new(&target);
+    if path == target {
+        return entity::Response {
+            code: 50000,
+            msg: "use move_section within a document".to_string(),
+            data: None,
+        };
+    }
+    let result = read_markdown(path).and_then(|content| {
+        let (rest, section, _) = section::take(&content, line)?;
+        let original = read_markdown(target)?;
+        let target_content = section::insert(&original, &section, target_line)?;
+        Ok((rest, original, target_content))
+    });
+    match result {
+        Ok((rest, original, target_content)) => {
+            if let Err(e) = history::write(target, &target_content) {
+                return entity::Response {
+                    code: 50000,
+                    msg: e.to_string(),
+                    data: None,
+                };
+            }
+            let response = save(path, rest);
+            if response.code != 10000 {
+                // the section must not end up in both documents
+                let _ = storage::for_path(target).write(target, original.as_bytes());
+            }
+            response
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+#[tauri::command]
+pub fn shift_section(
+    path: String,
+    line: i32,
+    delta: i32,
+) -> entity::Response<Option<entity::OpenFile>> {
+    let path = Path::new(&path);
+    match read_markdown(path).and_then(|content| section::shift(&content, line, delta)) {
+        Ok(content) => save(path, content),
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+#[tauri::command]
+pub fn extract_section(
+    path: String,
+    line: i32,
+    name: String,
+) -> entity::Response<Option<entity::OpenFile>> {
+    let path = Path::new(&path);
+    // the new document goes next to this one
+    if name.trim().is_empty() || name.contains("..") || name.contains(['/', '\\']) {
+        return entity::Response {
+            code: 50000,
+            msg: format!("{}: not a valid file name", name),
+            data: None,
+        };
+    }
+    let name = if name.ends_with(".md") {
+        name
+    } else {
+        name + ".md"
+    };
+    let target = path.with_file_name(&name);
+    if storage::for_path(&target).exists(&target) {
+        return entity::Response {
+            code: 50000,
+            msg: format!("{} already exists", name),
+            data: None,
+        };
+    }
+    match read_markdown(path).and_then(|content| section::extract(&content, line, &name)) {
+        Ok((content, section)) => {
+            if let Err(e) = storage::for_path(&target).write(&target, section.as_bytes()) {
+                return entity::Response {
+                    code: 50000,
+                    msg: e.to_string(),
+                    data: None,
+                };
+            }
+            save(path, content)
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+#[tauri::command]
+pub fn history(path: String) -> entity::Response<Vec<entity::History>> {
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: history::list(Path::new(&path)),
+    }
+}
+
+#[tauri::command]
+pub fn backlinks(
+    path: String,
+    file: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Vec<entity::Link>> {
+    index.ensure(Path::new(&path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: index.backlinks(&file),
+    }
+}
+
+#[tauri::command]
+pub fn outgoing_links(
+    path: String,
+    file: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Vec<entity::Link>> {
+    index.ensure(Path::new(&path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: index.outgoing(&file),
+    }
+}
+
+#[tauri::command]
+pub fn broken_links(
+    path: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Vec<entity::BrokenLink>> {
+    index.ensure(Path::new(&path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: index.broken(),
+    }
+}
+
+#[tauri::command]
+pub fn resolve_link(
+    path: String,
+    file: String,
+    link: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<entity::Resolution> {
+    index.ensure(Path::new(&path));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: index.resolve(&file, &link),
+    }
+}
+
+/// Creates the page a dangling wiki-link points at, titled after the link.
+#[tauri::command]
+pub fn create_page(
+    path: String,
+    file: String,
+    link: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::OpenFile>> {
+    index.ensure(Path::new(&path));
+    let resolution = index.resolve(&file, &link);
+    if !resolution.candidates.is_empty() {
+        return entity::Response {
+            code: 50000,
+            msg: format!(
+                "ambiguous page name, it matches {}",
+                resolution.candidates.join(", ")
+            ),
+            data: None,
+        };
+    }
+    if !resolution.target.is_empty() || resolution.page.is_empty() {
+        return entity::Response {
+            code: 50000,
+            msg: "page already exists".to_string(),
+            data: None,
+        };
+    }
+    let page = match links::page_path(Path::new(&path), Path::new(&file), &resolution.page) {
+        Some(page) => page,
+        None => {
+            return entity::Response {
+                code: 50000,
+                msg: format!("{} is outside the workspace", resolution.page),
+                data: None,
+            }
+        }
+    };
+    let storage = storage::for_path(&page);
+    if storage.exists(&page) {
+        return entity::Response {
+            code: 50000,
+            msg: "page already exists".to_string(),
+            data: None,
+        };
+    }
+    let title = resolution.page.rsplit('/').next().unwrap_or("");
+    let content = format!("# {}\n", title);
+    let result = match page.parent() {
+        Some(parent) => storage
+            .create_dir_all(parent)
+            .and_then(|_| storage.write(&page, content.as_bytes())),
+        None => storage.write(&page, content.as_bytes()),
+    };
+    match result.and_then(|_| storage.stat(&page)) {
+        Ok(metadata) => {
+            index.update(&page);
+            entity::Response {
+                code: 10000,
+                msg: "success".to_string(),
+                data: Some(entity::OpenFile {
+                    type_: 0,
+                    path: page.to_str().unwrap().to_string(),
+                    content,
+                    updated: micros(metadata.modified),
+                    front_matter: None,
+                    header: None,
+                    errors: Vec::new(),
+                    stripped: Vec::new(),
+                }),
+            }
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: None,
+        },
+    }
+}
+
+/// Returns `file` with its `![[...]]` embeds inlined, following nested embeds up to `depth`.
+#[tauri::command]
+pub fn expand_embeds(
+    path: String,
+    file: String,
+    depth: Option<i32>,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<String> {
+    index.ensure(Path::new(&path));
+    let content = match archive::read_to_string(Path::new(&file)) {
+        Ok(content) => content,
+        Err(e) => {
+            return entity::Response {
+                code: 50000,
+                msg: e.to_string(),
+                data: "".to_string(),
+            }
+        }
+    };
+    let content = index.read(|files| {
+        embed::Expander {
+            root: Path::new(&path),
+            files,
+            max_depth: depth.unwrap_or(embed::MAX_DEPTH),
+        }
+        .expand(&file, &content)
+    });
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: content,
+    }
+}
+
+/// Renders Markdown to HTML. Code is highlighted with CSS classes, or with inline styles
+/// when a `theme` is given, looking for extra themes in the workspace at `path`.
+#[tauri::command]
+pub fn render_markdown(
+    content: String,
+    path: Option<String>,
+    theme: Option<String>,
+) -> entity::Response<String> {
+    let theme_dir = path.map(|path| highlight::theme_dir(Path::new(&path)));
+    match highlight::Highlighter::load(theme_dir.as_deref(), theme) {
+        Ok(highlighter) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: render::markdown(&content, Some(&highlighter)),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: "".to_string(),
+        },
+    }
+}
+
+/// Renders a document to HTML with its embeds expanded.
+#[tauri::command]
+pub fn render_file(
+    path: String,
+    file: String,
+    theme: Option<String>,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<String> {
+    let highlighter =
+        match highlight::Highlighter::load(Some(&highlight::theme_dir(Path::new(&path))), theme) {
+            Ok(highlighter) => highlighter,
+            Err(e) => {
+                return entity::Response {
+                    code: 50000,
+                    msg: e,
+                    data: "".to_string(),
+                }
+            }
+        };
+    let expanded = expand_embeds(path, file.clone(), None, index);
+    if expanded.code != 10000 {
+        return expanded;
+    }
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: render::document(&file, &expanded.data, Some(&highlighter)),
+    }
+}
+
+#[tauri::command]
+pub fn highlight_languages() -> entity::Response<Vec<entity::Language>> {
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: highlight::languages(),
+    }
+}
+
+/// Built-in themes plus those in the workspace `.ahriknow/themes` folder.
+#[tauri::command]
+pub fn highlight_themes(path: String) -> entity::Response<Vec<String>> {
+    let theme_dir = highlight::theme_dir(Path::new(&path));
+    match highlight::Highlighter::load(Some(&theme_dir), None) {
+        Ok(highlighter) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: highlighter.themes(),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: Vec::new(),
+        },
+    }
+}
+
+/// Stylesheet for class-highlighted code in `theme`.
+#[tauri::command]
+pub fn highlight_css(path: String, theme: String) -> entity::Response<String> {
+    let theme_dir = highlight::theme_dir(Path::new(&path));
+    match highlight::Highlighter::load(Some(&theme_dir), None).and_then(|h| h.css(&theme)) {
+        Ok(css) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: css,
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: "".to_string(),
+        },
+    }
+}
+
+/// Converts a `.md` document to `.ahtml` or an `.ahtml` document to Markdown, next to the
+/// original. The original stays until the conversion is confirmed.
+#[tauri::command]
+pub fn convert(path: String) -> entity::Response<Option<entity::OpenFile>> {
+    match convert::file(Path::new(&path)) {
+        Ok((target, content)) => {
+            let (content, header, errors, stripped) = document(&target, content);
+            entity::Response {
+                code: 10000,
+                msg: "success".to_string(),
+                data: Some(entity::OpenFile {
+                    type_: if header.is_some() { 2 } else { 1 },
+                    path: target.to_str().unwrap().to_string(),
+                    front_matter: frontmatter::parse(&content),
+                    content,
+                    updated: storage::for_path(&target)
+                        .stat(&target)
+                        .map_or(0, |stat| micros(stat.modified)),
+                    header,
+                    errors,
+                    stripped,
+                }),
+            }
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Converts every document of `type_` (1 `.md`, 2 `.ahtml`) in a folder.
+#[tauri::command]
+pub fn convert_folder(path: String, type_: i32) -> entity::Response<Vec<entity::Conversion>> {
+    let ext = match type_ {
+        1 => "md",
+        2 => "ahtml",
+        _ => {
+            return entity::Response {
+                code: 50000,
+                msg: format!("unknown document type {}", type_),
+                data: Vec::new(),
+            }
+        }
+    };
+    let data = walk(Path::new(&path))
+        .into_iter()
+        .filter(|file| file.extension().map_or(false, |e| e == ext))
+        .map(|file| {
+            let source = file.to_str().unwrap().to_string();
+            match convert::file(&file) {
+                Ok((target, _)) => entity::Conversion {
+                    source,
+                    target: target.to_str().unwrap().to_string(),
+                    msg: "success".to_string(),
+                },
+                Err(e) => entity::Conversion {
+                    source,
+                    target: "".to_string(),
+                    msg: e,
+                },
+            }
+        })
+        .collect();
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data,
+    }
+}
+
+/// Removes the originals of confirmed conversions, keeping a copy in the history.
+#[tauri::command]
+pub fn confirm_convert(paths: Vec<String>) -> entity::Response<bool> {
+    for path in paths {
+        let path = Path::new(&path);
+        let storage = storage::for_path(path);
+        let result = convert::target(path).and_then(|target| {
+            if !storage.exists(&target) {
+                return Err(format!("{} has not been converted", path.display()));
+            }
+            history::record(path).map_err(|e| e.to_string())?;
+            storage.delete(path).map_err(|e| e.to_string())
+        });
+        if let Err(e) = result {
+            return entity::Response {
+                code: 50000,
+                msg: e,
+                data: false,
+            };
+        }
+    }
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: true,
+    }
+}
+
+/// Tags, attributes and URL schemes `.ahtml` documents in the workspace may use.
+#[tauri::command]
+pub fn get_allowlist(path: String) -> entity::Response<entity::Allowlist> {
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: sanitize::allowlist(Path::new(&path)),
+    }
+}
+
+#[tauri::command]
+pub fn set_allowlist(path: String, allowlist: entity::Allowlist) -> entity::Response<bool> {
+    let allowlist_path = sanitize::allowlist_path(Path::new(&path));
+    let storage = storage::for_path(&allowlist_path);
+    let result = serde_json::to_string_pretty(&allowlist)
+        .map_err(|e| e.to_string())
+        .and_then(|content| {
+            storage
+                .create_dir_all(allowlist_path.parent().unwrap())
+                .and_then(|_| storage.write(&allowlist_path, content.as_bytes()))
+                .map_err(|e| e.to_string())
+        });
+    match result {
+        Ok(_) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: true,
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: false,
+        },
+    }
+}
+
+/// Exports `folder`, the whole workspace at `path` or a folder inside it, as a static HTML
+/// site in `output`.
+#[tauri::command]
+pub fn export_site(
+    path: String,
+    folder: String,
+    output: String,
+    options: entity::SiteOptions,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Export>> {
+    index.ensure(Path::new(&path));
+    let result = index.read(|files| {
+        site::Site {
+            root: Path::new(&path),
+            folder: Path::new(&folder),
+            output: Path::new(&output),
+            files,
+            options: &options,
+        }
+        .export()
+    });
+    match result {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Exports `target`, a document or a folder of the workspace at `path`, as a PDF file at
+/// `output`. Folders get a table of contents and each document starts on a new page.
+#[tauri::command]
+pub fn export_pdf(
+    path: String,
+    target: String,
+    output: String,
+    options: entity::PdfOptions,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Export>> {
+    index.ensure(Path::new(&path));
+    let target = Path::new(&target);
+    let title = export::title(target, None);
+    let result = index.read(|files| {
+        pdf::Writer::new(Path::new(&path), files, &title, &options)?
+            .export(target, Path::new(&output))
+    });
+    match result {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Packages `folder` of the workspace at `path` as an EPUB book at `output`, with the title,
+/// author, language and cover of the project config.
+#[tauri::command]
+pub fn export_epub(
+    path: String,
+    folder: String,
+    output: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Export>> {
+    index.ensure(Path::new(&path));
+    let config_path = Path::new(&path).join(".ahriknow").join("config.json");
+    let config = storage::for_path(&config_path)
+        .read_to_string(&config_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    let result = index.read(|files| {
+        epub::Book {
+            root: Path::new(&path),
+            folder: Path::new(&folder),
+            output: Path::new(&output),
+            files,
+            config: &config,
+        }
+        .export()
+    });
+    match result {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Exports `folder` of the workspace at `path` as an mdBook (`format` `mdbook`), Docusaurus
+/// docs (`docusaurus`) or Hugo content (`hugo`) project in `output`.
+#[tauri::command]
+pub fn export_project(
+    path: String,
+    folder: String,
+    output: String,
+    format: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Export>> {
+    index.ensure(Path::new(&path));
+    let config_path = Path::new(&path).join(".ahriknow").join("config.json");
+    let config = storage::for_path(&config_path)
+        .read_to_string(&config_path)
+        .ok()
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    let result = index.read(|files| {
+        generator::Project {
+            root: Path::new(&path),
+            folder: Path::new(&folder),
+            output: Path::new(&output),
+            files,
+            config: &config,
+            format: &format,
+        }
+        .export()
+    });
+    match result {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Imports the Word document at `path` into `folder` as a document of `type_` (1 `.md`,
+/// 2 `.ahtml`), with its images extracted next to it. What could not be carried over is
+/// listed in the message.
+#[tauri::command]
+pub fn import_docx(
+    path: String,
+    folder: String,
+    type_: i32,
+) -> entity::Response<Option<entity::Conversion>> {
+    match docx::import(Path::new(&path), Path::new(&folder), type_) {
+        Ok((target, warnings)) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(entity::Conversion {
+                source: path,
+                target: target.to_str().unwrap().to_string(),
+                msg: warnings.join("\n"),
+            }),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Exports the document `source` of the workspace at `path` as a Word document at `output`.
+#[tauri::command]
+pub fn export_docx(
+    path: String,
+    source: String,
+    output: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Export>> {
+    index.ensure(Path::new(&path));
+    let result = index.read(|files| {
+        docx::export(
+            Path::new(&path),
+            Path::new(&source),
+            Path::new(&output),
+            files,
+        )
+    });
+    match result {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// `folder` once it is known to be a folder of the workspace at `path`, outside `.ahriknow`.
+fn workspace_folder(path: &str, folder: &str) -> Result<PathBuf, String> {
+    let folder = links::normalize(Path::new(folder));
+    if !folder.starts_with(path) || folder.starts_with(Path::new(path).join(".ahriknow")) {
+        return Err(format!(
+            "{} is not a folder of the workspace",
+            folder.display()
+        ));
+    }
+    Ok(folder)
+}
+
+/// Imports an Obsidian vault or a Notion export (folder or `.zip`) at `source` into a new
+/// folder below `folder` of the workspace at `path`. The report is also kept in
+/// `.ahriknow/migrations`.
+#[tauri::command]
+pub fn import_notes(
+    path: String,
+    source: String,
+    folder: String,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Migration>> {
+    let folder = match workspace_folder(&path, &folder) {
+        Ok(folder) => folder,
+        Err(e) => {
+            return entity::Response {
+                code: 50000,
+                msg: e,
+                data: None,
+            }
+        }
+    };
+    match migrate::import(Path::new(&source), &folder) {
+        Ok(report) => {
+            let dir = Path::new(&path).join(".ahriknow").join("migrations");
+            let saved = serde_json::to_string_pretty(&report)
+                .map_err(|e| e.to_string())
+                .and_then(|content| {
+                    let storage = storage::for_path(&dir);
+                    storage
+                        .create_dir_all(&dir)
+                        .and_then(|_| {
+                            storage.write(
+                                &dir.join(format!("{}.json", ahtml::now())),
+                                content.as_bytes(),
+                            )
+                        })
+                        .map_err(|e| e.to_string())
+                });
+            index.build(Path::new(&path));
+            entity::Response {
+                code: 10000,
+                msg: match saved {
+                    Ok(_) => "success".to_string(),
+                    Err(e) => e,
+                },
+                data: Some(report),
+            }
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// The file tree of `folder`, with the heading outline of every document when `headings`
+/// is set, as an OPML (`format` `opml`) or Markdown (`markdown`) outline.
+#[tauri::command]
+pub fn export_outline(
+    path: String,
+    folder: String,
+    format: String,
+    headings: bool,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<String> {
+    let folder = match workspace_folder(&path, &folder) {
+        Ok(folder) => folder,
+        Err(e) => {
+            return entity::Response {
+                code: 50000,
+                msg: e,
+                data: String::new(),
+            }
+        }
+    };
+    index.ensure(Path::new(&path));
+    match index.read(|files| opml::export(&folder, &format, headings, files)) {
+        Ok(outline) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: outline,
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: String::new(),
+        },
+    }
+}
+
+/// Scaffolds the folders and placeholder documents planned by the OPML file `source` below
+/// `folder`, as documents of `type_` (1 Markdown, 2 `.ahtml`).
+#[tauri::command]
+pub fn import_opml(
+    path: String,
+    source: String,
+    folder: String,
+    type_: i32,
+    index: tauri::State<'_, links::LinkIndex>,
+) -> entity::Response<Option<entity::Scaffold>> {
+    let result = workspace_folder(&path, &folder).and_then(|folder| {
+        std::fs::read_to_string(&source)
+            .map_err(|e| e.to_string())
+            .and_then(|content| opml::import(&content, &folder, type_))
+    });
+    match result {
+        Ok(report) => {
+            index.build(Path::new(&path));
+            entity::Response {
+                code: 10000,
+                msg: "success".to_string(),
+                data: Some(report),
+            }
+        }
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Writes the workspace at `path`, `.ahriknow` included, to the backup archive `output`.
+#[tauri::command]
+pub fn backup_workspace(path: String, output: String) -> entity::Response<Option<entity::Backup>> {
+    match backup::backup(Path::new(&path), Path::new(&output)) {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Restores the backup `archive` into the new or empty `folder`, checking every file
+/// against the checksums of the manifest.
+#[tauri::command]
+pub fn restore_workspace(
+    archive: String,
+    folder: String,
+) -> entity::Response<Option<entity::Backup>> {
+    match backup::restore(Path::new(&archive), Path::new(&folder)) {
+        Ok(report) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(report),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: None,
+        },
+    }
+}
+
+/// Unpacks the zip archive opened read-only at `path` into the new or empty `folder`.
+#[tauri::command]
+pub fn extract_archive(path: String, folder: String) -> entity::Response<bool> {
+    match archive::extract(Path::new(&path), Path::new(&folder)) {
+        Ok(_) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: true,
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e,
+            data: false,
+        },
+    }
+}
+
+/// Opens the WebDAV share at `url` as a workspace, whose files then have paths starting with
+/// the URL. The credentials are only kept until the share is closed or the app quits.
+#[tauri::command]
+pub fn open_webdav(
+    url: String,
+    username: String,
+    password: String,
+) -> entity::Response<Option<Vec<entity::FileTree>>> {
+    match webdav::connect(&url, &username, &password).and_then(|root| read_dir(&root, 0)) {
+        Ok(tree) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(tree),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: None,
+        },
+    }
+}
+
+#[tauri::command]
+pub fn close_webdav(url: String) -> entity::Response<bool> {
+    webdav::disconnect(&url);
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: true,
+    }
+}
+
+/// Opens the key prefix of an S3-compatible bucket as a workspace, whose files then have
+/// paths starting with `s3://bucket/prefix`. Recently read documents are cached, so that they
+/// can still be read when the bucket cannot be reached.
+#[tauri::command]
+pub fn open_bucket(bucket: entity::Bucket) -> entity::Response<Option<Vec<entity::FileTree>>> {
+    let cache = tauri::api::path::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("ahridocs")
+        .join("s3");
+    match s3::connect(&bucket, &cache).and_then(|root| read_dir(&root, 0)) {
+        Ok(tree) => entity::Response {
+            code: 10000,
+            msg: "success".to_string(),
+            data: Some(tree),
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: None,
+        },
+    }
+}
+
+#[tauri::command]
+pub fn close_bucket(bucket: entity::Bucket) -> entity::Response<bool> {
+    storage::unmount(&s3::root(&bucket));
+    entity::Response {
+        code: 10000,
+        msg: "success".to_string(),
+        data: true,
+    }
+}
+
+#[tauri::command]
+pub fn get_config(path: String) -> entity::Response<entity::Config> {
+    // path + ".ahriknow" + "config.json"
+    let path = Path::new(&path);
+    let config_path = path.join(".ahriknow").join("config.json");
+    let storage = storage::for_path(path);
+    if !storage.exists(&config_path) {
+        match config_path.parent() {
+            Some(parent) => match storage.create_dir_all(parent) {
+                Ok(_) => (),
+                Err(e) => {
+                    return entity::Response {
+                        code: 50000,
+                        msg: e.to_string(),
+                        data: entity::Config::default(),
+                    }
+                }
+            },
+            None => {
+                return entity::Response {
+                    code: 50000,
+                    msg: "config path not found".to_string(),
+                    data: entity::Config::default(),
+                }
+            }
+        }
+        // only a missing config gets the defaults, so that saved settings survive
+        match storage.write(
+            &config_path,
+            b"{\n  \"token\": \"\",\n  \"project\": \"\"\n}",
+        ) {
+            Ok(_) => (),
+            Err(e) => {
+                return entity::Response {
+                    code: 50000,
+                    msg: e.to_string(),
+                    data: entity::Config::default(),
+                }
+            }
+        }
+    }
+    match storage.read_to_string(&config_path) {
+        Ok(content) => match serde_json::from_str(&content) {
+            Ok(config) => entity::Response {
+                code: 10000,
+                msg: "success".to_string(),
+                data: config,
+            },
+            Err(e) => entity::Response {
+                code: 50000,
+                msg: e.to_string(),
+                data: entity::Config::default(),
+            },
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: entity::Config::default(),
+        },
+    }
+}
+
+#[tauri::command]
+pub fn set_config(path: String, config: entity::Config) -> entity::Response<bool> {
+    // path + ".ahriknow" + "config.json"
+    let path = Path::new(&path);
+    let config_path = path.join(".ahriknow").join("config.json");
+    if !config.cover.is_empty() && !links::normalize(&path.join(&config.cover)).starts_with(path) {
+        return entity::Response {
+            code: 50000,
+            msg: format!("cover {} is outside the workspace", config.cover),
+            data: false,
+        };
+    }
+    match serde_json::to_string_pretty(&config) {
+        Ok(content) => match storage::for_path(path).write(&config_path, content.as_bytes()) {
+            Ok(_) => entity::Response {
+                code: 10000,
+                msg: "success".to_string(),
+                data: true,
+            },
+            Err(e) => entity::Response {
+                code: 50000,
+                msg: e.to_string(),
+                data: false,
+            },
+        },
+        Err(e) => entity::Response {
+            code: 50000,
+            msg: e.to_string(),
+            data: false,
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::storage::Storage;
+
+    fn memory(name: &str) -> std::path::PathBuf {
+        let root = Path::new("/memory").join(name);
+        storage::mount(&root, Arc::new(storage::Memory::new(&root)));
+        storage::for_path(&root)
+            .create_dir(&root.join(".ahriknow"))
+            .unwrap();
+        root
+    }
+
+    #[test]
+    fn test_archive_read_only() {
+        let temp = crate::testing::TempDir::new("api-archive");
+        let archive = temp.join("bundle.zip");
+        let mut zip = zip::ZipWriter::new(fs::File::create(&archive).unwrap());
+        zip.start_file("a.md", zip::write::FileOptions::default())
+            .unwrap();
+        std::io::Write::write_all(&mut zip, b"# A\n").unwrap();
+        zip.finish().unwrap();
+        let path = archive.to_str().unwrap().to_string();
+        assert_eq!(delete(path.clone(), false).msg, archive::READ_ONLY);
+        assert!(!rename(path.clone(), "other.zip".to_string()));
+        assert_eq!(write(path.clone(), "".to_string()).msg, archive::READ_ONLY);
+        assert!(delete(format!("{}/a.md", path), false).code == 50000);
+        assert!(archive.is_file());
+        assert_eq!(read(format!("{}/a.md", path)).unwrap().content, "# A\n");
+    }
+
+    #[test]
+    fn test_write() {
+        let root = memory("write");
+        let storage = storage::for_path(&root);
+        let path = root.join("a.ahtml");
+        storage.write(&path, b"<p>a</p>").unwrap();
+        let file = write(
+            path.to_str().unwrap().to_string(),
+            "<h1 onclick=\"x()\">T</h1><script>alert(1)</script>".to_string(),
+        )
+        .data
+        .unwrap();
+        assert_eq!(file.content, "<h1>T</h1>");
+        assert_eq!(file.stripped.len(), 2);
+        let saved = storage.read_to_string(&path).unwrap();
+        assert!(!saved.contains("script") && !saved.contains("onclick"));
+        storage::unmount(&root);
+    }
+
+    /// Memory storage whose documents someone else changes before every save.
+    struct Conflicting(storage::Memory);
+
+    impl Storage for Conflicting {
+        fn list(&self, path: &Path) -> std::io::Result<Vec<storage::Entry>> {
+            self.0.list(path)
+        }
+        fn stat(&self, path: &Path) -> std::io::Result<storage::Stat> {
+            self.0.stat(path)
+        }
+        fn read(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+            self.0.read(path)
+        }
+        fn write(&self, path: &Path, _: &[u8]) -> std::io::Result<()> {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::Other,
+                format!("{} changed on the server", path.display()),
+            ))
+        }
+        fn create_dir(&self, path: &Path) -> std::io::Result<()> {
+            self.0.create_dir(path)
+        }
+        fn rename(&self, from: &Path, to: &Path) -> std::io::Result<()> {
+            self.0.rename(from, to)
+        }
+        fn delete(&self, path: &Path) -> std::io::Result<()> {
+            self.0.delete(path)
+        }
+        fn watch(&self, path: &Path, handler: storage::Handler) -> std::io::Result<()> {
+            self.0.watch(path, handler)
+        }
+        fn unwatch(&self, path: &Path) -> std::io::Result<()> {
+            self.0.unwatch(path)
+        }
+    }
+
+    #[test]
+    fn test_write_conflict() {
+        let root = Path::new("/memory/write-conflict");
+        let memory = storage::Memory::new(root);
+        let path = root.join("a.md");
+        memory.create_dir(&root.join(".ahriknow")).unwrap();
+        memory.write(&path, b"# A\n").unwrap();
+        storage::mount(root, Arc::new(Conflicting(memory)));
+        let file = path.to_str().unwrap().to_string();
+        let response = write(file.clone(), "# Mine\n".to_string());
+        assert_eq!(response.code, 50000);
+        assert!(response.msg.ends_with("changed on the server"));
+        let mut fields = serde_json::Map::new();
+        fields.insert("author".to_string(), serde_json::Value::from("ahri"));
+        assert_eq!(update_front_matter(file, fields).code, 50000);
+        assert_eq!(save(&path, "# Mine\n".to_string()).code, 50000);
+        assert_eq!(
+            storage::for_path(root).read_to_string(&path).unwrap(),
+            "# A\n"
+        );
+        storage::unmount(root);
+    }
+
+    #[test]
+    fn test_workspace_folder() {
+        assert_eq!(
+            workspace_folder("/ws", "/ws/notes/../docs").unwrap(),
+            Path::new("/ws/docs")
+        );
+        for folder in ["/ws/../other", "/ws/.ahriknow/history", "/elsewhere"] {
+            assert!(workspace_folder("/ws", folder).is_err());
+        }
+    }
+
+    #[test]
+    fn test_update_front_matter() {
+        let root = memory("update-front-matter");
+        let storage = storage::for_path(&root);
+        let mut fields = serde_json::Map::new();
+        fields.insert("author".to_string(), serde_json::Value::from("ahri"));
+        let path = root.join("a.ahtml");
+        storage.write(&path, b"<p>a</p>").unwrap();
+        let response = update_front_matter(path.to_str().unwrap().to_string(), fields.clone());
+        assert_eq!(response.code, 50000);
+        assert_eq!(storage.read_to_string(&path).unwrap(), "<p>a</p>");
+        let path = root.join("a.md");
+        storage.write(&path, b"# A\n").unwrap();
+        let response = update_front_matter(path.to_str().unwrap().to_string(), fields);
+        assert_eq!(
+            response.data.unwrap().front_matter.unwrap().data["author"],
+            "ahri"
+        );
+        storage::unmount(&root);
+    }
+
+    #[test]
+    fn test_extract_section() {
+        let root = memory("extract-section");
+        let storage = storage::for_path(&root);
+        let path = root.join("a.md");
+        storage.write(&path, b"# A\n## A1\nbody\n# B\n").unwrap();
+        let response = extract_section(path.to_str().unwrap().to_string(), 2, "A1".to_string());
+        assert_eq!(response.code, 10000);
+        assert_eq!(
+            storage.read_to_string(&root.join("A1.md")).unwrap(),
+            "# A1\nbody\n"
+        );
+        let response = extract_section(path.to_str().unwrap().to_string(), 1, "A1".to_string());
+        assert_eq!(response.msg, "A1.md already exists");
+        for name in ["../b", "sub/b", ""] {
+            let response = extract_section(path.to_str().unwrap().to_string(), 1, name.to_string());
+            assert_eq!(response.msg, format!("{}: not a valid file name", name));
+        }
+        storage::unmount(&root);
+    }
+
+    #[test]
+    fn test_query() {
+        let root = memory("query");
+        let storage = storage::for_path(&root);
+        storage.create_dir(&root.join("guide")).unwrap();
+        for (name, content) in [
+            ("b.md", "---\ntags: [api]\ndate: 2026-02-01\n---\n"),
+            ("guide/a.md", "+++\ntags = [\"api\", \"guide\"]\n+++\n"),
+            ("c.md", "---\ntags: [guide]\n---\n"),
+            ("d.md", "# No front matter\n"),
+        ] {
+            storage.write(&root.join(name), content.as_bytes()).unwrap();
+        }
+        let found = |q: &str| -> Vec<String> {
+            query(root.to_str().unwrap().to_string(), q.to_string())
+                .data
+                .into_iter()
+                .map(|file| file.name)
+                .collect()
+        };
+        assert_eq!(found("tags contains api"), vec!["b.md", "a.md"]);
+        assert_eq!(found("date > 2026-01-01"), vec!["b.md"]);
+        assert_eq!(found("not date exists"), vec!["c.md", "a.md"]);
+        let response = query(root.to_str().unwrap().to_string(), "tags =".to_string());
+        assert_eq!(response.code, 50000);
+        storage::unmount(&root);
+    }
+
+    #[test]
+    fn test_move_section_to() {
+        let root = memory("move-section");
+        let storage = storage::for_path(&root);
+        let (path, target) = (root.join("a.md"), root.join("b.md"));
+        storage.write(&path, b"# A\n## A1\nbody\n").unwrap();
+        storage.write(&target, b"# B\n").unwrap();
+        let response = move_section_to(
+            path.to_str().unwrap().to_string(),
+            2,
+            target.to_str().unwrap().to_string(),
+            Some(1),
+        );
+        assert_eq!(response.code, 10000);
+        assert_eq!(storage.read_to_string(&path).unwrap(), "# A\n");
+        assert_eq!(
+            storage.read_to_string(&target).unwrap(),
+            "# B\n## A1\nbody\n"
+        );
+        storage::unmount(&root);
+    }
+
+    #[test]
+    fn test_convert() {
+        let root = memory("convert");
+        let storage = storage::for_path(&root);
+        let path = root.join("a.md");
+        storage.write(&path, b"# A\n").unwrap();
+        let converted = convert(path.to_str().unwrap().to_string());
+        assert_eq!(converted.code, 10000);
+        assert!(storage.is_file(&root.join("a.ahtml")));
+        assert_eq!(
+            confirm_convert(vec![path.to_str().unwrap().to_string()]).code,
+            10000
+        );
+        assert!(!storage.exists(&path));
+        assert_eq!(history::list(&path).len(), 1);
+        let missing = root.join("b.md");
+        storage.write(&missing, b"").unwrap();
+        let response = confirm_convert(vec![missing.to_str().unwrap().to_string()]);
+        assert_eq!(
+            response.msg,
+            format!("{} has not been converted", missing.display())
+        );
+        storage::unmount(&root);
+    }
+
+    #[test]
+    fn test_config() {
+        let root = memory("config");
+        let path = root.to_str().unwrap().to_string();
+        assert_eq!(get_config(path.clone()).data.project, "");
+        let config = entity::Config {
+            author: "Ada".to_string(),
+            cover: "img/cover.png".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(set_config(path.clone(), config.clone()).code, 10000);
+        assert_eq!(get_config(path.clone()).data.author, "Ada");
+        let outside = entity::Config {
+            cover: "../cover.png".to_string(),
+            ..config
+        };
+        assert_eq!(
+            set_config(path.clone(), outside).msg,
+            "cover ../cover.png is outside the workspace"
+        );
+        assert_eq!(get_config(path).data.cover, "img/cover.png");
+        storage::unmount(&root);
+    }
+
+    #[test]
+    fn test_allowlist() {
+        let root = memory("allowlist");
+        let mut allowlist = sanitize::default_allowlist();
+        allowlist.tags.retain(|tag| tag != "img");
+        let path = root.to_str().unwrap().to_string();
+        assert_eq!(set_allowlist(path.clone(), allowlist).code, 10000);
+        assert!(storage::for_path(&root).is_file(&sanitize::allowlist_path(&root)));
+        assert!(!get_allowlist(path).data.tags.contains(&"img".to_string()));
+        storage::unmount(&root);
     }
 }
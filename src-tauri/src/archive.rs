@@ -0,0 +1,313 @@
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    fs::{self, File},
+    io::Read,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::SystemTime,
+};
+
+use once_cell::sync::Lazy;
+use zip::ZipArchive;
+
+use crate::{entity, frontmatter, storage};
+
+/// Zip archives open as read-only workspaces.
+pub const READ_ONLY: &str = "the archive is opened read-only, extract it to make changes";
+
+/// Splits a path at the zip archive it is inside of, or is, into the archive and the entry
+/// name below it joined with `/`, empty for the archive itself.
+pub fn split(path: &Path) -> Option<(PathBuf, String)> {
+    for ancestor in path.ancestors() {
+        if ancestor.is_dir() {
+            return None;
+        }
+        let zip = ancestor
+            .extension()
+            .map_or(false, |ext| ext.eq_ignore_ascii_case("zip"));
+        if zip && ancestor.is_file() {
+            let name = path
+                .strip_prefix(ancestor)
+                .unwrap()
+                .components()
+                .map(|c| c.as_os_str().to_string_lossy().to_string())
+                .collect::<Vec<_>>()
+                .join("/");
+            return Some((ancestor.to_path_buf(), name));
+        }
+    }
+    None
+}
+
+type Zip = Arc<Mutex<ZipArchive<File>>>;
+
+/// An archive as opened, with the modification time and size it had then.
+struct Opened {
+    modified: SystemTime,
+    len: u64,
+    zip: Zip,
+}
+
+/// Opened archives, so that reading the documents of an archive does not parse its
+/// central directory again for each one.
+static OPENED: Lazy<Mutex<HashMap<PathBuf, Opened>>> = Lazy::new(|| Mutex::new(HashMap::new()));
+
+fn open(archive: &Path) -> Result<Zip, String> {
+    let mut opened = OPENED.lock().unwrap();
+    let metadata = match fs::metadata(archive) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            opened.remove(archive);
+            return Err(e.to_string());
+        }
+    };
+    let modified = metadata.modified().map_err(|e| e.to_string())?;
+    if let Some(cached) = opened.get(archive) {
+        if cached.modified == modified && cached.len == metadata.len() {
+            return Ok(cached.zip.clone());
+        }
+    }
+    let file = File::open(archive).map_err(|e| e.to_string())?;
+    let zip = ZipArchive::new(file).map_err(|e| format!("not a zip archive: {}", e))?;
+    let zip = Arc::new(Mutex::new(zip));
+    opened.insert(
+        archive.to_path_buf(),
+        Opened {
+            modified,
+            len: metadata.len(),
+            zip: zip.clone(),
+        },
+    );
+    Ok(zip)
+}
+
+fn type_of(name: &str) -> Option<i32> {
+    match name.rsplit_once('.') {
+        Some((_, "md")) => Some(1),
+        Some((_, "ahtml")) => Some(2),
+        _ => None,
+    }
+}
+
+/// The folders and documents of an archive by entry name, with the content of the
+/// Markdown ones for their front matter. Entries that would land outside the archive,
+/// macOS metadata and the top `.ahriknow` folder are left out.
+fn entries(archive: &Path) -> Result<(BTreeSet<String>, BTreeMap<String, String>), String> {
+    let zip = open(archive)?;
+    let mut zip = zip.lock().unwrap();
+    let mut folders = BTreeSet::new();
+    let mut documents = BTreeMap::new();
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| e.to_string())?;
+        if entry.enclosed_name().is_none() {
+            continue;
+        }
+        let name = entry.name().trim_end_matches('/').to_string();
+        if name.is_empty()
+            || name.split('/').any(|part| part == "__MACOSX")
+            || name == ".ahriknow"
+            || name.starts_with(".ahriknow/")
+        {
+            continue;
+        }
+        let mut parent = name.as_str();
+        while let Some((dir, _)) = parent.rsplit_once('/') {
+            folders.insert(dir.to_string());
+            parent = dir;
+        }
+        if entry.is_dir() {
+            folders.insert(name);
+        } else if let Some(type_) = type_of(&name) {
+            let mut content = String::new();
+            if type_ == 1 {
+                let _ = entry.read_to_string(&mut content);
+            }
+            documents.insert(name, content);
+        }
+    }
+    Ok((folders, documents))
+}
+
+/// The file tree of the archive, or of a folder inside it, like `api::read_dir` gives for
+/// a folder on disk. Entries take the modification time of the archive.
+pub fn tree(path: &Path) -> Result<Vec<entity::FileTree>, String> {
+    let (archive, prefix) = split(path).ok_or_else(|| "not a zip archive".to_string())?;
+    let updated = fs::metadata(&archive)
+        .and_then(|metadata| metadata.modified())
+        .map_or(0, |time| {
+            time.elapsed()
+                .map_or(0, |elapsed| elapsed.as_micros() as i64)
+        });
+    let (folders, documents) = entries(&archive)?;
+    if !prefix.is_empty() && !folders.contains(&prefix) {
+        return Err(format!("{} is not a folder of the archive", prefix));
+    }
+    Ok(children(&archive, &prefix, &folders, &documents, updated))
+}
+
+fn children(
+    archive: &Path,
+    dir: &str,
+    folders: &BTreeSet<String>,
+    documents: &BTreeMap<String, String>,
+    updated: i64,
+) -> Vec<entity::FileTree> {
+    let direct = |name: &&String| {
+        let rest = if dir.is_empty() {
+            Some(name.as_str())
+        } else {
+            name.strip_prefix(dir)
+                .and_then(|rest| rest.strip_prefix('/'))
+        };
+        rest.map_or(false, |rest| !rest.is_empty() && !rest.contains('/'))
+    };
+    let base = |name: &str| name.rsplit('/').next().unwrap_or(name).to_string();
+    let mut result: Vec<entity::FileTree> = folders
+        .iter()
+        .filter(direct)
+        .map(|name| entity::FileTree {
+            type_: 0,
+            name: base(name),
+            path: archive.join(name).to_str().unwrap().to_string(),
+            updated,
+            front_matter: None,
+            children: Some(children(archive, name, folders, documents, updated)),
+        })
+        .collect();
+    for (name, content) in documents.iter().filter(|(name, _)| direct(name)) {
+        let type_ = type_of(name).unwrap();
+        result.push(entity::FileTree {
+            type_,
+            name: base(name),
+            path: archive.join(name).to_str().unwrap().to_string(),
+            updated,
+            front_matter: if type_ == 1 {
+                frontmatter::parse(content)
+            } else {
+                None
+            },
+            children: None,
+        });
+    }
+    result.sort_by_key(|node| node.type_);
+    result
+}
+
+/// Every `.md` and `.ahtml` document of the archive below `path`, like `api::walk`.
+pub fn documents(path: &Path) -> Vec<PathBuf> {
+    let (archive, prefix) = match split(path) {
+        Some(split) => split,
+        None => return Vec::new(),
+    };
+    match entries(&archive) {
+        Ok((_, documents)) => documents
+            .keys()
+            .filter(|name| {
+                prefix.is_empty()
+                    || name
+                        .strip_prefix(&prefix)
+                        .map_or(false, |rest| rest.starts_with('/'))
+            })
+            .map(|name| archive.join(name))
+            .collect(),
+        Err(_) => Vec::new(),
+    }
+}
+
+/// Reads a file from the storage of its workspace or, for a path inside a zip archive,
+/// the entry.
+pub fn read_to_string(path: &Path) -> std::io::Result<String> {
+    let not_found = || std::io::Error::from(std::io::ErrorKind::NotFound);
+    let (archive, name) = match split(path) {
+        Some(split) => split,
+        None => return storage::for_path(path).read_to_string(path),
+    };
+    let zip = open(&archive).map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))?;
+    let mut zip = zip.lock().unwrap();
+    let mut entry = zip.by_name(&name).map_err(|_| not_found())?;
+    if entry.is_dir() {
+        return Err(not_found());
+    }
+    let mut content = String::new();
+    entry.read_to_string(&mut content)?;
+    Ok(content)
+}
+
+/// Unpacks the archive into `folder`, which must not exist yet or be empty, to work on it
+/// as a regular workspace.
+pub fn extract(archive: &Path, folder: &Path) -> Result<(), String> {
+    if folder.exists()
+        && fs::read_dir(folder)
+            .map_err(|e| e.to_string())?
+            .next()
+            .is_some()
+    {
+        return Err(format!("{} is not empty", folder.display()));
+    }
+    let zip = open(archive)?;
+    fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+    let mut zip = zip.lock().unwrap();
+    zip.extract(folder).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use zip::{write::FileOptions, ZipWriter};
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_archive() {
+        let temp = TempDir::new("archive");
+        let dir = temp.join("archive");
+        fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("bundle.zip");
+        let mut zip = ZipWriter::new(File::create(&archive).unwrap());
+        for (name, content) in [
+            ("guide/setup.md", "---\ntags: [a]\n---\n# Setup\n"),
+            ("guide/img/p.png", "png"),
+            ("index.ahtml", "<p>hi</p>"),
+            (".ahriknow/config.json", "{}"),
+        ] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let tree = tree(&archive).unwrap();
+        let names: Vec<&str> = tree.iter().map(|node| node.name.as_str()).collect();
+        assert_eq!(names, vec!["guide", "index.ahtml"]);
+        let guide = tree[0].children.as_ref().unwrap();
+        assert_eq!(guide.len(), 2);
+        assert_eq!(guide[0].name, "img");
+        assert_eq!(
+            guide[1].path,
+            archive.join("guide/setup.md").to_str().unwrap()
+        );
+        assert!(guide[1].front_matter.is_some());
+
+        let setup = archive.join("guide/setup.md");
+        assert_eq!(
+            split(&setup),
+            Some((archive.clone(), "guide/setup.md".to_string()))
+        );
+        assert_eq!(split(&dir.join("other.md")), None);
+        assert_eq!(
+            read_to_string(&setup).unwrap(),
+            "---\ntags: [a]\n---\n# Setup\n"
+        );
+        assert!(read_to_string(&archive.join("guide/nope.md")).is_err());
+        assert!(Arc::ptr_eq(
+            &open(&archive).unwrap(),
+            &open(&archive).unwrap()
+        ));
+        assert_eq!(documents(&archive.join("guide")), vec![setup]);
+
+        extract(&archive, &dir.join("out")).unwrap();
+        assert!(dir.join("out/guide/img/p.png").is_file());
+        assert!(extract(&archive, &dir.join("out")).is_err());
+    }
+}
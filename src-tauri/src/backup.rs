@@ -0,0 +1,301 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{Read, Write},
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+use zip::{write::FileOptions, CompressionMethod, ZipArchive, ZipWriter};
+
+use crate::{ahtml, entity};
+
+/// Archive entry listing every file of the backup with its checksum.
+pub const MANIFEST: &str = "manifest.json";
+
+/// Folder of the archive holding the workspace, so that no workspace file can take the
+/// place of the manifest.
+pub const FILES: &str = "files";
+
+/// Version of the archive layout written by `backup`.
+pub const VERSION: i32 = 1;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Folders and files below `dir`, in name order. Symbolic links are not followed.
+fn walk(dir: &Path, folders: &mut Vec<PathBuf>, files: &mut Vec<PathBuf>) {
+    let mut entries: Vec<PathBuf> = match fs::read_dir(dir) {
+        Ok(entries) => entries.flatten().map(|entry| entry.path()).collect(),
+        Err(_) => return,
+    };
+    entries.sort();
+    for path in entries {
+        let metadata = match fs::symlink_metadata(&path) {
+            Ok(metadata) => metadata,
+            Err(_) => continue,
+        };
+        if metadata.is_dir() {
+            folders.push(path.clone());
+            walk(&path, folders, files);
+        } else if metadata.is_file() {
+            files.push(path);
+        }
+    }
+}
+
+fn name(root: &Path, path: &Path) -> String {
+    path.strip_prefix(root)
+        .unwrap_or(path)
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy().to_string())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+/// Copies `reader` to `writer` and returns the number of bytes and their SHA-256.
+fn copy(reader: &mut impl Read, writer: &mut impl Write) -> std::io::Result<(u64, String)> {
+    let mut hasher = Sha256::new();
+    let mut buffer = [0; 64 * 1024];
+    let mut size = 0;
+    loop {
+        let n = reader.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+        writer.write_all(&buffer[..n])?;
+        size += n as u64;
+    }
+    Ok((size, hex(&hasher.finalize())))
+}
+
+/// Writes the whole workspace, `.ahriknow` with its config and history included, to the
+/// zip archive `output`, with a manifest of the SHA-256 of every file.
+pub fn backup(workspace: &Path, output: &Path) -> Result<entity::Backup, String> {
+    if !workspace.is_dir() {
+        return Err(format!("{} is not a folder", workspace.display()));
+    }
+    let mut folders = Vec::new();
+    let mut files = Vec::new();
+    walk(workspace, &mut folders, &mut files);
+    if let Some(parent) = output.parent() {
+        fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+    }
+    let file = File::create(output).map_err(|e| e.to_string())?;
+    let mut zip = ZipWriter::new(file);
+    let error = |e: zip::result::ZipError| e.to_string();
+    let mut manifest = entity::BackupManifest {
+        version: VERSION,
+        name: workspace
+            .file_name()
+            .map(|name| name.to_string_lossy().to_string())
+            .unwrap_or_default(),
+        created: ahtml::now(),
+        files: Vec::new(),
+        folders: folders.iter().map(|path| name(workspace, path)).collect(),
+    };
+    for folder in &manifest.folders {
+        zip.add_directory(format!("{}/{}", FILES, folder), FileOptions::default())
+            .map_err(error)?;
+    }
+    let mut size = 0;
+    for path in files {
+        // an archive written into the workspace itself
+        if path == output {
+            continue;
+        }
+        let rel = name(workspace, &path);
+        let mut source = File::open(&path).map_err(|e| format!("{}: {}", rel, e))?;
+        let length = source.metadata().map_or(0, |metadata| metadata.len());
+        let options = FileOptions::default()
+            .compression_method(CompressionMethod::Deflated)
+            .large_file(length >= u32::MAX as u64);
+        zip.start_file(format!("{}/{}", FILES, rel), options)
+            .map_err(error)?;
+        let (length, sha256) =
+            copy(&mut source, &mut zip).map_err(|e| format!("{}: {}", rel, e))?;
+        size += length;
+        manifest.files.push(entity::BackupFile {
+            path: rel,
+            size: length as i64,
+            sha256,
+        });
+    }
+    zip.start_file(MANIFEST, FileOptions::default())
+        .map_err(error)?;
+    let json = serde_json::to_string_pretty(&manifest).map_err(|e| e.to_string())?;
+    zip.write_all(json.as_bytes()).map_err(|e| e.to_string())?;
+    zip.finish().map_err(error)?;
+    Ok(entity::Backup {
+        archive: output.to_str().unwrap().to_string(),
+        folder: workspace.to_str().unwrap().to_string(),
+        files: manifest.files.len() as i32,
+        size: size as i64,
+    })
+}
+
+/// Restores the backup `archive` into `folder`, which must not exist yet or be empty. Every
+/// file is checked against the manifest, and nothing is left behind when one does not match.
+pub fn restore(archive: &Path, folder: &Path) -> Result<entity::Backup, String> {
+    let existed = folder.exists();
+    if existed
+        && fs::read_dir(folder)
+            .map_err(|e| e.to_string())?
+            .next()
+            .is_some()
+    {
+        return Err(format!("{} is not empty", folder.display()));
+    }
+    let file = File::open(archive).map_err(|e| e.to_string())?;
+    let mut zip = ZipArchive::new(file).map_err(|e| format!("not a backup archive: {}", e))?;
+    let manifest: entity::BackupManifest = {
+        let mut entry = zip
+            .by_name(MANIFEST)
+            .map_err(|_| "not a backup archive: the manifest is missing".to_string())?;
+        let mut json = String::new();
+        entry.read_to_string(&mut json).map_err(|e| e.to_string())?;
+        serde_json::from_str(&json).map_err(|e| format!("invalid manifest: {}", e))?
+    };
+    if manifest.version > VERSION {
+        return Err(format!(
+            "the archive was written by a newer version (format {})",
+            manifest.version
+        ));
+    }
+    fs::create_dir_all(folder).map_err(|e| e.to_string())?;
+    let result = extract(&mut zip, &manifest, folder);
+    if result.is_err() {
+        let _ = fs::remove_dir_all(folder);
+        if existed {
+            let _ = fs::create_dir(folder);
+        }
+    }
+    let size = result?;
+    Ok(entity::Backup {
+        archive: archive.to_str().unwrap().to_string(),
+        folder: folder.to_str().unwrap().to_string(),
+        files: manifest.files.len() as i32,
+        size: size as i64,
+    })
+}
+
+fn extract(
+    zip: &mut ZipArchive<File>,
+    manifest: &entity::BackupManifest,
+    folder: &Path,
+) -> Result<u64, String> {
+    let mut expected: HashMap<&str, &entity::BackupFile> = manifest
+        .files
+        .iter()
+        .map(|file| (file.path.as_str(), file))
+        .collect();
+    for rel in &manifest.folders {
+        let path = Path::new(rel);
+        if !path
+            .components()
+            .all(|c| matches!(c, std::path::Component::Normal(_)))
+        {
+            return Err(format!("{}: unsafe path in the manifest", rel));
+        }
+        fs::create_dir_all(folder.join(path)).map_err(|e| e.to_string())?;
+    }
+    let mut size = 0;
+    for i in 0..zip.len() {
+        let mut entry = zip.by_index(i).map_err(|e| e.to_string())?;
+        let name = entry.name().trim_end_matches('/').to_string();
+        if name == MANIFEST || name == FILES {
+            continue;
+        }
+        let rel = match name
+            .strip_prefix(FILES)
+            .and_then(|rest| rest.strip_prefix('/'))
+        {
+            Some(rel) => rel.to_string(),
+            None => return Err(format!("{}: not part of a backup", name)),
+        };
+        let path = match entry.enclosed_name() {
+            Some(path) => folder.join(path.strip_prefix(FILES).unwrap()),
+            None => return Err(format!("{}: unsafe path in the archive", rel)),
+        };
+        if entry.is_dir() {
+            fs::create_dir_all(&path).map_err(|e| e.to_string())?;
+            continue;
+        }
+        let file = expected
+            .remove(rel.as_str())
+            .ok_or_else(|| format!("{}: not listed in the manifest", rel))?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        let mut target = File::create(&path).map_err(|e| format!("{}: {}", rel, e))?;
+        let (length, sha256) =
+            copy(&mut entry, &mut target).map_err(|e| format!("{}: {}", rel, e))?;
+        if length as i64 != file.size || sha256 != file.sha256 {
+            return Err(format!(
+                "{}: checksum mismatch, the archive is damaged",
+                rel
+            ));
+        }
+        size += length;
+    }
+    if let Some(rel) = expected.keys().min() {
+        return Err(format!("{}: missing from the archive", rel));
+    }
+    Ok(size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_round_trip() {
+        let temp = TempDir::new("backup");
+        let dir = temp.join("backup");
+        let workspace = dir.join("docs");
+        fs::create_dir_all(workspace.join(".ahriknow/history")).unwrap();
+        fs::create_dir_all(workspace.join("empty")).unwrap();
+        fs::write(workspace.join(".ahriknow/config.json"), "{}").unwrap();
+        fs::write(workspace.join("a.md"), "# A\n").unwrap();
+        let archive = dir.join("docs.zip");
+        let report = backup(&workspace, &archive).unwrap();
+        assert_eq!((report.files, report.size), (2, 6));
+
+        let report = restore(&archive, &dir.join("copy")).unwrap();
+        assert_eq!(report.files, 2);
+        assert_eq!(fs::read_to_string(dir.join("copy/a.md")).unwrap(), "# A\n");
+        assert!(dir.join("copy/.ahriknow/history").is_dir());
+        assert!(dir.join("copy/empty").is_dir());
+        assert!(restore(&archive, &dir.join("copy")).is_err());
+
+        // an archive whose file does not match its manifest
+        let mut zip = ZipWriter::new(File::create(dir.join("bad.zip")).unwrap());
+        zip.start_file("files/a.md", FileOptions::default())
+            .unwrap();
+        zip.write_all(b"# B\n").unwrap();
+        zip.start_file(MANIFEST, FileOptions::default()).unwrap();
+        let mut manifest: entity::BackupManifest = serde_json::from_str(
+            &ZipArchive::new(File::open(&archive).unwrap())
+                .unwrap()
+                .by_name(MANIFEST)
+                .map(|mut entry| {
+                    let mut json = String::new();
+                    entry.read_to_string(&mut json).unwrap();
+                    json
+                })
+                .unwrap(),
+        )
+        .unwrap();
+        manifest.files.retain(|file| file.path == "a.md");
+        manifest.folders.clear();
+        zip.write_all(serde_json::to_string(&manifest).unwrap().as_bytes())
+            .unwrap();
+        zip.finish().unwrap();
+        let error = restore(&dir.join("bad.zip"), &dir.join("bad")).unwrap_err();
+        assert_eq!(error, "a.md: checksum mismatch, the archive is damaged");
+        assert!(!dir.join("bad").exists());
+    }
+}
@@ -0,0 +1,373 @@
+use std::path::{Path, PathBuf};
+
+use kuchiki::{traits::TendrilSink, NodeRef};
+
+use crate::{ahtml, frontmatter, render, sanitize, storage};
+
+/// The sibling a document converts to: `.md` becomes `.ahtml` and the other way around.
+pub fn target(path: &Path) -> Result<PathBuf, String> {
+    match path.extension().and_then(|ext| ext.to_str()) {
+        Some("md") => Ok(path.with_extension("ahtml")),
+        Some("ahtml") => Ok(path.with_extension("md")),
+        _ => Err(format!("{} is not a document", path.display())),
+    }
+}
+
+/// Converts the document at `path` into its sibling of the other format. The original is
+/// kept, and an existing target is never overwritten.
+pub fn file(path: &Path) -> Result<(PathBuf, String), String> {
+    let target = target(path)?;
+    let storage = storage::for_path(path);
+    if storage.exists(&target) {
+        return Err(format!("{} already exists", target.display()));
+    }
+    let content = storage.read_to_string(path).map_err(|e| e.to_string())?;
+    let converted = if target.extension().map_or(false, |ext| ext == "md") {
+        let document = ahtml::parse(path, &content);
+        let markdown = to_markdown(&document.body);
+        if document.legacy {
+            markdown
+        } else {
+            // title and tags of the header move to the front matter
+            let mut fields = serde_json::Map::new();
+            if !document.header.title.is_empty() {
+                fields.insert("title".to_string(), document.header.title.into());
+            }
+            if !document.header.tags.is_empty() {
+                fields.insert("tags".to_string(), document.header.tags.into());
+            }
+            frontmatter::update(&markdown, fields)?
+        }
+    } else {
+        let body = to_ahtml(&content);
+        // raw HTML in the Markdown passes through the renderer
+        let (body, _) = sanitize::sanitize(&body, &sanitize::allowlist(path));
+        let mut header = ahtml::legacy(path, &body);
+        if let Some(front_matter) = frontmatter::parse(&content) {
+            if let Some(serde_json::Value::String(title)) = front_matter.data.get("title") {
+                header.title = title.trim().to_string();
+            }
+            header.tags = frontmatter::strings(&front_matter.data, "tags");
+        }
+        ahtml::to_string(&header, &body)
+    };
+    storage
+        .write(&target, converted.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok((target, converted))
+}
+
+/// Markdown document rendered as rich HTML for the `.ahtml` editor.
+pub fn to_ahtml(markdown: &str) -> String {
+    render::markdown(markdown, None)
+}
+
+/// Converts an `.ahtml` document back to Markdown.
+pub fn to_markdown(html: &str) -> String {
+    let document = kuchiki::parse_html().one(ahtml::body(html));
+    let body = document
+        .select_first("body")
+        .map(|body| body.as_node().clone())
+        .unwrap_or(document);
+    let mut result = blocks(&body).join("\n\n");
+    result.push('\n');
+    result
+}
+
+fn name(node: &NodeRef) -> String {
+    match node.as_element() {
+        Some(element) => element.name.local.to_string(),
+        None => "".to_string(),
+    }
+}
+
+fn attr(node: &NodeRef, attr: &str) -> String {
+    match node.as_element() {
+        Some(element) => element
+            .attributes
+            .borrow()
+            .get(attr)
+            .unwrap_or("")
+            .to_string(),
+        None => "".to_string(),
+    }
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "section"
+            | "article"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "pre"
+            | "blockquote"
+            | "table"
+            | "hr"
+    )
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    for c in text.chars() {
+        if matches!(c, '\\' | '*' | '_' | '`' | '[' | ']') {
+            result.push('\\');
+        }
+        result.push(c);
+    }
+    result
+}
+
+/// Markdown blocks for the children of `node`; runs of inline content become paragraphs.
+fn blocks(node: &NodeRef) -> Vec<String> {
+    let mut result = Vec::new();
+    let mut paragraph = String::new();
+    for child in node.children() {
+        let name = name(&child);
+        if is_block(&name) {
+            if !paragraph.trim().is_empty() {
+                result.push(paragraph.trim().to_string());
+            }
+            paragraph.clear();
+            if let Some(block) = block(&child, &name) {
+                result.push(block);
+            }
+        } else {
+            paragraph.push_str(&inline(&child));
+        }
+    }
+    if !paragraph.trim().is_empty() {
+        result.push(paragraph.trim().to_string());
+    }
+    result
+}
+
+fn block(node: &NodeRef, name: &str) -> Option<String> {
+    let result = match name {
+        "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+            let level = name[1..].parse::<usize>().unwrap();
+            format!("{} {}", "#".repeat(level), inline_children(node).trim())
+        }
+        "ul" | "ol" => list(node, name == "ol"),
+        "pre" => {
+            let lang = node
+                .select_first("code")
+                .ok()
+                .map(|code| attr(code.as_node(), "class"))
+                .unwrap_or_default();
+            let lang = lang
+                .split_whitespace()
+                .find_map(|class| class.strip_prefix("language-"))
+                .unwrap_or("");
+            let code = node.text_contents();
+            let fence = if code.contains("```") { "~~~" } else { "```" };
+            format!(
+                "{}{}\n{}\n{}",
+                fence,
+                lang,
+                code.trim_end_matches('\n'),
+                fence
+            )
+        }
+        "blockquote" => blocks(node)
+            .join("\n\n")
+            .lines()
+            .map(|line| format!("> {}", line).trim_end().to_string())
+            .collect::<Vec<String>>()
+            .join("\n"),
+        "table" => table(node),
+        "hr" => "---".to_string(),
+        _ => blocks(node).join("\n\n"),
+    };
+    if result.trim().is_empty() {
+        None
+    } else {
+        Some(result)
+    }
+}
+
+fn list(node: &NodeRef, ordered: bool) -> String {
+    let mut items = Vec::new();
+    for (i, item) in node.children().filter(|c| name(c) == "li").enumerate() {
+        let marker = if ordered {
+            format!("{}. ", i + 1)
+        } else {
+            "- ".to_string()
+        };
+        let checkbox = item
+            .select_first("input[type=checkbox]")
+            .ok()
+            .map(|input| {
+                if attr(input.as_node(), "checked").is_empty()
+                    && input.attributes.borrow().get("checked").is_none()
+                {
+                    "[ ] "
+                } else {
+                    "[x] "
+                }
+            })
+            .unwrap_or("");
+        let content = blocks(&item).join("\n");
+        let indent = " ".repeat(marker.len());
+        let mut lines = content.lines();
+        let mut text = format!("{}{}{}", marker, checkbox, lines.next().unwrap_or(""));
+        for line in lines {
+            text.push('\n');
+            if !line.is_empty() {
+                text.push_str(&indent);
+                text.push_str(line);
+            }
+        }
+        items.push(text);
+    }
+    items.join("\n")
+}
+
+fn table(node: &NodeRef) -> String {
+    let rows: Vec<Vec<String>> = match node.select("tr") {
+        Ok(rows) => rows
+            .map(|row| {
+                row.as_node()
+                    .children()
+                    .filter(|cell| matches!(name(cell).as_str(), "td" | "th"))
+                    .map(|cell| inline_children(&cell).trim().replace('|', "\\|"))
+                    .collect()
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+    let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+    if columns == 0 {
+        return "".to_string();
+    }
+    let line = |row: &[String]| {
+        let mut cells: Vec<String> = row.to_vec();
+        cells.resize(columns, "".to_string());
+        format!("| {} |", cells.join(" | "))
+    };
+    let mut result = vec![line(&rows[0]), format!("|{}", " --- |".repeat(columns))];
+    for row in &rows[1..] {
+        result.push(line(row));
+    }
+    result.join("\n")
+}
+
+fn inline_children(node: &NodeRef) -> String {
+    node.children().map(|child| inline(&child)).collect()
+}
+
+fn inline(node: &NodeRef) -> String {
+    if let Some(text) = node.as_text() {
+        let text = text.borrow();
+        // collapse the whitespace an HTML renderer would collapse too
+        let mut result = String::new();
+        let mut space = false;
+        for c in text.chars() {
+            if c.is_whitespace() && c != '\u{a0}' {
+                if !space {
+                    result.push(' ');
+                }
+                space = true;
+            } else {
+                result.push(if c == '\u{a0}' { ' ' } else { c });
+                space = false;
+            }
+        }
+        return escape(&result);
+    }
+    let content = inline_children(node);
+    match name(node).as_str() {
+        "strong" | "b" => wrap(&content, "**"),
+        "em" | "i" => wrap(&content, "*"),
+        "s" | "del" | "strike" => wrap(&content, "~~"),
+        "code" => {
+            let code = node.text_contents();
+            let fence = if code.contains('`') { "``" } else { "`" };
+            format!("{}{}{}", fence, code, fence)
+        }
+        "a" => {
+            let href = attr(node, "href");
+            if href.is_empty() {
+                content
+            } else {
+                format!("[{}]({})", content, href.replace(' ', "%20"))
+            }
+        }
+        "img" => format!(
+            "![{}]({})",
+            escape(&attr(node, "alt")),
+            attr(node, "src").replace(' ', "%20")
+        ),
+        "br" => "\\\n".to_string(),
+        "input" | "script" | "style" => "".to_string(),
+        "li" | "ul" | "ol" | "table" | "pre" | "blockquote" => blocks(node).join("\n\n") + "\n",
+        _ => content,
+    }
+}
+
+/// Wraps inline content in a marker, keeping surrounding spaces outside of it.
+fn wrap(content: &str, marker: &str) -> String {
+    let trimmed = content.trim();
+    if trimmed.is_empty() {
+        return content.to_string();
+    }
+    let start = &content[..content.len() - content.trim_start().len()];
+    let end = &content[content.trim_end().len()..];
+    format!("{}{}{}{}{}", start, marker, trimmed, marker, end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_markdown() {
+        let html = "<h1>Title</h1><p>Some <strong>bold </strong>and <em>it</em> <a href=\"b.md\">link</a><br>next</p>\
+            <ul><li>one<ul><li>nested</li></ul></li><li>two</li></ul>\
+            <ol><li>first</li></ol>\
+            <pre><code class=\"language-rust\">fn main() {}\n</code></pre>\
+            <table><tbody><tr><th>a</th><th>b</th></tr><tr><td>1</td><td>2|3</td></tr></tbody></table>\
+            <p><img src=\"img/a b.png\" alt=\"pic\"></p><blockquote><p>quote</p></blockquote>";
+        assert_eq!(
+            to_markdown(html),
+            "# Title\n\n\
+             Some **bold** and *it* [link](b.md)\\\nnext\n\n\
+             - one\n  - nested\n- two\n\n\
+             1. first\n\n\
+             ```rust\nfn main() {}\n```\n\n\
+             | a | b |\n| --- | --- |\n| 1 | 2\\|3 |\n\n\
+             ![pic](img/a%20b.png)\n\n\
+             > quote\n"
+        );
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let markdown = "# Title\n\n- [x] done\n- [ ] todo\n\n> quote\n";
+        assert_eq!(to_markdown(&to_ahtml(markdown)), markdown);
+    }
+
+    #[test]
+    fn test_file_in_storage() {
+        let root = Path::new("/memory/convert");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        let path = root.join("a.md");
+        storage.write(&path, b"# A\n").unwrap();
+        let (target, converted) = file(&path).unwrap();
+        assert_eq!(target, root.join("a.ahtml"));
+        assert_eq!(storage.read_to_string(&target).unwrap(), converted);
+        assert_eq!(
+            file(&path).unwrap_err(),
+            "/memory/convert/a.ahtml already exists"
+        );
+        storage::unmount(root);
+    }
+}
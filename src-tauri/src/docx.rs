@@ -0,0 +1,1328 @@
+use std::{
+    collections::HashMap,
+    io::{Read, Seek, Write},
+    path::{Path, PathBuf},
+};
+
+use kuchiki::{traits::TendrilSink, NodeRef};
+use roxmltree::Node;
+use zip::{write::FileOptions, ZipArchive, ZipWriter};
+
+use crate::{
+    ahtml, convert, entity,
+    export::{self, Target},
+    links::{self, Document},
+    render, sanitize, storage,
+};
+
+/// Widest an exported image gets: the text width of an A4 page with default margins, in EMU.
+const MAX_WIDTH: u64 = 5_760_720;
+/// EMU per pixel at 96 DPI.
+const EMU: u64 = 9525;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// The value of the attribute `name` in any namespace; Word writes `w:val` and the like.
+fn attr<'a>(node: Node<'a, '_>, name: &str) -> Option<&'a str> {
+    node.attributes()
+        .find(|a| a.name() == name)
+        .map(|a| a.value())
+}
+
+fn child<'a, 'b>(node: Node<'a, 'b>, name: &str) -> Option<Node<'a, 'b>> {
+    node.children().find(|c| c.tag_name().name() == name)
+}
+
+/// Whether a toggle property such as `<w:b/>` is on: present and not `0` or `false`.
+fn toggle(properties: Option<Node>, name: &str) -> bool {
+    properties.and_then(|p| child(p, name)).map_or(false, |p| {
+        !matches!(attr(p, "val"), Some("0" | "false" | "none"))
+    })
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+struct Format {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    code: bool,
+    sup: bool,
+    sub: bool,
+    link: Option<String>,
+}
+
+/// Reads a Word document into HTML, which `convert::to_markdown` turns into Markdown.
+struct Reader<'a, R: Read + Seek> {
+    archive: &'a mut ZipArchive<R>,
+    /// Style names by id, lowercased.
+    styles: HashMap<String, String>,
+    /// Relationship targets by id.
+    rels: HashMap<String, String>,
+    /// Whether each list level is numbered, by `numId` and `ilvl`.
+    numbering: HashMap<(String, String), bool>,
+    /// Where extracted images go and how documents link to it.
+    assets: (PathBuf, String),
+    images: HashMap<String, String>,
+    warnings: Vec<String>,
+}
+
+fn read_entry<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Option<Vec<u8>> {
+    let mut file = archive.by_name(name).ok()?;
+    let mut data = Vec::new();
+    file.read_to_end(&mut data).ok()?;
+    Some(data)
+}
+
+fn read_xml<R: Read + Seek>(archive: &mut ZipArchive<R>, name: &str) -> Option<String> {
+    read_entry(archive, name).and_then(|data| String::from_utf8(data).ok())
+}
+
+impl<'a, R: Read + Seek> Reader<'a, R> {
+    fn new(archive: &'a mut ZipArchive<R>, assets: (PathBuf, String)) -> Reader<'a, R> {
+        let mut styles = HashMap::new();
+        if let Some(xml) = read_xml(archive, "word/styles.xml") {
+            if let Ok(doc) = roxmltree::Document::parse(&xml) {
+                for style in doc.descendants().filter(|n| n.tag_name().name() == "style") {
+                    let name = child(style, "name").and_then(|n| attr(n, "val"));
+                    if let (Some(id), Some(name)) = (attr(style, "styleId"), name) {
+                        styles.insert(id.to_string(), name.to_lowercase());
+                    }
+                }
+            }
+        }
+        let mut rels = HashMap::new();
+        if let Some(xml) = read_xml(archive, "word/_rels/document.xml.rels") {
+            if let Ok(doc) = roxmltree::Document::parse(&xml) {
+                for rel in doc.descendants().filter(|n| n.has_tag_name("Relationship")) {
+                    if let (Some(id), Some(target)) = (attr(rel, "Id"), attr(rel, "Target")) {
+                        let target = if attr(rel, "TargetMode") == Some("External") {
+                            target.to_string()
+                        } else if let Some(target) = target.strip_prefix('/') {
+                            target.to_string()
+                        } else {
+                            format!("word/{}", target)
+                        };
+                        rels.insert(id.to_string(), target);
+                    }
+                }
+            }
+        }
+        let mut numbering = HashMap::new();
+        if let Some(xml) = read_xml(archive, "word/numbering.xml") {
+            if let Ok(doc) = roxmltree::Document::parse(&xml) {
+                let mut formats: HashMap<(String, String), bool> = HashMap::new();
+                for abstract_ in doc.descendants().filter(|n| n.has_tag_name("abstractNum")) {
+                    let id = attr(abstract_, "abstractNumId").unwrap_or("");
+                    for level in abstract_.children().filter(|n| n.has_tag_name("lvl")) {
+                        let format = child(level, "numFmt").and_then(|n| attr(n, "val"));
+                        formats.insert(
+                            (
+                                id.to_string(),
+                                attr(level, "ilvl").unwrap_or("0").to_string(),
+                            ),
+                            !matches!(format, Some("bullet") | Some("none") | None),
+                        );
+                    }
+                }
+                for num in doc.descendants().filter(|n| n.has_tag_name("num")) {
+                    let id = attr(num, "numId").unwrap_or("");
+                    let abstract_ = child(num, "abstractNumId")
+                        .and_then(|n| attr(n, "val"))
+                        .unwrap_or("");
+                    for ((a, level), ordered) in &formats {
+                        if a == abstract_ {
+                            numbering.insert((id.to_string(), level.clone()), *ordered);
+                        }
+                    }
+                }
+            }
+        }
+        Reader {
+            archive,
+            styles,
+            rels,
+            numbering,
+            assets,
+            images: HashMap::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Notes once that a kind of content, such as equations, could not be carried over.
+    fn unsupported(&mut self, what: &str) {
+        let warning = format!("{} are left out", what);
+        if !self.warnings.contains(&warning) {
+            self.warnings.push(warning);
+        }
+    }
+
+    fn style(&self, id: &str) -> String {
+        self.styles
+            .get(id)
+            .cloned()
+            .unwrap_or_else(|| id.to_lowercase())
+    }
+
+    /// The body of `word/document.xml` as HTML.
+    fn read(&mut self) -> Result<String, String> {
+        let xml = read_xml(self.archive, "word/document.xml")
+            .ok_or_else(|| "not a Word document: word/document.xml is missing".to_string())?;
+        let doc = roxmltree::Document::parse(&xml).map_err(|e| e.to_string())?;
+        let body = doc
+            .descendants()
+            .find(|n| n.has_tag_name("body"))
+            .ok_or_else(|| "the document has no body".to_string())?;
+        let mut html = String::new();
+        self.blocks(body, &mut html);
+        Ok(html)
+    }
+
+    fn blocks(&mut self, node: Node, html: &mut String) {
+        // open lists, with whether each is numbered
+        let mut lists: Vec<bool> = Vec::new();
+        let mut code: Vec<String> = Vec::new();
+        let close = |lists: &mut Vec<bool>, depth: usize, html: &mut String| {
+            while lists.len() > depth {
+                let ordered = lists.pop().unwrap();
+                html.push_str(if ordered { "</li></ol>" } else { "</li></ul>" });
+            }
+        };
+        let flush = |code: &mut Vec<String>, html: &mut String| {
+            if !code.is_empty() {
+                html.push_str(&format!("<pre><code>{}</code></pre>", code.join("\n")));
+                code.clear();
+            }
+        };
+        for node in node.children() {
+            match node.tag_name().name() {
+                "p" => {
+                    let properties = child(node, "pPr");
+                    let style = properties
+                        .and_then(|p| child(p, "pStyle"))
+                        .and_then(|s| attr(s, "val"))
+                        .map(|id| self.style(id))
+                        .unwrap_or_default();
+                    if style.contains("code") || style == "html preformatted" {
+                        close(&mut lists, 0, html);
+                        code.push(escape(&self.text(node)));
+                        continue;
+                    }
+                    flush(&mut code, html);
+                    let content = self.inline(node, &Format::default());
+                    let list = properties.and_then(|p| child(p, "numPr")).and_then(|n| {
+                        let id = child(n, "numId").and_then(|i| attr(i, "val"))?;
+                        let level = child(n, "ilvl").and_then(|i| attr(i, "val")).unwrap_or("0");
+                        // numId 0 takes numbering away
+                        if id == "0" {
+                            return None;
+                        }
+                        let ordered = self
+                            .numbering
+                            .get(&(id.to_string(), level.to_string()))
+                            .copied()
+                            .unwrap_or(false);
+                        Some((level.parse::<usize>().unwrap_or(0), ordered))
+                    });
+                    let list = list.or_else(|| {
+                        if style.starts_with("list bullet") {
+                            Some((0, false))
+                        } else if style.starts_with("list number") {
+                            Some((0, true))
+                        } else {
+                            None
+                        }
+                    });
+                    if let Some((level, ordered)) = list {
+                        close(&mut lists, level + 1, html);
+                        if lists.len() == level + 1 {
+                            if lists[level] == ordered {
+                                html.push_str("</li><li>");
+                            } else {
+                                close(&mut lists, level, html);
+                            }
+                        }
+                        while lists.len() < level + 1 {
+                            html.push_str(if ordered { "<ol><li>" } else { "<ul><li>" });
+                            lists.push(ordered);
+                        }
+                        html.push_str(&content);
+                        continue;
+                    }
+                    close(&mut lists, 0, html);
+                    if content.trim().is_empty() {
+                        continue;
+                    }
+                    let outline = properties
+                        .and_then(|p| child(p, "outlineLvl"))
+                        .and_then(|o| attr(o, "val"))
+                        .and_then(|v| v.parse::<usize>().ok())
+                        .map(|level| level + 1);
+                    let heading = style
+                        .strip_prefix("heading")
+                        .and_then(|level| level.trim().parse::<usize>().ok())
+                        .or(if style == "title" { Some(1) } else { None })
+                        .or(outline)
+                        .map(|level| level.clamp(1, 6));
+                    if let Some(level) = heading {
+                        html.push_str(&format!("<h{}>{}</h{}>", level, content, level));
+                    } else if style.contains("quote") {
+                        html.push_str(&format!("<blockquote><p>{}</p></blockquote>", content));
+                    } else {
+                        html.push_str(&format!("<p>{}</p>", content));
+                    }
+                }
+                "tbl" => {
+                    close(&mut lists, 0, html);
+                    flush(&mut code, html);
+                    self.table(node, html);
+                }
+                "sdt" => {
+                    close(&mut lists, 0, html);
+                    flush(&mut code, html);
+                    if let Some(content) = child(node, "sdtContent") {
+                        self.blocks(content, html);
+                    }
+                }
+                _ => {}
+            }
+        }
+        close(&mut lists, 0, html);
+        flush(&mut code, html);
+    }
+
+    fn table(&mut self, node: Node, html: &mut String) {
+        html.push_str("<table>");
+        for (i, row) in node.children().filter(|n| n.has_tag_name("tr")).enumerate() {
+            let tag = if i == 0 { "th" } else { "td" };
+            html.push_str("<tr>");
+            for cell in row.children().filter(|n| n.has_tag_name("tc")) {
+                let content: Vec<String> = cell
+                    .children()
+                    .filter(|n| n.has_tag_name("p"))
+                    .map(|p| self.inline(p, &Format::default()))
+                    .filter(|content| !content.trim().is_empty())
+                    .collect();
+                html.push_str(&format!("<{}>{}</{}>", tag, content.join("<br>"), tag));
+            }
+            html.push_str("</tr>");
+        }
+        html.push_str("</table>");
+    }
+
+    /// Plain text of a paragraph, for code.
+    fn text(&self, node: Node) -> String {
+        let mut result = String::new();
+        for n in node.descendants() {
+            match n.tag_name().name() {
+                "t" => result.push_str(n.text().unwrap_or("")),
+                "tab" if n.parent().map_or(false, |p| p.has_tag_name("r")) => result.push('\t'),
+                "br" => result.push('\n'),
+                _ => {}
+            }
+        }
+        result
+    }
+
+    /// HTML for the runs of a paragraph, with neighbouring runs of the same format merged.
+    fn inline(&mut self, node: Node, format: &Format) -> String {
+        let mut segments: Vec<(String, Format)> = Vec::new();
+        self.segments(node, format, &mut segments);
+        let mut merged: Vec<(String, Format)> = Vec::new();
+        for (html, format) in segments {
+            match merged.last_mut() {
+                Some((last, last_format)) if *last_format == format => last.push_str(&html),
+                _ => merged.push((html, format)),
+            }
+        }
+        let mut result = String::new();
+        let mut i = 0;
+        while i < merged.len() {
+            // a link spans several formats
+            let link = merged[i].1.link.clone();
+            let mut content = String::new();
+            while i < merged.len() && merged[i].1.link == link {
+                let (html, format) = &merged[i];
+                let mut html = html.clone();
+                for (on, tag) in [
+                    (format.code, "code"),
+                    (format.sup, "sup"),
+                    (format.sub, "sub"),
+                    (format.strike, "del"),
+                    (format.italic, "em"),
+                    (format.bold, "strong"),
+                ] {
+                    if on && !html.trim().is_empty() {
+                        html = format!("<{}>{}</{}>", tag, html, tag);
+                    }
+                }
+                content.push_str(&html);
+                i += 1;
+            }
+            match link {
+                Some(href) => {
+                    result.push_str(&format!("<a href=\"{}\">{}</a>", escape(&href), content))
+                }
+                None => result.push_str(&content),
+            }
+        }
+        result
+    }
+
+    fn segments(&mut self, node: Node, format: &Format, out: &mut Vec<(String, Format)>) {
+        for node in node.children() {
+            match node.tag_name().name() {
+                "r" => {
+                    let properties = child(node, "rPr");
+                    let style = properties
+                        .and_then(|p| child(p, "rStyle"))
+                        .and_then(|s| attr(s, "val"))
+                        .map(|id| self.style(id))
+                        .unwrap_or_default();
+                    let font = properties
+                        .and_then(|p| child(p, "rFonts"))
+                        .and_then(|f| attr(f, "ascii"))
+                        .unwrap_or("")
+                        .to_lowercase();
+                    let align = properties
+                        .and_then(|p| child(p, "vertAlign"))
+                        .and_then(|v| attr(v, "val"));
+                    let format = Format {
+                        bold: format.bold || toggle(properties, "b"),
+                        italic: format.italic || toggle(properties, "i"),
+                        strike: format.strike
+                            || toggle(properties, "strike")
+                            || toggle(properties, "dstrike"),
+                        code: format.code
+                            || style.contains("code")
+                            || ["consolas", "courier new", "menlo", "monaco"]
+                                .contains(&font.as_str()),
+                        sup: align == Some("superscript"),
+                        sub: align == Some("subscript"),
+                        link: format.link.clone(),
+                    };
+                    for part in node.children() {
+                        match part.tag_name().name() {
+                            "t" => out.push((escape(part.text().unwrap_or("")), format.clone())),
+                            "tab" => out.push((" ".to_string(), format.clone())),
+                            "br" | "cr" if attr(part, "type") != Some("page") => {
+                                out.push(("<br>".to_string(), format.clone()))
+                            }
+                            "drawing" | "pict" => {
+                                if let Some(image) = self.image(part) {
+                                    out.push((image, Format::default()));
+                                }
+                            }
+                            "footnoteReference" | "endnoteReference" => {
+                                self.unsupported("footnotes")
+                            }
+                            "object" => self.unsupported("embedded objects"),
+                            _ => {}
+                        }
+                    }
+                }
+                "hyperlink" => {
+                    let href = match (attr(node, "id"), attr(node, "anchor")) {
+                        (Some(id), _) => self.rels.get(id).cloned(),
+                        (None, Some(anchor)) => Some(format!("#{}", anchor)),
+                        _ => None,
+                    };
+                    let format = Format {
+                        link: href,
+                        ..format.clone()
+                    };
+                    self.segments(node, &format, out);
+                }
+                "ins" | "smartTag" | "fldSimple" | "customXml" => self.segments(node, format, out),
+                "oMath" | "oMathPara" => self.unsupported("equations"),
+                "sdt" => {
+                    if let Some(content) = child(node, "sdtContent") {
+                        self.segments(content, format, out);
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// `name`, or `name` numbered like `image1-2.png` when another image of the document or
+    /// a file already in the assets folder has it.
+    fn unique(&self, name: &str) -> String {
+        let (stem, ext) = match name.rsplit_once('.') {
+            Some((stem, ext)) => (stem, format!(".{}", ext)),
+            None => (name, String::new()),
+        };
+        let taken = |candidate: &str| {
+            self.assets.0.join(candidate).exists()
+                || self
+                    .images
+                    .values()
+                    .any(|src| src.rsplit('/').next() == Some(candidate))
+        };
+        let mut candidate = name.to_string();
+        let mut n = 1;
+        while taken(&candidate) {
+            n += 1;
+            candidate = format!("{}-{}{}", stem, n, ext);
+        }
+        candidate
+    }
+
+    /// Extracts the picture of a drawing to the assets folder, returning an `<img>` for it.
+    fn image(&mut self, node: Node) -> Option<String> {
+        let blip = node
+            .descendants()
+            .find(|n| n.has_tag_name("blip") || n.has_tag_name("imagedata"))?;
+        let id = attr(blip, "embed").or_else(|| attr(blip, "id"))?;
+        let target = self.rels.get(id)?.clone();
+        let alt = node
+            .descendants()
+            .find(|n| n.has_tag_name("docPr"))
+            .and_then(|n| attr(n, "descr"))
+            .unwrap_or("")
+            .to_string();
+        if !self.images.contains_key(&target) {
+            let name = self.unique(target.rsplit('/').next().unwrap_or(&target));
+            let data = match read_entry(self.archive, &target) {
+                Some(data) => data,
+                None => {
+                    self.warnings.push(format!("image {} is missing", target));
+                    return None;
+                }
+            };
+            let storage = storage::for_path(&self.assets.0);
+            let result = storage
+                .create_dir_all(&self.assets.0)
+                .and_then(|_| storage.write(&self.assets.0.join(&name), &data));
+            if let Err(e) = result {
+                self.warnings.push(format!("{}: {}", name, e));
+                return None;
+            }
+            self.images
+                .insert(target.clone(), format!("{}/{}", self.assets.1, name));
+        }
+        Some(format!(
+            "<img src=\"{}\" alt=\"{}\">",
+            escape(&self.images[&target]),
+            escape(&alt)
+        ))
+    }
+}
+
+/// Imports the Word document `source` as a document of `type_` (1 Markdown, 2 `.ahtml`)
+/// in `folder`, extracting its images to `assets/<name>/` there. Returns the new document
+/// and what could not be carried over.
+pub fn import(source: &Path, folder: &Path, type_: i32) -> Result<(PathBuf, Vec<String>), String> {
+    let stem = source
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_string())
+        .ok_or_else(|| format!("{} is not a file", source.display()))?;
+    let target = match type_ {
+        1 => folder.join(format!("{}.md", stem)),
+        2 => folder.join(format!("{}.ahtml", stem)),
+        _ => return Err(format!("unknown document type {}", type_)),
+    };
+    if storage::for_path(&target).exists(&target) {
+        return Err(format!("{} already exists", target.display()));
+    }
+    let file = std::fs::File::open(source).map_err(|e| e.to_string())?;
+    let mut archive = ZipArchive::new(file).map_err(|e| format!("not a Word document: {}", e))?;
+    let assets = (
+        folder.join("assets").join(&stem),
+        format!("assets/{}", stem),
+    );
+    let mut reader = Reader::new(&mut archive, assets);
+    let html = reader.read()?;
+    let mut warnings = std::mem::take(&mut reader.warnings);
+    let title = read_xml(&mut archive, "docProps/core.xml")
+        .and_then(|xml| {
+            let doc = roxmltree::Document::parse(&xml).ok()?;
+            let title = doc.descendants().find(|n| n.has_tag_name("title"))?;
+            title.text().map(|t| t.trim().to_string())
+        })
+        .filter(|title| !title.is_empty());
+    let content = if type_ == 1 {
+        let markdown = convert::to_markdown(&html);
+        match title {
+            Some(title) => {
+                let mut fields = serde_json::Map::new();
+                fields.insert("title".to_string(), title.into());
+                crate::frontmatter::update(&markdown, fields)?
+            }
+            None => markdown,
+        }
+    } else {
+        let (body, stripped) = sanitize::sanitize(&html, &sanitize::allowlist(folder));
+        warnings.extend(stripped);
+        let mut header = ahtml::legacy(&target, &body);
+        if let Some(title) = title {
+            header.title = title;
+        }
+        ahtml::to_string(&header, &body)
+    };
+    storage::for_path(&target)
+        .write(&target, content.as_bytes())
+        .map_err(|e| e.to_string())?;
+    Ok((target, warnings))
+}
+
+/// A relationship of `word/document.xml`.
+struct Rel {
+    id: String,
+    type_: &'static str,
+    target: String,
+    external: bool,
+}
+
+/// Writes a document as WordprocessingML, with the styles `STYLES` defines.
+struct Writer<'a> {
+    root: &'a Path,
+    source: &'a Path,
+    rels: Vec<Rel>,
+    media: Vec<(String, Vec<u8>)>,
+    /// Abstract numbering of each list instance: 0 bulleted, 1 numbered.
+    lists: Vec<usize>,
+    report: entity::Export,
+}
+
+#[derive(Debug, Clone, Default)]
+struct Run {
+    bold: bool,
+    italic: bool,
+    strike: bool,
+    underline: bool,
+    code: bool,
+    sup: bool,
+    sub: bool,
+    link: bool,
+}
+
+impl Run {
+    fn properties(&self) -> String {
+        let mut result = String::new();
+        if self.code {
+            result.push_str("<w:rStyle w:val=\"CodeChar\"/>");
+        } else if self.link {
+            result.push_str("<w:rStyle w:val=\"Hyperlink\"/>");
+        }
+        for (on, tag) in [
+            (self.bold, "<w:b/>"),
+            (self.italic, "<w:i/>"),
+            (self.strike, "<w:strike/>"),
+            (self.underline, "<w:u w:val=\"single\"/>"),
+            (self.sup, "<w:vertAlign w:val=\"superscript\"/>"),
+            (self.sub, "<w:vertAlign w:val=\"subscript\"/>"),
+        ] {
+            if on {
+                result.push_str(tag);
+            }
+        }
+        if result.is_empty() {
+            result
+        } else {
+            format!("<w:rPr>{}</w:rPr>", result)
+        }
+    }
+}
+
+fn element_name(node: &NodeRef) -> String {
+    node.as_element()
+        .map(|e| e.name.local.to_string())
+        .unwrap_or_default()
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "section"
+            | "article"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+            | "ul"
+            | "ol"
+            | "pre"
+            | "blockquote"
+            | "table"
+            | "hr"
+    )
+}
+
+fn paragraph(properties: &str, runs: &str) -> String {
+    if properties.is_empty() {
+        format!("<w:p>{}</w:p>", runs)
+    } else {
+        format!("<w:p><w:pPr>{}</w:pPr>{}</w:p>", properties, runs)
+    }
+}
+
+fn text_run(text: &str, run: &Run) -> String {
+    format!(
+        "<w:r>{}<w:t xml:space=\"preserve\">{}</w:t></w:r>",
+        run.properties(),
+        escape(text)
+    )
+}
+
+impl<'a> Writer<'a> {
+    fn rel(&mut self, type_: &'static str, target: String, external: bool) -> String {
+        let id = format!("rId{}", self.rels.len() + 10);
+        self.rels.push(Rel {
+            id: id.clone(),
+            type_,
+            target,
+            external,
+        });
+        id
+    }
+
+    /// Paragraphs for the children of `node`, with `style` for plain paragraphs.
+    fn blocks(&mut self, node: &NodeRef, style: &str, out: &mut String) {
+        let mut runs = String::new();
+        let flush = |runs: &mut String, out: &mut String| {
+            if !runs.is_empty() {
+                out.push_str(&paragraph(&style_property(style), runs));
+                runs.clear();
+            }
+        };
+        for child in node.children() {
+            let name = element_name(&child);
+            if !is_block(&name) {
+                self.runs(&child, &Run::default(), &mut runs);
+                continue;
+            }
+            flush(&mut runs, out);
+            match name.as_str() {
+                "h1" | "h2" | "h3" | "h4" | "h5" | "h6" => {
+                    let mut runs = String::new();
+                    self.runs(&child, &Run::default(), &mut runs);
+                    let properties = format!("<w:pStyle w:val=\"Heading{}\"/>", &name[1..]);
+                    out.push_str(&paragraph(&properties, &runs));
+                }
+                "p" => {
+                    let mut runs = String::new();
+                    self.runs(&child, &Run::default(), &mut runs);
+                    if !runs.is_empty() {
+                        out.push_str(&paragraph(&style_property(style), &runs));
+                    }
+                }
+                "ul" | "ol" => self.list(&child, name == "ol", 0, out),
+                "pre" => {
+                    let code = child.text_contents();
+                    for line in code.trim_end_matches('\n').split('\n') {
+                        let run = text_run(&line.replace('\t', "    "), &Run::default());
+                        out.push_str(&paragraph("<w:pStyle w:val=\"Code\"/>", &run));
+                    }
+                }
+                "blockquote" => self.blocks(&child, "Quote", out),
+                "table" => self.table(&child, out),
+                "hr" => out.push_str(&paragraph(
+                    "<w:pBdr><w:bottom w:val=\"single\" w:sz=\"6\" w:space=\"1\" w:color=\"auto\"/></w:pBdr>",
+                    "",
+                )),
+                _ => self.blocks(&child, style, out),
+            }
+        }
+        flush(&mut runs, out);
+    }
+
+    fn list(&mut self, node: &NodeRef, ordered: bool, level: usize, out: &mut String) {
+        self.lists.push(if ordered { 1 } else { 0 });
+        let id = self.lists.len();
+        for item in node.children().filter(|c| element_name(c) == "li") {
+            let properties = format!(
+                "<w:pStyle w:val=\"ListParagraph\"/><w:numPr><w:ilvl w:val=\"{}\"/><w:numId w:val=\"{}\"/></w:numPr>",
+                level.min(8),
+                id
+            );
+            let mut runs = String::new();
+            let mut nested = Vec::new();
+            for child in item.children() {
+                match element_name(&child).as_str() {
+                    "ul" | "ol" => nested.push(child),
+                    name if is_block(name) => {
+                        if !runs.is_empty() {
+                            runs.push_str("<w:r><w:br/></w:r>");
+                        }
+                        self.runs(&child, &Run::default(), &mut runs);
+                    }
+                    _ => self.runs(&child, &Run::default(), &mut runs),
+                }
+            }
+            out.push_str(&paragraph(&properties, &runs));
+            for child in nested {
+                let ordered = element_name(&child) == "ol";
+                self.list(&child, ordered, level + 1, out);
+            }
+        }
+    }
+
+    fn table(&mut self, node: &NodeRef, out: &mut String) {
+        let rows: Vec<NodeRef> = match node.select("tr") {
+            Ok(rows) => rows.map(|row| row.as_node().clone()).collect(),
+            Err(_) => return,
+        };
+        let columns = rows
+            .iter()
+            .map(|row| {
+                row.children()
+                    .filter(|c| matches!(element_name(c).as_str(), "td" | "th"))
+                    .count()
+            })
+            .max()
+            .unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        // twips of the text width, shared equally
+        let width = 9026 / columns;
+        out.push_str("<w:tbl><w:tblPr><w:tblStyle w:val=\"TableGrid\"/><w:tblW w:w=\"0\" w:type=\"auto\"/><w:tblLook w:val=\"0420\" w:firstRow=\"1\" w:lastRow=\"0\" w:firstColumn=\"0\" w:lastColumn=\"0\" w:noHBand=\"0\" w:noVBand=\"1\"/></w:tblPr><w:tblGrid>");
+        for _ in 0..columns {
+            out.push_str(&format!("<w:gridCol w:w=\"{}\"/>", width));
+        }
+        out.push_str("</w:tblGrid>");
+        for row in rows {
+            let cells: Vec<NodeRef> = row
+                .children()
+                .filter(|c| matches!(element_name(c).as_str(), "td" | "th"))
+                .collect();
+            // header rows repeat on every page and get the style's first row format
+            if cells.iter().all(|cell| element_name(cell) == "th") {
+                out.push_str("<w:tr><w:trPr><w:tblHeader/></w:trPr>");
+            } else {
+                out.push_str("<w:tr>");
+            }
+            for cell in &cells {
+                let run = Run::default();
+                let mut runs = String::new();
+                self.runs(cell, &run, &mut runs);
+                out.push_str(&format!(
+                    "<w:tc><w:tcPr><w:tcW w:w=\"{}\" w:type=\"dxa\"/></w:tcPr>{}</w:tc>",
+                    width,
+                    paragraph("", &runs)
+                ));
+            }
+            // Word wants every row to fill the grid
+            for _ in cells.len()..columns {
+                out.push_str(&format!(
+                    "<w:tc><w:tcPr><w:tcW w:w=\"{}\" w:type=\"dxa\"/></w:tcPr><w:p/></w:tc>",
+                    width
+                ));
+            }
+            out.push_str("</w:tr>");
+        }
+        out.push_str("</w:tbl>");
+    }
+
+    fn runs(&mut self, node: &NodeRef, run: &Run, out: &mut String) {
+        if let Some(text) = node.as_text() {
+            // collapse whitespace the way a browser would
+            let text = text.borrow();
+            let mut collapsed = String::new();
+            for c in text.chars() {
+                if c.is_whitespace() && c != '\u{a0}' {
+                    if !collapsed.ends_with(' ') {
+                        collapsed.push(' ');
+                    }
+                } else {
+                    collapsed.push(c);
+                }
+            }
+            // whitespace between blocks is not text
+            if !(collapsed.is_empty() || collapsed == " " && out.is_empty()) {
+                out.push_str(&text_run(&collapsed, run));
+            }
+            return;
+        }
+        let name = element_name(node);
+        let mut run = run.clone();
+        match name.as_str() {
+            "strong" | "b" => run.bold = true,
+            "em" | "i" => run.italic = true,
+            "s" | "del" | "strike" => run.strike = true,
+            "u" => run.underline = true,
+            "code" | "kbd" => run.code = true,
+            "sup" => run.sup = true,
+            "sub" => run.sub = true,
+            "br" => {
+                out.push_str("<w:r><w:br/></w:r>");
+                return;
+            }
+            "img" => {
+                if let Some(image) = self.image(node) {
+                    out.push_str(&image);
+                }
+                return;
+            }
+            "input" => {
+                let checked = node
+                    .as_element()
+                    .map_or(false, |e| e.attributes.borrow().contains("checked"));
+                out.push_str(&text_run(if checked { "☒ " } else { "☐ " }, &run));
+                return;
+            }
+            "a" => {
+                let href = node
+                    .as_element()
+                    .and_then(|e| e.attributes.borrow().get("href").map(|h| h.to_string()))
+                    .unwrap_or_default();
+                if links::external(&href) {
+                    let id = self.rel("hyperlink", href, true);
+                    let mut inner = String::new();
+                    run.link = true;
+                    for child in node.children() {
+                        self.runs(&child, &run, &mut inner);
+                    }
+                    out.push_str(&format!(
+                        "<w:hyperlink r:id=\"{}\">{}</w:hyperlink>",
+                        id, inner
+                    ));
+                    return;
+                }
+            }
+            "script" | "style" => return,
+            _ => {}
+        }
+        for child in node.children() {
+            self.runs(&child, &run, out);
+        }
+    }
+
+    /// Embeds a local image, scaled down to the text width.
+    fn image(&mut self, node: &NodeRef) -> Option<String> {
+        let (src, alt) = {
+            let attributes = node.as_element()?.attributes.borrow();
+            (
+                attributes.get("src").unwrap_or("").to_string(),
+                attributes.get("alt").unwrap_or("").to_string(),
+            )
+        };
+        let path = match export::target(self.root, self.source, &src) {
+            Target::Asset(path) => path,
+            _ => {
+                self.report.warnings.push(format!(
+                    "{}: image {} left out, only local images are embedded",
+                    self.source.display(),
+                    src
+                ));
+                return None;
+            }
+        };
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        // the formats `[Content_Types].xml` lists and every Word version shows
+        if !["png", "jpg", "jpeg", "gif"].contains(&ext.as_str()) {
+            self.report.warnings.push(format!(
+                "{}: image {} left out, only PNG, JPEG and GIF images are embedded",
+                self.source.display(),
+                src
+            ));
+            return None;
+        }
+        let data = match storage::for_path(&path).read(&path) {
+            Ok(data) => data,
+            Err(e) => {
+                self.report
+                    .warnings
+                    .push(format!("{}: {}", path.display(), e));
+                return None;
+            }
+        };
+        let size = image::io::Reader::new(std::io::Cursor::new(&data))
+            .with_guessed_format()
+            .map_err(image::ImageError::IoError)
+            .and_then(|reader| reader.into_dimensions());
+        let (width, height) = match size {
+            Ok(size) => size,
+            Err(e) => {
+                self.report
+                    .warnings
+                    .push(format!("{}: {}", path.display(), e));
+                return None;
+            }
+        };
+        let n = self.media.len() + 1;
+        let name = format!("image{}.{}", n, ext);
+        self.media.push((name.clone(), data));
+        self.report.assets += 1;
+        let id = self.rel("image", format!("media/{}", name), false);
+        let (mut cx, mut cy) = (width as u64 * EMU, height as u64 * EMU);
+        if cx > MAX_WIDTH {
+            cy = cy * MAX_WIDTH / cx;
+            cx = MAX_WIDTH;
+        }
+        Some(format!(
+            "<w:r><w:drawing><wp:inline distT=\"0\" distB=\"0\" distL=\"0\" distR=\"0\">\
+             <wp:extent cx=\"{cx}\" cy=\"{cy}\"/><wp:docPr id=\"{n}\" name=\"Picture {n}\" descr=\"{alt}\"/>\
+             <a:graphic xmlns:a=\"http://schemas.openxmlformats.org/drawingml/2006/main\">\
+             <a:graphicData uri=\"http://schemas.openxmlformats.org/drawingml/2006/picture\">\
+             <pic:pic xmlns:pic=\"http://schemas.openxmlformats.org/drawingml/2006/picture\">\
+             <pic:nvPicPr><pic:cNvPr id=\"{n}\" name=\"{name}\"/><pic:cNvPicPr/></pic:nvPicPr>\
+             <pic:blipFill><a:blip r:embed=\"{id}\"/><a:stretch><a:fillRect/></a:stretch></pic:blipFill>\
+             <pic:spPr><a:xfrm><a:off x=\"0\" y=\"0\"/><a:ext cx=\"{cx}\" cy=\"{cy}\"/></a:xfrm>\
+             <a:prstGeom prst=\"rect\"><a:avLst/></a:prstGeom></pic:spPr></pic:pic>\
+             </a:graphicData></a:graphic></wp:inline></w:drawing></w:r>",
+            cx = cx,
+            cy = cy,
+            n = n,
+            alt = escape(&alt),
+            name = name,
+            id = id
+        ))
+    }
+
+    fn numbering(&self) -> String {
+        let mut result = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <w:numbering xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\">",
+        );
+        for (id, ordered) in [(0, false), (1, true)] {
+            result.push_str(&format!(
+                "<w:abstractNum w:abstractNumId=\"{}\"><w:multiLevelType w:val=\"hybridMultilevel\"/>",
+                id
+            ));
+            for level in 0..9 {
+                let (format, text) = if ordered {
+                    ("decimal", format!("%{}.", level + 1))
+                } else {
+                    ("bullet", ["•", "◦", "▪"][level % 3].to_string())
+                };
+                result.push_str(&format!(
+                    "<w:lvl w:ilvl=\"{level}\"><w:start w:val=\"1\"/><w:numFmt w:val=\"{format}\"/>\
+                     <w:lvlText w:val=\"{text}\"/><w:lvlJc w:val=\"left\"/>\
+                     <w:pPr><w:ind w:left=\"{left}\" w:hanging=\"360\"/></w:pPr></w:lvl>",
+                    level = level,
+                    format = format,
+                    text = text,
+                    left = 720 * (level + 1)
+                ));
+            }
+            result.push_str("</w:abstractNum>");
+        }
+        // a list instance per list, so that numbering restarts
+        for (i, abstract_) in self.lists.iter().enumerate() {
+            result.push_str(&format!(
+                "<w:num w:numId=\"{}\"><w:abstractNumId w:val=\"{}\"/>\
+                 <w:lvlOverride w:ilvl=\"0\"><w:startOverride w:val=\"1\"/></w:lvlOverride></w:num>",
+                i + 1,
+                abstract_
+            ));
+        }
+        result.push_str("</w:numbering>");
+        result
+    }
+
+    fn relationships(&self) -> String {
+        let mut result = String::from(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+             <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles\" Target=\"styles.xml\"/>\
+             <Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/numbering\" Target=\"numbering.xml\"/>",
+        );
+        for rel in &self.rels {
+            result.push_str(&format!(
+                "<Relationship Id=\"{}\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/{}\" Target=\"{}\"{}/>",
+                rel.id,
+                rel.type_,
+                escape(&rel.target),
+                if rel.external { " TargetMode=\"External\"" } else { "" }
+            ));
+        }
+        result.push_str("</Relationships>");
+        result
+    }
+}
+
+fn style_property(style: &str) -> String {
+    if style.is_empty() {
+        "".to_string()
+    } else {
+        format!("<w:pStyle w:val=\"{}\"/>", style)
+    }
+}
+
+/// Styles of exported documents, named like Word's built-in ones so that imports map them
+/// back.
+const STYLES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:styles xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main">
+<w:docDefaults><w:rPrDefault><w:rPr><w:rFonts w:ascii="Calibri" w:hAnsi="Calibri" w:eastAsia="Microsoft YaHei" w:cs="Calibri"/><w:sz w:val="22"/><w:szCs w:val="22"/><w:lang w:val="en-US" w:eastAsia="zh-CN"/></w:rPr></w:rPrDefault><w:pPrDefault><w:pPr><w:spacing w:after="160" w:line="276" w:lineRule="auto"/></w:pPr></w:pPrDefault></w:docDefaults>
+<w:style w:type="paragraph" w:default="1" w:styleId="Normal"><w:name w:val="Normal"/><w:qFormat/></w:style>
+<w:style w:type="paragraph" w:styleId="Title"><w:name w:val="Title"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:rPr><w:sz w:val="56"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading1"><w:name w:val="heading 1"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before="360" w:after="120"/><w:outlineLvl w:val="0"/></w:pPr><w:rPr><w:b/><w:sz w:val="36"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading2"><w:name w:val="heading 2"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before="240" w:after="120"/><w:outlineLvl w:val="1"/></w:pPr><w:rPr><w:b/><w:sz w:val="30"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading3"><w:name w:val="heading 3"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before="240" w:after="80"/><w:outlineLvl w:val="2"/></w:pPr><w:rPr><w:b/><w:sz w:val="26"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading4"><w:name w:val="heading 4"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:spacing w:before="200" w:after="80"/><w:outlineLvl w:val="3"/></w:pPr><w:rPr><w:b/><w:sz w:val="24"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading5"><w:name w:val="heading 5"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:outlineLvl w:val="4"/></w:pPr><w:rPr><w:b/><w:sz w:val="22"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Heading6"><w:name w:val="heading 6"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:keepNext/><w:outlineLvl w:val="5"/></w:pPr><w:rPr><w:b/><w:i/><w:sz w:val="22"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Quote"><w:name w:val="Quote"/><w:basedOn w:val="Normal"/><w:next w:val="Normal"/><w:qFormat/><w:pPr><w:pBdr><w:left w:val="single" w:sz="18" w:space="8" w:color="D0D7DE"/></w:pBdr><w:ind w:left="360"/></w:pPr><w:rPr><w:color w:val="57606A"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="Code"><w:name w:val="Code"/><w:basedOn w:val="Normal"/><w:qFormat/><w:pPr><w:shd w:val="clear" w:color="auto" w:fill="F6F8FA"/><w:spacing w:after="0" w:line="240" w:lineRule="auto"/></w:pPr><w:rPr><w:rFonts w:ascii="Consolas" w:hAnsi="Consolas" w:cs="Consolas"/><w:sz w:val="20"/></w:rPr></w:style>
+<w:style w:type="paragraph" w:styleId="ListParagraph"><w:name w:val="List Paragraph"/><w:basedOn w:val="Normal"/><w:qFormat/><w:pPr><w:spacing w:after="60"/><w:contextualSpacing/></w:pPr></w:style>
+<w:style w:type="character" w:styleId="CodeChar"><w:name w:val="Code Char"/><w:rPr><w:rFonts w:ascii="Consolas" w:hAnsi="Consolas" w:cs="Consolas"/><w:shd w:val="clear" w:color="auto" w:fill="F6F8FA"/></w:rPr></w:style>
+<w:style w:type="character" w:styleId="Hyperlink"><w:name w:val="Hyperlink"/><w:rPr><w:color w:val="0563C1"/><w:u w:val="single"/></w:rPr></w:style>
+<w:style w:type="table" w:styleId="TableGrid"><w:name w:val="Table Grid"/><w:tblPr><w:tblBorders><w:top w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:left w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:bottom w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:right w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:insideH w:val="single" w:sz="4" w:space="0" w:color="auto"/><w:insideV w:val="single" w:sz="4" w:space="0" w:color="auto"/></w:tblBorders><w:tblCellMar><w:left w:w="108" w:type="dxa"/><w:right w:w="108" w:type="dxa"/></w:tblCellMar></w:tblPr><w:tblStylePr w:type="firstRow"><w:rPr><w:b/></w:rPr></w:tblStylePr></w:style>
+</w:styles>
+"#;
+
+/// Exports the document `source` of the workspace at `root` as a Word document at `output`.
+pub fn export(
+    root: &Path,
+    source: &Path,
+    output: &Path,
+    files: &HashMap<String, Document>,
+) -> Result<entity::Export, String> {
+    let mut writer = Writer {
+        root,
+        source,
+        rels: Vec::new(),
+        media: Vec::new(),
+        lists: Vec::new(),
+        report: entity::Export {
+            output: output.to_str().unwrap().to_string(),
+            pages: 1,
+            assets: 0,
+            warnings: Vec::new(),
+        },
+    };
+    let content = export::prepare(root, source, files, &mut writer.report.warnings)?;
+    let html = render::document(source.to_str().unwrap(), &content, None);
+    let document = kuchiki::parse_html().one(format!("<body>{}", html));
+    let mut body = String::new();
+    if let Ok(node) = document.select_first("body") {
+        writer.blocks(node.as_node(), "", &mut body);
+    }
+    let title = export::title(source, files.get(source.to_str().unwrap()));
+
+    let file = std::fs::File::create(output).map_err(|e| e.to_string())?;
+    let mut zip = ZipWriter::new(file);
+    let mut add = |name: &str, data: &[u8]| -> Result<(), String> {
+        zip.start_file(name, FileOptions::default())
+            .map_err(|e| e.to_string())?;
+        zip.write_all(data).map_err(|e| e.to_string())
+    };
+    add(
+        "[Content_Types].xml",
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+          <Types xmlns=\"http://schemas.openxmlformats.org/package/2006/content-types\">\
+          <Default Extension=\"rels\" ContentType=\"application/vnd.openxmlformats-package.relationships+xml\"/>\
+          <Default Extension=\"xml\" ContentType=\"application/xml\"/>\
+          <Default Extension=\"png\" ContentType=\"image/png\"/>\
+          <Default Extension=\"jpg\" ContentType=\"image/jpeg\"/>\
+          <Default Extension=\"jpeg\" ContentType=\"image/jpeg\"/>\
+          <Default Extension=\"gif\" ContentType=\"image/gif\"/>\
+          <Override PartName=\"/word/document.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.document.main+xml\"/>\
+          <Override PartName=\"/word/styles.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.styles+xml\"/>\
+          <Override PartName=\"/word/numbering.xml\" ContentType=\"application/vnd.openxmlformats-officedocument.wordprocessingml.numbering+xml\"/>\
+          <Override PartName=\"/docProps/core.xml\" ContentType=\"application/vnd.openxmlformats-package.core-properties+xml\"/>\
+          </Types>",
+    )?;
+    add(
+        "_rels/.rels",
+        b"<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+          <Relationships xmlns=\"http://schemas.openxmlformats.org/package/2006/relationships\">\
+          <Relationship Id=\"rId1\" Type=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships/officeDocument\" Target=\"word/document.xml\"/>\
+          <Relationship Id=\"rId2\" Type=\"http://schemas.openxmlformats.org/package/2006/relationships/metadata/core-properties\" Target=\"docProps/core.xml\"/>\
+          </Relationships>",
+    )?;
+    add(
+        "docProps/core.xml",
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <cp:coreProperties xmlns:cp=\"http://schemas.openxmlformats.org/package/2006/metadata/core-properties\" \
+             xmlns:dc=\"http://purl.org/dc/elements/1.1/\"><dc:title>{}</dc:title></cp:coreProperties>",
+            escape(&title)
+        )
+        .as_bytes(),
+    )?;
+    add(
+        "word/document.xml",
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\" standalone=\"yes\"?>\n\
+             <w:document xmlns:w=\"http://schemas.openxmlformats.org/wordprocessingml/2006/main\" \
+             xmlns:r=\"http://schemas.openxmlformats.org/officeDocument/2006/relationships\" \
+             xmlns:wp=\"http://schemas.openxmlformats.org/drawingml/2006/wordprocessingDrawing\">\
+             <w:body>{}<w:sectPr><w:pgSz w:w=\"11906\" w:h=\"16838\"/>\
+             <w:pgMar w:top=\"1440\" w:right=\"1440\" w:bottom=\"1440\" w:left=\"1440\" w:header=\"708\" w:footer=\"708\" w:gutter=\"0\"/>\
+             </w:sectPr></w:body></w:document>",
+            body
+        )
+        .as_bytes(),
+    )?;
+    add("word/styles.xml", STYLES.as_bytes())?;
+    add("word/numbering.xml", writer.numbering().as_bytes())?;
+    add(
+        "word/_rels/document.xml.rels",
+        writer.relationships().as_bytes(),
+    )?;
+    for (name, data) in &writer.media {
+        add(&format!("word/media/{}", name), data)?;
+    }
+    zip.finish().map_err(|e| e.to_string())?;
+    Ok(writer.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_round_trip() {
+        let temp = TempDir::new("docx");
+        let root = temp.join("docx");
+        std::fs::create_dir_all(root.join("in")).unwrap();
+        std::fs::create_dir_all(root.join("out")).unwrap();
+        let image = image::RgbImage::new(4, 2);
+        image.save(root.join("in/p.png")).unwrap();
+        let markdown = "# Title\n\nSome **bold** and *it* [link](https://example.com)\n\n\
+            - one\n  - nested\n- two\n\n1. first\n\n> quoted\n\n```\nlet x = 1;\n```\n\n\
+            | a | b |\n| --- | --- |\n| `1` | 2 |\n\n![pic](p.png)\n";
+        std::fs::write(root.join("in/doc.md"), markdown).unwrap();
+        let output = root.join("doc.docx");
+        let report = export(&root, &root.join("in/doc.md"), &output, &HashMap::new()).unwrap();
+        assert_eq!(report.assets, 1);
+
+        let (target, warnings) = import(&output, &root.join("out"), 1).unwrap();
+        assert!(warnings.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&target).unwrap(),
+            "---\ntitle: Title\n---\n# Title\n\nSome **bold** and *it* [link](https://example.com)\n\n\
+             - one\n  - nested\n- two\n\n1. first\n\n> quoted\n\n```\nlet x = 1;\n```\n\n\
+             | a | b |\n| --- | --- |\n| `1` | 2 |\n\n![pic](assets/doc/image1.png)\n"
+        );
+        assert!(root.join("out/assets/doc/image1.png").exists());
+        assert!(import(&output, &root.join("out"), 1).is_err());
+    }
+
+    fn drawing(id: &str) -> String {
+        format!(
+            "<w:r><w:drawing><wp:inline><wp:docPr id=\"1\" descr=\"pic\"/><a:graphic><a:graphicData>\
+             <pic:pic><pic:blipFill><a:blip r:embed=\"{}\"/></pic:blipFill></pic:pic>\
+             </a:graphicData></a:graphic></wp:inline></w:drawing></w:r>",
+            id
+        )
+    }
+
+    #[test]
+    fn test_import() {
+        let temp = TempDir::new("docx-import");
+        let root = temp.join("docx");
+        std::fs::create_dir_all(root.join("assets/doc")).unwrap();
+        std::fs::write(root.join("assets/doc/a.png"), "kept").unwrap();
+        let source = temp.join("doc.docx");
+        let body = format!(
+            "<w:p><w:pPr><w:pStyle w:val=\"Heading1\"/></w:pPr><w:r><w:t>Doc</w:t></w:r></w:p>\
+             <w:p><w:pPr><w:numPr><w:ilvl w:val=\"0\"/><w:numId w:val=\"1\"/></w:numPr></w:pPr><w:r><w:t>dot</w:t></w:r></w:p>\
+             <w:p><w:pPr><w:numPr><w:ilvl w:val=\"1\"/><w:numId w:val=\"2\"/></w:numPr></w:pPr><w:r><w:t>first</w:t></w:r></w:p>\
+             <w:tbl><w:tr><w:tc><w:p><w:r><w:t>a</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>b</w:t></w:r></w:p></w:tc></w:tr>\
+             <w:tr><w:tc><w:p><w:r><w:rPr><w:b/></w:rPr><w:t>1</w:t></w:r></w:p></w:tc><w:tc><w:p><w:r><w:t>2</w:t></w:r></w:p></w:tc></w:tr></w:tbl>\
+             <w:p>{}{}</w:p>\
+             <w:p><w:r><w:t>x</w:t></w:r><m:oMath><m:r><m:t>y</m:t></m:r></m:oMath><w:r><w:footnoteReference w:id=\"1\"/></w:r></w:p>",
+            drawing("rId1"),
+            drawing("rId2")
+        );
+        let mut zip = ZipWriter::new(std::fs::File::create(&source).unwrap());
+        for (name, content) in [
+            (
+                "word/document.xml",
+                format!(
+                    "<w:document xmlns:w=\"w\" xmlns:r=\"r\" xmlns:m=\"m\" xmlns:wp=\"wp\" xmlns:a=\"a\" xmlns:pic=\"pic\">\
+                     <w:body>{}</w:body></w:document>",
+                    body
+                ),
+            ),
+            (
+                "word/numbering.xml",
+                "<w:numbering xmlns:w=\"w\">\
+                 <w:abstractNum w:abstractNumId=\"0\"><w:lvl w:ilvl=\"0\"><w:numFmt w:val=\"bullet\"/></w:lvl></w:abstractNum>\
+                 <w:abstractNum w:abstractNumId=\"1\"><w:lvl w:ilvl=\"1\"><w:numFmt w:val=\"decimal\"/></w:lvl></w:abstractNum>\
+                 <w:num w:numId=\"1\"><w:abstractNumId w:val=\"0\"/></w:num>\
+                 <w:num w:numId=\"2\"><w:abstractNumId w:val=\"1\"/></w:num></w:numbering>"
+                    .to_string(),
+            ),
+            (
+                "word/_rels/document.xml.rels",
+                "<Relationships xmlns=\"rels\">\
+                 <Relationship Id=\"rId1\" Target=\"media/a.png\"/>\
+                 <Relationship Id=\"rId2\" Target=\"embeddings/a.png\"/></Relationships>"
+                    .to_string(),
+            ),
+            ("word/media/a.png", "one".to_string()),
+            ("word/embeddings/a.png", "two".to_string()),
+        ] {
+            zip.start_file(name, FileOptions::default()).unwrap();
+            zip.write_all(content.as_bytes()).unwrap();
+        }
+        zip.finish().unwrap();
+
+        let (target, warnings) = import(&source, &root, 1).unwrap();
+        assert_eq!(
+            warnings,
+            vec!["equations are left out", "footnotes are left out"]
+        );
+        assert_eq!(
+            std::fs::read_to_string(&target).unwrap(),
+            "# Doc\n\n- dot\n  1. first\n\n| a | b |\n| --- | --- |\n| **1** | 2 |\n\n\
+             ![pic](assets/doc/a-2.png)![pic](assets/doc/a-3.png)\n\nx\n"
+        );
+        let read =
+            |name: &str| std::fs::read_to_string(root.join("assets/doc").join(name)).unwrap();
+        assert_eq!(
+            (read("a.png"), read("a-2.png"), read("a-3.png")),
+            ("kept".to_string(), "one".to_string(), "two".to_string())
+        );
+    }
+
+    #[test]
+    fn test_export_report() {
+        let temp = TempDir::new("docx-export");
+        let root = temp.join("docx");
+        std::fs::create_dir_all(&root).unwrap();
+        image::RgbImage::new(2, 2).save(root.join("p.png")).unwrap();
+        std::fs::write(root.join("v.svg"), "<svg/>").unwrap();
+        std::fs::write(root.join("w.webp"), "RIFF").unwrap();
+        std::fs::write(
+            root.join("doc.md"),
+            "![p](p.png) ![v](v.svg) ![w](w.webp) ![r](https://example.com/r.png)\n",
+        )
+        .unwrap();
+        let output = temp.join("doc.docx");
+        let report = export(&root, &root.join("doc.md"), &output, &HashMap::new()).unwrap();
+        assert_eq!(report.assets, 1);
+        let warnings: Vec<&str> = report
+            .warnings
+            .iter()
+            .map(|w| w.split(": ").nth(1).unwrap())
+            .collect();
+        assert_eq!(
+            warnings,
+            vec![
+                "image v.svg left out, only PNG, JPEG and GIF images are embedded",
+                "image w.webp left out, only PNG, JPEG and GIF images are embedded",
+                "image https://example.com/r.png left out, only local images are embedded",
+            ]
+        );
+        let mut archive = ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        let names: Vec<String> = archive.file_names().map(|n| n.to_string()).collect();
+        assert!(names.contains(&"word/media/image1.png".to_string()));
+        assert_eq!(
+            names
+                .iter()
+                .filter(|n| n.starts_with("word/media/"))
+                .count(),
+            1
+        );
+        assert!(read_xml(&mut archive, "[Content_Types].xml")
+            .unwrap()
+            .contains("Extension=\"png\""));
+    }
+}
@@ -0,0 +1,260 @@
+use std::{collections::HashMap, path::Path};
+
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use regex::Regex;
+
+use crate::{
+    ahtml, frontmatter,
+    links::{self, Document},
+    outline, storage,
+};
+
+/// How many documents deep embeds are followed unless the caller asks otherwise.
+pub const MAX_DEPTH: i32 = 8;
+
+/// A line holding nothing but an embed.
+static EMBED: Lazy<Regex> = Lazy::new(|| Regex::new(r"^!\[\[([^\[\]\n]+)\]\]$").unwrap());
+
+/// The body of a document, or only the section whose heading matches `heading`.
+pub fn section(path: &str, content: &str, heading: &str) -> Option<String> {
+    if heading.is_empty() && path.ends_with(".ahtml") {
+        return Some(ahtml::body(content).to_string());
+    }
+    if heading.is_empty() {
+        return Some(frontmatter::body(content).to_string());
+    }
+    let slug = outline::slug(heading);
+    let heading = outline::headings(path, content)
+        .into_iter()
+        .find(|h| h.slug == slug || h.text == heading)?;
+    Some(
+        content
+            .split_inclusive('\n')
+            .skip((heading.line - 1) as usize)
+            .take((heading.end - heading.line + 1) as usize)
+            .collect(),
+    )
+}
+
+/// Rewrites the relative link and image destinations of Markdown written in the document
+/// `from` so that they still resolve when the text is placed in the document `to`.
+pub fn rebase(content: &str, from: &Path, to: &Path) -> String {
+    let (from, to) = match (from.parent(), to.parent()) {
+        (Some(from), Some(to)) if from != to => (from, to),
+        _ => return content.to_string(),
+    };
+    let mut edits = Vec::new();
+    for (event, range) in Parser::new_ext(content, outline::options()).into_offset_iter() {
+        let dest = match event {
+            Event::Start(Tag::Link(LinkType::Inline, dest, _)) => dest,
+            Event::Start(Tag::Image(LinkType::Inline, dest, _)) => dest,
+            _ => continue,
+        };
+        if dest.is_empty()
+            || dest.starts_with('#')
+            || dest.starts_with('/')
+            || links::external(&dest)
+        {
+            continue;
+        }
+        // only rewrite destinations written plainly, `<...>` and escapes are left alone
+        let start = match content[range.clone()].rfind(&format!("]({}", dest)) {
+            Some(i) => range.start + i + 2,
+            None => continue,
+        };
+        let target = links::normalize(&from.join(&*dest));
+        edits.push((start..start + dest.len(), links::relative(to, &target)));
+    }
+    let mut result = content.to_string();
+    for (range, dest) in edits.into_iter().rev() {
+        result.replace_range(range, &dest);
+    }
+    result
+}
+
+fn notice(msg: &str) -> String {
+    format!("> {}\n", msg)
+}
+
+/// Inlines the `![[Doc]]` and `![[Doc#Section]]` embeds standing on lines of their own in a
+/// Markdown document.
+pub struct Expander<'a> {
+    pub root: &'a Path,
+    pub files: &'a HashMap<String, Document>,
+    pub max_depth: i32,
+}
+
+impl<'a> Expander<'a> {
+    /// Expands the embeds of `content`, the text of the document at `path`.
+    pub fn expand(&self, path: &str, content: &str) -> String {
+        let mut stack = vec![path.to_string()];
+        self.expand_in(path, content, Path::new(path), &mut stack)
+    }
+
+    fn expand_in(&self, path: &str, content: &str, top: &Path, stack: &mut Vec<String>) -> String {
+        if path.ends_with(".ahtml") {
+            return content.to_string();
+        }
+        let mut result = String::new();
+        let mut last = 0;
+        for (offset, line) in outline::prose(content) {
+            // only an embed on a line of its own is expanded, since the headings and
+            // paragraphs it brings along cannot sit inside other text
+            let cap = match EMBED.captures(line.trim()) {
+                Some(cap) => cap,
+                None => continue,
+            };
+            let start = offset + line.find("![[").unwrap();
+            let embedded = self.embed(path, &cap[1], top, stack);
+            result.push_str(&content[last..start]);
+            result.push_str(&embedded);
+            last = offset + line.trim_end().len();
+            if embedded.ends_with('\n') {
+                // the embed took the whole line, drop its own line break
+                if content[last..].starts_with("\r\n") {
+                    last += 2;
+                } else if content[last..].starts_with('\n') {
+                    last += 1;
+                }
+            }
+        }
+        result.push_str(&content[last..]);
+        result
+    }
+
+    fn embed(&self, source: &str, inner: &str, top: &Path, stack: &mut Vec<String>) -> String {
+        let (page, heading) = links::wiki(inner);
+        let asset = Path::new(&page)
+            .extension()
+            .map_or(false, |ext| ext != "md" && ext != "ahtml");
+        if asset {
+            return match links::resolve(self.root, Path::new(source), &page) {
+                Some(target) => format!(
+                    "![{}]({})",
+                    page,
+                    links::relative(top.parent().unwrap_or(self.root), &target).replace(' ', "%20")
+                ),
+                None => notice(&format!("Embed not found: {}", page)),
+            };
+        }
+        let candidates = if page.is_empty() {
+            vec![source.to_string()]
+        } else {
+            links::candidates(self.files, &page)
+        };
+        let target = match candidates.len() {
+            1 => &candidates[0],
+            0 => return notice(&format!("Embed not found: {}", inner)),
+            _ => return notice(&format!("Ambiguous embed: {}", inner)),
+        };
+        if stack.contains(target) {
+            return notice(&format!("Embed cycle: {}", inner));
+        }
+        if stack.len() as i32 > self.max_depth {
+            return notice(&format!("Embed depth limit reached: {}", inner));
+        }
+        let path = Path::new(target);
+        let content = match storage::for_path(path).read_to_string(path) {
+            Ok(content) => content,
+            Err(e) => return notice(&format!("Embed {} failed: {}", inner, e)),
+        };
+        let section = match section(target, &content, &heading) {
+            Some(section) => section,
+            None => return notice(&format!("Section not found: {}", inner)),
+        };
+        let section = if target.ends_with(".ahtml") {
+            section
+        } else {
+            rebase(&section, Path::new(target), top)
+        };
+        stack.push(target.clone());
+        let mut result = self.expand_in(target, &section, top, stack);
+        stack.pop();
+        if !result.ends_with('\n') {
+            result.push('\n');
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_rebase() {
+        let content = "![a](img/a.png) [b](../b.md#x) [c](https://c.d)\n";
+        assert_eq!(
+            rebase(
+                content,
+                Path::new("/w/docs/part.md"),
+                Path::new("/w/book.md")
+            ),
+            "![a](docs/img/a.png) [b](b.md#x) [c](https://c.d)\n"
+        );
+    }
+
+    #[test]
+    fn test_expand() {
+        let temp = TempDir::new("embed");
+        let root = temp.join("embed");
+        std::fs::create_dir_all(&root).unwrap();
+        let files = [
+            (
+                "book.md",
+                "# Book\n\n![[intro]]\n\nsee ![[intro#Part Two]]\n\n  ![[intro#Part Two]]\n",
+            ),
+            (
+                "intro.md",
+                "# Intro\n\none\n\n## Part Two\n\ntwo\n\n![[book]]\n",
+            ),
+        ];
+        let mut index = HashMap::new();
+        for (name, content) in files {
+            let path = root.join(name);
+            std::fs::write(&path, content).unwrap();
+            index.insert(
+                path.to_str().unwrap().to_string(),
+                Document::new(&path, content),
+            );
+        }
+        let expander = Expander {
+            root: &root,
+            files: &index,
+            max_depth: MAX_DEPTH,
+        };
+        let book = root.join("book.md");
+        let result = expander.expand(book.to_str().unwrap(), files[0].1);
+        assert_eq!(
+            result,
+            "# Book\n\n# Intro\n\none\n\n## Part Two\n\ntwo\n\n> Embed cycle: book\n\nsee ![[intro#Part Two]]\n\n  ## Part Two\n\ntwo\n\n> Embed cycle: book\n"
+        );
+    }
+
+    #[test]
+    fn test_expand_in_storage() {
+        let root = Path::new("/memory/embed");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let part = root.join("part.md");
+        storage::for_path(root)
+            .write(&part, b"# Part\n\ntext\n")
+            .unwrap();
+        let mut index = HashMap::new();
+        index.insert(
+            part.to_str().unwrap().to_string(),
+            Document::new(&part, "# Part\n\ntext\n"),
+        );
+        let expander = Expander {
+            root,
+            files: &index,
+            max_depth: MAX_DEPTH,
+        };
+        assert_eq!(
+            expander.expand("/memory/embed/book.md", "![[part]]\n"),
+            "# Part\n\ntext\n"
+        );
+        storage::unmount(root);
+    }
+}
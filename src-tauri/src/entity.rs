@@ -13,6 +13,7 @@ pub struct FileTree {
     pub name: String,
     pub path: String,
     pub updated: i64,
+    pub front_matter: Option<FrontMatter>,
     pub children: Option<Vec<FileTree>>,
 }
 
@@ -35,10 +36,222 @@ pub struct OpenFile {
     pub path: String,
     pub content: String,
     pub updated: i64,
+    pub front_matter: Option<FrontMatter>,
+    // `.ahtml` header, `content` is then the body only
+    pub header: Option<AhtmlHeader>,
+    // problems found in the header
+    pub errors: Vec<String>,
+    // what the `.ahtml` sanitizer removed
+    pub stripped: Vec<String>,
 }
 
-#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
 pub struct Config {
     pub token: String,
     pub project: String,
+    // book metadata for EPUB exports, `project` being the title
+    #[serde(default)]
+    pub author: String,
+    #[serde(default)]
+    pub language: String,
+    // cover image, relative to the workspace
+    #[serde(default)]
+    pub cover: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FrontMatter {
+    // 1: yaml, 2: toml
+    pub type_: i32,
+    pub data: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Tag {
+    pub name: String,
+    pub count: i32,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Heading {
+    pub level: i32,
+    pub text: String,
+    pub slug: String,
+    // first and last line of the section, 1-based
+    pub line: i32,
+    pub end: i32,
+    pub children: Vec<Heading>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct History {
+    pub path: String,
+    pub created: i64,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Link {
+    // 1: link, 2: image, 3: wiki-link, 4: embed
+    pub type_: i32,
+    pub source: String,
+    // path part of the link as written, or the page name of a wiki-link
+    pub href: String,
+    // resolved document or asset, empty when nothing matches
+    pub target: String,
+    pub anchor: String,
+    pub line: i32,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BrokenLink {
+    pub link: Link,
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Resolution {
+    pub page: String,
+    // empty when the page is missing or ambiguous
+    pub target: String,
+    pub anchor: String,
+    // every matching document when ambiguous
+    pub candidates: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Language {
+    pub name: String,
+    pub extensions: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Conversion {
+    pub source: String,
+    // empty when the conversion failed
+    pub target: String,
+    pub msg: String,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+#[serde(default)]
+pub struct AhtmlHeader {
+    pub version: i32,
+    pub title: String,
+    pub tags: Vec<String>,
+    // unix milliseconds
+    pub created: i64,
+    pub updated: i64,
+    pub editor: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Allowlist {
+    pub tags: Vec<String>,
+    // attributes allowed per tag, `*` for every tag
+    pub attributes: std::collections::BTreeMap<String, Vec<String>>,
+    // URL schemes allowed in `href`, `src` and the like; relative URLs always are
+    pub schemes: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct SiteOptions {
+    // site name, the folder name when empty
+    pub title: String,
+    // `light` or `dark`
+    pub theme: String,
+    // code highlighting theme, the default one when empty
+    pub highlight: String,
+    // extra stylesheet to include, none when empty
+    pub css: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PdfOptions {
+    // TrueType font for text, needed for CJK; the built-in Helvetica when empty
+    pub font: String,
+    // bold variant, `font` or the built-in Helvetica Bold when empty
+    pub bold_font: String,
+    // font for code, `font` or the built-in Courier when empty
+    pub mono_font: String,
+    // `A4` or `Letter`
+    pub paper: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Export {
+    pub output: String,
+    pub pages: i32,
+    pub assets: i32,
+    // broken links and other problems that did not stop the export
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Migration {
+    pub source: String,
+    // the folder the notes were written to
+    pub target: String,
+    // `obsidian` or `notion`
+    pub kind: String,
+    pub documents: i32,
+    pub attachments: i32,
+    // links, embeds and images pointed at their new paths
+    pub links: i32,
+    // `old → new` for every file whose name changed
+    pub renamed: Vec<String>,
+    // broken links and other problems that did not stop the import
+    pub warnings: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Scaffold {
+    // folders and documents written for the outline
+    pub created: Vec<String>,
+    // those that already existed and were left alone
+    pub skipped: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupFile {
+    // relative to the workspace, joined with `/`
+    pub path: String,
+    pub size: i64,
+    // hex encoded SHA-256 of the content
+    pub sha256: String,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct BackupManifest {
+    pub version: i32,
+    // name of the workspace folder
+    pub name: String,
+    // unix milliseconds
+    pub created: i64,
+    pub files: Vec<BackupFile>,
+    // every folder, so empty ones come back too
+    pub folders: Vec<String>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Backup {
+    pub archive: String,
+    // the workspace backed up or the folder restored into
+    pub folder: String,
+    pub files: i32,
+    // total bytes of the files
+    pub size: i64,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct Bucket {
+    // e.g. https://s3.eu-west-1.amazonaws.com or http://127.0.0.1:9000
+    pub endpoint: String,
+    #[serde(default)]
+    pub region: String,
+    pub bucket: String,
+    // the key prefix the workspace is at, empty for the whole bucket
+    #[serde(default)]
+    pub prefix: String,
+    pub access_key: String,
+    pub secret_key: String,
 }
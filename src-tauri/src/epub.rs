@@ -0,0 +1,580 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use kuchiki::{traits::TendrilSink, NodeRef};
+use zip::{write::FileOptions, CompressionMethod, ZipWriter};
+
+use crate::{
+    entity,
+    export::{self, Page, Target},
+    highlight::{self, Highlighter},
+    links::{self, Document},
+    outline, render, storage,
+};
+
+const STYLE: &str = r#"
+body { font-family: serif; line-height: 1.5; }
+h1, h2, h3, h4, h5, h6 { font-family: sans-serif; line-height: 1.25; }
+img { max-width: 100%; }
+pre { padding: 0.5em; white-space: pre-wrap; background: #f6f8fa; font-size: 0.85em; }
+code { font-family: monospace; }
+table { border-collapse: collapse; }
+th, td { border: 1px solid #d0d7de; padding: 0.2em 0.5em; }
+blockquote { margin-left: 0; padding-left: 1em; color: #57606a; border-left: 0.25em solid #d0d7de; }
+.title-page { text-align: center; }
+.title-page img { max-height: 70vh; }
+"#;
+
+/// Elements XHTML writes as `<x/>`.
+const VOID: [&str; 14] = [
+    "area", "base", "br", "col", "embed", "hr", "img", "input", "link", "meta", "param", "source",
+    "track", "wbr",
+];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+fn encode(href: &str) -> String {
+    href.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('#', "%23")
+        .replace('?', "%3F")
+}
+
+fn media_type(path: &str) -> &'static str {
+    let ext = path.rsplit('.').next().unwrap_or("").to_lowercase();
+    match ext.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "svg" => "image/svg+xml",
+        "webp" => "image/webp",
+        "css" => "text/css",
+        "xhtml" => "application/xhtml+xml",
+        "mp3" => "audio/mpeg",
+        "mp4" => "video/mp4",
+        "ttf" => "font/ttf",
+        "otf" => "font/otf",
+        "woff" => "font/woff",
+        "woff2" => "font/woff2",
+        _ => "application/octet-stream",
+    }
+}
+
+/// `CCYY-MM-DDThh:mm:ssZ`, as `dcterms:modified` wants it.
+fn timestamp(secs: u64) -> String {
+    let days = (secs / 86400) as i64;
+    let rest = secs % 86400;
+    // days to a civil date, after Howard Hinnant's algorithm
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// Serializes the children of `node` as XHTML: void elements closed, attributes quoted and
+/// comments left out.
+fn xhtml(node: &NodeRef, out: &mut String) {
+    for child in node.children() {
+        if let Some(text) = child.as_text() {
+            out.push_str(&escape(&text.borrow()));
+        } else if let Some(element) = child.as_element() {
+            let name = element.name.local.to_string();
+            out.push('<');
+            out.push_str(&name);
+            for (key, attribute) in element.attributes.borrow().map.iter() {
+                let key = key.local.to_string();
+                let valid = key.chars().enumerate().all(|(i, c)| {
+                    c.is_ascii_alphabetic()
+                        || c == '_'
+                        || (i > 0 && "-.:".contains(c))
+                        || (i > 0 && c.is_ascii_digit())
+                });
+                if valid {
+                    out.push_str(&format!(" {}=\"{}\"", key, escape(&attribute.value)));
+                }
+            }
+            if VOID.contains(&name.as_str()) {
+                out.push_str("/>");
+            } else {
+                out.push('>');
+                xhtml(&child, out);
+                out.push_str(&format!("</{}>", name));
+            }
+        }
+    }
+}
+
+/// Gives the headings of an HTML fragment missing an `id` the slug the outline uses for
+/// them, so that the navigation can link to every heading.
+fn anchors(body: &NodeRef, headings: &[entity::Heading]) {
+    let selector = "h1, h2, h3, h4, h5, h6";
+    let elements = match body.select(selector) {
+        Ok(elements) => elements,
+        Err(_) => return,
+    };
+    for (element, heading) in elements.zip(headings) {
+        let mut attributes = element.attributes.borrow_mut();
+        if !attributes.contains("id") {
+            attributes.insert("id", heading.slug.clone());
+        }
+    }
+}
+
+/// An XHTML page; `prefix` leads from it back to `OEBPS`.
+fn page(title: &str, language: &str, prefix: &str, body: &str) -> String {
+    format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE html>\n\
+         <html xmlns=\"http://www.w3.org/1999/xhtml\" xmlns:epub=\"http://www.idpf.org/2007/ops\" lang=\"{lang}\" xml:lang=\"{lang}\">\n\
+         <head>\n<meta charset=\"utf-8\"/>\n<title>{title}</title>\n\
+         <link rel=\"stylesheet\" href=\"{prefix}style.css\"/>\n<link rel=\"stylesheet\" href=\"{prefix}highlight.css\"/>\n\
+         </head>\n<body>\n{body}\n</body>\n</html>\n",
+        lang = escape(language),
+        title = escape(title),
+        prefix = prefix,
+        body = body
+    )
+}
+
+/// A chapter of the book: one document.
+struct Chapter {
+    page: Page,
+    /// Path inside `OEBPS`.
+    href: String,
+    headings: Vec<entity::Heading>,
+}
+
+/// Packages the documents below `folder` as an EPUB 3 book: one chapter per document in
+/// tree order, or the order file's, with a navigation document built from their headings.
+/// Title, author, language and cover come from the project config.
+pub struct Book<'a> {
+    pub root: &'a Path,
+    pub folder: &'a Path,
+    pub output: &'a Path,
+    pub files: &'a HashMap<String, Document>,
+    pub config: &'a entity::Config,
+}
+
+impl<'a> Book<'a> {
+    pub fn export(&self) -> Result<entity::Export, String> {
+        let mut report = entity::Export {
+            output: self.output.to_str().unwrap().to_string(),
+            pages: 0,
+            assets: 0,
+            warnings: Vec::new(),
+        };
+        let tree = export::tree(self.folder)?;
+        let pages = export::pages(self.folder, &tree, self.files);
+        let pages = export::order(self.folder, pages, &mut report.warnings);
+        if pages.is_empty() {
+            return Err("the folder has no documents".to_string());
+        }
+        let title = if self.config.project.is_empty() {
+            self.folder
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            self.config.project.clone()
+        };
+        let language = if self.config.language.is_empty() {
+            "en"
+        } else {
+            self.config.language.as_str()
+        };
+        let chapters: Vec<Chapter> = pages
+            .into_iter()
+            .enumerate()
+            .map(|(i, page)| Chapter {
+                page,
+                href: format!("text/chapter-{:03}.xhtml", i + 1),
+                headings: Vec::new(),
+            })
+            .collect();
+        let hrefs: HashMap<PathBuf, String> = chapters
+            .iter()
+            .map(|chapter| (chapter.page.source.clone(), chapter.href.clone()))
+            .collect();
+
+        let file = std::fs::File::create(self.output).map_err(|e| e.to_string())?;
+        let mut zip = ZipWriter::new(file);
+        let error = |e: zip::result::ZipError| e.to_string();
+        // readers identify the format by this first, uncompressed entry
+        zip.start_file(
+            "mimetype",
+            FileOptions::default().compression_method(CompressionMethod::Stored),
+        )
+        .map_err(error)?;
+        zip.write_all(b"application/epub+zip")
+            .map_err(|e| e.to_string())?;
+        let add = |zip: &mut ZipWriter<std::fs::File>, name: &str, data: &[u8]| {
+            zip.start_file(name, FileOptions::default())
+                .map_err(error)?;
+            zip.write_all(data).map_err(|e| e.to_string())
+        };
+        add(
+            &mut zip,
+            "META-INF/container.xml",
+            b"<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+              <container version=\"1.0\" xmlns=\"urn:oasis:names:tc:opendocument:xmlns:container\">\n\
+              <rootfiles>\n<rootfile full-path=\"OEBPS/content.opf\" media-type=\"application/oebps-package+xml\"/>\n\
+              </rootfiles>\n</container>\n",
+        )?;
+
+        // path inside OEBPS, id and properties of everything but the chapters
+        let mut manifest: Vec<(String, String, &str)> = vec![
+            ("nav.xhtml".to_string(), "nav".to_string(), "nav"),
+            ("style.css".to_string(), "style".to_string(), ""),
+            ("highlight.css".to_string(), "highlight".to_string(), ""),
+            ("text/title.xhtml".to_string(), "title-page".to_string(), ""),
+        ];
+        add(&mut zip, "OEBPS/style.css", STYLE.trim_start().as_bytes())?;
+        let highlighter = Highlighter::new(Some(&highlight::theme_dir(self.root)), None);
+        let css = highlighter
+            .css(highlight::DEFAULT_THEME)
+            .unwrap_or_default();
+        add(&mut zip, "OEBPS/highlight.css", css.as_bytes())?;
+
+        let mut cover = String::new();
+        let path = links::normalize(&self.root.join(&self.config.cover));
+        if !self.config.cover.is_empty() && !path.starts_with(self.root) {
+            report.warnings.push(format!(
+                "cover {} is outside the workspace",
+                self.config.cover
+            ));
+        } else if !self.config.cover.is_empty() {
+            match storage::for_path(&path).read(&path) {
+                Ok(data) => {
+                    let ext = path
+                        .extension()
+                        .map(|ext| ext.to_string_lossy().to_lowercase())
+                        .unwrap_or_default();
+                    let name = format!("images/cover.{}", ext);
+                    add(&mut zip, &format!("OEBPS/{}", name), &data)?;
+                    cover = name.clone();
+                    manifest.push((name, "cover-image".to_string(), "cover-image"));
+                }
+                Err(e) => report.warnings.push(format!("{}: {}", path.display(), e)),
+            }
+        }
+        let mut body = String::from("<section class=\"title-page\" epub:type=\"titlepage\">\n");
+        if !cover.is_empty() {
+            body.push_str(&format!("<img src=\"../{}\" alt=\"cover\"/>\n", cover));
+        }
+        body.push_str(&format!("<h1>{}</h1>\n", escape(&title)));
+        if !self.config.author.is_empty() {
+            body.push_str(&format!("<p>{}</p>\n", escape(&self.config.author)));
+        }
+        body.push_str("</section>");
+        add(
+            &mut zip,
+            "OEBPS/text/title.xhtml",
+            page(&title, language, "../", &body).as_bytes(),
+        )?;
+
+        let mut assets: HashMap<PathBuf, String> = HashMap::new();
+        let mut chapters = chapters;
+        for chapter in chapters.iter_mut() {
+            let source = chapter.page.source.clone();
+            let content =
+                match export::prepare(self.root, &source, self.files, &mut report.warnings) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        report.warnings.push(format!("{}: {}", source.display(), e));
+                        String::new()
+                    }
+                };
+            let html = render::document(source.to_str().unwrap(), &content, Some(&highlighter));
+            let html = export::rewrite(&html, |_, href| {
+                let target = export::target(self.root, &source, href);
+                match target {
+                    Target::Document(path, anchor) => match hrefs.get(&path) {
+                        Some(href) => {
+                            let href = encode(href.trim_start_matches("text/"));
+                            if anchor.is_empty() {
+                                Some(href)
+                            } else {
+                                Some(format!("{}#{}", href, anchor))
+                            }
+                        }
+                        None => {
+                            report.warnings.push(format!(
+                                "{}: {} is not part of the book",
+                                source.display(),
+                                href
+                            ));
+                            None
+                        }
+                    },
+                    Target::Asset(path) => {
+                        if !assets.contains_key(&path) {
+                            let name = format!(
+                                "images/{}-{}",
+                                assets.len() + 1,
+                                path.file_name().unwrap().to_string_lossy()
+                            );
+                            match storage::for_path(&path).read(&path) {
+                                Ok(data) => {
+                                    if let Err(e) = add(&mut zip, &format!("OEBPS/{}", name), &data)
+                                    {
+                                        report.warnings.push(e);
+                                    }
+                                }
+                                Err(e) => {
+                                    report.warnings.push(format!("{}: {}", path.display(), e));
+                                    return None;
+                                }
+                            }
+                            assets.insert(path.clone(), name);
+                        }
+                        Some(format!("../{}", encode(&assets[&path])))
+                    }
+                    Target::Missing => {
+                        report
+                            .warnings
+                            .push(format!("{}: broken link {}", source.display(), href));
+                        None
+                    }
+                    Target::Unchanged => None,
+                }
+            });
+            chapter.headings = outline::headings(source.to_str().unwrap(), &content);
+            let document = kuchiki::parse_html().one(format!("<body>{}", html));
+            let mut body = String::new();
+            if let Ok(node) = document.select_first("body") {
+                anchors(node.as_node(), &chapter.headings);
+                xhtml(node.as_node(), &mut body);
+            }
+            add(
+                &mut zip,
+                &format!("OEBPS/{}", chapter.href),
+                page(&chapter.page.title, language, "../", &body).as_bytes(),
+            )?;
+            report.pages += 1;
+        }
+        report.assets = assets.len() as i32;
+        let mut images: Vec<&String> = assets.values().collect();
+        images.sort();
+        for (i, name) in images.into_iter().enumerate() {
+            manifest.push((name.clone(), format!("image-{}", i + 1), ""));
+        }
+
+        add(
+            &mut zip,
+            "OEBPS/nav.xhtml",
+            self.nav(&title, language, &chapters).as_bytes(),
+        )?;
+        add(
+            &mut zip,
+            "OEBPS/content.opf",
+            self.package(&title, language, &manifest, &chapters, !cover.is_empty())
+                .as_bytes(),
+        )?;
+        zip.finish().map_err(error)?;
+        Ok(report)
+    }
+
+    /// The navigation document: a chapter per document with its headings nested below.
+    fn nav(&self, title: &str, language: &str, chapters: &[Chapter]) -> String {
+        fn list(href: &str, headings: &[entity::Heading], depth: usize, out: &mut String) {
+            if headings.is_empty() || depth > 2 {
+                return;
+            }
+            out.push_str("<ol>\n");
+            for heading in headings {
+                out.push_str(&format!(
+                    "<li><a href=\"{}#{}\">{}</a>",
+                    encode(href),
+                    escape(&heading.slug),
+                    escape(&heading.text)
+                ));
+                list(href, &heading.children, depth + 1, out);
+                out.push_str("</li>\n");
+            }
+            out.push_str("</ol>\n");
+        }
+        let mut body =
+            String::from("<nav epub:type=\"toc\" id=\"toc\">\n<h1>Contents</h1>\n<ol>\n");
+        for chapter in chapters {
+            body.push_str(&format!(
+                "<li><a href=\"{}\">{}</a>\n",
+                encode(&chapter.href),
+                escape(&chapter.page.title)
+            ));
+            let mut headings = outline::nest(chapter.headings.clone());
+            // a lone top heading repeats the chapter title
+            if headings.len() == 1 && headings[0].text == chapter.page.title {
+                headings = headings.remove(0).children;
+            }
+            list(&chapter.href, &headings, 0, &mut body);
+            body.push_str("</li>\n");
+        }
+        body.push_str("</ol>\n</nav>");
+        page(title, language, "", &body)
+    }
+
+    fn package(
+        &self,
+        title: &str,
+        language: &str,
+        manifest: &[(String, String, &str)],
+        chapters: &[Chapter],
+        cover: bool,
+    ) -> String {
+        let mut hasher = DefaultHasher::new();
+        self.folder.hash(&mut hasher);
+        let modified = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or(0);
+        let mut metadata = format!(
+            "<dc:identifier id=\"id\">urn:ahridocs:{:016x}</dc:identifier>\n\
+             <dc:title>{}</dc:title>\n<dc:language>{}</dc:language>\n\
+             <meta property=\"dcterms:modified\">{}</meta>\n",
+            hasher.finish(),
+            escape(title),
+            escape(language),
+            timestamp(modified)
+        );
+        if !self.config.author.is_empty() {
+            metadata.push_str(&format!(
+                "<dc:creator>{}</dc:creator>\n",
+                escape(&self.config.author)
+            ));
+        }
+        if cover {
+            // for EPUB 2 readers
+            metadata.push_str("<meta name=\"cover\" content=\"cover-image\"/>\n");
+        }
+        let mut items = String::new();
+        for (href, id, properties) in manifest {
+            let properties = if properties.is_empty() {
+                "".to_string()
+            } else {
+                format!(" properties=\"{}\"", properties)
+            };
+            items.push_str(&format!(
+                "<item id=\"{}\" href=\"{}\" media-type=\"{}\"{}/>\n",
+                id,
+                escape(&encode(href)),
+                media_type(href),
+                properties
+            ));
+        }
+        let mut spine = String::from("<itemref idref=\"title-page\"/>\n");
+        for (i, chapter) in chapters.iter().enumerate() {
+            items.push_str(&format!(
+                "<item id=\"chapter-{}\" href=\"{}\" media-type=\"application/xhtml+xml\"/>\n",
+                i + 1,
+                chapter.href
+            ));
+            spine.push_str(&format!("<itemref idref=\"chapter-{}\"/>\n", i + 1));
+        }
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+             <package xmlns=\"http://www.idpf.org/2007/opf\" version=\"3.0\" unique-identifier=\"id\">\n\
+             <metadata xmlns:dc=\"http://purl.org/dc/elements/1.1/\">\n{}</metadata>\n\
+             <manifest>\n{}</manifest>\n<spine>\n{}</spine>\n</package>\n",
+            metadata, items, spine
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_timestamp() {
+        assert_eq!(timestamp(0), "1970-01-01T00:00:00Z");
+        assert_eq!(timestamp(1709210096), "2024-02-29T12:34:56Z");
+    }
+
+    #[test]
+    fn test_export() {
+        let temp = TempDir::new("epub");
+        let root = temp.join("epub");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(
+            root.join("a.md"),
+            "# A\n\n## Part\n\nsee [b](docs/b.md#x)<br>\n",
+        )
+        .unwrap();
+        std::fs::write(root.join("docs/b.md"), "# B\n\n![p](p.png)\n").unwrap();
+        std::fs::write(root.join("docs/p.png"), b"png").unwrap();
+        std::fs::write(root.join(export::ORDER), "docs/b.md\n").unwrap();
+        let output = temp.join("epub.epub");
+        let config = entity::Config {
+            project: "Hand & Book".to_string(),
+            cover: "../secret.png".to_string(),
+            ..Default::default()
+        };
+        let report = Book {
+            root: &root,
+            folder: &root,
+            output: &output,
+            files: &HashMap::new(),
+            config: &config,
+        }
+        .export()
+        .unwrap();
+        assert_eq!((report.pages, report.assets), (2, 1));
+        assert_eq!(
+            report.warnings,
+            vec!["cover ../secret.png is outside the workspace"]
+        );
+
+        let mut archive = zip::ZipArchive::new(std::fs::File::open(&output).unwrap()).unwrap();
+        assert_eq!(archive.by_index(0).unwrap().name(), "mimetype");
+        let mut read = |name: &str| {
+            let mut content = String::new();
+            archive
+                .by_name(name)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            content
+        };
+        let nav = read("OEBPS/nav.xhtml");
+        assert!(nav.contains(
+            "<li><a href=\"text/chapter-001.xhtml\">B</a>\n</li>\n\
+             <li><a href=\"text/chapter-002.xhtml\">A</a>\n\
+             <ol>\n<li><a href=\"text/chapter-002.xhtml#part\">Part</a></li>\n</ol>\n</li>"
+        ));
+        assert!(read("OEBPS/text/chapter-001.xhtml")
+            .contains("<img alt=\"p\" src=\"../images/1-p.png\"/>"));
+        assert!(read("OEBPS/text/chapter-002.xhtml")
+            .contains("<a href=\"chapter-001.xhtml#x\">b</a><br/>"));
+        let package = read("OEBPS/content.opf");
+        assert!(package.contains("<dc:title>Hand &amp; Book</dc:title>"));
+        assert!(package
+            .contains("<item id=\"image-1\" href=\"images/1-p.png\" media-type=\"image/png\"/>"));
+    }
+}
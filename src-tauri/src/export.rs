@@ -0,0 +1,309 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use kuchiki::traits::TendrilSink;
+
+use crate::{
+    api, embed, entity,
+    links::{self, Document},
+    outline, storage,
+};
+
+/// A document picked for export.
+#[derive(Debug, Clone)]
+pub struct Page {
+    pub source: PathBuf,
+    /// Path below the exported folder, joined with `/`.
+    pub rel: String,
+    pub title: String,
+}
+
+/// The documents and folders below `folder`, folders first and then by name, with empty
+/// folders left out.
+pub fn tree(folder: &Path) -> Result<Vec<entity::FileTree>, String> {
+    let mut tree = api::read_dir(folder, 0).map_err(|e| e.to_string())?;
+    sort(&mut tree);
+    Ok(tree)
+}
+
+fn sort(tree: &mut Vec<entity::FileTree>) {
+    for node in tree.iter_mut() {
+        if let Some(children) = node.children.as_mut() {
+            sort(children);
+        }
+    }
+    tree.retain(|node| node.children.as_ref().map_or(true, |c| !c.is_empty()));
+    tree.sort_by(|a, b| {
+        (a.type_ != 0, a.name.to_lowercase()).cmp(&(b.type_ != 0, b.name.to_lowercase()))
+    });
+}
+
+/// Documents of `tree` in reading order.
+pub fn pages(
+    folder: &Path,
+    tree: &[entity::FileTree],
+    files: &HashMap<String, Document>,
+) -> Vec<Page> {
+    let mut flat = Vec::new();
+    api::flatten(tree.to_vec(), &mut flat);
+    flat.into_iter()
+        .filter(|node| node.type_ != 0)
+        .map(|node| {
+            let source = PathBuf::from(&node.path);
+            Page {
+                rel: links::relative(folder, &source),
+                title: title(&source, files.get(&node.path)),
+                source,
+            }
+        })
+        .collect()
+}
+
+/// File in an exported folder listing documents in the order to export them, one path
+/// relative to the folder per line. `#` starts a comment.
+pub const ORDER: &str = ".order";
+
+/// Puts the documents listed in the order file of `folder` first, in its order, followed
+/// by the rest in tree order.
+pub fn order(folder: &Path, pages: Vec<Page>, warnings: &mut Vec<String>) -> Vec<Page> {
+    let path = folder.join(ORDER);
+    let content = match storage::for_path(&path).read_to_string(&path) {
+        Ok(content) => content,
+        Err(_) => return pages,
+    };
+    let mut rest = pages;
+    let mut result = Vec::new();
+    for line in content.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let rel = line.trim_start_matches("./").replace('\\', "/");
+        match rest.iter().position(|page| page.rel == rel) {
+            Some(i) => result.push(rest.remove(i)),
+            None => warnings.push(format!(
+                "{}: {} is not a document of the folder",
+                folder.join(ORDER).display(),
+                line
+            )),
+        }
+    }
+    result.extend(rest);
+    result
+}
+
+/// The front matter or header title of a document, then its first heading, then its name.
+pub fn title(path: &Path, document: Option<&Document>) -> String {
+    if let Some(document) = document {
+        if !document.title.is_empty() {
+            return document.title.clone();
+        }
+    }
+    storage::for_path(path)
+        .read_to_string(path)
+        .ok()
+        .and_then(|content| {
+            outline::headings(path.to_str().unwrap(), &content)
+                .into_iter()
+                .min_by_key(|heading| heading.level)
+                .map(|heading| heading.text)
+        })
+        .filter(|text| !text.is_empty())
+        .unwrap_or_else(|| {
+            path.file_stem()
+                .map(|stem| stem.to_string_lossy().to_string())
+                .unwrap_or_default()
+        })
+}
+
+/// Replaces the `[[Page#Heading|label]]` wiki-links of Markdown with regular links to the
+/// documents they resolve to. Links that do not resolve become their label.
+pub fn wiki_links(
+    source: &Path,
+    content: &str,
+    files: &HashMap<String, Document>,
+    warnings: &mut Vec<String>,
+) -> String {
+    let mut result = String::new();
+    let mut last = 0;
+    for (offset, line) in outline::prose(content) {
+        for cap in links::WIKI_LINK.captures_iter(line) {
+            let m = cap.get(0).unwrap();
+            if !cap[1].is_empty() || outline::in_code_span(line, m.start()) {
+                continue;
+            }
+            let (page, heading) = links::wiki(&cap[2]);
+            let label = match cap[2].split_once('|') {
+                Some((_, label)) => label.trim().to_string(),
+                None => cap[2].trim().to_string(),
+            };
+            let candidates = if page.is_empty() {
+                vec![source.to_str().unwrap().to_string()]
+            } else {
+                links::candidates(files, &page)
+            };
+            let replacement = if candidates.len() == 1 {
+                let mut href =
+                    links::relative(source.parent().unwrap_or(source), Path::new(&candidates[0]));
+                if !heading.is_empty() {
+                    href = format!("{}#{}", href, outline::slug(&heading));
+                }
+                format!("[{}](<{}>)", label, href)
+            } else {
+                warnings.push(format!(
+                    "{}: {} wiki-link [[{}]]",
+                    source.display(),
+                    if candidates.is_empty() {
+                        "broken"
+                    } else {
+                        "ambiguous"
+                    },
+                    &cap[2]
+                ));
+                label
+            };
+            result.push_str(&content[last..offset + m.start()]);
+            result.push_str(&replacement);
+            last = offset + m.end();
+        }
+    }
+    result.push_str(&content[last..]);
+    result
+}
+
+/// The content of a document ready to render on its own: embeds expanded and, for
+/// Markdown, wiki-links turned into regular links.
+pub fn prepare(
+    root: &Path,
+    source: &Path,
+    files: &HashMap<String, Document>,
+    warnings: &mut Vec<String>,
+) -> Result<String, String> {
+    let content = storage::for_path(source)
+        .read_to_string(source)
+        .map_err(|e| e.to_string())?;
+    let path = source.to_str().unwrap();
+    if path.ends_with(".ahtml") {
+        return Ok(content);
+    }
+    let content = embed::Expander {
+        root,
+        files,
+        max_depth: embed::MAX_DEPTH,
+    }
+    .expand(path, &content);
+    Ok(wiki_links(source, &content, files, warnings))
+}
+
+/// What a link or image reference found in an exported document points at.
+pub enum Target {
+    /// Another document, with the anchor it links to.
+    Document(PathBuf, String),
+    Asset(PathBuf),
+    /// Nothing to rewrite: external URLs and anchors within the page.
+    Unchanged,
+    Missing,
+}
+
+pub fn target(root: &Path, source: &Path, href: &str) -> Target {
+    if href.is_empty() || href.starts_with('#') || links::external(href) {
+        return Target::Unchanged;
+    }
+    let (path, anchor) = links::split(href);
+    match links::resolve(root, source, &path) {
+        Some(target) => {
+            if target
+                .extension()
+                .map_or(false, |ext| ext == "md" || ext == "ahtml")
+            {
+                Target::Document(target, anchor)
+            } else {
+                Target::Asset(target)
+            }
+        }
+        None => Target::Missing,
+    }
+}
+
+/// Rewrites the `href` and `src` attributes of an HTML fragment with `f`, called with the
+/// tag and the value, which returns the new value or `None` to keep it.
+pub fn rewrite(html: &str, mut f: impl FnMut(&str, &str) -> Option<String>) -> String {
+    let document = kuchiki::parse_html().one(format!("<body>{}", html));
+    let body = match document.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(_) => return html.to_string(),
+    };
+    for node in body.descendants() {
+        let element = match node.as_element() {
+            Some(element) => element,
+            None => continue,
+        };
+        let mut attributes = element.attributes.borrow_mut();
+        for attribute in ["href", "src"] {
+            let value = match attributes.get(attribute) {
+                Some(value) => value.to_string(),
+                None => continue,
+            };
+            if let Some(value) = f(&element.name.local, &value) {
+                attributes.insert(attribute, value);
+            }
+        }
+    }
+    body.children().map(|child| child.to_string()).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wiki_links() {
+        let mut files = HashMap::new();
+        for path in ["/w/a.md", "/w/docs/b c.md"] {
+            files.insert(path.to_string(), Document::new(Path::new(path), ""));
+        }
+        let mut warnings = Vec::new();
+        let content = "see [[b c#Part One|B]], [[nope]] and `[[a]]`\n";
+        assert_eq!(
+            wiki_links(Path::new("/w/a.md"), content, &files, &mut warnings),
+            "see [B](<docs/b c.md#part-one>), nope and `[[a]]`\n"
+        );
+        assert_eq!(warnings, vec!["/w/a.md: broken wiki-link [[nope]]"]);
+    }
+
+    #[test]
+    fn test_order_in_storage() {
+        let root = Path::new("/memory/export");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        storage.write(&root.join("a.md"), b"# Alpha\n").unwrap();
+        storage.write(&root.join("b.md"), b"[[a]]\n").unwrap();
+        storage
+            .write(&root.join(ORDER), b"b.md\nnope.md\n")
+            .unwrap();
+        let mut files = HashMap::new();
+        for name in ["a.md", "b.md"] {
+            let path = root.join(name);
+            files.insert(path.to_str().unwrap().to_string(), Document::new(&path, ""));
+        }
+        let tree = tree(root).unwrap();
+        let mut warnings = Vec::new();
+        let pages = order(root, pages(root, &tree, &files), &mut warnings);
+        let found: Vec<(&str, &str)> = pages
+            .iter()
+            .map(|page| (page.rel.as_str(), page.title.as_str()))
+            .collect();
+        assert_eq!(found, vec![("b.md", "b"), ("a.md", "Alpha")]);
+        assert_eq!(
+            warnings,
+            vec!["/memory/export/.order: nope.md is not a document of the folder"]
+        );
+        assert_eq!(
+            prepare(root, &root.join("b.md"), &files, &mut warnings).unwrap(),
+            "[a](<a.md>)\n"
+        );
+        storage::unmount(root);
+    }
+}
@@ -0,0 +1,266 @@
+use serde_json::{Map, Value};
+
+use crate::entity;
+
+pub const YAML: i32 = 1;
+pub const TOML: i32 = 2;
+
+/// A front matter block located at the top of a document.
+pub struct Block<'a> {
+    pub type_: i32,
+    /// Text between the opening and closing fences.
+    pub raw: &'a str,
+    /// Everything after the closing fence, untouched.
+    pub body: &'a str,
+    pub data: Map<String, Value>,
+}
+
+/// Locates a `---` (YAML) or `+++` (TOML) fenced block at the start of `content`. Only a
+/// closed block holding a valid mapping counts, so that a document opening with a thematic
+/// break is left alone.
+pub fn split(content: &str) -> Option<Block<'_>> {
+    let (type_, raw, body) = fenced(content)?;
+    Some(Block {
+        type_,
+        raw,
+        body,
+        data: parse_raw(type_, raw).ok()?,
+    })
+}
+
+/// The type, text and following body of a closed fenced block at the start of `content`,
+/// whether or not the text parses.
+fn fenced(content: &str) -> Option<(i32, &str, &str)> {
+    let start = if content.starts_with('\u{feff}') {
+        3
+    } else {
+        0
+    };
+    let text = &content[start..];
+    let first = text.lines().next()?;
+    let (type_, fence) = match first.trim_end() {
+        "---" => (YAML, "---"),
+        "+++" => (TOML, "+++"),
+        _ => return None,
+    };
+    let raw_start = start + first.len() + line_break_len(&text[first.len()..]);
+    let mut offset = raw_start;
+    for line in content[raw_start..].split_inclusive('\n') {
+        let trimmed = line.trim_end();
+        if trimmed == fence || (type_ == YAML && trimmed == "...") {
+            let raw = &content[raw_start..offset];
+            return Some((type_, raw, &content[offset + line.len()..]));
+        }
+        offset += line.len();
+    }
+    None
+}
+
+fn line_break_len(rest: &str) -> usize {
+    if rest.starts_with("\r\n") {
+        2
+    } else if rest.starts_with('\n') {
+        1
+    } else {
+        0
+    }
+}
+
+/// Parses the front matter of `content`.
+pub fn parse(content: &str) -> Option<entity::FrontMatter> {
+    split(content).map(|block| entity::FrontMatter {
+        type_: block.type_,
+        data: block.data,
+    })
+}
+
+/// Returns the document body with any front matter block removed.
+pub fn body(content: &str) -> &str {
+    match split(content) {
+        Some(block) => block.body,
+        None => content,
+    }
+}
+
+pub fn parse_raw(type_: i32, raw: &str) -> Result<Map<String, Value>, String> {
+    if raw.trim().is_empty() {
+        return Ok(Map::new());
+    }
+    let value = if type_ == TOML {
+        let value: toml::Value = toml::from_str(raw).map_err(|e| e.to_string())?;
+        toml_to_json(value)
+    } else {
+        serde_yaml::from_str::<Value>(raw).map_err(|e| e.to_string())?
+    };
+    match value {
+        Value::Object(map) => Ok(map),
+        Value::Null => Ok(Map::new()),
+        _ => Err("front matter must be a mapping".to_string()),
+    }
+}
+
+/// Serializes front matter back into a fenced block, including the trailing newline.
+pub fn to_string(front_matter: &entity::FrontMatter) -> Result<String, String> {
+    if front_matter.type_ == TOML {
+        let table = json_to_toml(Value::Object(front_matter.data.clone()));
+        let raw = toml::to_string(&table).map_err(|e| e.to_string())?;
+        Ok(format!("+++\n{}+++\n", raw))
+    } else {
+        let raw = serde_yaml::to_string(&front_matter.data).map_err(|e| e.to_string())?;
+        Ok(format!("---\n{}---\n", raw))
+    }
+}
+
+/// Merges `fields` into the front matter of `content` without touching the body.
+///
+/// A `null` value removes the field. Documents without front matter get a new
+/// YAML block; the block is dropped entirely once it has no fields left. A closed block
+/// that does not parse is an error rather than a reason to add a second one.
+pub fn update(content: &str, fields: Map<String, Value>) -> Result<String, String> {
+    let (mut front_matter, body) = match fenced(content) {
+        Some((type_, raw, body)) => (
+            entity::FrontMatter {
+                type_,
+                data: parse_raw(type_, raw)?,
+            },
+            body,
+        ),
+        None => (
+            entity::FrontMatter {
+                type_: YAML,
+                data: Map::new(),
+            },
+            content,
+        ),
+    };
+    for (key, value) in fields {
+        if value.is_null() {
+            front_matter.data.remove(&key);
+        } else {
+            front_matter.data.insert(key, value);
+        }
+    }
+    if front_matter.data.is_empty() {
+        return Ok(body.to_string());
+    }
+    Ok(to_string(&front_matter)? + body)
+}
+
+/// Looks up a dotted field path such as `author.name`.
+pub fn get<'a>(data: &'a Map<String, Value>, field: &str) -> Option<&'a Value> {
+    let mut parts = field.split('.');
+    let mut value = data.get(parts.next()?)?;
+    for part in parts {
+        value = value.as_object()?.get(part)?;
+    }
+    Some(value)
+}
+
+/// Reads a field holding a list of strings, also accepting a single comma separated string.
+pub fn strings(data: &Map<String, Value>, field: &str) -> Vec<String> {
+    let items = match data.get(field) {
+        Some(Value::Array(items)) => items
+            .iter()
+            .filter_map(|item| match item {
+                Value::String(s) => Some(s.clone()),
+                Value::Number(n) => Some(n.to_string()),
+                _ => None,
+            })
+            .collect(),
+        Some(Value::String(s)) => s.split(',').map(|s| s.to_string()).collect(),
+        _ => Vec::new(),
+    };
+    items
+        .into_iter()
+        .map(|s| s.trim().to_string())
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+fn toml_to_json(value: toml::Value) -> Value {
+    match value {
+        toml::Value::String(s) => Value::String(s),
+        toml::Value::Integer(i) => Value::from(i),
+        toml::Value::Float(f) => serde_json::Number::from_f64(f)
+            .map(Value::Number)
+            .unwrap_or(Value::Null),
+        toml::Value::Boolean(b) => Value::Bool(b),
+        toml::Value::Datetime(d) => Value::String(d.to_string()),
+        toml::Value::Array(a) => Value::Array(a.into_iter().map(toml_to_json).collect()),
+        toml::Value::Table(t) => {
+            Value::Object(t.into_iter().map(|(k, v)| (k, toml_to_json(v))).collect())
+        }
+    }
+}
+
+fn json_to_toml(value: Value) -> toml::Value {
+    match value {
+        Value::Null => toml::Value::String("".to_string()),
+        Value::Bool(b) => toml::Value::Boolean(b),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => toml::Value::Integer(i),
+            None => toml::Value::Float(n.as_f64().unwrap_or(0.0)),
+        },
+        // dates are kept as strings on the json side, turn them back into toml datetimes
+        Value::String(s) => match s.parse::<toml::value::Datetime>() {
+            Ok(d) => toml::Value::Datetime(d),
+            Err(_) => toml::Value::String(s),
+        },
+        Value::Array(a) => toml::Value::Array(a.into_iter().map(json_to_toml).collect()),
+        Value::Object(o) => toml::Value::Table(
+            o.into_iter()
+                .filter(|(_, v)| !v.is_null())
+                .map(|(k, v)| (k, json_to_toml(v)))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_yaml() {
+        let content = "---\ntitle: Hello\ntags: [api, guide]\n---\n# Hello\n";
+        let front_matter = parse(content).unwrap();
+        assert_eq!(front_matter.type_, YAML);
+        assert_eq!(front_matter.data["title"], "Hello");
+        assert_eq!(body(content), "# Hello\n");
+    }
+
+    #[test]
+    fn test_toml() {
+        let content = "+++\ntitle = \"Hello\"\ndate = 2026-01-01\n+++\nbody";
+        let front_matter = parse(content).unwrap();
+        assert_eq!(front_matter.type_, TOML);
+        assert_eq!(front_matter.data["date"], "2026-01-01");
+        assert_eq!(body(content), "body");
+    }
+
+    #[test]
+    fn test_update() {
+        let mut fields = Map::new();
+        fields.insert("author".to_string(), Value::from("ahri"));
+        let content = update("# Hello\n", fields).unwrap();
+        assert_eq!(content, "---\nauthor: ahri\n---\n# Hello\n");
+
+        let mut fields = Map::new();
+        fields.insert("author".to_string(), Value::Null);
+        assert_eq!(update(&content, fields.clone()).unwrap(), "# Hello\n");
+
+        // a broken block is reported, not shadowed by a new one
+        assert!(update("---\ntitle: [unclosed\n---\nbody\n", fields).is_err());
+    }
+
+    #[test]
+    fn test_not_front_matter() {
+        // thematic breaks around a paragraph, and a block that is never closed
+        for content in ["---\nJust text.\n---\nbody\n", "---\ntitle: Hello\nbody\n"] {
+            assert!(parse(content).is_none());
+            assert_eq!(body(content), content);
+        }
+        assert!(parse("---\ntitle: [unclosed\n---\n").is_none());
+        assert!(parse("---\n---\nbody\n").unwrap().data.is_empty());
+    }
+}
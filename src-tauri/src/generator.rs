@@ -0,0 +1,601 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::{
+    convert, entity,
+    export::{self, Page, Target},
+    frontmatter,
+    links::{self, Document},
+    outline, storage,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Generator {
+    MdBook,
+    Docusaurus,
+    Hugo,
+}
+
+/// Encodes what would end a Markdown link destination early.
+fn encode(href: &str) -> String {
+    href.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+        .replace('#', "%23")
+}
+
+fn parent(rel: &str) -> &str {
+    rel.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+fn join(dir: &str, name: &str) -> String {
+    if dir.is_empty() {
+        name.to_string()
+    } else {
+        format!("{}/{}", dir, name)
+    }
+}
+
+fn markdown_path(rel: &str) -> String {
+    match rel.rsplit_once('.') {
+        Some((stem, _)) => format!("{}.md", stem),
+        None => format!("{}.md", rel),
+    }
+}
+
+/// Whether a document introduces the folder it is in: `index` and `README` do.
+fn is_index(rel: &str) -> bool {
+    let name = rel.rsplit('/').next().unwrap_or(rel);
+    let stem = name.rsplit_once('.').map_or(name, |(stem, _)| stem);
+    stem.eq_ignore_ascii_case("index") || stem.eq_ignore_ascii_case("readme")
+}
+
+/// Number prefixes, like `01-`, Docusaurus strips from every part of a document id.
+static NUMBER_PREFIX: Lazy<Regex> = Lazy::new(|| Regex::new(r"^\d+\s*[-_.]+\s*(.+)$").unwrap());
+
+/// The id Docusaurus gives a document: its path without the extension and without the
+/// number prefixes it strips from every part.
+fn doc_id(dest: &str) -> String {
+    dest.strip_suffix(".md")
+        .unwrap_or(dest)
+        .split('/')
+        .map(|part| {
+            NUMBER_PREFIX
+                .captures(part)
+                .map_or(part, |cap| cap.get(1).unwrap().as_str())
+        })
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+fn label(text: &str) -> String {
+    text.replace('[', "\\[").replace(']', "\\]")
+}
+
+fn js(text: &str) -> String {
+    serde_json::to_string(text).unwrap()
+}
+
+/// Sorts the tree by the position of the first document below each node in `rank`, and
+/// returns the position of the first document of the whole tree.
+fn sort(folder: &Path, tree: &mut Vec<entity::FileTree>, rank: &HashMap<String, usize>) -> usize {
+    let mut keyed: Vec<(usize, entity::FileTree)> = tree
+        .drain(..)
+        .map(|mut node| {
+            let key = match node.children.as_mut() {
+                Some(children) => sort(folder, children, rank),
+                None => rank
+                    .get(&links::relative(folder, Path::new(&node.path)))
+                    .copied()
+                    .unwrap_or(usize::MAX),
+            };
+            (key, node)
+        })
+        .collect();
+    keyed.sort_by_key(|(key, _)| *key);
+    let first = keyed.first().map_or(usize::MAX, |(key, _)| *key);
+    tree.extend(keyed.into_iter().map(|(_, node)| node));
+    first
+}
+
+/// Where every document goes, by its path below the exported folder.
+struct Plan {
+    pages: HashMap<String, Page>,
+    /// Path of the written Markdown file below the content folder.
+    dests: HashMap<String, String>,
+    /// The document introducing each folder.
+    indexes: HashMap<String, String>,
+    rank: HashMap<String, usize>,
+}
+
+impl Plan {
+    /// Whether `rel` introduces its folder and so is listed as the folder itself.
+    fn is_index(&self, rel: &str) -> bool {
+        self.indexes
+            .get(parent(rel))
+            .map_or(false, |index| index == rel)
+    }
+}
+
+/// Exports the documents below `folder` as the source of a static-site generator project
+/// in `output`: a ready-to-build mdBook (`mdbook`), the docs folder and sidebar of a
+/// Docusaurus site (`docusaurus`) or the content folder of a Hugo site (`hugo`).
+pub struct Project<'a> {
+    /// Workspace root, for `/` links and embeds.
+    pub root: &'a Path,
+    pub folder: &'a Path,
+    pub output: &'a Path,
+    pub files: &'a HashMap<String, Document>,
+    /// Title, author and language of the book.
+    pub config: &'a entity::Config,
+    pub format: &'a str,
+}
+
+impl<'a> Project<'a> {
+    pub fn export(&self) -> Result<entity::Export, String> {
+        let generator = match self.format {
+            "mdbook" => Generator::MdBook,
+            "docusaurus" => Generator::Docusaurus,
+            "hugo" => Generator::Hugo,
+            _ => return Err(format!("unknown project format {}", self.format)),
+        };
+        if self.output.starts_with(self.folder) {
+            return Err("the output folder cannot be inside the exported folder".to_string());
+        }
+        let mut report = entity::Export {
+            output: self.output.to_str().unwrap().to_string(),
+            pages: 0,
+            assets: 0,
+            warnings: Vec::new(),
+        };
+        let mut tree = export::tree(self.folder)?;
+        let pages = export::pages(self.folder, &tree, self.files);
+        let pages = export::order(self.folder, pages, &mut report.warnings);
+        let rank: HashMap<String, usize> = pages
+            .iter()
+            .enumerate()
+            .map(|(i, page)| (page.rel.clone(), i))
+            .collect();
+        sort(self.folder, &mut tree, &rank);
+
+        let mut indexes = HashMap::new();
+        for page in &pages {
+            let dir = parent(&page.rel);
+            // only Hugo has a page for the exported folder itself
+            if is_index(&page.rel) && (!dir.is_empty() || generator == Generator::Hugo) {
+                indexes
+                    .entry(dir.to_string())
+                    .or_insert_with(|| page.rel.clone());
+            }
+        }
+        let mut dests = HashMap::new();
+        for page in &pages {
+            let dest = if generator == Generator::Hugo
+                && indexes.get(parent(&page.rel)) == Some(&page.rel)
+            {
+                join(parent(&page.rel), "_index.md")
+            } else {
+                markdown_path(&page.rel)
+            };
+            if let Some((other, _)) = dests.iter().find(|(_, d)| **d == dest) {
+                report.warnings.push(format!(
+                    "{} and {} are both exported as {}",
+                    other, page.rel, dest
+                ));
+            }
+            dests.insert(page.rel.clone(), dest);
+        }
+        let plan = Plan {
+            pages: pages
+                .iter()
+                .map(|page| (page.rel.clone(), page.clone()))
+                .collect(),
+            dests,
+            indexes,
+            rank,
+        };
+
+        let content_dir = self.output.join(match generator {
+            Generator::MdBook => "src",
+            Generator::Docusaurus => "docs",
+            Generator::Hugo => "content",
+        });
+        let mut copied = Vec::new();
+        for page in &pages {
+            let content =
+                match export::prepare(self.root, &page.source, self.files, &mut report.warnings) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        report
+                            .warnings
+                            .push(format!("{}: {}", page.source.display(), e));
+                        continue;
+                    }
+                };
+            let (mut data, type_, body) = if page.rel.ends_with(".ahtml") {
+                (
+                    Map::new(),
+                    frontmatter::YAML,
+                    convert::to_markdown(&content),
+                )
+            } else {
+                match frontmatter::split(&content) {
+                    Some(block) => (block.data, block.type_, block.body.to_string()),
+                    None => (Map::new(), frontmatter::YAML, content),
+                }
+            };
+            let body = self.links(generator, page, &body, &plan, &mut copied, &mut report);
+            let position = plan.rank[&page.rel] + 1;
+            let text = match generator {
+                // mdBook shows front matter as text
+                Generator::MdBook => body,
+                Generator::Docusaurus => {
+                    data.entry("title")
+                        .or_insert_with(|| page.title.clone().into());
+                    data.entry("sidebar_position").or_insert(position.into());
+                    // plain CommonMark rather than MDX, which rejects stray `{` and `<`
+                    data.entry("format").or_insert_with(|| "md".into());
+                    let front_matter = entity::FrontMatter {
+                        type_: frontmatter::YAML,
+                        data,
+                    };
+                    frontmatter::to_string(&front_matter)? + &body
+                }
+                Generator::Hugo => {
+                    data.entry("title")
+                        .or_insert_with(|| page.title.clone().into());
+                    data.entry("weight").or_insert(position.into());
+                    frontmatter::to_string(&entity::FrontMatter { type_, data })? + &body
+                }
+            };
+            let path = content_dir.join(&plan.dests[&page.rel]);
+            fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+            fs::write(&path, text).map_err(|e| e.to_string())?;
+            report.pages += 1;
+        }
+        report.assets = copied.len() as i32;
+
+        match generator {
+            Generator::MdBook => {
+                let mut summary = "# Summary\n\n".to_string();
+                self.summary(&tree, &plan, 0, &mut summary);
+                fs::write(content_dir.join("SUMMARY.md"), summary).map_err(|e| e.to_string())?;
+                fs::write(self.output.join("book.toml"), self.book()).map_err(|e| e.to_string())?;
+            }
+            Generator::Docusaurus => {
+                let mut items = String::new();
+                self.sidebar(&tree, &plan, 2, &mut items);
+                let sidebars = format!(
+                    "/** @type {{import('@docusaurus/plugin-content-docs').SidebarsConfig}} */\nconst sidebars = {{\n  docs: [\n{}  ],\n}};\n\nmodule.exports = sidebars;\n",
+                    items
+                );
+                fs::write(self.output.join("sidebars.js"), sidebars).map_err(|e| e.to_string())?;
+            }
+            Generator::Hugo => self.sections(&tree, &plan, &content_dir)?,
+        }
+        Ok(report)
+    }
+
+    /// Points links to documents at their exported files and copies the assets the
+    /// document uses. Hugo links go through `ref` so they follow its URLs.
+    fn links(
+        &self,
+        generator: Generator,
+        page: &Page,
+        content: &str,
+        plan: &Plan,
+        copied: &mut Vec<PathBuf>,
+        report: &mut entity::Export,
+    ) -> String {
+        let from = Path::new(parent(&plan.dests[&page.rel]));
+        let mut edits = Vec::new();
+        for (event, range) in Parser::new_ext(content, outline::options()).into_offset_iter() {
+            let dest = match event {
+                Event::Start(Tag::Link(LinkType::Inline, dest, _)) => dest,
+                Event::Start(Tag::Image(LinkType::Inline, dest, _)) => dest,
+                _ => continue,
+            };
+            let target = export::target(self.root, &page.source, &dest);
+            let path = match &target {
+                Target::Document(path, _) | Target::Asset(path) => path,
+                Target::Missing => {
+                    report.warnings.push(format!(
+                        "{}: broken link {}",
+                        page.source.display(),
+                        dest
+                    ));
+                    continue;
+                }
+                Target::Unchanged => continue,
+            };
+            if !path.starts_with(self.folder) {
+                report.warnings.push(format!(
+                    "{}: {} is outside the exported folder",
+                    page.source.display(),
+                    dest
+                ));
+                continue;
+            }
+            let rel = links::relative(self.folder, path);
+            let href = match target {
+                Target::Document(_, anchor) => {
+                    let to = match plan.dests.get(&rel) {
+                        Some(to) => to,
+                        None => continue,
+                    };
+                    let anchor = if anchor.is_empty() {
+                        anchor
+                    } else {
+                        format!("#{}", anchor)
+                    };
+                    match generator {
+                        Generator::Hugo => format!("{{{{< ref \"/{}{}\" >}}}}", to, anchor),
+                        _ => format!(
+                            "{}{}",
+                            encode(&links::relative(from, Path::new(to))),
+                            anchor
+                        ),
+                    }
+                }
+                _ => {
+                    if !copied.contains(path) {
+                        let to = match generator {
+                            // content files only publish next to a page bundle
+                            Generator::Hugo => self.output.join("static").join(&rel),
+                            Generator::MdBook => self.output.join("src").join(&rel),
+                            Generator::Docusaurus => self.output.join("docs").join(&rel),
+                        };
+                        let result = storage::for_path(path).read(path).and_then(|data| {
+                            fs::create_dir_all(to.parent().unwrap())?;
+                            fs::write(&to, data)
+                        });
+                        match result {
+                            Ok(_) => copied.push(path.clone()),
+                            Err(e) => report.warnings.push(format!("{}: {}", path.display(), e)),
+                        }
+                    }
+                    match generator {
+                        Generator::Hugo => encode(&format!("/{}", rel)),
+                        _ => encode(&links::relative(from, Path::new(&rel))),
+                    }
+                }
+            };
+            let text = &content[range.clone()];
+            let (start, len) = match text.rfind(&format!("](<{}>", dest)) {
+                Some(i) => (range.start + i + 2, dest.len() + 2),
+                None => match text.rfind(&format!("]({}", dest)) {
+                    Some(i) => (range.start + i + 2, dest.len()),
+                    None => continue,
+                },
+            };
+            edits.push((start..start + len, href));
+        }
+        let mut result = content.to_string();
+        for (range, href) in edits.into_iter().rev() {
+            result.replace_range(range, &href);
+        }
+        result
+    }
+
+    fn title(&self) -> String {
+        if self.config.project.is_empty() {
+            self.folder
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            self.config.project.clone()
+        }
+    }
+
+    fn book(&self) -> String {
+        let quote = |text: &str| toml::Value::String(text.to_string()).to_string();
+        let mut book = format!("[book]\ntitle = {}\n", quote(&self.title()));
+        if !self.config.author.is_empty() {
+            book.push_str(&format!("authors = [{}]\n", quote(&self.config.author)));
+        }
+        if !self.config.language.is_empty() {
+            book.push_str(&format!("language = {}\n", quote(&self.config.language)));
+        }
+        book.push_str("src = \"src\"\n");
+        book
+    }
+
+    /// The chapters of the mdBook `SUMMARY.md`. Folders without an index document become
+    /// draft chapters, which mdBook lists without a page.
+    fn summary(&self, tree: &[entity::FileTree], plan: &Plan, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for node in tree {
+            let rel = links::relative(self.folder, Path::new(&node.path));
+            match &node.children {
+                Some(children) => {
+                    match plan.indexes.get(&rel) {
+                        Some(index) => out.push_str(&format!(
+                            "{}- [{}](<{}>)\n",
+                            indent,
+                            label(&plan.pages[index].title),
+                            plan.dests[index]
+                        )),
+                        None => out.push_str(&format!("{}- [{}]()\n", indent, label(&node.name))),
+                    }
+                    self.summary(children, plan, depth + 1, out);
+                }
+                None if !plan.is_index(&rel) && plan.pages.contains_key(&rel) => {
+                    out.push_str(&format!(
+                        "{}- [{}](<{}>)\n",
+                        indent,
+                        label(&plan.pages[&rel].title),
+                        plan.dests[&rel]
+                    ));
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// The items of the Docusaurus sidebar, a category per folder.
+    fn sidebar(&self, tree: &[entity::FileTree], plan: &Plan, depth: usize, out: &mut String) {
+        let indent = "  ".repeat(depth);
+        for node in tree {
+            let rel = links::relative(self.folder, Path::new(&node.path));
+            match &node.children {
+                Some(children) => {
+                    out.push_str(&format!(
+                        "{0}{{\n{0}  type: \"category\",\n{0}  label: {1},\n",
+                        indent,
+                        js(&node.name)
+                    ));
+                    if let Some(index) = plan.indexes.get(&rel) {
+                        out.push_str(&format!(
+                            "{}  link: {{ type: \"doc\", id: {} }},\n",
+                            indent,
+                            js(&doc_id(&plan.dests[index]))
+                        ));
+                    }
+                    out.push_str(&format!("{}  items: [\n", indent));
+                    self.sidebar(children, plan, depth + 2, out);
+                    out.push_str(&format!("{0}  ],\n{0}}},\n", indent));
+                }
+                None if !plan.is_index(&rel) && plan.dests.contains_key(&rel) => {
+                    out.push_str(&format!("{}{},\n", indent, js(&doc_id(&plan.dests[&rel]))));
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Writes an `_index.md` for the Hugo sections of folders without an index document.
+    fn sections(
+        &self,
+        tree: &[entity::FileTree],
+        plan: &Plan,
+        content_dir: &Path,
+    ) -> Result<(), String> {
+        for node in tree {
+            let children = match &node.children {
+                Some(children) => children,
+                None => continue,
+            };
+            let rel = links::relative(self.folder, Path::new(&node.path));
+            if !plan.indexes.contains_key(&rel) {
+                let mut flat = Vec::new();
+                crate::api::flatten(children.clone(), &mut flat);
+                let weight = flat
+                    .iter()
+                    .filter_map(|node| {
+                        plan.rank
+                            .get(&links::relative(self.folder, Path::new(&node.path)))
+                    })
+                    .min()
+                    .map_or(0, |rank| rank + 1);
+                let mut data = Map::new();
+                data.insert("title".to_string(), Value::String(node.name.clone()));
+                data.insert("weight".to_string(), weight.into());
+                let front_matter = entity::FrontMatter {
+                    type_: frontmatter::YAML,
+                    data,
+                };
+                let path = content_dir.join(&rel).join("_index.md");
+                fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+                fs::write(path, frontmatter::to_string(&front_matter)?)
+                    .map_err(|e| e.to_string())?;
+            }
+            self.sections(children, plan, content_dir)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_export() {
+        let temp = TempDir::new("generator");
+        let root = temp.join("generator");
+        let output = temp.join("generator-out");
+        fs::create_dir_all(root.join("01-guide/img")).unwrap();
+        let files = [
+            (
+                "intro.md",
+                "---\ntags: [a]\n---\n# Intro\n\n[setup](01-guide/setup.md#run)\n",
+            ),
+            ("01-guide/README.md", "# Guide\n"),
+            (
+                "01-guide/setup.md",
+                "# Setup\n\n![pic](img/p.png) [home](../intro.md)\n",
+            ),
+            (".order", "intro.md\n01-guide/README.md\n"),
+        ];
+        let mut index = HashMap::new();
+        for (name, content) in files {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            index.insert(
+                path.to_str().unwrap().to_string(),
+                Document::new(&path, content),
+            );
+        }
+        fs::write(root.join("01-guide/img/p.png"), "png").unwrap();
+        let config = entity::Config {
+            project: "Docs".to_string(),
+            ..Default::default()
+        };
+        let export = |format| {
+            let _ = fs::remove_dir_all(&output);
+            Project {
+                root: &root,
+                folder: &root,
+                output: &output,
+                files: &index,
+                config: &config,
+                format,
+            }
+            .export()
+            .unwrap()
+        };
+
+        let report = export("mdbook");
+        assert_eq!((report.pages, report.assets), (3, 1));
+        assert!(report.warnings.is_empty());
+        assert_eq!(
+            fs::read_to_string(output.join("src/SUMMARY.md")).unwrap(),
+            "# Summary\n\n- [Intro](<intro.md>)\n- [Guide](<01-guide/README.md>)\n  - [Setup](<01-guide/setup.md>)\n"
+        );
+        assert_eq!(
+            fs::read_to_string(output.join("src/intro.md")).unwrap(),
+            "# Intro\n\n[setup](01-guide/setup.md#run)\n"
+        );
+        assert!(output.join("src/01-guide/img/p.png").is_file());
+        assert!(fs::read_to_string(output.join("book.toml"))
+            .unwrap()
+            .starts_with("[book]\ntitle = \"Docs\"\n"));
+
+        export("docusaurus");
+        let sidebars = fs::read_to_string(output.join("sidebars.js")).unwrap();
+        assert!(sidebars.contains(
+            "    \"intro\",\n    {\n      type: \"category\",\n      label: \"01-guide\",\n      link: { type: \"doc\", id: \"guide/README\" },\n      items: [\n        \"guide/setup\",\n      ],\n    },\n"
+        ));
+        assert_eq!(
+            fs::read_to_string(output.join("docs/intro.md")).unwrap(),
+            "---\ntags:\n- a\ntitle: Intro\nsidebar_position: 1\nformat: md\n---\n# Intro\n\n[setup](01-guide/setup.md#run)\n"
+        );
+
+        export("hugo");
+        let setup = fs::read_to_string(output.join("content/01-guide/setup.md")).unwrap();
+        assert!(setup.contains("![pic](/01-guide/img/p.png) [home]({{< ref \"/intro.md\" >}})"));
+        assert!(output.join("content/01-guide/_index.md").is_file());
+        assert!(output.join("static/01-guide/img/p.png").is_file());
+    }
+}
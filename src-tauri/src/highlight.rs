@@ -0,0 +1,170 @@
+use std::path::{Path, PathBuf};
+
+use once_cell::sync::Lazy;
+use syntect::{
+    highlighting::{Theme, ThemeSet},
+    html::{
+        css_for_theme_with_class_style, highlighted_html_for_string, ClassStyle,
+        ClassedHTMLGenerator,
+    },
+    parsing::SyntaxSet,
+    util::LinesWithEndings,
+};
+
+use crate::entity;
+
+static SYNTAXES: Lazy<SyntaxSet> = Lazy::new(SyntaxSet::load_defaults_newlines);
+static THEMES: Lazy<ThemeSet> = Lazy::new(ThemeSet::load_defaults);
+
+/// Theme used for inline styles when none is picked.
+pub const DEFAULT_THEME: &str = "InspiredGitHub";
+
+/// Folder of a workspace holding extra `.tmTheme` files.
+pub fn theme_dir(root: &Path) -> PathBuf {
+    root.join(".ahriknow").join("themes")
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Languages code blocks can be highlighted in.
+pub fn languages() -> Vec<entity::Language> {
+    let mut result: Vec<entity::Language> = SYNTAXES
+        .syntaxes()
+        .iter()
+        .filter(|syntax| !syntax.hidden)
+        .map(|syntax| entity::Language {
+            name: syntax.name.clone(),
+            extensions: syntax.file_extensions.clone(),
+        })
+        .collect();
+    result.sort_by_key(|language| language.name.to_lowercase());
+    result
+}
+
+/// Highlights fenced code blocks, either with CSS classes or inline styles from a theme.
+pub struct Highlighter {
+    /// The `.tmTheme` files of the workspace, next to the built-in `THEMES`.
+    themes: ThemeSet,
+    /// Inline styles from this theme; `None` emits `class` attributes for `css`.
+    pub theme: Option<String>,
+}
+
+impl Highlighter {
+    /// Built-in themes plus the `.tmTheme` files found in `theme_dir`.
+    pub fn new(theme_dir: Option<&Path>, theme: Option<String>) -> Highlighter {
+        let mut themes = ThemeSet::new();
+        if let Some(dir) = theme_dir {
+            if dir.is_dir() {
+                if let Err(e) = themes.add_from_folder(dir) {
+                    println!("{:?}", e);
+                }
+            }
+        }
+        Highlighter { themes, theme }
+    }
+
+    /// Like `new`, but fails when a theme file cannot be loaded or `theme` is none of them.
+    pub fn load(theme_dir: Option<&Path>, theme: Option<String>) -> Result<Highlighter, String> {
+        let mut themes = ThemeSet::new();
+        if let Some(dir) = theme_dir {
+            if dir.is_dir() {
+                themes
+                    .add_from_folder(dir)
+                    .map_err(|e| format!("{}: {}", dir.display(), e))?;
+            }
+        }
+        let highlighter = Highlighter { themes, theme };
+        if let Some(theme) = &highlighter.theme {
+            highlighter.find(theme)?;
+        }
+        Ok(highlighter)
+    }
+
+    fn find(&self, theme: &str) -> Result<&Theme, String> {
+        self.themes
+            .themes
+            .get(theme)
+            .or_else(|| THEMES.themes.get(theme))
+            .ok_or_else(|| format!("theme {} not found", theme))
+    }
+
+    pub fn themes(&self) -> Vec<String> {
+        let mut result: Vec<String> = THEMES
+            .themes
+            .keys()
+            .chain(self.themes.themes.keys())
+            .cloned()
+            .collect();
+        result.sort();
+        result.dedup();
+        result
+    }
+
+    /// Stylesheet for class-based output in the given theme.
+    pub fn css(&self, theme: &str) -> Result<String, String> {
+        css_for_theme_with_class_style(self.find(theme)?, ClassStyle::Spaced)
+            .map_err(|e| e.to_string())
+    }
+
+    /// Highlights `code` as `lang`, or returns `None` when the language is unknown.
+    pub fn highlight(&self, code: &str, lang: &str) -> Option<String> {
+        let syntax = SYNTAXES.find_syntax_by_token(lang)?;
+        match &self.theme {
+            Some(theme) => {
+                let theme = self
+                    .find(theme)
+                    .or_else(|_| self.find(DEFAULT_THEME))
+                    .ok()?;
+                highlighted_html_for_string(code, &SYNTAXES, syntax, theme).ok()
+            }
+            None => {
+                let mut generator = ClassedHTMLGenerator::new_with_class_style(
+                    syntax,
+                    &SYNTAXES,
+                    ClassStyle::Spaced,
+                );
+                for line in LinesWithEndings::from(code) {
+                    generator
+                        .parse_html_for_line_which_includes_newline(line)
+                        .ok()?;
+                }
+                Some(format!(
+                    "<pre class=\"code\"><code class=\"language-{}\">{}</code></pre>\n",
+                    escape(lang),
+                    generator.finalize()
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_themes() {
+        assert_eq!(
+            Highlighter::load(None, Some("nope".to_string()))
+                .err()
+                .unwrap(),
+            "theme nope not found"
+        );
+        let highlighter = Highlighter::load(None, Some(DEFAULT_THEME.to_string())).unwrap();
+        assert!(highlighter
+            .highlight("fn main() {}\n", "rust")
+            .unwrap()
+            .contains("style="));
+        assert!(highlighter.css("nope").is_err());
+
+        let temp = TempDir::new("highlight");
+        std::fs::write(temp.join("broken.tmTheme"), "not a theme").unwrap();
+        assert!(Highlighter::load(Some(temp.path()), None).is_err());
+    }
+}
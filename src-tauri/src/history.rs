@@ -0,0 +1,93 @@
+use std::{
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use crate::{entity, storage};
+
+/// The workspace root: the closest ancestor of `path` holding a `.ahriknow` folder.
+pub fn root(path: &Path) -> Option<PathBuf> {
+    let storage = storage::for_path(path);
+    path.ancestors()
+        .skip(1)
+        .find(|dir| storage.is_dir(&dir.join(".ahriknow")))
+        .map(|dir| dir.to_path_buf())
+}
+
+/// Folder holding the snapshots of `path`, mirroring its place in the workspace.
+pub fn dir(path: &Path) -> Option<PathBuf> {
+    let root = root(path)?;
+    let relative = path.strip_prefix(&root).ok()?;
+    Some(root.join(".ahriknow").join("history").join(relative))
+}
+
+/// Saves the current content of `path` as a new snapshot before it gets changed.
+pub fn record(path: &Path) -> std::io::Result<()> {
+    let dir = match dir(path) {
+        Some(dir) => dir,
+        None => return Ok(()),
+    };
+    let storage = storage::for_path(path);
+    if !storage.is_file(path) {
+        return Ok(());
+    }
+    storage.create_dir_all(&dir)?;
+    let mut now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis());
+    // snapshots within the same millisecond take the next free one
+    while storage.exists(&dir.join(now.to_string())) {
+        now += 1;
+    }
+    storage.copy(path, &dir.join(now.to_string()))?;
+    Ok(())
+}
+
+/// Writes `content` to `path`, recording the previous version first.
+pub fn write(path: &Path, content: &str) -> std::io::Result<()> {
+    record(path)?;
+    storage::for_path(path).write(path, content.as_bytes())
+}
+
+/// Snapshots of `path`, newest first.
+pub fn list(path: &Path) -> Vec<entity::History> {
+    let mut result = Vec::new();
+    if let Some(dir) = dir(path) {
+        if let Ok(entries) = storage::for_path(&dir).list(&dir) {
+            for entry in entries {
+                if let Ok(created) = entry.name.parse::<i64>() {
+                    result.push(entity::History {
+                        path: entry.path.to_str().unwrap().to_string(),
+                        created,
+                    });
+                }
+            }
+        }
+    }
+    result.sort_by_key(|history| std::cmp::Reverse(history.created));
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let root = Path::new("/memory/history");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        storage.create_dir(&root.join(".ahriknow")).unwrap();
+        let path = root.join("a.md");
+        for content in ["a", "b", "c"] {
+            write(&path, content).unwrap();
+        }
+        let history = list(&path);
+        assert_eq!(history.len(), 2);
+        assert_eq!(
+            storage.read_to_string(Path::new(&history[0].path)).unwrap(),
+            "b"
+        );
+        storage::unmount(root);
+    }
+}
@@ -0,0 +1,131 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+};
+
+use crate::{api, archive, storage};
+
+struct Inner<T> {
+    root: String,
+    files: HashMap<String, T>,
+    // counts the changes, for callers keeping data derived from the index
+    generation: u64,
+}
+
+/// Per-document data for the watched workspace, kept up to date from watcher events.
+pub struct Index<T> {
+    inner: Arc<Mutex<Inner<T>>>,
+    extract: fn(&Path, &str) -> Option<T>,
+}
+
+impl<T> Clone for Index<T> {
+    fn clone(&self) -> Self {
+        Index {
+            inner: self.inner.clone(),
+            extract: self.extract,
+        }
+    }
+}
+
+impl<T> Index<T> {
+    /// `extract` turns a document into its entry, or `None` to leave the document out.
+    pub fn new(extract: fn(&Path, &str) -> Option<T>) -> Index<T> {
+        Index {
+            inner: Arc::new(Mutex::new(Inner {
+                root: "".to_string(),
+                files: HashMap::new(),
+                generation: 0,
+            })),
+            extract,
+        }
+    }
+
+    fn entry(&self, path: &Path) -> Option<T> {
+        let content = archive::read_to_string(path).ok()?;
+        (self.extract)(path, &content)
+    }
+
+    pub fn build(&self, root: &Path) {
+        let mut files = HashMap::new();
+        for path in api::walk(root) {
+            if let Some(entry) = self.entry(&path) {
+                files.insert(path.to_str().unwrap().to_string(), entry);
+            }
+        }
+        let mut inner = self.inner.lock().unwrap();
+        inner.root = root.to_str().unwrap().to_string();
+        inner.files = files;
+        inner.generation += 1;
+    }
+
+    /// Builds the index for `root` unless it is already the indexed workspace.
+    pub fn ensure(&self, root: &Path) {
+        let indexed = self.inner.lock().unwrap().root == root.to_str().unwrap();
+        if !indexed {
+            self.build(root);
+        }
+    }
+
+    pub fn root(&self) -> String {
+        self.inner.lock().unwrap().root.clone()
+    }
+
+    pub fn update(&self, path: &Path) {
+        if !storage::for_path(path).is_file(path) {
+            return self.remove(path);
+        }
+        let entry = self.entry(path);
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        let path = path.to_str().unwrap().to_string();
+        match entry {
+            Some(entry) => inner.files.insert(path, entry),
+            None => inner.files.remove(&path),
+        };
+    }
+
+    /// Drops a document, or every document below a folder.
+    pub fn remove(&self, path: &Path) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.generation += 1;
+        inner
+            .files
+            .retain(|file, _| !Path::new(file).starts_with(path));
+    }
+
+    /// Applies a `file-system-changed` message emitted by the watcher.
+    pub fn changed(&self, type_: i32, path: &str, path2: &str) {
+        match type_ {
+            2 | 4 => self.update(Path::new(path)),
+            -1 | -2 => self.remove(Path::new(path)),
+            5 => {
+                self.remove(Path::new(path));
+                for file in api::walk(Path::new(path2)) {
+                    self.update(&file);
+                }
+            }
+            6 => {
+                self.remove(Path::new(path));
+                self.update(Path::new(path2));
+            }
+            1 => {
+                for file in api::walk(Path::new(path)) {
+                    self.update(&file);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Runs `f` over the indexed documents, keyed by path.
+    pub fn read<R>(&self, f: impl FnOnce(&HashMap<String, T>) -> R) -> R {
+        f(&self.inner.lock().unwrap().files)
+    }
+
+    /// Changes with every build, update and removal, also of files that are not indexed,
+    /// like images a link may point at.
+    pub fn generation(&self) -> u64 {
+        self.inner.lock().unwrap().generation
+    }
+}
@@ -1,2 +1,32 @@
+pub mod ahtml;
 pub mod api;
+pub mod archive;
+pub mod backup;
+pub mod convert;
+pub mod docx;
+pub mod embed;
 pub mod entity;
+pub mod epub;
+pub mod export;
+pub mod frontmatter;
+pub mod generator;
+pub mod highlight;
+pub mod history;
+pub mod index;
+pub mod links;
+pub mod migrate;
+pub mod opml;
+pub mod outline;
+pub mod pdf;
+pub mod query;
+pub mod render;
+pub mod s3;
+pub mod sanitize;
+pub mod search;
+pub mod section;
+pub mod site;
+pub mod storage;
+pub mod tags;
+#[cfg(test)]
+mod testing;
+pub mod webdav;
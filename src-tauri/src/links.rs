@@ -0,0 +1,595 @@
+use std::{
+    collections::HashMap,
+    ops::Deref,
+    path::{Component, Path, PathBuf},
+    sync::{Arc, Mutex},
+};
+
+use once_cell::sync::Lazy;
+
+use percent_encoding::percent_decode_str;
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use regex::Regex;
+
+use crate::{ahtml, entity, frontmatter, index::Index, outline, storage};
+
+pub const LINK: i32 = 1;
+pub const IMAGE: i32 = 2;
+pub const WIKI: i32 = 3;
+pub const EMBED: i32 = 4;
+
+static SCHEME: Lazy<Regex> = Lazy::new(|| Regex::new(r"^[a-zA-Z][a-zA-Z0-9+.\-]*:").unwrap());
+
+/// A `[[Page#Heading|label]]` wiki-link, or with a leading `!` an embed.
+pub static WIKI_LINK: Lazy<Regex> = Lazy::new(|| Regex::new(r"(!?)\[\[([^\[\]\n]+)\]\]").unwrap());
+
+static AHTML_LINK: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r#"(?is)<(a|img)\s[^>]*?\b(?:href|src)\s*=\s*["']([^"']*)["']"#).unwrap()
+});
+
+/// What the link graph keeps for every document.
+pub struct Document {
+    pub links: Vec<entity::Link>,
+    /// Heading anchors other documents may point at.
+    pub anchors: Vec<String>,
+    /// Front matter `title`, empty when not set.
+    pub title: String,
+    /// Front matter `aliases` (or `alias`) wiki-links may use instead of the file name.
+    pub aliases: Vec<String>,
+}
+
+impl Document {
+    pub fn new(path: &Path, content: &str) -> Document {
+        let (title, aliases) = match frontmatter::parse(content) {
+            Some(front_matter) => {
+                let mut aliases = frontmatter::strings(&front_matter.data, "aliases");
+                aliases.extend(frontmatter::strings(&front_matter.data, "alias"));
+                let title = match front_matter.data.get("title") {
+                    Some(serde_json::Value::String(title)) => title.trim().to_string(),
+                    _ => "".to_string(),
+                };
+                (title, aliases)
+            }
+            None => match ahtml::header(content) {
+                Some(header) => (header.title.trim().to_string(), Vec::new()),
+                None => ("".to_string(), Vec::new()),
+            },
+        };
+        Document {
+            links: extract(path, content),
+            anchors: outline::headings(path.to_str().unwrap(), content)
+                .into_iter()
+                .map(|heading| heading.slug)
+                .collect(),
+            title,
+            aliases,
+        }
+    }
+}
+
+/// Whether `href` points outside the workspace (`https:`, `mailto:`, `data:`, `//host`, ...).
+pub fn external(href: &str) -> bool {
+    href.starts_with("//") || SCHEME.is_match(href)
+}
+
+fn link(type_: i32, source: &Path, href: &str, anchor: &str, line: i32) -> entity::Link {
+    entity::Link {
+        type_,
+        source: source.to_str().unwrap().to_string(),
+        href: href.to_string(),
+        target: "".to_string(),
+        anchor: anchor.to_string(),
+        line,
+    }
+}
+
+/// Splits a relative href into its decoded path and anchor.
+pub fn split(href: &str) -> (String, String) {
+    let (path, anchor) = match href.find('#') {
+        Some(i) => (&href[..i], &href[i + 1..]),
+        None => (href, ""),
+    };
+    let path = match path.find('?') {
+        Some(i) => &path[..i],
+        None => path,
+    };
+    (
+        percent_decode_str(path).decode_utf8_lossy().to_string(),
+        percent_decode_str(anchor).decode_utf8_lossy().to_string(),
+    )
+}
+
+/// Parses the `[[Page#Heading|label]]` part between the brackets into page and heading.
+pub fn wiki(inner: &str) -> (String, String) {
+    let target = inner.split('|').next().unwrap_or("");
+    let (page, heading) = match target.find('#') {
+        Some(i) => (&target[..i], &target[i + 1..]),
+        None => (target, ""),
+    };
+    (page.trim().to_string(), heading.trim().to_string())
+}
+
+/// Relative links, images, wiki-links and embeds of a Markdown document.
+pub fn markdown(source: &Path, content: &str) -> Vec<entity::Link> {
+    let body = frontmatter::body(content);
+    let offset = content.len() - body.len();
+    let lines = outline::Lines::new(content);
+    let mut result = Vec::new();
+    for (event, range) in Parser::new_ext(body, outline::options()).into_offset_iter() {
+        let (type_, dest) = match event {
+            Event::Start(Tag::Link(kind, dest, _))
+                if kind != LinkType::Autolink && kind != LinkType::Email =>
+            {
+                (LINK, dest)
+            }
+            Event::Start(Tag::Image(_, dest, _)) => (IMAGE, dest),
+            _ => continue,
+        };
+        if external(&dest) {
+            continue;
+        }
+        let (path, anchor) = split(&dest);
+        let line = lines.line(offset + range.start);
+        result.push(link(type_, source, &path, &anchor, line));
+    }
+    for (start, line) in outline::prose(body) {
+        for cap in WIKI_LINK.captures_iter(line) {
+            let m = cap.get(0).unwrap();
+            if outline::in_code_span(line, m.start()) {
+                continue;
+            }
+            let (page, heading) = wiki(&cap[2]);
+            let type_ = if cap[1].is_empty() { WIKI } else { EMBED };
+            let line = lines.line(offset + start + m.start());
+            result.push(link(type_, source, &page, &heading, line));
+        }
+    }
+    result.sort_by_key(|link| link.line);
+    result
+}
+
+/// `<a href>` and `<img src>` references of an `.ahtml` document.
+pub fn ahtml(source: &Path, content: &str) -> Vec<entity::Link> {
+    let lines = outline::Lines::new(content);
+    let mut result = Vec::new();
+    for cap in AHTML_LINK.captures_iter(content) {
+        let href = outline::decode(&cap[2]);
+        if external(&href) {
+            continue;
+        }
+        let type_ = if cap[1].eq_ignore_ascii_case("img") {
+            IMAGE
+        } else {
+            LINK
+        };
+        let (path, anchor) = split(&href);
+        let line = lines.line(cap.get(0).unwrap().start());
+        result.push(link(type_, source, &path, &anchor, line));
+    }
+    result
+}
+
+pub fn extract(source: &Path, content: &str) -> Vec<entity::Link> {
+    if source.extension().map_or(false, |ext| ext == "ahtml") {
+        ahtml(source, content)
+    } else {
+        markdown(source, content)
+    }
+}
+
+/// Removes `.` and `..` components without touching the file system. The `scheme://host`
+/// a remote workspace starts with is kept as it is.
+pub fn normalize(path: &Path) -> PathBuf {
+    if let Some(text) = path.to_str() {
+        if let Some(i) = text.find("://").filter(|i| SCHEME.is_match(&text[..=*i])) {
+            let end = text[i + 3..].find('/').map_or(text.len(), |j| i + 3 + j);
+            let rest = normalize(Path::new("/").join(&text[end..]).as_path());
+            let rest = rest.to_str().unwrap().trim_end_matches('/');
+            return PathBuf::from(format!("{}{}", &text[..end], rest));
+        }
+    }
+    let mut result = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                result.pop();
+            }
+            _ => result.push(component),
+        }
+    }
+    result
+}
+
+/// Path of `to` relative to the folder `from`, joined with `/` for use in links.
+pub fn relative(from: &Path, to: &Path) -> String {
+    let from: Vec<Component> = from.components().collect();
+    let to: Vec<Component> = to.components().collect();
+    let common = from
+        .iter()
+        .zip(to.iter())
+        .take_while(|(a, b)| a == b)
+        .count();
+    let mut parts: Vec<String> = vec!["..".to_string(); from.len() - common];
+    parts.extend(
+        to[common..]
+            .iter()
+            .map(|c| c.as_os_str().to_string_lossy().to_string()),
+    );
+    parts.join("/")
+}
+
+/// Resolves the path part of a relative link found in `source`.
+///
+/// An empty path points back at `source`, a leading `/` is relative to `root`, and
+/// extension-less paths fall back to the `.md` or `.ahtml` document of that name.
+pub fn resolve(root: &Path, source: &Path, path: &str) -> Option<PathBuf> {
+    if path.is_empty() {
+        return Some(source.to_path_buf());
+    }
+    let target = match path.strip_prefix('/') {
+        Some(path) => root.join(path),
+        None => source.parent().unwrap_or(root).join(path),
+    };
+    let target = normalize(&target);
+    let storage = storage::for_path(&target);
+    if storage.is_file(&target) {
+        return Some(target);
+    }
+    if target.extension().is_none() {
+        for ext in ["md", "ahtml"] {
+            let candidate = target.with_extension(ext);
+            if storage.is_file(&candidate) {
+                return Some(candidate);
+            }
+        }
+    }
+    None
+}
+
+/// Documents a wiki-link page name refers to.
+///
+/// File names win, matching relative paths when the name contains a folder; front matter
+/// titles and aliases are only considered when no file name matches.
+pub fn candidates(files: &HashMap<String, Document>, page: &str) -> Vec<String> {
+    let page = page.to_lowercase().replace('\\', "/");
+    let page = page
+        .strip_suffix(".md")
+        .or_else(|| page.strip_suffix(".ahtml"))
+        .unwrap_or(&page);
+    let suffix = format!("/{}", page.trim_start_matches('/'));
+    let mut result: Vec<String> = files
+        .keys()
+        .filter(|file| {
+            let file = file.to_lowercase().replace('\\', "/");
+            let stem = file
+                .rsplit_once('.')
+                .map_or(file.as_str(), |(stem, _)| stem);
+            stem.ends_with(&suffix)
+        })
+        .cloned()
+        .collect();
+    if result.is_empty() {
+        result = files
+            .iter()
+            .filter(|(_, document)| {
+                document.title.to_lowercase() == page
+                    || document.aliases.iter().any(|a| a.to_lowercase() == page)
+            })
+            .map(|(file, _)| file.clone())
+            .collect();
+    }
+    result.sort();
+    result
+}
+
+/// Where a new page for a dangling wiki-link goes: next to `source`, or below `root` when
+/// the page name contains a folder. `None` when that would be outside the workspace.
+pub fn page_path(root: &Path, source: &Path, page: &str) -> Option<PathBuf> {
+    let page = page.replace('\\', "/");
+    let page = page.trim_start_matches('/');
+    let name = if page.ends_with(".md") || page.ends_with(".ahtml") {
+        page.to_string()
+    } else {
+        format!("{}.md", page)
+    };
+    let path = if page.contains('/') {
+        normalize(&root.join(name))
+    } else {
+        normalize(&source.parent().unwrap_or(root).join(name))
+    };
+    let root = normalize(root);
+    Some(path).filter(|path| path.starts_with(&root) && *path != root)
+}
+
+/// A link with its target filled in, and the candidates of an ambiguous wiki-link.
+type Resolved = (entity::Link, Vec<String>);
+
+/// The links resolved at an index generation, kept until the index changes.
+struct ResolvedLinks {
+    generation: u64,
+    links: Arc<Vec<Resolved>>,
+}
+
+/// Links of every document in the watched workspace.
+#[derive(Clone)]
+pub struct LinkIndex {
+    index: Index<Document>,
+    resolved: Arc<Mutex<Option<ResolvedLinks>>>,
+}
+
+impl Default for LinkIndex {
+    fn default() -> Self {
+        LinkIndex {
+            index: Index::new(|path, content| Some(Document::new(path, content))),
+            resolved: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl Deref for LinkIndex {
+    type Target = Index<Document>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.index
+    }
+}
+
+impl LinkIndex {
+    /// Fills in `target`, returning the other candidates when a wiki-link is ambiguous.
+    fn resolved(
+        &self,
+        root: &Path,
+        files: &HashMap<String, Document>,
+        link: &entity::Link,
+    ) -> (entity::Link, Vec<String>) {
+        let mut link = link.clone();
+        let mut others = Vec::new();
+        if link.type_ == WIKI || link.type_ == EMBED {
+            let mut candidates = if link.href.is_empty() {
+                vec![link.source.clone()]
+            } else {
+                candidates(files, &link.href)
+            };
+            if candidates.len() == 1 {
+                link.target = candidates.remove(0);
+            } else {
+                others = candidates;
+            }
+        } else if let Some(target) = resolve(root, Path::new(&link.source), &link.href) {
+            link.target = target.to_str().unwrap().to_string();
+        }
+        (link, others)
+    }
+
+    /// Resolves the text of a `[[...]]` link written in `source`.
+    pub fn resolve(&self, source: &str, link: &str) -> entity::Resolution {
+        let inner = link.trim().trim_start_matches('!');
+        let inner = inner
+            .strip_prefix("[[")
+            .and_then(|inner| inner.strip_suffix("]]"))
+            .unwrap_or(inner);
+        let (page, heading) = wiki(inner);
+        let mut candidates = if page.is_empty() {
+            vec![source.to_string()]
+        } else {
+            self.read(|files| candidates(files, &page))
+        };
+        let target = if candidates.len() == 1 {
+            candidates.remove(0)
+        } else {
+            "".to_string()
+        };
+        entity::Resolution {
+            page,
+            target,
+            anchor: if heading.is_empty() {
+                "".to_string()
+            } else {
+                outline::slug(&heading)
+            },
+            candidates,
+        }
+    }
+
+    /// Every link, resolved again only after the index changed.
+    fn all(&self) -> Arc<Vec<Resolved>> {
+        let generation = self.generation();
+        if let Some(resolved) = &*self.resolved.lock().unwrap() {
+            if resolved.generation == generation {
+                return resolved.links.clone();
+            }
+        }
+        let root = self.root();
+        let links = Arc::new(self.read(|files| {
+            let mut result = Vec::new();
+            for document in files.values() {
+                for link in &document.links {
+                    result.push(self.resolved(Path::new(&root), files, link));
+                }
+            }
+            result.sort_by(|a, b| (&a.0.source, a.0.line).cmp(&(&b.0.source, b.0.line)));
+            result
+        }));
+        *self.resolved.lock().unwrap() = Some(ResolvedLinks {
+            generation,
+            links: links.clone(),
+        });
+        links
+    }
+
+    pub fn outgoing(&self, file: &str) -> Vec<entity::Link> {
+        self.all()
+            .iter()
+            .map(|(link, _)| link)
+            .filter(|link| link.source == file)
+            .cloned()
+            .collect()
+    }
+
+    pub fn backlinks(&self, file: &str) -> Vec<entity::Link> {
+        self.all()
+            .iter()
+            .map(|(link, _)| link)
+            .filter(|link| link.target == file && link.source != file)
+            .cloned()
+            .collect()
+    }
+
+    /// Links to missing files, ambiguous wiki-links and anchors with no matching heading.
+    pub fn broken(&self) -> Vec<entity::BrokenLink> {
+        let links = self.all();
+        self.read(|files| {
+            let mut result = Vec::new();
+            for (link, others) in links.iter() {
+                let msg = if link.target.is_empty() {
+                    if others.is_empty() {
+                        "missing file".to_string()
+                    } else {
+                        format!("ambiguous: {}", others.join(", "))
+                    }
+                } else if link.anchor.is_empty() {
+                    continue;
+                } else {
+                    let anchor = if link.type_ == WIKI || link.type_ == EMBED {
+                        outline::slug(&link.anchor)
+                    } else {
+                        link.anchor.clone()
+                    };
+                    match files.get(&link.target) {
+                        Some(document) if !document.anchors.contains(&anchor) => {
+                            format!("missing anchor #{}", link.anchor)
+                        }
+                        _ => continue,
+                    }
+                };
+                result.push(entity::BrokenLink {
+                    link: link.clone(),
+                    msg,
+                });
+            }
+            result
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let content = "# A\n[b](b.md#x) ![i](img/a%20b.png) [w](https://x.y)\n\n`[[no]]` [[Page#Head|label]] ![[Other]]\n";
+        let links = markdown(Path::new("/w/a.md"), content);
+        let found: Vec<(i32, &str, &str)> = links
+            .iter()
+            .map(|l| (l.type_, l.href.as_str(), l.anchor.as_str()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (LINK, "b.md", "x"),
+                (IMAGE, "img/a b.png", ""),
+                (WIKI, "Page", "Head"),
+                (EMBED, "Other", "")
+            ]
+        );
+        assert_eq!(links[2].line, 4);
+    }
+
+    #[test]
+    fn test_candidates() {
+        let mut files = HashMap::new();
+        for (path, content) in [
+            (
+                "/w/guide.md",
+                "---\ntitle: User Guide\naliases: [handbook]\n---\n",
+            ),
+            ("/w/api/guide.md", ""),
+            ("/w/setup.md", ""),
+        ] {
+            files.insert(path.to_string(), Document::new(Path::new(path), content));
+        }
+        assert_eq!(candidates(&files, "Setup"), vec!["/w/setup.md"]);
+        assert_eq!(candidates(&files, "api/guide"), vec!["/w/api/guide.md"]);
+        assert_eq!(candidates(&files, "guide").len(), 2);
+        assert_eq!(candidates(&files, "handbook"), vec!["/w/guide.md"]);
+        assert_eq!(candidates(&files, "user guide"), vec!["/w/guide.md"]);
+    }
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(
+            normalize(Path::new("/w/docs/../img/./a.png")),
+            PathBuf::from("/w/img/a.png")
+        );
+        for (path, normalized) in [
+            ("https://host/dav/a/../b.md", "https://host/dav/b.md"),
+            ("s3://bucket/a/./b.md", "s3://bucket/a/b.md"),
+            ("s3://bucket/../../b.md", "s3://bucket/b.md"),
+            ("s3://bucket", "s3://bucket"),
+        ] {
+            assert_eq!(normalize(Path::new(path)), PathBuf::from(normalized));
+        }
+    }
+
+    #[test]
+    fn test_page_path() {
+        let (root, source) = (Path::new("/w"), Path::new("/w/docs/a.md"));
+        assert_eq!(
+            page_path(root, source, "New page"),
+            Some(PathBuf::from("/w/docs/New page.md"))
+        );
+        assert_eq!(
+            page_path(root, source, "guide/../b.ahtml"),
+            Some(PathBuf::from("/w/b.ahtml"))
+        );
+        assert_eq!(page_path(root, source, "../../x"), None);
+        assert_eq!(page_path(root, source, "guide/../../x"), None);
+    }
+
+    #[test]
+    fn test_resolve_in_storage() {
+        let root = Path::new("/memory/links");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        storage.create_dir(&root.join("docs")).unwrap();
+        storage.write(&root.join("docs/b.md"), b"").unwrap();
+        let source = root.join("a.md");
+        assert_eq!(
+            resolve(root, &source, "docs/b"),
+            Some(root.join("docs/b.md"))
+        );
+        assert_eq!(
+            resolve(root, &root.join("docs/b.md"), "/docs/b.md"),
+            Some(root.join("docs/b.md"))
+        );
+        assert_eq!(resolve(root, &source, "docs/c.md"), None);
+        storage::unmount(root);
+    }
+
+    #[test]
+    fn test_index_resolves_after_changes() {
+        let root = Path::new("/memory/link-index");
+        storage::mount(root, Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        storage
+            .write(&root.join("a.md"), b"![i](img.png) [[b]]\n")
+            .unwrap();
+        let index = LinkIndex::default();
+        index.build(root);
+        let broken: Vec<String> = index.broken().into_iter().map(|b| b.msg).collect();
+        assert_eq!(broken, vec!["missing file", "missing file"]);
+        // the image is not indexed, but its creation still counts as a change
+        storage.write(&root.join("img.png"), b"").unwrap();
+        index.update(&root.join("img.png"));
+        storage.write(&root.join("b.md"), b"# B\n").unwrap();
+        index.update(&root.join("b.md"));
+        assert!(index.broken().is_empty());
+        assert_eq!(index.backlinks("/memory/link-index/b.md").len(), 1);
+        storage.delete(&root.join("b.md")).unwrap();
+        index.remove(&root.join("b.md"));
+        assert_eq!(index.broken().len(), 1);
+        storage::unmount(root);
+    }
+}
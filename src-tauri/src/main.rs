@@ -5,11 +5,10 @@
 
 use std::path::Path;
 
-use hotwatch::{Event, Hotwatch};
 use serde_json;
 use tauri::Manager;
 
-use ahridocs::{api, entity};
+use ahridocs::{api, entity, links, storage, tags};
 
 #[tauri::command]
 async fn close_splashscreen(window: tauri::Window) {
@@ -28,6 +27,10 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tauri::Builder::default()
         .setup(|app| {
             let window = app.get_window("main").unwrap();
+            let tag_index = tags::TagIndex::default();
+            app.manage(tag_index.clone());
+            let link_index = links::LinkIndex::default();
+            app.manage(link_index.clone());
             let (tx, tr) = std::sync::mpsc::channel::<&entity::Message>();
 
             let tx2 = tx.clone();
@@ -36,7 +39,8 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 let open: entity::OpenFolder = serde_json::from_str(payload).unwrap();
                 if open.type_ == 0 {
                     let path = open.path.clone();
-                    if std::path::Path::new(path.as_str()).is_dir() {
+                    let dir = Path::new(path.as_str());
+                    if storage::for_path(dir).is_dir(dir) {
                         tx2.send(Box::leak(Box::new(entity::Message {
                             type_: 100,
                             path: Box::new(path.to_string()),
@@ -62,113 +66,28 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             });
 
             let _ = std::thread::spawn(move || {
-                let mut hotwatch = Hotwatch::new().expect("hotwatch failed to initialize!");
                 let mut old_watch = "".to_string();
                 loop {
                     let txx = tx.clone();
                     let msg = tr.recv().unwrap();
                     if msg.type_ == -100 {
-                        hotwatch.unwatch(old_watch.clone()).unwrap_or(());
+                        let old = Path::new(&old_watch);
+                        storage::for_path(old).unwatch(old).unwrap_or(());
                     } else if msg.type_ == 100 {
-                        hotwatch.unwatch(old_watch).unwrap_or(());
+                        let old = Path::new(&old_watch);
+                        storage::for_path(old).unwatch(old).unwrap_or(());
                         old_watch = msg.path.to_string();
-                        hotwatch
+                        let path = Path::new(msg.path.as_str());
+                        tag_index.build(path);
+                        link_index.build(path);
+                        storage::for_path(path)
                             .watch(
-                                msg.path.as_str().to_string(),
-                                move |event: Event| match event {
-                                    Event::Create(path) => {
-                                        if path.is_dir() {
-                                            txx.send(Box::leak(Box::new(entity::Message {
-                                                type_: 1,
-                                                path: Box::new(path.to_str().unwrap().to_string()),
-                                                path2: Box::new("".to_string()),
-                                            })))
-                                            .unwrap();
-                                        } else {
-                                            let ext = path.extension().unwrap().to_str().unwrap();
-                                            if ext == "md" || ext == "json" {
-                                                txx.send(Box::leak(Box::new(entity::Message {
-                                                    type_: 2,
-                                                    path: Box::new(
-                                                        path.to_str().unwrap().to_string(),
-                                                    ),
-                                                    path2: Box::new("".to_string()),
-                                                })))
-                                                .unwrap();
-                                            }
-                                        }
+                                path,
+                                Box::new(move |change: storage::Change| {
+                                    if let Some(message) = change.message() {
+                                        txx.send(Box::leak(Box::new(message))).unwrap();
                                     }
-                                    Event::Write(path) => {
-                                        if path.is_dir() {
-                                            txx.send(Box::leak(Box::new(entity::Message {
-                                                type_: 3,
-                                                path: Box::new(path.to_str().unwrap().to_string()),
-                                                path2: Box::new("".to_string()),
-                                            })))
-                                            .unwrap();
-                                        } else {
-                                            let ext = path.extension().unwrap().to_str().unwrap();
-                                            if ext == "md" || ext == "json" {
-                                                txx.send(Box::leak(Box::new(entity::Message {
-                                                    type_: 4,
-                                                    path: Box::new(
-                                                        path.to_str().unwrap().to_string(),
-                                                    ),
-                                                    path2: Box::new("".to_string()),
-                                                })))
-                                                .unwrap();
-                                            }
-                                        }
-                                    }
-                                    Event::Remove(path) => {
-                                        if path.is_dir() {
-                                            txx.send(Box::leak(Box::new(entity::Message {
-                                                type_: -1,
-                                                path: Box::new(path.to_str().unwrap().to_string()),
-                                                path2: Box::new("".to_string()),
-                                            })))
-                                            .unwrap();
-                                        } else {
-                                            let path: String = path.to_str().unwrap().to_string();
-                                            if path.ends_with(".md") || path.ends_with(".json") {
-                                                txx.send(Box::leak(Box::new(entity::Message {
-                                                    type_: -2,
-                                                    path: Box::new(path),
-                                                    path2: Box::new("".to_string()),
-                                                })))
-                                                .unwrap();
-                                            }
-                                        }
-                                    }
-                                    Event::Rename(from, to) => {
-                                        if to.is_dir() {
-                                            txx.send(Box::leak(Box::new(entity::Message {
-                                                type_: 5,
-                                                path: Box::new(from.to_str().unwrap().to_string()),
-                                                path2: Box::new(to.to_str().unwrap().to_string()),
-                                            })))
-                                            .unwrap();
-                                        } else {
-                                            let ext = from.extension().unwrap().to_str().unwrap();
-                                            let ext2 = to.extension().unwrap().to_str().unwrap();
-                                            if (ext == "md" && ext2 == "md")
-                                                || (ext == "json" && ext2 == "json")
-                                            {
-                                                txx.send(Box::leak(Box::new(entity::Message {
-                                                    type_: 6,
-                                                    path: Box::new(
-                                                        from.to_str().unwrap().to_string(),
-                                                    ),
-                                                    path2: Box::new(
-                                                        to.to_str().unwrap().to_string(),
-                                                    ),
-                                                })))
-                                                .unwrap();
-                                            }
-                                        }
-                                    }
-                                    _ => {}
-                                },
+                                }),
                             )
                             .expect("failed to watch file!");
                     } else {
@@ -176,6 +95,14 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                         // if startwith \\?\, remove it
                         let p = if p.starts_with("\\\\?\\") { &p[4..] } else { p };
                         let path: &Path = Path::new(p);
+                        let path2 = msg.path2.as_str();
+                        let path2 = path2.strip_prefix("\\\\?\\").unwrap_or(path2);
+                        tag_index.changed(msg.type_, path.to_str().unwrap(), path2);
+                        link_index.changed(msg.type_, path.to_str().unwrap(), path2);
+                        api::forget_front_matter(path);
+                        if !path2.is_empty() {
+                            api::forget_front_matter(Path::new(path2));
+                        }
                         window
                             .emit(
                                 "file-system-changed",
@@ -201,6 +128,50 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             api::read,
             api::reads,
             api::write,
+            api::update_front_matter,
+            api::query,
+            api::list_tags,
+            api::tag_files,
+            api::rename_tag,
+            api::merge_tags,
+            api::outline,
+            api::move_section,
+            api::move_section_to,
+            api::shift_section,
+            api::extract_section,
+            api::history,
+            api::backlinks,
+            api::outgoing_links,
+            api::broken_links,
+            api::resolve_link,
+            api::create_page,
+            api::expand_embeds,
+            api::render_markdown,
+            api::render_file,
+            api::highlight_languages,
+            api::highlight_themes,
+            api::highlight_css,
+            api::convert,
+            api::convert_folder,
+            api::confirm_convert,
+            api::get_allowlist,
+            api::set_allowlist,
+            api::export_site,
+            api::export_pdf,
+            api::export_epub,
+            api::export_project,
+            api::import_docx,
+            api::export_docx,
+            api::import_notes,
+            api::export_outline,
+            api::import_opml,
+            api::backup_workspace,
+            api::restore_workspace,
+            api::extract_archive,
+            api::open_webdav,
+            api::close_webdav,
+            api::open_bucket,
+            api::close_bucket,
             api::get_config,
             api::set_config,
         ])
@@ -0,0 +1,623 @@
+use std::{
+    collections::{HashMap, HashSet},
+    io::{Cursor, Read, Seek},
+    path::Path,
+};
+
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, LinkType, Parser, Tag};
+use regex::Regex;
+use zip::ZipArchive;
+
+use crate::{entity, links, outline, storage};
+
+/// Notion appends the page id to every exported file and folder name.
+static NOTION_ID: Lazy<Regex> = Lazy::new(|| Regex::new(r"^(.+?) ?[0-9a-f]{32}(_all)?$").unwrap());
+
+/// Folders of a vault that are not notes.
+const SKIP: [&str; 4] = [".obsidian", ".trash", ".git", "__MACOSX"];
+
+/// Where attachments go, below the imported folder.
+const ASSETS: &str = "assets";
+
+/// A file of the export, by its path inside it joined with `/`.
+struct Entry {
+    path: String,
+    data: Vec<u8>,
+}
+
+fn folder_entries(dir: &Path, prefix: &str, out: &mut Vec<Entry>) -> Result<(), String> {
+    let entries = std::fs::read_dir(dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+    for entry in entries.flatten() {
+        let name = entry.file_name().to_string_lossy().to_string();
+        if SKIP.contains(&name.as_str()) {
+            continue;
+        }
+        let path = format!("{}{}", prefix, name);
+        if entry.path().is_dir() {
+            folder_entries(&entry.path(), &format!("{}/", path), out)?;
+        } else {
+            let data = std::fs::read(entry.path()).map_err(|e| e.to_string())?;
+            out.push(Entry { path, data });
+        }
+    }
+    Ok(())
+}
+
+/// The files of a zip archive; archives inside it, which Notion uses to split large
+/// exports, are unpacked in place.
+fn zip_entries<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    out: &mut Vec<Entry>,
+) -> Result<(), String> {
+    for i in 0..archive.len() {
+        let mut file = archive.by_index(i).map_err(|e| e.to_string())?;
+        if file.is_dir() {
+            continue;
+        }
+        let path = file.name().replace('\\', "/");
+        if path.split('/').any(|part| SKIP.contains(&part)) {
+            continue;
+        }
+        let mut data = Vec::new();
+        file.read_to_end(&mut data).map_err(|e| e.to_string())?;
+        if path.to_lowercase().ends_with(".zip") {
+            let mut inner =
+                ZipArchive::new(Cursor::new(data)).map_err(|e| format!("{}: {}", path, e))?;
+            zip_entries(&mut inner, out)?;
+        } else {
+            out.push(Entry { path, data });
+        }
+    }
+    Ok(())
+}
+
+fn extension(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => ext.to_lowercase(),
+        _ => "".to_string(),
+    }
+}
+
+/// A file or folder name without characters file systems reject.
+pub fn clean(name: &str) -> String {
+    imported(name, false)
+}
+
+/// Like `clean`, and for a Notion export also without the page id. Names in an Obsidian
+/// vault may end in hex digits of their own.
+fn imported(name: &str, notion: bool) -> String {
+    let (stem, ext) = match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() && !ext.contains(' ') => (stem, Some(ext)),
+        _ => (name, None),
+    };
+    let stem = match NOTION_ID.captures(stem) {
+        Some(cap) if notion => cap.get(1).unwrap().as_str(),
+        _ => stem,
+    };
+    let stem: String = stem
+        .chars()
+        .map(|c| {
+            if matches!(c, '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() {
+                '-'
+            } else {
+                c
+            }
+        })
+        .collect();
+    let stem = stem.trim().trim_end_matches('.');
+    let stem = if stem.is_empty() { "untitled" } else { stem };
+    match ext {
+        Some(ext) => format!("{}.{}", stem, ext),
+        None => stem.to_string(),
+    }
+}
+
+/// Joins a relative link onto the folder it was written in, resolving `.` and `..`.
+fn join(dir: &str, href: &str) -> String {
+    let mut parts: Vec<&str> = dir.split('/').filter(|p| !p.is_empty()).collect();
+    for part in href.split('/') {
+        match part {
+            "" | "." => {}
+            ".." => {
+                parts.pop();
+            }
+            part => parts.push(part),
+        }
+    }
+    parts.join("/")
+}
+
+fn parent(path: &str) -> &str {
+    path.rsplit_once('/').map_or("", |(dir, _)| dir)
+}
+
+/// Encodes what would end a Markdown link destination early.
+fn encode(href: &str) -> String {
+    href.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+        .replace('#', "%23")
+}
+
+/// Splits CSV text into rows of fields, quotes and doubled quotes included.
+fn csv(text: &str) -> Vec<Vec<String>> {
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = text.trim_start_matches('\u{feff}').chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => row.push(std::mem::take(&mut field)),
+            '\r' if !quoted => {}
+            '\n' if !quoted => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+            }
+            c => field.push(c),
+        }
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+    rows
+}
+
+/// Imports an Obsidian vault or a Notion export into a workspace folder.
+struct Migration {
+    /// New path of every file, by its path in the export.
+    paths: HashMap<String, String>,
+    /// Files left out as duplicates, by their path in the export, with the path of the
+    /// file kept instead.
+    aliases: HashMap<String, String>,
+    /// New paths of the attachments, by lowercase file name, for Obsidian's `![[image.png]]`.
+    attachments: HashMap<String, Vec<String>>,
+    /// New paths of the documents without extension, lowercase, for wiki-links.
+    documents: Vec<String>,
+    /// Whether the source is a Notion export, whose names carry page ids.
+    notion: bool,
+    report: entity::Migration,
+}
+
+impl Migration {
+    /// Picks the new path of every entry: Notion ids stripped, attachments moved below
+    /// `assets/`, Notion databases turned into Markdown documents.
+    fn plan(&mut self, entries: &[Entry]) {
+        let mut taken: HashSet<String> = HashSet::new();
+        let mut databases: HashMap<String, String> = HashMap::new();
+        let mut sorted: Vec<&Entry> = entries.iter().collect();
+        // documents first so they keep their names, and the complete `_all` database
+        // exports before the partial ones
+        sorted.sort_by_key(|entry| {
+            let ext = extension(&entry.path);
+            (
+                ext != "md",
+                ext == "csv" && !entry.path.ends_with("_all.csv"),
+                entry.path.clone(),
+            )
+        });
+        for entry in sorted {
+            let ext = extension(&entry.path);
+            let parts: Vec<String> = entry
+                .path
+                .split('/')
+                .map(|part| imported(part, self.notion))
+                .collect();
+            let mut path = parts.join("/");
+            match ext.as_str() {
+                "md" => {}
+                "csv" => {
+                    path = format!("{}.md", &path[..path.len() - 4]);
+                    if let Some(kept) = databases.get(&path.to_lowercase()) {
+                        // the other export of the same database
+                        self.aliases.insert(entry.path.clone(), kept.clone());
+                        continue;
+                    }
+                }
+                _ => path = format!("{}/{}", ASSETS, path),
+            }
+            let (stem, suffix) = match path.rsplit_once('.') {
+                Some((stem, ext)) if !ext.contains('/') => (stem.to_string(), format!(".{}", ext)),
+                _ => (path.clone(), "".to_string()),
+            };
+            let mut n = 1;
+            while taken.contains(&path.to_lowercase()) {
+                n += 1;
+                path = format!("{} {}{}", stem, n, suffix);
+            }
+            taken.insert(path.to_lowercase());
+            if ext == "csv" {
+                databases.insert(
+                    parts.join("/").to_lowercase().replace(".csv", ".md"),
+                    path.clone(),
+                );
+            }
+            let name = |path: &str| path.rsplit('/').next().unwrap_or(path).to_string();
+            if name(&entry.path) != name(&path) && !path.starts_with(&format!("{}/", ASSETS)) {
+                self.report
+                    .renamed
+                    .push(format!("{} → {}", entry.path, path));
+            }
+            if path.ends_with(".md") {
+                self.documents.push(path[..path.len() - 3].to_lowercase());
+            } else {
+                self.attachments
+                    .entry(name(&entry.path).to_lowercase())
+                    .or_default()
+                    .push(path.clone());
+            }
+            self.paths.insert(entry.path.clone(), path);
+        }
+    }
+
+    /// The new path of what a relative link written in `source` points at.
+    fn target(&self, source: &str, href: &str) -> Option<String> {
+        let (path, _) = links::split(href);
+        let path = join(parent(source), &path);
+        let md = format!("{}.md", path);
+        // links may leave out the extension
+        [&path, &md]
+            .iter()
+            .find_map(|path| self.paths.get(*path).or_else(|| self.aliases.get(*path)))
+            .cloned()
+    }
+
+    /// Points the relative links of a Notion page at the renamed files.
+    fn markdown_links(&mut self, source: &str, target: &str, content: &str) -> String {
+        let mut edits = Vec::new();
+        for (event, range) in Parser::new_ext(content, outline::options()).into_offset_iter() {
+            let dest = match event {
+                Event::Start(Tag::Link(LinkType::Inline, dest, _)) => dest,
+                Event::Start(Tag::Image(LinkType::Inline, dest, _)) => dest,
+                _ => continue,
+            };
+            if dest.is_empty() || dest.starts_with('#') || links::external(&dest) {
+                continue;
+            }
+            let text = &content[range.clone()];
+            let (start, len) = match text.rfind(&format!("](<{}>", dest)) {
+                Some(i) => (range.start + i + 2, dest.len() + 2),
+                None => match text.rfind(&format!("]({}", dest)) {
+                    Some(i) => (range.start + i + 2, dest.len()),
+                    None => continue,
+                },
+            };
+            match self.target(source, &dest) {
+                Some(new) => {
+                    let anchor = dest.find('#').map_or("", |i| &dest[i..]);
+                    let href = links::relative(Path::new(parent(target)), Path::new(&new));
+                    edits.push((start..start + len, format!("{}{}", encode(&href), anchor)));
+                }
+                None => self
+                    .report
+                    .warnings
+                    .push(format!("{}: broken link {}", source, dest)),
+            }
+        }
+        self.report.links += edits.len() as i32;
+        let mut result = content.to_string();
+        for (range, href) in edits.into_iter().rev() {
+            result.replace_range(range, &href);
+        }
+        result
+    }
+
+    /// Turns Obsidian embeds and wiki-links of attachments into Markdown images and links,
+    /// which is how the workspace refers to files that are not documents. Wiki-links and
+    /// embeds of documents already work and stay, apart from a check that they resolve.
+    fn wiki_links(&mut self, source: &str, target: &str, content: &str) -> String {
+        let mut result = String::new();
+        let mut last = 0;
+        for (offset, line) in outline::prose(content) {
+            for cap in links::WIKI_LINK.captures_iter(line) {
+                let m = cap.get(0).unwrap();
+                if outline::in_code_span(line, m.start()) {
+                    continue;
+                }
+                let inner = &cap[2];
+                let (page, _) = links::wiki(inner);
+                if page.is_empty() {
+                    continue;
+                }
+                let page = page.replace('\\', "/");
+                let lower = page.to_lowercase();
+                let ext = extension(&page);
+                if ext.is_empty() || ext == "md" {
+                    let stem = lower.strip_suffix(".md").unwrap_or(&lower);
+                    let suffix = format!("/{}", stem);
+                    let found = self.documents.iter().any(|doc| {
+                        doc == stem || doc.ends_with(&suffix) || imported(stem, self.notion) == *doc
+                    });
+                    if !found {
+                        self.report
+                            .warnings
+                            .push(format!("{}: unresolved wiki-link [[{}]]", source, inner));
+                    }
+                    continue;
+                }
+                let name = lower.rsplit('/').next().unwrap_or(&lower).to_string();
+                let candidates = self.attachments.get(&name).cloned().unwrap_or_default();
+                let path = match candidates.len() {
+                    0 => {
+                        self.report
+                            .warnings
+                            .push(format!("{}: missing attachment {}", source, page));
+                        continue;
+                    }
+                    1 => candidates[0].clone(),
+                    // Obsidian takes the one the path points at, or the closest one
+                    _ => candidates
+                        .iter()
+                        .find(|c| c.to_lowercase().ends_with(&lower))
+                        .unwrap_or(&candidates[0])
+                        .clone(),
+                };
+                let href = encode(&links::relative(
+                    Path::new(parent(target)),
+                    Path::new(&path),
+                ));
+                // `![[image.png|300]]` sets a width, which plain Markdown has no room for
+                let label = match inner.split_once('|') {
+                    Some((_, label))
+                        if !label.trim().chars().all(|c| c.is_ascii_digit() || c == 'x') =>
+                    {
+                        label.trim().to_string()
+                    }
+                    _ => name.clone(),
+                };
+                let replacement = if &cap[1] == "!" {
+                    format!("![{}]({})", label, href)
+                } else {
+                    format!("[{}]({})", label, href)
+                };
+                result.push_str(&content[last..offset + m.start()]);
+                result.push_str(&replacement);
+                last = offset + m.end();
+                self.report.links += 1;
+            }
+        }
+        result.push_str(&content[last..]);
+        result
+    }
+
+    /// A Notion database as a Markdown table, the first column linking to the row pages.
+    fn database(&self, source: &str, target: &str, text: &str) -> String {
+        let rows = csv(text);
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return String::new();
+        }
+        let cell = |text: &str| text.trim().replace('|', "\\|").replace('\n', "<br>");
+        // row pages are in a folder named like the database
+        let name = source.rsplit('/').next().unwrap_or(source);
+        let pages = imported(&name[..name.len() - 4], self.notion);
+        let title = target
+            .rsplit('/')
+            .next()
+            .unwrap_or(target)
+            .trim_end_matches(".md");
+        let mut lines = vec![format!("# {}\n", title)];
+        for (i, row) in rows.iter().enumerate() {
+            let mut cells: Vec<String> = row.iter().map(|c| cell(c)).collect();
+            cells.resize(columns, String::new());
+            if i > 0 {
+                // the page of a row is named after its first column
+                let page = format!("{}/{}.md", pages, imported(row[0].trim(), self.notion));
+                let page = join(parent(target), &page).to_lowercase();
+                if let Some(new) = self.paths.values().find(|p| p.to_lowercase() == page) {
+                    let href = links::relative(Path::new(parent(target)), Path::new(new));
+                    cells[0] = format!("[{}]({})", cells[0], encode(&href));
+                }
+            }
+            lines.push(format!("| {} |", cells.join(" | ")));
+            if i == 0 {
+                lines.push(format!("|{}", " --- |".repeat(columns)));
+            }
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+/// Imports the Obsidian vault or the Notion export (folder or `.zip`) at `source` into a
+/// new folder below `folder`, named after it.
+pub fn import(source: &Path, folder: &Path) -> Result<entity::Migration, String> {
+    let mut entries = Vec::new();
+    if source.is_dir() {
+        folder_entries(source, "", &mut entries)?;
+    } else {
+        let file = std::fs::File::open(source).map_err(|e| e.to_string())?;
+        let mut archive = ZipArchive::new(file).map_err(|e| format!("not a zip archive: {}", e))?;
+        zip_entries(&mut archive, &mut entries)?;
+    }
+    // a vault keeps its settings in `.obsidian`, whatever its file names look like
+    let vault = source.join(".obsidian").is_dir();
+    let notion = !vault
+        && entries.iter().any(|entry| {
+            entry.path.split('/').any(|part| {
+                NOTION_ID.is_match(part.rsplit_once('.').map_or(part, |(stem, _)| stem))
+            })
+        });
+    let kind = if notion { "notion" } else { "obsidian" };
+    let name = source
+        .file_stem()
+        .map(|stem| imported(&stem.to_string_lossy(), notion))
+        .unwrap_or_else(|| "import".to_string());
+    let target = folder.join(&name);
+    let storage = storage::for_path(&target);
+    if storage.exists(&target) {
+        return Err(format!("{} already exists", target.display()));
+    }
+
+    let mut migration = Migration {
+        paths: HashMap::new(),
+        aliases: HashMap::new(),
+        attachments: HashMap::new(),
+        documents: Vec::new(),
+        notion,
+        report: entity::Migration {
+            source: source.to_str().unwrap().to_string(),
+            target: target.to_str().unwrap().to_string(),
+            kind: kind.to_string(),
+            documents: 0,
+            attachments: 0,
+            links: 0,
+            renamed: Vec::new(),
+            warnings: Vec::new(),
+        },
+    };
+    migration.plan(&entries);
+    for entry in &entries {
+        let path = match migration.paths.get(&entry.path) {
+            Some(path) => path.clone(),
+            None => continue,
+        };
+        let data = match extension(&entry.path).as_str() {
+            "md" => {
+                let content = String::from_utf8_lossy(&entry.data).to_string();
+                let content = migration.markdown_links(&entry.path, &path, &content);
+                let content = migration.wiki_links(&entry.path, &path, &content);
+                migration.report.documents += 1;
+                content.into_bytes()
+            }
+            "csv" => {
+                let text = String::from_utf8_lossy(&entry.data);
+                migration.report.documents += 1;
+                migration.database(&entry.path, &path, &text).into_bytes()
+            }
+            _ => {
+                migration.report.attachments += 1;
+                entry.data.clone()
+            }
+        };
+        let file = target.join(&path);
+        storage
+            .create_dir_all(file.parent().unwrap())
+            .and_then(|_| storage.write(&file, &data))
+            .map_err(|e| format!("{}: {}", file.display(), e))?;
+    }
+    Ok(migration.report)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_clean() {
+        let hashed = "Page 0123456789abcdef0123456789abcdef.md";
+        assert_eq!(imported(hashed, true), "Page.md");
+        assert_eq!(clean(hashed), hashed);
+        assert_eq!(
+            imported("DB 0123456789abcdef0123456789abcdef_all.csv", true),
+            "DB.csv"
+        );
+        assert_eq!(clean("a: b?.md"), "a- b-.md");
+    }
+
+    #[test]
+    fn test_obsidian() {
+        let temp = TempDir::new("migrate-obsidian");
+        let dir = temp.join("migrate-obsidian");
+        let vault = dir.join("Vault");
+        std::fs::create_dir_all(vault.join(".obsidian")).unwrap();
+        std::fs::create_dir_all(vault.join("notes")).unwrap();
+        std::fs::create_dir_all(vault.join("files")).unwrap();
+        std::fs::write(vault.join(".obsidian/app.json"), "{}").unwrap();
+        std::fs::write(vault.join("files/pic.png"), b"png").unwrap();
+        std::fs::write(
+            vault.join("notes/a.md"),
+            "![[pic.png|300]] [[b]] [[gone]] `![[pic.png]]`\n",
+        )
+        .unwrap();
+        std::fs::write(vault.join("b.md"), "# B\n").unwrap();
+        let log = "Log 0123456789abcdef0123456789abcdef.md";
+        std::fs::write(vault.join(log), "").unwrap();
+        let workspace = dir.join("workspace");
+        std::fs::create_dir_all(&workspace).unwrap();
+        let report = import(&vault, &workspace).unwrap();
+        assert_eq!(report.kind, "obsidian");
+        assert_eq!(
+            (report.documents, report.attachments, report.links),
+            (3, 1, 1)
+        );
+        assert!(workspace.join("Vault").join(log).exists());
+        assert_eq!(
+            std::fs::read_to_string(workspace.join("Vault/notes/a.md")).unwrap(),
+            "![pic.png](../assets/files/pic.png) [[b]] [[gone]] `![[pic.png]]`\n"
+        );
+        assert!(workspace.join("Vault/assets/files/pic.png").exists());
+        assert!(!workspace.join("Vault/.obsidian").exists());
+        assert_eq!(
+            report.warnings,
+            vec!["notes/a.md: unresolved wiki-link [[gone]]"]
+        );
+    }
+
+    #[test]
+    fn test_notion() {
+        let temp = TempDir::new("migrate-notion");
+        let dir = temp.join("migrate-notion");
+        std::fs::create_dir_all(&dir).unwrap();
+        let id = "0123456789abcdef0123456789abcdef";
+        let other = "fedcba9876543210fedcba9876543210";
+        let zip_path = dir.join("Export.zip");
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path).unwrap());
+        let mut add = |name: &str, data: &[u8]| {
+            zip.start_file(name, zip::write::FileOptions::default())
+                .unwrap();
+            zip.write_all(data).unwrap();
+        };
+        add(
+            &format!("Home {}.md", id),
+            format!(
+                "# Home\n\n[Tasks](Tasks%20{other}.csv) ![](Home%20{id}/shot.png)\n",
+                id = id,
+                other = other
+            )
+            .as_bytes(),
+        );
+        add(&format!("Home {}/shot.png", id), b"png");
+        add(
+            &format!("Tasks {}.csv", other),
+            b"\xef\xbb\xbfName,Status\nWrite docs,\"Done, mostly\"\n",
+        );
+        add(
+            &format!("Tasks {}_all.csv", other),
+            b"\xef\xbb\xbfName,Status\nWrite docs,\"Done, mostly\"\n",
+        );
+        add(
+            &format!("Tasks {}/Write docs {}.md", other, id),
+            b"# Write docs\n",
+        );
+        zip.finish().unwrap();
+
+        let workspace = dir.join("workspace");
+        std::fs::create_dir_all(&workspace).unwrap();
+        let report = import(&zip_path, &workspace).unwrap();
+        assert_eq!(report.kind, "notion");
+        assert_eq!((report.documents, report.attachments), (3, 1));
+        assert!(report.warnings.is_empty());
+        let root = workspace.join("Export");
+        assert_eq!(
+            std::fs::read_to_string(root.join("Home.md")).unwrap(),
+            "# Home\n\n[Tasks](Tasks.md) ![](assets/Home/shot.png)\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(root.join("Tasks.md")).unwrap(),
+            "# Tasks\n\n| Name | Status |\n| --- | --- |\n| [Write docs](Tasks/Write%20docs.md) | Done, mostly |\n"
+        );
+        assert!(root.join("Tasks/Write docs.md").exists());
+    }
+}
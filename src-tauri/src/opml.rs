@@ -0,0 +1,370 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use roxmltree::Node;
+
+use crate::{
+    ahtml, api, entity, export,
+    links::{self, Document},
+    migrate, outline, storage,
+};
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\n', "&#10;")
+}
+
+/// The file tree of `folder` with every folder kept, folders first and then by name.
+fn tree(folder: &Path) -> Result<Vec<entity::FileTree>, String> {
+    fn sort(tree: &mut [entity::FileTree]) {
+        for node in tree.iter_mut() {
+            if let Some(children) = node.children.as_mut() {
+                sort(children);
+            }
+        }
+        tree.sort_by(|a, b| {
+            (a.type_ != 0, a.name.to_lowercase()).cmp(&(b.type_ != 0, b.name.to_lowercase()))
+        });
+    }
+    let mut tree = api::read_dir(folder, 0).map_err(|e| e.to_string())?;
+    sort(&mut tree);
+    Ok(tree)
+}
+
+/// A node of the outline: a folder, a document or a heading inside one.
+struct Item {
+    text: String,
+    /// Path below the outlined folder, with the heading anchor for headings.
+    url: Option<String>,
+    children: Vec<Item>,
+}
+
+fn headings(href: &str, headings: Vec<entity::Heading>) -> Vec<Item> {
+    headings
+        .into_iter()
+        .map(|heading| Item {
+            url: Some(format!("{}#{}", href, heading.slug)),
+            text: heading.text,
+            children: self::headings(href, heading.children),
+        })
+        .collect()
+}
+
+fn items(
+    folder: &Path,
+    tree: Vec<entity::FileTree>,
+    files: &HashMap<String, Document>,
+    with_headings: bool,
+) -> Result<Vec<Item>, String> {
+    tree.into_iter()
+        .map(|node| {
+            let path = PathBuf::from(&node.path);
+            Ok(match node.children {
+                Some(children) => Item {
+                    text: node.name,
+                    url: None,
+                    children: items(folder, children, files, with_headings)?,
+                },
+                None => {
+                    let href = links::relative(folder, &path);
+                    let children = if with_headings {
+                        let content = storage::for_path(&path)
+                            .read_to_string(&path)
+                            .map_err(|e| format!("{}: {}", path.display(), e))?;
+                        headings(
+                            &href,
+                            outline::nest(outline::headings(&node.path, &content)),
+                        )
+                    } else {
+                        Vec::new()
+                    };
+                    Item {
+                        text: export::title(&path, files.get(&node.path)),
+                        url: Some(href),
+                        children,
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+fn opml(items: &[Item], depth: usize, out: &mut String) {
+    let indent = "  ".repeat(depth + 2);
+    for item in items {
+        out.push_str(&format!(
+            "{}<outline text=\"{}\"",
+            indent,
+            escape(&item.text)
+        ));
+        if let Some(url) = &item.url {
+            out.push_str(&format!(" type=\"link\" url=\"{}\"", escape(url)));
+        }
+        if item.children.is_empty() {
+            out.push_str("/>\n");
+        } else {
+            out.push_str(">\n");
+            opml(&item.children, depth + 1, out);
+            out.push_str(&format!("{}</outline>\n", indent));
+        }
+    }
+}
+
+fn markdown(items: &[Item], depth: usize, out: &mut String) {
+    for item in items {
+        let text = item.text.replace('[', "\\[").replace(']', "\\]");
+        let line = match &item.url {
+            Some(url) => format!("[{}](<{}>)", text, url),
+            // a trailing slash tells folders apart
+            None => format!("{}/", text),
+        };
+        out.push_str(&format!("{}- {}\n", "  ".repeat(depth), line));
+        markdown(&item.children, depth + 1, out);
+    }
+}
+
+/// The file tree of `folder`, and with `with_headings` the heading outline of every
+/// document, as OPML (`format` `opml`) or as a nested Markdown list (`markdown`).
+pub fn export(
+    folder: &Path,
+    format: &str,
+    with_headings: bool,
+    files: &HashMap<String, Document>,
+) -> Result<String, String> {
+    let title = folder
+        .file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let items = items(folder, tree(folder)?, files, with_headings)?;
+    let mut out = String::new();
+    match format {
+        "opml" => {
+            out.push_str(&format!(
+                "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n  <head>\n    <title>{}</title>\n  </head>\n  <body>\n",
+                escape(&title)
+            ));
+            opml(&items, 0, &mut out);
+            out.push_str("  </body>\n</opml>\n");
+        }
+        "markdown" => {
+            out.push_str(&format!("# {}\n\n", title));
+            markdown(&items, 0, &mut out);
+        }
+        _ => return Err(format!("unknown outline format {}", format)),
+    }
+    Ok(out)
+}
+
+/// `name` as the name of a single file or folder in it: separators are replaced, and names
+/// that would lead out of the folder are rejected.
+fn component(name: &str) -> Result<String, String> {
+    let name = name.replace(|c| c == '/' || c == '\\', "-");
+    if matches!(name.trim(), "" | "." | "..") {
+        return Err(format!("{}: not a valid file name", name));
+    }
+    Ok(migrate::clean(&name))
+}
+
+/// `name` joined to `dir`, which it must stay inside of.
+fn inside(dir: &Path, name: &str) -> Result<PathBuf, String> {
+    let path = dir.join(name);
+    if !path.starts_with(dir) || path.components().count() != dir.components().count() + 1 {
+        return Err(format!("{}: not a valid file name", name));
+    }
+    Ok(path)
+}
+
+/// Creates what an OPML outline plans below `dir`: outlines with children become folders
+/// and the others placeholder documents, unless they link to a document, whose children
+/// then become its headings. Returns the paths created and those that already existed.
+fn scaffold(
+    node: Node,
+    dir: &Path,
+    type_: i32,
+    result: &mut entity::Scaffold,
+) -> Result<(), String> {
+    let ext = if type_ == 2 { "ahtml" } else { "md" };
+    for outline in node.children().filter(|n| n.has_tag_name("outline")) {
+        let text = outline
+            .attribute("text")
+            .or_else(|| outline.attribute("title"))
+            .unwrap_or("")
+            .trim();
+        if text.is_empty() {
+            continue;
+        }
+        let children: Vec<Node> = outline
+            .children()
+            .filter(|n| n.has_tag_name("outline"))
+            .collect();
+        let url = outline.attribute("url").unwrap_or("");
+        let (path, _) = links::split(url);
+        let document = path.ends_with(".md") || path.ends_with(".ahtml");
+        if !children.is_empty() && !document {
+            let folder = inside(dir, &component(text)?)?;
+            let storage = storage::for_path(&folder);
+            if storage.is_dir(&folder) {
+                result.skipped.push(folder.to_str().unwrap().to_string());
+            } else {
+                storage.create_dir_all(&folder).map_err(|e| e.to_string())?;
+                result.created.push(folder.to_str().unwrap().to_string());
+            }
+            scaffold(outline, &folder, type_, result)?;
+            continue;
+        }
+        // the name the document had when the outline was exported, when there is one
+        let name = match Path::new(&path).file_stem().filter(|_| document) {
+            Some(stem) => component(&stem.to_string_lossy())?,
+            None => component(text)?,
+        };
+        let file = inside(dir, &format!("{}.{}", name, ext))?;
+        let storage = storage::for_path(&file);
+        if storage.exists(&file) {
+            result.skipped.push(file.to_str().unwrap().to_string());
+            continue;
+        }
+        let mut body = String::new();
+        if type_ == 2 {
+            body.push_str(&format!("<h1>{}</h1>", escape(text)));
+            if let Some(note) = outline.attribute("_note") {
+                body.push_str(&format!("<p>{}</p>", escape(note.trim())));
+            }
+            sections(&children, 2, true, &mut body);
+        } else {
+            body.push_str(&format!("# {}\n", text));
+            if let Some(note) = outline.attribute("_note") {
+                body.push_str(&format!("\n{}\n", note.trim()));
+            }
+            sections(&children, 2, false, &mut body);
+        }
+        let content = if type_ == 2 {
+            let mut header = ahtml::legacy(&file, &body);
+            header.title = text.to_string();
+            ahtml::to_string(&header, &body)
+        } else {
+            body
+        };
+        storage
+            .write(&file, content.as_bytes())
+            .map_err(|e| e.to_string())?;
+        result.created.push(file.to_str().unwrap().to_string());
+    }
+    Ok(())
+}
+
+/// Headings for the children of a document outline.
+fn sections(outlines: &[Node], level: usize, html: bool, body: &mut String) {
+    for outline in outlines {
+        let text = outline.attribute("text").unwrap_or("").trim();
+        if text.is_empty() {
+            continue;
+        }
+        let level = level.min(6);
+        if html {
+            body.push_str(&format!("<h{}>{}</h{}>", level, escape(text), level));
+        } else {
+            body.push_str(&format!("\n{} {}\n", "#".repeat(level), text));
+        }
+        let children: Vec<Node> = outline
+            .children()
+            .filter(|n| n.has_tag_name("outline"))
+            .collect();
+        sections(&children, level + 1, html, body);
+    }
+}
+
+/// Scaffolds the folders and documents the OPML outline `opml` plans below `folder`, as
+/// documents of `type_` (1 Markdown, 2 `.ahtml`). Existing files are left alone.
+pub fn import(opml: &str, folder: &Path, type_: i32) -> Result<entity::Scaffold, String> {
+    let doc = roxmltree::Document::parse(opml).map_err(|e| format!("not an OPML file: {}", e))?;
+    let body = doc
+        .descendants()
+        .find(|n| n.has_tag_name("body"))
+        .ok_or_else(|| "not an OPML file: the body is missing".to_string())?;
+    let mut result = entity::Scaffold {
+        created: Vec::new(),
+        skipped: Vec::new(),
+    };
+    scaffold(body, folder, type_, &mut result)?;
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_round_trip() {
+        let temp = TempDir::new("opml");
+        let dir = temp.join("opml");
+        std::fs::create_dir_all(dir.join("a/guide")).unwrap();
+        std::fs::write(
+            dir.join("a/guide/setup.md"),
+            "# Setup & run\n\n## Install\n",
+        )
+        .unwrap();
+        std::fs::write(dir.join("a/intro.md"), "intro\n").unwrap();
+        let files = HashMap::new();
+        let opml = export(&dir.join("a"), "opml", true, &files).unwrap();
+        assert_eq!(
+            opml,
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<opml version=\"2.0\">\n  <head>\n    <title>a</title>\n  </head>\n  <body>\n\
+             \x20   <outline text=\"guide\">\n\
+             \x20     <outline text=\"Setup &amp; run\" type=\"link\" url=\"guide/setup.md\">\n\
+             \x20       <outline text=\"Setup &amp; run\" type=\"link\" url=\"guide/setup.md#setup--run\">\n\
+             \x20         <outline text=\"Install\" type=\"link\" url=\"guide/setup.md#install\"/>\n\
+             \x20       </outline>\n\
+             \x20     </outline>\n\
+             \x20   </outline>\n\
+             \x20   <outline text=\"intro\" type=\"link\" url=\"intro.md\"/>\n\
+             \x20 </body>\n</opml>\n"
+        );
+        assert_eq!(
+            export(&dir.join("a"), "markdown", false, &files).unwrap(),
+            "# a\n\n- guide/\n  - [Setup & run](<guide/setup.md>)\n- [intro](<intro.md>)\n"
+        );
+
+        std::fs::create_dir_all(dir.join("b")).unwrap();
+        let plan = "<opml version=\"2.0\"><body>\
+            <outline text=\"guide\"><outline text=\"Setup &amp; run\" type=\"link\" url=\"guide/setup.md\">\
+            <outline text=\"Install\"/></outline></outline>\
+            <outline text=\"FAQ?\" _note=\"todo\"/></body></opml>";
+        let result = import(plan, &dir.join("b"), 1).unwrap();
+        assert_eq!(result.created.len(), 3);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("b/guide/setup.md")).unwrap(),
+            "# Setup & run\n\n## Install\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("b/FAQ-.md")).unwrap(),
+            "# FAQ?\n\ntodo\n"
+        );
+        let result = import(plan, &dir.join("b"), 1).unwrap();
+        assert_eq!((result.created.len(), result.skipped.len()), (0, 3));
+
+        // names are single path components that stay inside the folder
+        std::fs::create_dir_all(dir.join("c")).unwrap();
+        let plan = "<opml version=\"2.0\"><body><outline text=\"A/B\"/></body></opml>";
+        import(plan, &dir.join("c"), 1).unwrap();
+        assert!(dir.join("c/A-B.md").is_file());
+        for plan in [
+            "<opml version=\"2.0\"><body><outline text=\"..\"><outline text=\"x\"/></outline></body></opml>",
+            "<opml version=\"2.0\"><body><outline text=\"x\" url=\"../...md\"/></body></opml>",
+        ] {
+            assert!(import(plan, &dir.join("c"), 1).is_err());
+        }
+        let plan = "<opml version=\"2.0\"><body><outline text=\"../../x\"/></body></opml>";
+        let result = import(plan, &dir.join("c"), 1).unwrap();
+        assert_eq!(
+            Path::new(&result.created[0]).parent(),
+            Some(&*dir.join("c"))
+        );
+        assert!(!dir.join("x.md").exists());
+    }
+}
@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+
+use once_cell::sync::Lazy;
+use pulldown_cmark::{Event, Options, Parser, Tag};
+use regex::Regex;
+
+use crate::{entity, frontmatter};
+
+/// Markdown extensions shared by every parser in the backend.
+pub fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_HEADING_ATTRIBUTES
+}
+
+/// Turns heading text into an anchor the same way the exported HTML does.
+pub fn slug(text: &str) -> String {
+    let mut result = String::new();
+    for c in text.trim().chars() {
+        if c.is_alphanumeric() || c == '_' || c == '-' {
+            result.extend(c.to_lowercase());
+        } else if c.is_whitespace() {
+            result.push('-');
+        }
+    }
+    result
+}
+
+/// Lines of a Markdown body that are not code blocks, with their byte offsets.
+pub fn prose(body: &str) -> Vec<(usize, &str)> {
+    let mut result = Vec::new();
+    let mut offset = 0;
+    let mut fence: Option<&str> = None;
+    for line in body.split_inclusive('\n') {
+        let trimmed = line.trim_start();
+        if let Some(f) = fence {
+            if trimmed.starts_with(f) {
+                fence = None;
+            }
+        } else if trimmed.starts_with("```") {
+            fence = Some("```");
+        } else if trimmed.starts_with("~~~") {
+            fence = Some("~~~");
+        } else if !line.starts_with("    ") && !line.starts_with('\t') {
+            result.push((offset, line));
+        }
+        offset += line.len();
+    }
+    result
+}
+
+/// Whether byte `index` of `line` falls inside an inline code span.
+pub fn in_code_span(line: &str, index: usize) -> bool {
+    line[..index].matches('`').count() % 2 == 1
+}
+
+/// Hands out unique anchors, suffixing repeated slugs with `-1`, `-2`, ...
+#[derive(Default)]
+pub struct Slugger {
+    seen: HashMap<String, i32>,
+}
+
+impl Slugger {
+    pub fn slug(&mut self, text: &str) -> String {
+        let base = slug(text);
+        let count = self.seen.entry(base.clone()).or_insert(0);
+        let result = if *count == 0 {
+            base
+        } else {
+            format!("{}-{}", base, count)
+        };
+        *count += 1;
+        result
+    }
+}
+
+/// Maps byte offsets of a document to line numbers.
+pub struct Lines {
+    starts: Vec<usize>,
+}
+
+impl Lines {
+    pub fn new(content: &str) -> Lines {
+        let mut starts = vec![0];
+        for (i, c) in content.bytes().enumerate() {
+            if c == b'\n' && i + 1 < content.len() {
+                starts.push(i + 1);
+            }
+        }
+        Lines { starts }
+    }
+
+    /// 1-based line number of a byte offset.
+    pub fn line(&self, offset: usize) -> i32 {
+        self.starts.partition_point(|start| *start <= offset) as i32
+    }
+
+    pub fn count(&self) -> i32 {
+        self.starts.len() as i32
+    }
+}
+
+/// Flat list of the headings of a Markdown document, in document order.
+pub fn markdown(content: &str) -> Vec<entity::Heading> {
+    let body = frontmatter::body(content);
+    let offset = content.len() - body.len();
+    let lines = Lines::new(content);
+    let mut slugger = Slugger::default();
+    let mut result = Vec::new();
+    let mut current: Option<(i32, Option<String>, usize, String)> = None;
+    for (event, range) in Parser::new_ext(body, options()).into_offset_iter() {
+        match event {
+            Event::Start(Tag::Heading(level, id, _)) => {
+                current = Some((
+                    level as i32,
+                    id.map(|id| id.to_string()),
+                    range.start,
+                    String::new(),
+                ));
+            }
+            Event::End(Tag::Heading(..)) => {
+                if let Some((level, id, start, text)) = current.take() {
+                    let slug = match id {
+                        Some(id) => id,
+                        None => slugger.slug(&text),
+                    };
+                    result.push(entity::Heading {
+                        level,
+                        text,
+                        slug,
+                        line: lines.line(offset + start),
+                        end: 0,
+                        children: Vec::new(),
+                    });
+                }
+            }
+            Event::Text(text) | Event::Code(text) => {
+                if let Some((_, _, _, heading)) = current.as_mut() {
+                    heading.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    finish(result, lines.count())
+}
+
+static HEADING: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"(?is)<h([1-6])(\s[^>]*)?>(.*?)</h[1-6]\s*>").unwrap());
+static ID: Lazy<Regex> = Lazy::new(|| Regex::new(r#"(?i)\sid\s*=\s*["']([^"']+)["']"#).unwrap());
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?s)<[^>]*>").unwrap());
+
+/// Flat list of the `<h1>`..`<h6>` elements of an `.ahtml` document.
+pub fn ahtml(content: &str) -> Vec<entity::Heading> {
+    let lines = Lines::new(content);
+    let mut slugger = Slugger::default();
+    let mut result = Vec::new();
+    for cap in HEADING.captures_iter(content) {
+        let text = decode(TAG.replace_all(&cap[3], "").trim());
+        let slug = match cap.get(2).and_then(|attrs| ID.captures(attrs.as_str())) {
+            Some(id) => id[1].to_string(),
+            None => slugger.slug(&text),
+        };
+        result.push(entity::Heading {
+            level: cap[1].parse().unwrap(),
+            text,
+            slug,
+            line: lines.line(cap.get(0).unwrap().start()),
+            end: 0,
+            children: Vec::new(),
+        });
+    }
+    finish(result, lines.count())
+}
+
+/// Decodes the handful of entities an HTML editor emits in heading text.
+pub fn decode(text: &str) -> String {
+    text.replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Sets each heading's last line: the line before the next heading of the same or higher level.
+fn finish(mut headings: Vec<entity::Heading>, last: i32) -> Vec<entity::Heading> {
+    for i in 0..headings.len() {
+        let level = headings[i].level;
+        headings[i].end = headings[i + 1..]
+            .iter()
+            .find(|next| next.level <= level)
+            .map_or(last, |next| next.line - 1);
+    }
+    headings
+}
+
+/// Nests a flat heading list into a tree by level.
+pub fn nest(headings: Vec<entity::Heading>) -> Vec<entity::Heading> {
+    let mut roots: Vec<entity::Heading> = Vec::new();
+    let mut stack: Vec<entity::Heading> = Vec::new();
+    for heading in headings {
+        while stack.last().map_or(false, |top| top.level >= heading.level) {
+            let top = stack.pop().unwrap();
+            match stack.last_mut() {
+                Some(parent) => parent.children.push(top),
+                None => roots.push(top),
+            }
+        }
+        stack.push(heading);
+    }
+    while let Some(top) = stack.pop() {
+        match stack.last_mut() {
+            Some(parent) => parent.children.push(top),
+            None => roots.push(top),
+        }
+    }
+    roots
+}
+
+/// Flat outline of a document, picking the parser from the file extension.
+pub fn headings(path: &str, content: &str) -> Vec<entity::Heading> {
+    if path.ends_with(".ahtml") {
+        ahtml(content)
+    } else {
+        markdown(content)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let content =
+            "---\ntitle: x\n---\n# Guide\n\ntext\n\n## Setup\n\n## Setup\n\n# API {#api}\n";
+        let outline = nest(markdown(content));
+        assert_eq!(outline.len(), 2);
+        assert_eq!(outline[0].line, 4);
+        assert_eq!(outline[0].end, 11);
+        assert_eq!(outline[0].children[1].slug, "setup-1");
+        assert_eq!(outline[1].slug, "api");
+    }
+
+    #[test]
+    fn test_ahtml() {
+        let content = "<h1>Guide</h1>\n<p>text</p>\n<h2 id=\"s\">Set <b>up</b> &amp; run</h2>\n";
+        let outline = nest(ahtml(content));
+        assert_eq!(outline[0].children[0].text, "Set up & run");
+        assert_eq!(outline[0].children[0].slug, "s");
+        assert_eq!(outline[0].end, 3);
+    }
+}
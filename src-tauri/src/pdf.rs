@@ -0,0 +1,894 @@
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+use printpdf::{
+    image_crate, BuiltinFont, Color, Image, ImageTransform, IndirectFontRef, Line, Mm, PdfDocument,
+    PdfDocumentReference, PdfLayerReference, PdfPageIndex, Point, Rgb,
+};
+use pulldown_cmark::{Event, Parser, Tag};
+
+use crate::{
+    ahtml, convert, entity, export,
+    links::{self, Document},
+    outline,
+};
+
+const MARGIN: f32 = 20.0;
+const PT: f32 = 0.3528;
+const BODY: f32 = 11.0;
+const CODE: f32 = 9.0;
+const HEADINGS: [f32; 6] = [20.0, 16.0, 14.0, 12.0, 11.0, 11.0];
+const INDENT: f32 = 6.0;
+/// Resolution assumed for images, which are mostly screenshots.
+const DPI: f32 = 96.0;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Style {
+    bold: bool,
+    code: bool,
+    link: bool,
+}
+
+#[derive(Debug, Clone)]
+struct Span {
+    text: String,
+    style: Style,
+}
+
+enum Block {
+    Heading(usize, Vec<Span>),
+    Paragraph {
+        spans: Vec<Span>,
+        marker: Option<String>,
+        indent: f32,
+        quote: bool,
+    },
+    Code(String),
+    Rule,
+    Image(String),
+    Table(Vec<Vec<Vec<Span>>>),
+}
+
+/// Turns Markdown into the blocks the PDF writer lays out.
+fn blocks(markdown: &str) -> Vec<Block> {
+    struct State {
+        result: Vec<Block>,
+        spans: Vec<Span>,
+        style: Style,
+        lists: Vec<Option<u64>>,
+        marker: Option<String>,
+        quote: usize,
+        code: Option<String>,
+        table: Option<Vec<Vec<Vec<Span>>>>,
+        image: bool,
+    }
+    impl State {
+        fn flush(&mut self) {
+            if self.spans.iter().all(|span| span.text.trim().is_empty()) && self.marker.is_none() {
+                self.spans.clear();
+                return;
+            }
+            self.result.push(Block::Paragraph {
+                spans: std::mem::take(&mut self.spans),
+                marker: self.marker.take(),
+                indent: INDENT * self.lists.len() as f32,
+                quote: self.quote > 0,
+            });
+        }
+        fn text(&mut self, text: &str, style: Style) {
+            if self.image {
+                return;
+            }
+            if let Some(code) = self.code.as_mut() {
+                code.push_str(text);
+                return;
+            }
+            let span = Span {
+                text: text.to_string(),
+                style,
+            };
+            match self.table.as_mut() {
+                Some(rows) => {
+                    if let Some(cell) = rows.last_mut().and_then(|row| row.last_mut()) {
+                        cell.push(span);
+                    }
+                }
+                None => self.spans.push(span),
+            }
+        }
+    }
+    let mut state = State {
+        result: Vec::new(),
+        spans: Vec::new(),
+        style: Style::default(),
+        lists: Vec::new(),
+        marker: None,
+        quote: 0,
+        code: None,
+        table: None,
+        image: false,
+    };
+    for event in Parser::new_ext(markdown, outline::options()) {
+        match event {
+            Event::Start(Tag::Heading(..)) | Event::Start(Tag::Paragraph) => {}
+            Event::End(Tag::Heading(level, ..)) => {
+                let spans = std::mem::take(&mut state.spans);
+                state.result.push(Block::Heading(level as usize, spans));
+            }
+            Event::End(Tag::Paragraph) => state.flush(),
+            Event::Start(Tag::List(start)) => {
+                state.flush();
+                state.lists.push(start);
+            }
+            Event::End(Tag::List(_)) => {
+                state.flush();
+                state.lists.pop();
+            }
+            Event::Start(Tag::Item) => {
+                state.flush();
+                state.marker = Some(match state.lists.last_mut() {
+                    Some(Some(n)) => {
+                        *n += 1;
+                        format!("{}.", *n - 1)
+                    }
+                    _ => "•".to_string(),
+                });
+            }
+            Event::End(Tag::Item) => state.flush(),
+            Event::TaskListMarker(checked) => {
+                let text = if checked { "[x] " } else { "[ ] " };
+                state.text(text, Style::default());
+            }
+            Event::Start(Tag::BlockQuote) => {
+                state.flush();
+                state.quote += 1;
+            }
+            Event::End(Tag::BlockQuote) => {
+                state.flush();
+                state.quote -= 1;
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                state.flush();
+                state.code = Some(String::new());
+            }
+            Event::End(Tag::CodeBlock(_)) => {
+                let code = state.code.take().unwrap_or_default();
+                state.result.push(Block::Code(code));
+            }
+            Event::Start(Tag::Image(_, dest, _)) => {
+                state.flush();
+                state.image = true;
+                state.result.push(Block::Image(dest.to_string()));
+            }
+            Event::End(Tag::Image(..)) => state.image = false,
+            Event::Start(Tag::Table(_)) => {
+                state.flush();
+                state.table = Some(Vec::new());
+            }
+            Event::End(Tag::Table(_)) => {
+                let rows = state.table.take().unwrap_or_default();
+                state.result.push(Block::Table(rows));
+            }
+            Event::Start(Tag::TableHead) | Event::Start(Tag::TableRow) => {
+                if let Some(rows) = state.table.as_mut() {
+                    rows.push(Vec::new());
+                }
+            }
+            Event::Start(Tag::TableCell) => {
+                if let Some(row) = state.table.as_mut().and_then(|rows| rows.last_mut()) {
+                    row.push(Vec::new());
+                }
+            }
+            Event::Start(Tag::Strong) => state.style.bold = true,
+            Event::End(Tag::Strong) => state.style.bold = false,
+            Event::Start(Tag::Link(..)) => state.style.link = true,
+            Event::End(Tag::Link(..)) => state.style.link = false,
+            Event::Text(text) => {
+                let style = state.style;
+                state.text(&text, style);
+            }
+            Event::Code(text) => {
+                let style = Style {
+                    code: true,
+                    ..state.style
+                };
+                state.text(&text, style);
+            }
+            Event::SoftBreak => {
+                let style = state.style;
+                state.text(" ", style);
+            }
+            Event::HardBreak => {
+                let style = state.style;
+                state.text("\n", style);
+            }
+            Event::FootnoteReference(name) => {
+                let style = state.style;
+                state.text(&format!("[{}]", name), style);
+            }
+            Event::Rule => {
+                state.flush();
+                state.result.push(Block::Rule);
+            }
+            _ => {}
+        }
+    }
+    state.flush();
+    state.result
+}
+
+/// A font along with what is needed to measure text set in it.
+struct Font {
+    font: IndirectFontRef,
+    /// Advance widths in em of the characters an external font has, read once when it is
+    /// loaded; `None` for the built-in fonts.
+    advances: Option<HashMap<char, f32>>,
+    mono: bool,
+}
+
+impl Font {
+    fn load(doc: &PdfDocumentReference, path: &str, fallback: BuiltinFont) -> Result<Font, String> {
+        if path.is_empty() {
+            return Ok(Font {
+                font: doc.add_builtin_font(fallback).map_err(|e| e.to_string())?,
+                advances: None,
+                mono: fallback == BuiltinFont::Courier,
+            });
+        }
+        let data = std::fs::read(path).map_err(|e| format!("{}: {}", path, e))?;
+        let face = ttf_parser::Face::parse(&data, 0).map_err(|e| format!("{}: {}", path, e))?;
+        let units = face.units_per_em() as f32;
+        let mut advances = HashMap::new();
+        let subtables = face.tables().cmap.map(|cmap| cmap.subtables);
+        for subtable in subtables.into_iter().flatten().filter(|s| s.is_unicode()) {
+            subtable.codepoints(|code| {
+                let advance = char::from_u32(code).and_then(|c| {
+                    let glyph = face.glyph_index(c)?;
+                    Some((c, face.glyph_hor_advance(glyph)? as f32 / units))
+                });
+                if let Some((c, advance)) = advance {
+                    advances.insert(c, advance);
+                }
+            });
+        }
+        Ok(Font {
+            font: doc
+                .add_external_font(data.as_slice())
+                .map_err(|e| format!("{}: {}", path, e))?,
+            advances: Some(advances),
+            mono: false,
+        })
+    }
+
+    /// Width of `text` in millimeters.
+    fn width(&self, text: &str, size: f32) -> f32 {
+        let em = match &self.advances {
+            Some(advances) => text
+                .chars()
+                .map(|c| advances.get(&c).copied().unwrap_or(0.5))
+                .sum(),
+            None if self.mono => text.chars().count() as f32 * 0.6,
+            // close enough to Helvetica for wrapping
+            None => text
+                .chars()
+                .map(|c| match c {
+                    'i' | 'j' | 'l' | '.' | ',' | ':' | ';' | '\'' | '|' | '!' => 0.25,
+                    ' ' | 'f' | 't' | 'r' | 'I' | '(' | ')' | '[' | ']' | '-' => 0.33,
+                    'm' | 'w' | 'M' | 'W' => 0.85,
+                    c if c.is_uppercase() => 0.68,
+                    _ => 0.55,
+                })
+                .sum(),
+        };
+        em * size * PT
+    }
+
+    /// Built-in fonts only cover Latin-1; anything else is replaced.
+    fn encode(&self, text: &str) -> String {
+        if self.advances.is_some() {
+            text.to_string()
+        } else {
+            text.chars()
+                .map(|c| if (c as u32) < 0x100 { c } else { '?' })
+                .collect()
+        }
+    }
+}
+
+struct Fonts {
+    regular: Font,
+    bold: Font,
+    mono: Font,
+}
+
+impl Fonts {
+    fn get(&self, style: Style) -> &Font {
+        if style.code {
+            &self.mono
+        } else if style.bold {
+            &self.bold
+        } else {
+            &self.regular
+        }
+    }
+}
+
+/// A run of text set in one style on one line.
+type Run = (String, Style);
+
+/// Exports documents to PDF without a browser: text is laid out from the Markdown (`.ahtml`
+/// is converted to Markdown first) with fonts embedded from the configured paths.
+pub struct Writer<'a> {
+    root: &'a Path,
+    files: &'a HashMap<String, Document>,
+    doc: PdfDocumentReference,
+    fonts: Fonts,
+    width: f32,
+    height: f32,
+    layers: Vec<PdfLayerReference>,
+    /// The page being written.
+    page: Option<PdfPageIndex>,
+    y: f32,
+    source: PathBuf,
+    report: entity::Export,
+}
+
+impl<'a> Writer<'a> {
+    pub fn new(
+        root: &'a Path,
+        files: &'a HashMap<String, Document>,
+        title: &str,
+        options: &entity::PdfOptions,
+    ) -> Result<Writer<'a>, String> {
+        let doc = PdfDocument::empty(title);
+        let regular = Font::load(&doc, &options.font, BuiltinFont::Helvetica)?;
+        let bold = if options.bold_font.is_empty() && !options.font.is_empty() {
+            Font::load(&doc, &options.font, BuiltinFont::HelveticaBold)?
+        } else {
+            Font::load(&doc, &options.bold_font, BuiltinFont::HelveticaBold)?
+        };
+        let mono = if options.mono_font.is_empty() && !options.font.is_empty() {
+            // the built-in Courier has no CJK either
+            Font::load(&doc, &options.font, BuiltinFont::Courier)?
+        } else {
+            Font::load(&doc, &options.mono_font, BuiltinFont::Courier)?
+        };
+        let (width, height) = match options.paper.as_str() {
+            "Letter" => (215.9, 279.4),
+            _ => (210.0, 297.0),
+        };
+        Ok(Writer {
+            root,
+            files,
+            doc,
+            fonts: Fonts {
+                regular,
+                bold,
+                mono,
+            },
+            width,
+            height,
+            layers: Vec::new(),
+            page: None,
+            y: 0.0,
+            source: PathBuf::new(),
+            report: entity::Export {
+                output: "".to_string(),
+                pages: 0,
+                assets: 0,
+                warnings: Vec::new(),
+            },
+        })
+    }
+
+    /// Writes a single document, or every document of a folder in tree order after a
+    /// table of contents, to `output`.
+    pub fn export(mut self, target: &Path, output: &Path) -> Result<entity::Export, String> {
+        if target.is_dir() {
+            let tree = export::tree(target)?;
+            let pages = export::pages(target, &tree, self.files);
+            let toc_pages = self.reserve_toc(pages.len());
+            let mut starts = Vec::new();
+            for page in &pages {
+                self.new_page();
+                starts.push(self.layers.len());
+                self.doc
+                    .add_bookmark(page.title.clone(), self.page.unwrap());
+                self.document(&page.source);
+            }
+            self.toc(&toc_pages, &pages, &starts);
+        } else {
+            self.new_page();
+            self.document(target);
+        }
+        self.page_numbers();
+        self.report.output = output.to_string_lossy().to_string();
+        self.report.pages = self.layers.len() as i32;
+        let bytes = self.doc.save_to_bytes().map_err(|e| e.to_string())?;
+        std::fs::write(output, bytes).map_err(|e| e.to_string())?;
+        if self.fonts.regular.advances.is_none() {
+            self.report
+                .warnings
+                .push("no font configured, characters outside Latin-1 were replaced".to_string());
+        }
+        Ok(self.report)
+    }
+
+    fn new_page(&mut self) {
+        let (page, layer) = self
+            .doc
+            .add_page(Mm(self.width), Mm(self.height), "content");
+        self.layers.push(self.doc.get_page(page).get_layer(layer));
+        self.page = Some(page);
+        self.y = MARGIN;
+    }
+
+    fn layer(&self) -> PdfLayerReference {
+        self.layers.last().unwrap().clone()
+    }
+
+    fn bottom(&self) -> f32 {
+        self.height - MARGIN
+    }
+
+    /// Moves down by `height`, starting a new page when it does not fit.
+    fn advance(&mut self, height: f32) {
+        if self.y + height > self.bottom() && self.y > MARGIN {
+            self.new_page();
+        }
+    }
+
+    fn text(&self, text: &str, font: &Font, size: f32, x: f32, color: (f32, f32, f32)) {
+        let layer = self.layer();
+        layer.set_fill_color(Color::Rgb(Rgb::new(color.0, color.1, color.2, None)));
+        // PDF coordinates start at the bottom; the baseline sits one font size below the top
+        let baseline = self.height - self.y - size * PT;
+        layer.use_text(font.encode(text), size, Mm(x), Mm(baseline), &font.font);
+    }
+
+    fn document(&mut self, source: &Path) {
+        self.source = source.to_path_buf();
+        let content =
+            match export::prepare(self.root, source, self.files, &mut self.report.warnings) {
+                Ok(content) => content,
+                Err(e) => {
+                    self.report
+                        .warnings
+                        .push(format!("{}: {}", source.display(), e));
+                    return;
+                }
+            };
+        let markdown = if source.extension().map_or(false, |ext| ext == "ahtml") {
+            convert::to_markdown(ahtml::body(&content))
+        } else {
+            content
+        };
+        for block in blocks(crate::frontmatter::body(&markdown)) {
+            self.block(block);
+        }
+    }
+
+    /// Splits spans into lines of runs no wider than `width`. Latin text breaks at spaces,
+    /// CJK text between any two characters.
+    fn wrap(&self, spans: &[Span], size: f32, width: f32) -> Vec<Vec<Run>> {
+        let mut lines: Vec<Vec<Run>> = vec![Vec::new()];
+        let mut used = 0.0;
+        for span in spans {
+            let font = self.fonts.get(span.style);
+            let size = if span.style.code { size * 0.9 } else { size };
+            let mut pieces = Vec::new();
+            let mut piece = String::new();
+            for c in span.text.chars() {
+                if c == '\n' {
+                    pieces.push(std::mem::take(&mut piece));
+                    pieces.push("\n".to_string());
+                } else if crate::search::is_cjk(c) {
+                    if !piece.is_empty() {
+                        pieces.push(std::mem::take(&mut piece));
+                    }
+                    pieces.push(c.to_string());
+                } else {
+                    piece.push(c);
+                    if c == ' ' {
+                        pieces.push(std::mem::take(&mut piece));
+                    }
+                }
+            }
+            pieces.push(piece);
+            for piece in pieces.into_iter().filter(|piece| !piece.is_empty()) {
+                if piece == "\n" {
+                    lines.push(Vec::new());
+                    used = 0.0;
+                    continue;
+                }
+                let w = font.width(&piece, size);
+                if used + w > width && used > 0.0 {
+                    lines.push(Vec::new());
+                    used = 0.0;
+                    if piece == " " {
+                        continue;
+                    }
+                }
+                used += w;
+                let line = lines.last_mut().unwrap();
+                match line.last_mut() {
+                    Some((text, style)) if *style == span.style => text.push_str(&piece),
+                    _ => line.push((piece, span.style)),
+                }
+            }
+        }
+        lines
+    }
+
+    /// Draws one wrapped line at `x` on the current line position.
+    fn runs(&self, runs: &[Run], size: f32, x: f32, color: (f32, f32, f32)) {
+        let mut x = x;
+        for (text, style) in runs {
+            let font = self.fonts.get(*style);
+            let size = if style.code { size * 0.9 } else { size };
+            let color = if style.link {
+                (0.04, 0.41, 0.85)
+            } else {
+                color
+            };
+            self.text(text, font, size, x, color);
+            x += font.width(text, size);
+        }
+    }
+
+    fn paragraph(&mut self, spans: &[Span], size: f32, x: f32, color: (f32, f32, f32)) {
+        let line_height = size * PT * 1.5;
+        for line in self.wrap(spans, size, self.width - MARGIN - x) {
+            self.advance(line_height);
+            self.runs(&line, size, x, color);
+            self.y += line_height;
+        }
+    }
+
+    fn block(&mut self, block: Block) {
+        let black = (0.14, 0.16, 0.18);
+        match block {
+            Block::Heading(level, spans) => {
+                let size = HEADINGS[(level - 1).min(5)];
+                let spans: Vec<Span> = spans
+                    .into_iter()
+                    .map(|span| Span {
+                        style: Style {
+                            bold: true,
+                            ..span.style
+                        },
+                        ..span
+                    })
+                    .collect();
+                // keep the heading together with the first line after it
+                self.advance(size * PT * 1.5 + BODY * PT * 3.0);
+                self.y += size * PT * 0.6;
+                self.paragraph(&spans, size, MARGIN, black);
+                self.y += size * PT * 0.3;
+            }
+            Block::Paragraph {
+                spans,
+                marker,
+                indent,
+                quote,
+            } => {
+                let x = MARGIN + indent + if quote { INDENT } else { 0.0 };
+                let color = if quote { (0.34, 0.38, 0.42) } else { black };
+                if let Some(marker) = marker {
+                    self.advance(BODY * PT * 1.5);
+                    let font = &self.fonts.regular;
+                    let w = font.width(&marker, BODY);
+                    self.text(&marker, font, BODY, x - w - 1.5, color);
+                }
+                self.paragraph(&spans, BODY, x, color);
+                self.y += BODY * PT * 0.5;
+            }
+            Block::Code(code) => {
+                let line_height = CODE * PT * 1.4;
+                let x = MARGIN + 4.0;
+                let width = self.width - MARGIN - x;
+                for line in code.trim_end_matches('\n').lines() {
+                    let spans = [Span {
+                        text: line.replace('\t', "    "),
+                        style: Style {
+                            code: true,
+                            ..Style::default()
+                        },
+                    }];
+                    for runs in self.wrap(&spans, CODE / 0.9, width) {
+                        self.advance(line_height);
+                        self.runs(&runs, CODE / 0.9, x, (0.2, 0.2, 0.2));
+                        self.y += line_height;
+                    }
+                }
+                self.y += BODY * PT * 0.5;
+            }
+            Block::Rule => {
+                self.advance(6.0);
+                self.y += 3.0;
+                let y = self.height - self.y;
+                self.layer().add_line(Line {
+                    points: vec![
+                        (Point::new(Mm(MARGIN), Mm(y)), false),
+                        (Point::new(Mm(self.width - MARGIN), Mm(y)), false),
+                    ],
+                    is_closed: false,
+                });
+                self.y += 3.0;
+            }
+            Block::Image(dest) => self.image(&dest),
+            Block::Table(rows) => self.table(rows),
+        }
+    }
+
+    fn image(&mut self, dest: &str) {
+        let (path, _) = links::split(dest);
+        if links::external(dest) {
+            self.report.warnings.push(format!(
+                "{}: remote image {} left out",
+                self.source.display(),
+                dest
+            ));
+            return;
+        }
+        let image = links::resolve(self.root, &self.source, &path)
+            .ok_or_else(|| "not found".to_string())
+            .and_then(|path| image_crate::open(path).map_err(|e| e.to_string()));
+        let image = match image {
+            Ok(image) => image,
+            Err(e) => {
+                self.report.warnings.push(format!(
+                    "{}: image {}: {}",
+                    self.source.display(),
+                    dest,
+                    e
+                ));
+                return;
+            }
+        };
+        // drop the alpha channel, which PDF keeps in a separate mask
+        let image = image_crate::DynamicImage::ImageRgb8(image.to_rgb8());
+        let natural = (
+            image.width() as f32 / DPI * 25.4,
+            image.height() as f32 / DPI * 25.4,
+        );
+        let max = (self.width - 2.0 * MARGIN, self.height - 2.0 * MARGIN);
+        let scale = (max.0 / natural.0).min(max.1 / natural.1).min(1.0);
+        let height = natural.1 * scale;
+        self.advance(height);
+        self.y += height;
+        Image::from_dynamic_image(&image).add_to_layer(
+            self.layer(),
+            ImageTransform {
+                translate_x: Some(Mm(MARGIN)),
+                translate_y: Some(Mm(self.height - self.y)),
+                scale_x: Some(scale),
+                scale_y: Some(scale),
+                dpi: Some(DPI),
+                ..Default::default()
+            },
+        );
+        self.y += BODY * PT * 0.5;
+        self.report.assets += 1;
+    }
+
+    fn table(&mut self, rows: Vec<Vec<Vec<Span>>>) {
+        let columns = rows.iter().map(|row| row.len()).max().unwrap_or(0);
+        if columns == 0 {
+            return;
+        }
+        let size = BODY * 0.9;
+        let line_height = size * PT * 1.5;
+        let column = (self.width - 2.0 * MARGIN) / columns as f32;
+        for (i, row) in rows.iter().enumerate() {
+            let cells: Vec<Vec<Vec<Run>>> = row
+                .iter()
+                .map(|cell| {
+                    let spans: Vec<Span> = cell
+                        .iter()
+                        .map(|span| Span {
+                            style: Style {
+                                bold: span.style.bold || i == 0,
+                                ..span.style
+                            },
+                            ..span.clone()
+                        })
+                        .collect();
+                    self.wrap(&spans, size, column - 2.0)
+                })
+                .collect();
+            let lines = cells.iter().map(|cell| cell.len()).max().unwrap_or(1);
+            // a row that fits on a page is kept together, a taller one continues on the
+            // next pages line by line
+            self.advance(lines as f32 * line_height);
+            for line in 0..lines {
+                self.advance(line_height);
+                for (c, cell) in cells.iter().enumerate() {
+                    if let Some(runs) = cell.get(line) {
+                        let x = MARGIN + c as f32 * column;
+                        self.runs(runs, size, x, (0.14, 0.16, 0.18));
+                    }
+                }
+                self.y += line_height;
+            }
+            let y = self.height - self.y + 1.0;
+            self.layer().add_line(Line {
+                points: vec![
+                    (Point::new(Mm(MARGIN), Mm(y)), false),
+                    (Point::new(Mm(self.width - MARGIN), Mm(y)), false),
+                ],
+                is_closed: false,
+            });
+            self.y += 1.5;
+        }
+        self.y += BODY * PT * 0.5;
+    }
+
+    fn toc_line(&self) -> f32 {
+        BODY * PT * 1.8
+    }
+
+    /// Adds the pages the table of contents of `count` documents will take.
+    fn reserve_toc(&mut self, count: usize) -> Vec<PdfLayerReference> {
+        let title = HEADINGS[0] * PT * 2.5;
+        let per_page = ((self.height - 2.0 * MARGIN - title) / self.toc_line()).floor() as usize;
+        let pages = (count.max(1) + per_page - 1) / per_page;
+        (0..pages)
+            .map(|_| {
+                self.new_page();
+                self.layer()
+            })
+            .collect()
+    }
+
+    /// Fills the reserved pages with the title and first page of each document.
+    fn toc(&mut self, layers: &[PdfLayerReference], pages: &[export::Page], starts: &[usize]) {
+        let documents = std::mem::take(&mut self.layers);
+        let mut entries = pages.iter().zip(starts);
+        for (i, layer) in layers.iter().enumerate() {
+            // text goes to the last layer
+            self.layers = vec![layer.clone()];
+            self.y = MARGIN;
+            if i == 0 {
+                let font = &self.fonts.bold;
+                self.text("Contents", font, HEADINGS[0], MARGIN, (0.14, 0.16, 0.18));
+                self.y += HEADINGS[0] * PT * 2.5;
+            }
+            while self.y + self.toc_line() <= self.bottom() {
+                let (entry, start) = match entries.next() {
+                    Some(entry) => entry,
+                    None => break,
+                };
+                let number = start.to_string();
+                let font = &self.fonts.regular;
+                let number_width = font.width(&number, BODY);
+                let mut title = entry.title.clone();
+                let max = self.width - 2.0 * MARGIN - number_width - 6.0;
+                while font.width(&title, BODY) > max && title.chars().count() > 1 {
+                    title.pop();
+                }
+                self.text(&title, font, BODY, MARGIN, (0.14, 0.16, 0.18));
+                self.text(
+                    &number,
+                    font,
+                    BODY,
+                    self.width - MARGIN - number_width,
+                    (0.14, 0.16, 0.18),
+                );
+                self.y += self.toc_line();
+            }
+        }
+        self.layers = documents;
+    }
+
+    fn page_numbers(&mut self) {
+        let total = self.layers.len();
+        let size = 9.0;
+        for (i, layer) in self.layers.iter().enumerate() {
+            let text = format!("{} / {}", i + 1, total);
+            let font = &self.fonts.regular;
+            let x = (self.width - font.width(&text, size)) / 2.0;
+            layer.set_fill_color(Color::Rgb(Rgb::new(0.34, 0.38, 0.42, None)));
+            layer.use_text(text, size, Mm(x), Mm(MARGIN / 2.0), &font.font);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_blocks() {
+        let blocks = blocks("# T\n\n- a\n  1. b\n\n> q **s**\n\n```\nx\n```\n\n![i](p.png)\n");
+        let summary: Vec<String> = blocks
+            .iter()
+            .map(|block| match block {
+                Block::Heading(level, spans) => format!("h{} {}", level, spans[0].text),
+                Block::Paragraph {
+                    spans,
+                    marker,
+                    indent,
+                    quote,
+                } => format!(
+                    "{} {} {} {}",
+                    marker.clone().unwrap_or_default(),
+                    indent,
+                    quote,
+                    spans.iter().map(|s| s.text.as_str()).collect::<String>()
+                ),
+                Block::Code(code) => format!("code {:?}", code),
+                Block::Rule => "rule".to_string(),
+                Block::Image(dest) => format!("image {}", dest),
+                Block::Table(_) => "table".to_string(),
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            vec![
+                "h1 T",
+                "• 6 false a",
+                "1. 12 false b",
+                " 0 true q s",
+                "code \"x\\n\"",
+                "image p.png"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let temp = TempDir::new("pdf");
+        let root = temp.join("pdf");
+        std::fs::create_dir_all(root.join("docs")).unwrap();
+        std::fs::write(root.join("a.md"), "# A\n\ntext ".repeat(200)).unwrap();
+        std::fs::write(
+            root.join("docs/b.md"),
+            "# B\n\n| x | y |\n|---|---|\n| 1 | 2 |\n",
+        )
+        .unwrap();
+        let options = entity::PdfOptions {
+            font: "".to_string(),
+            bold_font: "".to_string(),
+            mono_font: "".to_string(),
+            paper: "A4".to_string(),
+        };
+        let files = HashMap::new();
+        let output = temp.join("pdf.pdf");
+        let report = Writer::new(&root, &files, "Test", &options)
+            .unwrap()
+            .export(&root, &output)
+            .unwrap();
+        assert!(report.pages > 3);
+        assert!(std::fs::read(&output).unwrap().starts_with(b"%PDF"));
+    }
+
+    #[test]
+    fn test_tall_table_row() {
+        let temp = TempDir::new("pdf-table");
+        let root = temp.join("pdf");
+        std::fs::create_dir_all(&root).unwrap();
+        let cell = "word ".repeat(3000);
+        std::fs::write(
+            root.join("t.md"),
+            format!("| x | y |\n|---|---|\n| {} | 2 |\n", cell),
+        )
+        .unwrap();
+        let options = entity::PdfOptions {
+            font: "".to_string(),
+            bold_font: "".to_string(),
+            mono_font: "".to_string(),
+            paper: "A4".to_string(),
+        };
+        let files = HashMap::new();
+        let mut writer = Writer::new(&root, &files, "Test", &options).unwrap();
+        writer.new_page();
+        writer.document(&root.join("t.md"));
+        // every line of the row landed inside the page it was written on
+        assert!(writer.layers.len() > 3);
+        assert!(writer.y <= writer.bottom());
+    }
+}
@@ -0,0 +1,340 @@
+use std::cmp::Ordering;
+
+use serde_json::{Map, Value};
+
+use crate::frontmatter;
+
+/// A parsed metadata query such as `tags contains "api" and date > 2026-01-01`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Not(Box<Expr>),
+    Exists(String),
+    Compare(String, Op, Value),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ne,
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Contains,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Quoted(String),
+    Op(Op),
+    Open,
+    Close,
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::Open);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::Close);
+            }
+            '"' | '\'' => {
+                chars.next();
+                let mut s = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => match chars.next() {
+                            Some(e) => s.push(e),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(e) if e == c => break,
+                        Some(e) => s.push(e),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Quoted(s));
+            }
+            '=' | '!' | '>' | '<' => {
+                chars.next();
+                let eq = chars.peek() == Some(&'=');
+                if eq {
+                    chars.next();
+                }
+                let op = match (c, eq) {
+                    ('=', _) => Op::Eq,
+                    ('!', true) => Op::Ne,
+                    ('>', false) => Op::Gt,
+                    ('>', true) => Op::Ge,
+                    ('<', false) => Op::Lt,
+                    ('<', true) => Op::Le,
+                    _ => return Err(format!("unexpected character '{}'", c)),
+                };
+                tokens.push(Token::Op(op));
+            }
+            _ => {
+                let mut s = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "()\"'=!<>".contains(c) {
+                        break;
+                    }
+                    s.push(c);
+                    chars.next();
+                }
+                if s.eq_ignore_ascii_case("contains") {
+                    tokens.push(Token::Op(Op::Contains));
+                } else {
+                    tokens.push(Token::Word(s));
+                }
+            }
+        }
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn keyword(&mut self, keyword: &str) -> bool {
+        match self.peek() {
+            Some(Token::Word(w)) if w.eq_ignore_ascii_case(keyword) => {
+                self.pos += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut left = self.and()?;
+        while self.keyword("or") {
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut left = self.unary()?;
+        while self.keyword("and") {
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        if self.keyword("not") {
+            return Ok(Expr::Not(Box::new(self.unary()?)));
+        }
+        match self.next() {
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("expected ')'".to_string()),
+                }
+            }
+            Some(Token::Word(field)) | Some(Token::Quoted(field)) => {
+                if self.keyword("exists") {
+                    return Ok(Expr::Exists(field));
+                }
+                let op = match self.next() {
+                    Some(Token::Op(op)) => op,
+                    _ => return Err(format!("expected operator after '{}'", field)),
+                };
+                let value = match self.next() {
+                    Some(Token::Quoted(s)) => Value::String(s),
+                    Some(Token::Word(w)) => literal(&w),
+                    _ => return Err(format!("expected value after '{}'", field)),
+                };
+                Ok(Expr::Compare(field, op, value))
+            }
+            Some(token) => Err(format!("unexpected token {:?}", token)),
+            None => Err("unexpected end of query".to_string()),
+        }
+    }
+}
+
+fn literal(word: &str) -> Value {
+    if word == "true" || word == "false" {
+        return Value::Bool(word == "true");
+    }
+    if let Ok(i) = word.parse::<i64>() {
+        return Value::from(i);
+    }
+    if let Ok(f) = word.parse::<f64>() {
+        if let Some(n) = serde_json::Number::from_f64(f) {
+            return Value::Number(n);
+        }
+    }
+    Value::String(word.to_string())
+}
+
+pub fn parse(input: &str) -> Result<Expr, String> {
+    let mut parser = Parser {
+        tokens: tokenize(input)?,
+        pos: 0,
+    };
+    let expr = parser.or()?;
+    match parser.peek() {
+        None => Ok(expr),
+        Some(token) => Err(format!("unexpected token {:?}", token)),
+    }
+}
+
+fn compare(a: &Value, b: &Value) -> Option<Ordering> {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::String(a), _) => Some(a.as_str().cmp(&scalar(b)?)),
+        (_, Value::String(b)) => Some(scalar(a)?.as_str().cmp(b.as_str())),
+        _ => None,
+    }
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+impl Expr {
+    pub fn matches(&self, data: &Map<String, Value>) -> bool {
+        match self {
+            Expr::And(a, b) => a.matches(data) && b.matches(data),
+            Expr::Or(a, b) => a.matches(data) || b.matches(data),
+            Expr::Not(a) => !a.matches(data),
+            Expr::Exists(field) => frontmatter::get(data, field).is_some(),
+            Expr::Compare(field, op, value) => match frontmatter::get(data, field) {
+                None => *op == Op::Ne,
+                Some(Value::Array(items)) => match op {
+                    Op::Ne => !items
+                        .iter()
+                        .any(|item| compare(item, value) == Some(Ordering::Equal)),
+                    Op::Eq | Op::Contains => items
+                        .iter()
+                        .any(|item| compare(item, value) == Some(Ordering::Equal)),
+                    _ => items.iter().any(|item| test(item, *op, value)),
+                },
+                Some(field) => test(field, *op, value),
+            },
+        }
+    }
+}
+
+fn test(field: &Value, op: Op, value: &Value) -> bool {
+    if op == Op::Contains {
+        return match (field, scalar(value)) {
+            (Value::String(s), Some(v)) => s.to_lowercase().contains(&v.to_lowercase()),
+            (Value::Object(o), Some(v)) => o.contains_key(&v),
+            _ => false,
+        };
+    }
+    match compare(field, value) {
+        Some(ordering) => match op {
+            Op::Eq => ordering == Ordering::Equal,
+            Op::Ne => ordering != Ordering::Equal,
+            Op::Gt => ordering == Ordering::Greater,
+            Op::Ge => ordering != Ordering::Less,
+            Op::Lt => ordering == Ordering::Less,
+            Op::Le => ordering != Ordering::Greater,
+            Op::Contains => false,
+        },
+        None => op == Op::Ne,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data(yaml: &str) -> Map<String, Value> {
+        frontmatter::parse_raw(frontmatter::YAML, yaml).unwrap()
+    }
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            parse("not draft = true or (tags contains 'a b' and n >= 2)").unwrap(),
+            Expr::Or(
+                Box::new(Expr::Not(Box::new(Expr::Compare(
+                    "draft".to_string(),
+                    Op::Eq,
+                    Value::Bool(true)
+                )))),
+                Box::new(Expr::And(
+                    Box::new(Expr::Compare(
+                        "tags".to_string(),
+                        Op::Contains,
+                        Value::from("a b")
+                    )),
+                    Box::new(Expr::Compare("n".to_string(), Op::Ge, Value::from(2))),
+                )),
+            )
+        );
+        for (input, error) in [
+            ("", "unexpected end of query"),
+            ("title = \"open", "unterminated string"),
+            ("title", "expected operator after 'title'"),
+            ("title =", "expected value after 'title'"),
+            ("(a exists", "expected ')'"),
+            ("a exists b", "unexpected token Word(\"b\")"),
+            ("a ! b", "unexpected character '!'"),
+        ] {
+            assert_eq!(parse(input).unwrap_err(), error, "{}", input);
+        }
+    }
+
+    #[test]
+    fn test_matches() {
+        let page = data("title: Setup Guide\ntags: [api, guide]\ndate: 2026-03-01\nweight: 10\nauthor:\n  name: ahri\n");
+        let matches = |query: &str| parse(query).unwrap().matches(&page);
+        assert!(matches("tags contains api and tags = guide"));
+        assert!(matches("tags != draft"));
+        assert!(matches("title contains setup"));
+        assert!(matches("author.name = ahri"));
+        assert!(matches("author contains name"));
+        assert!(matches("missing != x"));
+        assert!(!matches("missing = x"));
+        assert!(!matches("missing exists"));
+    }
+
+    #[test]
+    fn test_ordering() {
+        let page = data("date: 2026-03-01\nweight: 10\nversion: '9'\n");
+        let matches = |query: &str| parse(query).unwrap().matches(&page);
+        // ISO dates compare as text, numbers by value
+        assert!(matches("date > 2026-01-01 and date < 2026-12-31"));
+        assert!(matches("date >= 2026-03-01 and date <= 2026-03-01"));
+        assert!(matches("weight > 9.5 and weight < 100"));
+        // a string field compares as text against a number
+        assert!(matches("version > 10"));
+        assert!(!matches("weight > true"));
+    }
+}
@@ -0,0 +1,141 @@
+use pulldown_cmark::{html, CodeBlockKind, CowStr, Event, Parser, Tag};
+
+use crate::{ahtml, frontmatter, highlight::Highlighter, outline, sanitize};
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Gives every heading an `id`, using the same slugs as the outline.
+fn anchors<'a>(events: Vec<Event<'a>>) -> Vec<Event<'a>> {
+    let mut slugger = outline::Slugger::default();
+    let mut result = Vec::with_capacity(events.len());
+    let mut i = 0;
+    while i < events.len() {
+        if let Event::Start(Tag::Heading(level, id, classes)) = &events[i] {
+            let end = i + events[i..]
+                .iter()
+                .position(|e| matches!(e, Event::End(Tag::Heading(..))))
+                .unwrap_or(events.len() - i - 1);
+            let id = match id {
+                Some(id) => id.to_string(),
+                None => {
+                    let text: String = events[i + 1..end]
+                        .iter()
+                        .filter_map(|e| match e {
+                            Event::Text(text) | Event::Code(text) => Some(text.as_ref()),
+                            _ => None,
+                        })
+                        .collect();
+                    slugger.slug(&text)
+                }
+            };
+            let class = if classes.is_empty() {
+                "".to_string()
+            } else {
+                format!(" class=\"{}\"", escape(&classes.join(" ")))
+            };
+            result.push(Event::Html(CowStr::from(format!(
+                "<{} id=\"{}\"{}>",
+                level,
+                escape(&id),
+                class
+            ))));
+            result.extend(events[i + 1..end].iter().cloned());
+            result.push(Event::Html(CowStr::from(format!("</{}>\n", level))));
+            i = end + 1;
+        } else {
+            result.push(events[i].clone());
+            i += 1;
+        }
+    }
+    result
+}
+
+/// Replaces fenced code blocks in a language the highlighter knows with highlighted HTML.
+fn code_blocks<'a>(events: Vec<Event<'a>>, highlighter: &Highlighter) -> Vec<Event<'a>> {
+    let mut result = Vec::with_capacity(events.len());
+    let mut block: Option<(String, String, Vec<Event<'a>>)> = None;
+    for event in events {
+        match event {
+            Event::Start(Tag::CodeBlock(CodeBlockKind::Fenced(ref info))) if !info.is_empty() => {
+                let lang = info.split_whitespace().next().unwrap_or("").to_string();
+                block = Some((lang, String::new(), vec![event]));
+            }
+            Event::End(Tag::CodeBlock(_)) if block.is_some() => {
+                let (lang, code, mut original) = block.take().unwrap();
+                match highlighter.highlight(&code, &lang) {
+                    Some(html) => result.push(Event::Html(CowStr::from(html))),
+                    None => {
+                        original.push(event);
+                        result.extend(original);
+                    }
+                }
+            }
+            event => match block.as_mut() {
+                Some((_, code, original)) => {
+                    if let Event::Text(text) = &event {
+                        code.push_str(text);
+                    }
+                    original.push(event);
+                }
+                None => result.push(event),
+            },
+        }
+    }
+    result
+}
+
+/// Renders Markdown to HTML: CommonMark with GFM tables, task lists, strikethrough,
+/// footnotes and heading anchors, highlighting code when a highlighter is given.
+/// Front matter is left out.
+pub fn markdown(content: &str, highlighter: Option<&Highlighter>) -> String {
+    let body = frontmatter::body(content);
+    let mut events = anchors(Parser::new_ext(body, outline::options()).collect());
+    if let Some(highlighter) = highlighter {
+        events = code_blocks(events, highlighter);
+    }
+    let mut result = String::new();
+    html::push_html(&mut result, events.into_iter());
+    result
+}
+
+/// Renders any document to HTML; `.ahtml` documents already are HTML and only get sanitized.
+pub fn document(path: &str, content: &str, highlighter: Option<&Highlighter>) -> String {
+    if path.ends_with(".ahtml") {
+        let allowlist = sanitize::allowlist(std::path::Path::new(path));
+        sanitize::sanitize(ahtml::body(content), &allowlist).0
+    } else {
+        markdown(content, highlighter)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_markdown() {
+        let content = "---\ntitle: x\n---\n# Hello *World*\n\n- [x] done\n\n| a |\n|---|\n| ~~b~~ |\n\n## Hello World\n";
+        assert_eq!(
+            markdown(content, None),
+            "<h1 id=\"hello-world\">Hello <em>World</em></h1>\n\
+             <ul>\n<li><input disabled=\"\" type=\"checkbox\" checked=\"\"/>\ndone</li>\n</ul>\n\
+             <table><thead><tr><th>a</th></tr></thead><tbody>\n<tr><td><del>b</del></td></tr>\n</tbody></table>\n\
+             <h2 id=\"hello-world-1\">Hello World</h2>\n"
+        );
+    }
+
+    #[test]
+    fn test_highlight() {
+        let content = "```rust\nfn main() {}\n```\n\n```nope\nx\n```\n";
+        let html = markdown(content, Some(&Highlighter::new(None, None)));
+        assert!(html.starts_with(
+            "<pre class=\"code\"><code class=\"language-rust\"><span class=\"source rust\">"
+        ));
+        assert!(html.ends_with("<pre><code class=\"language-nope\">x\n</code></pre>\n"));
+    }
+}
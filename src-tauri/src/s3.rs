@@ -0,0 +1,1230 @@
+use std::{
+    collections::HashMap,
+    fs,
+    io::{Error, ErrorKind, Read, Result},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use hmac::{Hmac, Mac};
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    entity,
+    storage::{self, Entry, Handler, Poller, Snapshot, Stat, Storage},
+};
+
+/// Characters left as they are when encoding a URI for a signature.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// How often a watched bucket is listed again to find the changes made on it.
+pub const POLL: Duration = Duration::from_secs(30);
+
+/// How many documents and folder listings the cache keeps.
+pub const CACHE: usize = 500;
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn sha256(data: &[u8]) -> String {
+    hex(&Sha256::digest(data))
+}
+
+fn hmac(key: &[u8], data: &str) -> Vec<u8> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    mac.update(data.as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// `20130524T000000Z`, the time of a request as signatures want it.
+fn amz_date(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let rest = secs % 86400;
+    // days to a civil date, after Howard Hinnant's algorithm
+    let z = (secs / 86400) as i64 + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        rest / 3600,
+        rest / 60 % 60,
+        rest % 60
+    )
+}
+
+/// Parses the `2013-05-24T00:00:00.000Z` times of a listing.
+fn iso_date(value: &str) -> Option<SystemTime> {
+    let (date, time) = value.trim_end_matches('Z').split_once('T')?;
+    let date = date
+        .split('-')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    let time = time
+        .split(':')
+        .map(|part| part.parse::<f64>().ok())
+        .collect::<Option<Vec<f64>>>()?;
+    if date.len() != 3 || time.len() != 3 {
+        return None;
+    }
+    let (year, month, day) = (date[0], date[1], date[2]);
+    // days since 1970-01-01 of the proleptic Gregorian calendar
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+    let secs = days * 86400 + time[0] as i64 * 3600 + time[1] as i64 * 60 + time[2] as i64;
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// Signs a request with AWS Signature Version 4 and returns the signed headers and the
+/// signature. `headers` are lowercase, in name order, and include `host` and `x-amz-date`.
+fn sign(
+    secret: &str,
+    region: &str,
+    method: &str,
+    uri: &str,
+    query: &str,
+    headers: &[(&str, &str)],
+    payload: &str,
+) -> (String, String) {
+    let date = headers
+        .iter()
+        .find(|(name, _)| *name == "x-amz-date")
+        .map_or("", |(_, value)| *value);
+    let signed = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+    let canonical = format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        method,
+        uri,
+        query,
+        headers
+            .iter()
+            .map(|(name, value)| format!("{}:{}\n", name, value.trim()))
+            .collect::<String>(),
+        signed,
+        payload
+    );
+    let scope = format!("{}/{}/s3/aws4_request", &date[..8], region);
+    let to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        date,
+        scope,
+        sha256(canonical.as_bytes())
+    );
+    let mut key = hmac(format!("AWS4{}", secret).as_bytes(), &date[..8]);
+    for part in [region, "s3", "aws4_request"] {
+        key = hmac(&key, part);
+    }
+    (signed, hex(&hmac(&key, &to_sign)))
+}
+
+/// An object as listed by ListObjectsV2.
+struct Object {
+    key: String,
+    size: u64,
+    modified: SystemTime,
+    etag: String,
+}
+
+struct Listing {
+    objects: Vec<Object>,
+    // the folders, with a delimiter
+    prefixes: Vec<String>,
+}
+
+struct Client {
+    root: PathBuf,
+    // without the trailing slash
+    endpoint: String,
+    host: String,
+    region: String,
+    bucket: String,
+    // the key prefix of the workspace, without slashes around it
+    prefix: String,
+    access_key: String,
+    secret_key: String,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    fn key(&self, path: &Path) -> Result<String> {
+        let rel = path.strip_prefix(&self.root).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not in {}", path.display(), self.root.display()),
+            )
+        })?;
+        let mut parts: Vec<String> = Vec::new();
+        if !self.prefix.is_empty() {
+            parts.push(self.prefix.clone());
+        }
+        for component in rel.components() {
+            parts.push(component.as_os_str().to_string_lossy().to_string());
+        }
+        Ok(parts.join("/"))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        let rel = if self.prefix.is_empty() {
+            key
+        } else {
+            key.strip_prefix(&self.prefix).unwrap_or(key)
+        };
+        rel.split('/')
+            .filter(|part| !part.is_empty())
+            .fold(self.root.clone(), |path, part| path.join(part))
+    }
+
+    /// Sends a signed request for `key`, or for the bucket with an empty key.
+    fn send(
+        &self,
+        method: &str,
+        key: &str,
+        query: &[(&str, &str)],
+        headers: &[(&str, String)],
+        body: &[u8],
+    ) -> Result<ureq::Response> {
+        let encode = |part: &str| utf8_percent_encode(part, UNRESERVED).to_string();
+        let mut uri = format!("/{}", encode(&self.bucket));
+        if !key.is_empty() {
+            uri.push('/');
+            uri.push_str(&key.split('/').map(encode).collect::<Vec<_>>().join("/"));
+        }
+        let mut pairs: Vec<String> = query
+            .iter()
+            .map(|(name, value)| format!("{}={}", encode(name), encode(value)))
+            .collect();
+        pairs.sort();
+        let pairs = pairs.join("&");
+        let date = amz_date(SystemTime::now());
+        let payload = sha256(body);
+        let mut signed: Vec<(&str, &str)> = vec![
+            ("host", self.host.as_str()),
+            ("x-amz-content-sha256", payload.as_str()),
+            ("x-amz-date", date.as_str()),
+        ];
+        signed.extend(headers.iter().map(|(name, value)| (*name, value.as_str())));
+        signed.sort();
+        let (names, signature) = sign(
+            &self.secret_key,
+            &self.region,
+            method,
+            &uri,
+            &pairs,
+            &signed,
+            &payload,
+        );
+        let url = if pairs.is_empty() {
+            format!("{}{}", self.endpoint, uri)
+        } else {
+            format!("{}{}?{}", self.endpoint, uri, pairs)
+        };
+        let mut request = self.agent.request(method, &url).set(
+            "Authorization",
+            &format!(
+                "AWS4-HMAC-SHA256 Credential={}/{}/{}/s3/aws4_request, SignedHeaders={}, Signature={}",
+                self.access_key,
+                &date[..8],
+                self.region,
+                names,
+                signature
+            ),
+        );
+        for (name, value) in signed.iter().filter(|(name, _)| *name != "host") {
+            request = request.set(name, value);
+        }
+        let response = if method == "PUT" {
+            request.send_bytes(body)
+        } else {
+            request.call()
+        };
+        // a listing is about the folder of its prefix
+        let target = query
+            .iter()
+            .find(|(name, _)| key.is_empty() && *name == "prefix")
+            .map_or(key, |(_, prefix)| *prefix);
+        response.map_err(|e| error(method, &self.path(target), e))
+    }
+
+    /// The objects with keys starting with `prefix`, and with `delimiter` the folders
+    /// directly below it instead of what is inside them.
+    fn objects(&self, prefix: &str, delimiter: bool, max: Option<usize>) -> Result<Listing> {
+        let mut listing = Listing {
+            objects: Vec::new(),
+            prefixes: Vec::new(),
+        };
+        let max = max.map(|max| max.to_string());
+        let mut token: Option<String> = None;
+        loop {
+            let mut query = vec![("list-type", "2"), ("prefix", prefix)];
+            if delimiter {
+                query.push(("delimiter", "/"));
+            }
+            if let Some(max) = &max {
+                query.push(("max-keys", max.as_str()));
+            }
+            if let Some(token) = &token {
+                query.push(("continuation-token", token.as_str()));
+            }
+            let body = self.send("GET", "", &query, &[], b"")?.into_string()?;
+            let document = roxmltree::Document::parse(&body)
+                .map_err(|e| Error::new(ErrorKind::InvalidData, e.to_string()))?;
+            let text = |node: roxmltree::Node, name: &str| {
+                node.children()
+                    .find(|n| n.tag_name().name() == name)
+                    .and_then(|n| n.text())
+                    .unwrap_or("")
+                    .to_string()
+            };
+            let result = document.root_element();
+            for node in result.children() {
+                match node.tag_name().name() {
+                    "Contents" => listing.objects.push(Object {
+                        key: text(node, "Key"),
+                        size: text(node, "Size").parse().unwrap_or(0),
+                        // a server clock running ahead would give times in the future
+                        modified: iso_date(&text(node, "LastModified"))
+                            .unwrap_or(UNIX_EPOCH)
+                            .min(SystemTime::now()),
+                        etag: text(node, "ETag"),
+                    }),
+                    "CommonPrefixes" => listing.prefixes.push(text(node, "Prefix")),
+                    _ => {}
+                }
+            }
+            token = Some(text(result, "NextContinuationToken"));
+            if max.is_some() || text(result, "IsTruncated") != "true" {
+                break;
+            }
+        }
+        Ok(listing)
+    }
+
+    fn copy(&self, from: &str, to: &str) -> Result<()> {
+        let source = format!(
+            "/{}/{}",
+            self.bucket,
+            utf8_percent_encode(from, UNRESERVED)
+                .to_string()
+                .replace("%2F", "/")
+        );
+        self.send("PUT", to, &[], &[("x-amz-copy-source", source)], b"")?;
+        Ok(())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.send("DELETE", key, &[], &[], b"")?;
+        Ok(())
+    }
+
+    /// `key` and, for a folder, every object below it.
+    fn keys(&self, key: &str, is_dir: bool) -> Result<Vec<String>> {
+        if !is_dir {
+            return Ok(vec![key.to_string()]);
+        }
+        Ok(self
+            .objects(&format!("{}/", key), false, None)?
+            .objects
+            .into_iter()
+            .map(|object| object.key)
+            .collect())
+    }
+
+    fn snapshot(&self, dir: &Path) -> Result<Snapshot> {
+        let key = self.key(dir)?;
+        let prefix = if key.is_empty() { key } else { key + "/" };
+        let mut snapshot = Snapshot::new();
+        for object in self.objects(&prefix, false, None)?.objects {
+            let path = self.path(&object.key);
+            // folders only exist through the keys below them
+            for folder in path.ancestors().skip(1).take_while(|folder| *folder != dir) {
+                snapshot.insert(folder.to_path_buf(), (true, String::new()));
+            }
+            if object.key.ends_with('/') {
+                if path != dir {
+                    snapshot.insert(path, (true, String::new()));
+                }
+            } else {
+                snapshot.insert(path, (false, object.etag));
+            }
+        }
+        Ok(snapshot)
+    }
+}
+
+fn error(method: &str, path: &Path, error: ureq::Error) -> Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let kind = match code {
+                404 => ErrorKind::NotFound,
+                401 | 403 => ErrorKind::PermissionDenied,
+                412 => ErrorKind::AlreadyExists,
+                _ => ErrorKind::Other,
+            };
+            let status = response.status_text().to_string();
+            // S3 explains errors in an XML body
+            let message = response
+                .into_string()
+                .ok()
+                .and_then(|body| {
+                    let document = roxmltree::Document::parse(&body).ok()?;
+                    let message = document
+                        .descendants()
+                        .find(|n| n.has_tag_name("Message"))?
+                        .text()?
+                        .to_string();
+                    Some(message)
+                })
+                .unwrap_or(status);
+            Error::new(
+                kind,
+                format!("{} {}: {} {}", method, path.display(), code, message),
+            )
+        }
+        // the bucket cannot be reached, so the cache is all there is
+        ureq::Error::Transport(e) => Error::new(ErrorKind::NotConnected, e.to_string()),
+    }
+}
+
+/// Recently read documents and folder listings, kept on disk so that a workspace can still
+/// be read without a connection.
+struct Cache {
+    dir: PathBuf,
+    // the endpoint, bucket and prefix, to tell the entries of different workspaces apart
+    scope: String,
+}
+
+impl Cache {
+    fn file(&self, name: &str) -> PathBuf {
+        self.dir
+            .join(sha256(format!("{}\n{}", self.scope, name).as_bytes()))
+    }
+
+    /// The ETag and data of an entry.
+    fn get(&self, name: &str) -> Option<(String, Vec<u8>)> {
+        let data = fs::read(self.file(name)).ok()?;
+        let end = data.iter().position(|b| *b == b'\n')?;
+        Some((
+            String::from_utf8_lossy(&data[..end]).to_string(),
+            data[end + 1..].to_vec(),
+        ))
+    }
+
+    fn put(&self, name: &str, etag: &str, data: &[u8]) {
+        if fs::create_dir_all(&self.dir).is_err() {
+            return;
+        }
+        let mut content = Vec::with_capacity(etag.len() + 1 + data.len());
+        content.extend_from_slice(etag.as_bytes());
+        content.push(b'\n');
+        content.extend_from_slice(data);
+        if fs::write(self.file(name), content).is_ok() {
+            self.evict();
+        }
+    }
+
+    fn remove(&self, name: &str) {
+        let _ = fs::remove_file(self.file(name));
+    }
+
+    /// Drops the entries stored longest ago beyond `CACHE`.
+    fn evict(&self) {
+        let mut entries: Vec<(SystemTime, PathBuf)> = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .flatten()
+                .filter_map(|entry| Some((entry.metadata().ok()?.modified().ok()?, entry.path())))
+                .collect(),
+            Err(_) => return,
+        };
+        if entries.len() <= CACHE {
+            return;
+        }
+        entries.sort();
+        for (_, path) in &entries[..entries.len() - CACHE] {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+/// The documents below a key prefix of an S3-compatible bucket, mounted at
+/// `s3://bucket/prefix`. Folders are the `/` separated parts of the keys, and empty ones are
+/// kept as zero-byte objects whose key ends with `/`.
+///
+/// Writing a document only succeeds while its object still has the ETag it was last read or
+/// written with, and a new document only while there is no object for it yet. The documents
+/// read last stay in a cache on disk, which answers reads when the bucket cannot be reached.
+/// Changes are found by listing the watched folder every `POLL`.
+pub struct S3 {
+    client: Arc<Client>,
+    etags: Mutex<HashMap<PathBuf, String>>,
+    cache: Cache,
+    poller: Poller,
+}
+
+/// The folder the workspace of `bucket` is mounted at.
+pub fn root(bucket: &entity::Bucket) -> PathBuf {
+    bucket
+        .prefix
+        .split('/')
+        .filter(|part| !part.is_empty())
+        .fold(
+            PathBuf::from(format!("s3://{}", bucket.bucket)),
+            |path, part| path.join(part),
+        )
+}
+
+impl S3 {
+    /// The workspace of `bucket`, caching documents in the folder `cache`.
+    pub fn new(bucket: &entity::Bucket, cache: &Path) -> S3 {
+        let endpoint = bucket.endpoint.trim_end_matches('/').to_string();
+        let (scheme, rest) = endpoint
+            .split_once("://")
+            .unwrap_or(("https", endpoint.as_str()));
+        let host = rest.split('/').next().unwrap_or(rest);
+        let default_port = if scheme == "http" { ":80" } else { ":443" };
+        let host = host.strip_suffix(default_port).unwrap_or(host).to_string();
+        let region = if bucket.region.is_empty() {
+            "us-east-1".to_string()
+        } else {
+            bucket.region.clone()
+        };
+        S3 {
+            cache: Cache {
+                dir: cache.to_path_buf(),
+                scope: format!("{}\n{}\n{}", endpoint, bucket.bucket, bucket.prefix),
+            },
+            client: Arc::new(Client {
+                root: root(bucket),
+                prefix: bucket.prefix.trim_matches('/').to_string(),
+                endpoint,
+                host,
+                region,
+                bucket: bucket.bucket.clone(),
+                access_key: bucket.access_key.clone(),
+                secret_key: bucket.secret_key.clone(),
+                agent: ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(30))
+                    .build(),
+            }),
+            etags: Mutex::new(HashMap::new()),
+            poller: Poller::new(POLL),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> S3 {
+        self.poller = Poller::new(interval);
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.client.root
+    }
+
+    /// The entries of the folder `path` when it was last listed.
+    fn cached_list(&self, path: &Path, key: &str) -> Option<Vec<Entry>> {
+        let (_, data) = self.cache.get(&format!("list:{}", key))?;
+        let entries: Vec<(String, bool, u64, u64)> = serde_json::from_slice(&data).ok()?;
+        Some(
+            entries
+                .into_iter()
+                .map(|(name, is_dir, size, modified)| Entry {
+                    path: path.join(&name),
+                    name,
+                    stat: Stat {
+                        is_dir,
+                        size,
+                        modified: UNIX_EPOCH + Duration::from_secs(modified),
+                    },
+                })
+                .collect(),
+        )
+    }
+
+    /// Forgets the ETags of `path` and everything inside it, or moves them to `to`.
+    fn forget(&self, path: &Path, to: Option<&Path>) {
+        let mut etags = self.etags.lock().unwrap();
+        let moved: Vec<PathBuf> = etags
+            .keys()
+            .filter(|known| known.starts_with(path))
+            .cloned()
+            .collect();
+        for known in moved {
+            let etag = etags.remove(&known).unwrap();
+            if let Some(to) = to {
+                etags.insert(to.join(known.strip_prefix(path).unwrap()), etag);
+            }
+        }
+    }
+
+    fn already_exists(path: &Path) -> Error {
+        Error::new(
+            ErrorKind::AlreadyExists,
+            format!("{}: already exists", path.display()),
+        )
+    }
+}
+
+impl Storage for S3 {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        let key = self.client.key(path)?;
+        let marker = if key.is_empty() {
+            key.clone()
+        } else {
+            format!("{}/", key)
+        };
+        let listing = match self.client.objects(&marker, true, None) {
+            Ok(listing) => listing,
+            Err(e) if e.kind() == ErrorKind::NotConnected => {
+                return self.cached_list(path, &key).ok_or(e)
+            }
+            Err(e) => return Err(e),
+        };
+        let mut result: Vec<Entry> = listing
+            .prefixes
+            .iter()
+            .map(|prefix| {
+                let path = self.client.path(prefix);
+                Entry {
+                    name: path.file_name().unwrap().to_string_lossy().to_string(),
+                    path,
+                    stat: Stat {
+                        is_dir: true,
+                        size: 0,
+                        modified: UNIX_EPOCH,
+                    },
+                }
+            })
+            .collect();
+        for object in listing.objects.iter().filter(|object| object.key != marker) {
+            let path = self.client.path(&object.key);
+            result.push(Entry {
+                name: path.file_name().unwrap().to_string_lossy().to_string(),
+                path,
+                stat: Stat {
+                    is_dir: false,
+                    size: object.size,
+                    modified: object.modified,
+                },
+            });
+        }
+        if result.is_empty() && listing.objects.is_empty() && path != self.root() {
+            return Err(Error::new(
+                ErrorKind::NotFound,
+                format!("{}: no such folder", path.display()),
+            ));
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        let entries: Vec<(&str, bool, u64, u64)> = result
+            .iter()
+            .map(|entry| {
+                let modified = entry
+                    .stat
+                    .modified
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                (
+                    entry.name.as_str(),
+                    entry.stat.is_dir,
+                    entry.stat.size,
+                    modified,
+                )
+            })
+            .collect();
+        if let Ok(data) = serde_json::to_vec(&entries) {
+            self.cache.put(&format!("list:{}", key), "", &data);
+        }
+        Ok(result)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        let key = self.client.key(path)?;
+        if path == self.root() {
+            return Ok(Stat {
+                is_dir: true,
+                size: 0,
+                modified: UNIX_EPOCH,
+            });
+        }
+        // one listing of the keys starting with `key` holds the object itself or, rolled up
+        // by the delimiter, the folder of that name
+        let found = self.client.objects(&key, true, None).map(|listing| {
+            let folder = format!("{}/", key);
+            match listing.objects.iter().find(|object| object.key == key) {
+                Some(object) => Some(Stat {
+                    is_dir: false,
+                    size: object.size,
+                    modified: object.modified,
+                }),
+                None => Some(Stat {
+                    is_dir: true,
+                    size: 0,
+                    modified: UNIX_EPOCH,
+                })
+                .filter(|_| listing.prefixes.contains(&folder)),
+            }
+        });
+        let not_found = || {
+            Error::new(
+                ErrorKind::NotFound,
+                format!("{}: no such file or folder", path.display()),
+            )
+        };
+        match found {
+            Ok(found) => found.ok_or_else(not_found),
+            Err(e) if e.kind() == ErrorKind::NotConnected => {
+                let parent = path.parent().ok_or_else(not_found)?;
+                let entries = self
+                    .cached_list(parent, &self.client.key(parent)?)
+                    .ok_or(e)?;
+                entries
+                    .into_iter()
+                    .find(|entry| entry.path == path)
+                    .map(|entry| entry.stat)
+                    .ok_or_else(not_found)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let key = self.client.key(path)?;
+        let name = format!("object:{}", key);
+        let cached = self.cache.get(&name);
+        let headers: Vec<(&str, String)> = cached
+            .iter()
+            .map(|(etag, _)| ("if-none-match", etag.clone()))
+            .collect();
+        let (etag, data) = match self.client.send("GET", &key, &[], &headers, b"") {
+            Ok(response) if response.status() == 304 => cached.unwrap(),
+            Ok(response) => {
+                let etag = response.header("ETag").unwrap_or("").to_string();
+                let mut data = Vec::new();
+                response.into_reader().read_to_end(&mut data)?;
+                (etag, data)
+            }
+            // offline
+            Err(e) if e.kind() == ErrorKind::NotConnected && cached.is_some() => cached.unwrap(),
+            Err(e) => return Err(e),
+        };
+        self.cache.put(&name, &etag, &data);
+        let mut etags = self.etags.lock().unwrap();
+        if etag.is_empty() {
+            etags.remove(path);
+        } else {
+            etags.insert(path.to_path_buf(), etag);
+        }
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let key = self.client.key(path)?;
+        // an object without a known ETag must not exist yet, so nothing unseen is overwritten
+        let known = self.etags.lock().unwrap().get(path).cloned();
+        let headers = match &known {
+            Some(etag) => vec![("if-match", etag.clone())],
+            None => vec![("if-none-match", "*".to_string())],
+        };
+        let response = match self.client.send("PUT", &key, &[], &headers, data) {
+            Ok(response) => response,
+            // only the preconditions can fail
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "{}: {} in the bucket since it was opened, reload it before saving",
+                        path.display(),
+                        if known.is_some() {
+                            "changed"
+                        } else {
+                            "created"
+                        }
+                    ),
+                ))
+            }
+            Err(e) => return Err(e),
+        };
+        let etag = response.header("ETag").unwrap_or("").to_string();
+        self.cache.put(&format!("object:{}", key), &etag, data);
+        let mut etags = self.etags.lock().unwrap();
+        if etag.is_empty() {
+            etags.remove(path);
+        } else {
+            etags.insert(path.to_path_buf(), etag);
+        }
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        if self.exists(path) {
+            return Err(S3::already_exists(path));
+        }
+        let key = format!("{}/", self.client.key(path)?);
+        self.client.send("PUT", &key, &[], &[], b"")?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let is_dir = self.stat(from)?.is_dir;
+        if self.exists(to) {
+            return Err(S3::already_exists(to));
+        }
+        let (from_key, to_key) = (self.client.key(from)?, self.client.key(to)?);
+        // objects cannot be renamed, only copied
+        for key in self.client.keys(&from_key, is_dir)? {
+            self.client
+                .copy(&key, &format!("{}{}", to_key, &key[from_key.len()..]))?;
+            self.client.delete(&key)?;
+            self.cache.remove(&format!("object:{}", key));
+        }
+        self.forget(from, Some(to));
+        Ok(())
+    }
+
+    /// Copies in the bucket, without the document being read.
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        let to_key = self.client.key(to)?;
+        self.client.copy(&self.client.key(from)?, &to_key)?;
+        self.cache.remove(&format!("object:{}", to_key));
+        self.forget(to, None);
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        let is_dir = self.stat(path)?.is_dir;
+        for key in self.client.keys(&self.client.key(path)?, is_dir)? {
+            self.client.delete(&key)?;
+            self.cache.remove(&format!("object:{}", key));
+        }
+        self.forget(path, None);
+        Ok(())
+    }
+
+    fn watch(&self, path: &Path, handler: Handler) -> Result<()> {
+        let client = self.client.clone();
+        let root = path.to_path_buf();
+        self.poller
+            .watch(path, move || client.snapshot(&root), handler)
+    }
+
+    fn unwatch(&self, path: &Path) -> Result<()> {
+        self.poller.unwatch(path);
+        Ok(())
+    }
+}
+
+/// Mounts the workspace of `bucket` and returns the folder to open it with. A bucket that
+/// cannot be reached still opens from the cache when its top folder was listed before.
+pub fn connect(bucket: &entity::Bucket, cache: &Path) -> Result<PathBuf> {
+    if !bucket.endpoint.starts_with("http://") && !bucket.endpoint.starts_with("https://") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not an http or https URL", bucket.endpoint),
+        ));
+    }
+    let s3 = S3::new(bucket, cache);
+    let root = s3.root().to_path_buf();
+    // checks the credentials
+    s3.list(&root)?;
+    storage::mount(&root, Arc::new(s3));
+    Ok(root)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::BTreeMap,
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        sync::atomic::{AtomicBool, AtomicUsize, Ordering},
+        thread,
+    };
+
+    use percent_encoding::percent_decode_str;
+
+    use super::*;
+    use crate::{links::LinkIndex, storage::Change, tags::TagIndex, testing::TempDir};
+
+    /// Objects by key, with their ETag.
+    type Objects = Arc<Mutex<BTreeMap<String, (Vec<u8>, String)>>>;
+
+    static VERSION: AtomicUsize = AtomicUsize::new(0);
+
+    fn put(objects: &Objects, key: &str, data: &[u8]) {
+        let etag = format!("\"{}\"", VERSION.fetch_add(1, Ordering::Relaxed));
+        objects
+            .lock()
+            .unwrap()
+            .insert(key.to_string(), (data.to_vec(), etag));
+    }
+
+    /// A stand-in for an S3 server on a free local port, with the single bucket `docs`. While
+    /// `offline` is set it closes every connection without an answer.
+    fn serve(objects: Objects, offline: Arc<AtomicBool>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                if !offline.load(Ordering::Relaxed) {
+                    let _ = handle(&objects, stream);
+                }
+            }
+        });
+        format!("http://127.0.0.1:{}", port)
+    }
+
+    fn handle(objects: &Objects, mut stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("").to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string())
+                }
+                None => break,
+            };
+        }
+        let length = headers
+            .get("content-length")
+            .map_or(0, |l| l.parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let decode = |text: &str| percent_decode_str(text).decode_utf8_lossy().to_string();
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        let key = decode(
+            path.strip_prefix("/docs")
+                .unwrap_or(path)
+                .trim_start_matches('/'),
+        );
+        let query: HashMap<String, String> = query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(name, value)| (decode(name), decode(value)))
+            .collect();
+        let etag = |key: &str| {
+            objects
+                .lock()
+                .unwrap()
+                .get(key)
+                .map(|(_, etag)| etag.clone())
+        };
+        let empty = || (String::new(), Vec::new());
+        let authorized = headers.get("authorization").map_or(false, |auth| {
+            auth.starts_with("AWS4-HMAC-SHA256 Credential=AKID/")
+                && headers.contains_key("x-amz-date")
+        });
+        let (status, (extra, content)) = match method.as_str() {
+            _ if !authorized => ("403 Forbidden", empty()),
+            "GET" if query.contains_key("list-type") => {
+                let prefix = query.get("prefix").cloned().unwrap_or_default();
+                let delimiter = query.contains_key("delimiter");
+                let mut items: Vec<(String, bool)> = Vec::new();
+                for key in objects.lock().unwrap().keys() {
+                    let rest = match key.strip_prefix(&prefix) {
+                        Some(rest) => rest,
+                        None => continue,
+                    };
+                    let item = match rest.find('/').filter(|_| delimiter) {
+                        Some(i) => (format!("{}{}", prefix, &rest[..=i]), true),
+                        None => (key.clone(), false),
+                    };
+                    if !items.contains(&item) {
+                        items.push(item);
+                    }
+                }
+                let token = query.get("continuation-token").cloned().unwrap_or_default();
+                items.retain(|(item, _)| *item > token);
+                // small pages, to list the way large buckets are listed
+                let page = query.get("max-keys").map_or(2, |max| max.parse().unwrap());
+                let truncated = items.len() > page;
+                items.truncate(page);
+                let mut xml = String::from(
+                    r#"<ListBucketResult xmlns="http://s3.amazonaws.com/doc/2006-03-01/">"#,
+                );
+                xml.push_str(&format!("<IsTruncated>{}</IsTruncated>", truncated));
+                if truncated {
+                    xml.push_str(&format!(
+                        "<NextContinuationToken>{}</NextContinuationToken>",
+                        items.last().unwrap().0
+                    ));
+                }
+                for (item, is_prefix) in &items {
+                    if *is_prefix {
+                        xml.push_str(&format!(
+                            "<CommonPrefixes><Prefix>{}</Prefix></CommonPrefixes>",
+                            item
+                        ));
+                    } else {
+                        let (data, etag) = objects.lock().unwrap()[item].clone();
+                        xml.push_str(&format!(
+                            "<Contents><Key>{}</Key><LastModified>2026-01-12T10:00:00.000Z</LastModified>\
+                             <ETag>{}</ETag><Size>{}</Size></Contents>",
+                            item,
+                            etag.replace('"', "&quot;"),
+                            data.len()
+                        ));
+                    }
+                }
+                xml.push_str("</ListBucketResult>");
+                ("200 OK", (String::new(), xml.into_bytes()))
+            }
+            "GET" => match objects.lock().unwrap().get(&key).cloned() {
+                Some((_, etag)) if headers.get("if-none-match") == Some(&etag) => {
+                    ("304 Not Modified", empty())
+                }
+                Some((data, etag)) => ("200 OK", (format!("ETag: {}\r\n", etag), data)),
+                None => (
+                    "404 Not Found",
+                    (
+                        String::new(),
+                        b"<Error><Code>NoSuchKey</Code><Message>The specified key does not exist.</Message></Error>".to_vec(),
+                    ),
+                ),
+            },
+            "PUT" if headers.contains_key("x-amz-copy-source") => {
+                let source = decode(&headers["x-amz-copy-source"]);
+                let source = source.strip_prefix("/docs/").unwrap();
+                let source = objects.lock().unwrap().get(source).cloned();
+                match source {
+                    Some((data, _)) => {
+                        put(objects, &key, &data);
+                        ("200 OK", empty())
+                    }
+                    None => ("404 Not Found", empty()),
+                }
+            }
+            "PUT" if headers
+                .get("if-match")
+                .map_or(false, |m| Some(m) != etag(&key).as_ref())
+                || headers.get("if-none-match").map(String::as_str) == Some("*")
+                    && etag(&key).is_some() =>
+            {
+                ("412 Precondition Failed", empty())
+            }
+            "PUT" => {
+                put(objects, &key, &body);
+                (
+                    "200 OK",
+                    (format!("ETag: {}\r\n", etag(&key).unwrap()), Vec::new()),
+                )
+            }
+            "DELETE" => {
+                objects.lock().unwrap().remove(&key);
+                ("204 No Content", empty())
+            }
+            _ => ("405 Method Not Allowed", empty()),
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nConnection: close\r\n{}Content-Length: {}\r\n\r\n",
+            status,
+            extra,
+            content.len()
+        )?;
+        stream.write_all(&content)
+    }
+
+    #[test]
+    fn test_sign() {
+        // the GET Object example of the Signature Version 4 documentation
+        let (signed, signature) = sign(
+            "wJalrXUtnFEMI/K7MDENG/bPxRfiCYEXAMPLEKEY",
+            "us-east-1",
+            "GET",
+            "/test.txt",
+            "",
+            &[
+                ("host", "examplebucket.s3.amazonaws.com"),
+                ("range", "bytes=0-9"),
+                (
+                    "x-amz-content-sha256",
+                    "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+                ),
+                ("x-amz-date", "20130524T000000Z"),
+            ],
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855",
+        );
+        assert_eq!(signed, "host;range;x-amz-content-sha256;x-amz-date");
+        assert_eq!(
+            signature,
+            "f0e8bdb87c964420e857bd35b5d6ed310bd44f0170aba48dd91039c6036bdb41"
+        );
+        let time = UNIX_EPOCH + Duration::from_secs(1369353600);
+        assert_eq!(amz_date(time), "20130524T000000Z");
+        assert_eq!(iso_date("2013-05-24T00:00:00.000Z"), Some(time));
+    }
+
+    #[test]
+    fn test_s3() {
+        let temp = TempDir::new("s3");
+        let cache = temp.join("s3");
+        let objects: Objects = Arc::new(Mutex::new(BTreeMap::new()));
+        put(&objects, "other/x.md", b"# X");
+        let offline = Arc::new(AtomicBool::new(false));
+        let bucket = entity::Bucket {
+            endpoint: serve(objects.clone(), offline.clone()),
+            region: String::new(),
+            bucket: "docs".to_string(),
+            prefix: "/team/handbook/".to_string(),
+            access_key: "AKID".to_string(),
+            secret_key: "secret".to_string(),
+        };
+        // changes are polled by the test itself
+        let s3 = S3::new(&bucket, &cache).with_interval(Duration::from_secs(3600));
+        let root = s3.root().to_path_buf();
+        assert_eq!(root, PathBuf::from("s3://docs/team/handbook"));
+        assert!(s3.list(&root).unwrap().is_empty());
+
+        s3.create_dir(&root.join("guide")).unwrap();
+        assert!(s3.create_dir(&root.join("guide")).is_err());
+        let a = root.join("guide/a b.md");
+        for (path, content) in [
+            (a.clone(), "# A"),
+            (root.join("guide/b.md"), "# B"),
+            (root.join("guide/c.md"), "# C"),
+            (root.join("index.md"), "# Home"),
+        ] {
+            s3.write(&path, content.as_bytes()).unwrap();
+        }
+        assert!(objects
+            .lock()
+            .unwrap()
+            .contains_key("team/handbook/guide/a b.md"));
+        let names: Vec<String> = s3
+            .list(&root)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["guide", "index.md"]);
+        assert_eq!(s3.list(&root.join("guide")).unwrap().len(), 3);
+        assert!(s3.stat(&root.join("guide")).unwrap().is_dir);
+        assert_eq!(s3.stat(&root.join("index.md")).unwrap().size, 6);
+        assert!(!s3.exists(&root.join("guide/nope.md")));
+        assert_eq!(s3.read_to_string(&a).unwrap(), "# A");
+
+        // someone else saves the document in the meantime
+        put(&objects, "team/handbook/guide/a b.md", b"# Theirs");
+        let error = s3.write(&a, b"# Mine").unwrap_err();
+        assert!(error.to_string().contains("changed in the bucket"));
+        assert_eq!(s3.read_to_string(&a).unwrap(), "# Theirs");
+        s3.write(&a, b"# Mine").unwrap();
+        s3.write(&a, b"# Mine!").unwrap();
+        // an object created by someone else is not overwritten before it has been read
+        let e = root.join("e.md");
+        put(&objects, "team/handbook/e.md", b"# E");
+        let error = s3.write(&e, b"# Mine").unwrap_err();
+        assert!(error.to_string().contains("created in the bucket"));
+        s3.read(&e).unwrap();
+        s3.write(&e, b"# Mine").unwrap();
+        s3.delete(&e).unwrap();
+        // a sibling sharing the name as a prefix is neither the file nor the folder
+        put(&objects, "team/handbook/guide-old.md", b"");
+        assert!(s3.stat(&root.join("guide")).unwrap().is_dir);
+        assert!(!s3.exists(&root.join("guide-old")));
+        objects.lock().unwrap().remove("team/handbook/guide-old.md");
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = changes.clone();
+        s3.watch(
+            &root,
+            Box::new(move |change| seen.lock().unwrap().push(change)),
+        )
+        .unwrap();
+        put(&objects, "team/handbook/guide/d.md", b"# D");
+        s3.poller.poll(&root);
+        s3.unwatch(&root).unwrap();
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![Change::Create(root.join("guide/d.md"), false)]
+        );
+
+        // the same bucket without a connection
+        offline.store(true, Ordering::Relaxed);
+        let reopened = S3::new(&bucket, &cache);
+        assert_eq!(reopened.read_to_string(&a).unwrap(), "# Mine!");
+        assert_eq!(reopened.list(&root).unwrap().len(), 2);
+        assert!(reopened.is_dir(&root.join("guide")));
+        assert_eq!(
+            reopened.write(&a, b"# Offline").unwrap_err().kind(),
+            ErrorKind::NotConnected
+        );
+        assert!(reopened.read(&root.join("guide/d.md")).is_err());
+        // another prefix of the bucket does not share the cache
+        let other = S3::new(
+            &entity::Bucket {
+                prefix: "other".to_string(),
+                ..bucket.clone()
+            },
+            &cache,
+        );
+        assert!(other.list(other.root()).is_err());
+        offline.store(false, Ordering::Relaxed);
+
+        s3.copy(&a, &root.join("a.md")).unwrap();
+        s3.rename(&root.join("guide"), &root.join("docs")).unwrap();
+        assert_eq!(s3.list(&root.join("docs")).unwrap().len(), 4);
+        assert!(!s3.exists(&root.join("guide")));
+        s3.delete(&root.join("docs")).unwrap();
+        assert!(!s3.exists(&root.join("docs")));
+        let keys: Vec<String> = objects.lock().unwrap().keys().cloned().collect();
+        assert_eq!(
+            keys,
+            vec!["other/x.md", "team/handbook/a.md", "team/handbook/index.md"]
+        );
+
+        // links and tags of a mounted bucket
+        put(
+            &objects,
+            "team/handbook/notes/n.md",
+            b"[Home](../index.md) [[index]] #handbook #team/docs\n",
+        );
+        storage::mount(&root, Arc::new(S3::new(&bucket, &cache)));
+        let links = LinkIndex::default();
+        links.build(&root);
+        let home = "s3://docs/team/handbook/index.md";
+        let targets: Vec<String> = links
+            .outgoing("s3://docs/team/handbook/notes/n.md")
+            .into_iter()
+            .map(|link| link.target)
+            .collect();
+        assert_eq!(targets, vec![home, home]);
+        assert_eq!(links.backlinks(home).len(), 2);
+        let tags = TagIndex::default();
+        tags.build(&root);
+        assert_eq!(
+            tags.files("team/docs"),
+            vec!["s3://docs/team/handbook/notes/n.md"]
+        );
+        assert_eq!(tags.tags().len(), 2);
+        storage::unmount(&root);
+    }
+}
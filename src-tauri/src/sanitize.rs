@@ -0,0 +1,295 @@
+use std::{collections::BTreeMap, path::Path};
+
+use kuchiki::{traits::TendrilSink, NodeRef};
+
+use crate::{entity, history, storage};
+
+/// Tags dropped together with everything inside them, whatever the allowlist says.
+const DROP: [&str; 12] = [
+    "script", "style", "iframe", "frame", "frameset", "object", "embed", "applet", "noscript",
+    "template", "base", "meta",
+];
+
+/// Attributes holding a URL, checked against the allowed schemes.
+const URLS: [&str; 7] = [
+    "href",
+    "src",
+    "cite",
+    "action",
+    "poster",
+    "background",
+    // the link WangEditor keeps on images
+    "data-href",
+];
+
+/// Tags, attributes and URL schemes the WangEditor view produces.
+pub fn default_allowlist() -> entity::Allowlist {
+    let strings = |items: &[&str]| items.iter().map(|s| s.to_string()).collect::<Vec<_>>();
+    let mut attributes = BTreeMap::new();
+    attributes.insert(
+        "*".to_string(),
+        strings(&[
+            "id",
+            "class",
+            "style",
+            "title",
+            "lang",
+            "dir",
+            "data-w-e-type",
+        ]),
+    );
+    attributes.insert("a".to_string(), strings(&["href", "target", "rel"]));
+    attributes.insert(
+        "img".to_string(),
+        strings(&["src", "alt", "width", "height", "data-href"]),
+    );
+    attributes.insert(
+        "video".to_string(),
+        strings(&["src", "poster", "controls", "width", "height"]),
+    );
+    attributes.insert("source".to_string(), strings(&["src", "type"]));
+    attributes.insert("td".to_string(), strings(&["colspan", "rowspan", "width"]));
+    attributes.insert("th".to_string(), strings(&["colspan", "rowspan", "width"]));
+    attributes.insert("col".to_string(), strings(&["span", "width"]));
+    attributes.insert("ol".to_string(), strings(&["start", "type"]));
+    attributes.insert(
+        "input".to_string(),
+        strings(&["type", "checked", "disabled"]),
+    );
+    attributes.insert("blockquote".to_string(), strings(&["cite"]));
+    entity::Allowlist {
+        tags: strings(&[
+            "a",
+            "b",
+            "blockquote",
+            "br",
+            "code",
+            "col",
+            "colgroup",
+            "del",
+            "div",
+            "em",
+            "h1",
+            "h2",
+            "h3",
+            "h4",
+            "h5",
+            "h6",
+            "hr",
+            "i",
+            "img",
+            "input",
+            "kbd",
+            "li",
+            "mark",
+            "ol",
+            "p",
+            "pre",
+            "s",
+            "source",
+            "span",
+            "strike",
+            "strong",
+            "sub",
+            "sup",
+            "table",
+            "tbody",
+            "td",
+            "tfoot",
+            "th",
+            "thead",
+            "tr",
+            "u",
+            "ul",
+            "video",
+        ]),
+        attributes,
+        schemes: strings(&["http", "https", "mailto", "tel"]),
+    }
+}
+
+/// Workspace file overriding the default allowlist.
+pub fn allowlist_path(root: &Path) -> std::path::PathBuf {
+    root.join(".ahriknow").join("sanitize.json")
+}
+
+/// The allowlist of the workspace holding `path`, falling back to the default one.
+pub fn allowlist(path: &Path) -> entity::Allowlist {
+    let storage = storage::for_path(path);
+    let root = if storage.is_dir(&path.join(".ahriknow")) {
+        Some(path.to_path_buf())
+    } else {
+        history::root(path)
+    };
+    root.and_then(|root| storage.read_to_string(&allowlist_path(&root)).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_else(default_allowlist)
+}
+
+fn name(node: &NodeRef) -> String {
+    node.as_element()
+        .map(|element| element.name.local.to_string())
+        .unwrap_or_default()
+}
+
+/// Whether `url` is relative, an anchor, or uses one of `schemes`. Images may also be
+/// inline `data:image/` URLs.
+fn safe_url(url: &str, schemes: &[String], image: bool) -> bool {
+    // browsers ignore control characters and whitespace inside the scheme
+    let url: String = url
+        .chars()
+        .filter(|c| !c.is_whitespace() && !c.is_control())
+        .collect::<String>()
+        .to_lowercase();
+    let scheme = match url.find(':') {
+        Some(i) if !url[..i].contains(['/', '?', '#']) => &url[..i],
+        _ => return true,
+    };
+    if image && url.starts_with("data:image/") && !url.starts_with("data:image/svg") {
+        return true;
+    }
+    schemes.iter().any(|s| s.eq_ignore_ascii_case(scheme))
+}
+
+fn safe_style(style: &str) -> bool {
+    let style = style.to_lowercase();
+    ![
+        "expression(",
+        "javascript:",
+        "vbscript:",
+        "url(",
+        "@import",
+        "behavior:",
+    ]
+    .iter()
+    .any(|s| style.contains(s))
+}
+
+/// Removes everything from an HTML fragment the allowlist does not permit. Returns the
+/// cleaned fragment and a description of each removal; the fragment is returned unchanged
+/// when nothing had to go.
+pub fn sanitize(html: &str, allowlist: &entity::Allowlist) -> (String, Vec<String>) {
+    // a leading `<body>` keeps `<style>` and friends from moving into a `<head>`
+    let document = kuchiki::parse_html().one(format!("<body>{}", html));
+    let body = match document.select_first("body") {
+        Ok(body) => body.as_node().clone(),
+        Err(_) => return (html.to_string(), Vec::new()),
+    };
+    let global = allowlist.attributes.get("*");
+    let mut stripped = Vec::new();
+    for node in body.descendants().collect::<Vec<NodeRef>>() {
+        if !node.ancestors().any(|a| a.as_document().is_some()) {
+            // inside something already removed
+            continue;
+        }
+        let element = match node.as_element() {
+            Some(element) => element,
+            None => continue,
+        };
+        let tag = name(&node);
+        if DROP.contains(&tag.as_str()) {
+            stripped.push(format!("removed <{}>", tag));
+            node.detach();
+            continue;
+        }
+        if !allowlist.tags.iter().any(|t| t.eq_ignore_ascii_case(&tag)) {
+            stripped.push(format!("unwrapped <{}>", tag));
+            for child in node.children().collect::<Vec<NodeRef>>() {
+                node.insert_before(child);
+            }
+            node.detach();
+            continue;
+        }
+        let allowed = allowlist.attributes.get(&tag);
+        let mut attributes = element.attributes.borrow_mut();
+        let names: Vec<String> = attributes
+            .map
+            .keys()
+            .map(|name| name.local.to_string())
+            .collect();
+        for attribute in names {
+            let value = attributes.get(attribute.as_str()).unwrap_or("").to_string();
+            // event handlers never pass, even when listed
+            let reason = if attribute.starts_with("on")
+                || (!global.map_or(false, |g| g.contains(&attribute))
+                    && !allowed.map_or(false, |a| a.contains(&attribute)))
+            {
+                Some(format!("removed {} from <{}>", attribute, tag))
+            } else if URLS.contains(&attribute.as_str())
+                && !safe_url(
+                    &value,
+                    &allowlist.schemes,
+                    tag == "img" && attribute == "src",
+                )
+            {
+                Some(format!("removed unsafe {} from <{}>", attribute, tag))
+            } else if attribute == "style" && !safe_style(&value) {
+                Some(format!("removed unsafe style from <{}>", tag))
+            } else {
+                None
+            };
+            if let Some(reason) = reason {
+                attributes.remove(attribute.as_str());
+                stripped.push(reason);
+            }
+        }
+    }
+    if stripped.is_empty() {
+        return (html.to_string(), stripped);
+    }
+    let result = body.children().map(|child| child.to_string()).collect();
+    (result, stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sanitize() {
+        let allowlist = default_allowlist();
+        let html = "<p onclick=\"x()\">a <font>b</font></p><script>alert(1)</script>\
+            <a href=\" java\tscript:alert(1)\">c</a><a href=\"b.md#x\">d</a>\
+            <img src=\"data:image/png;base64,AA\" style=\"background: url(x)\">";
+        let (result, stripped) = sanitize(html, &allowlist);
+        assert_eq!(
+            result,
+            "<p>a b</p><a>c</a><a href=\"b.md#x\">d</a><img src=\"data:image/png;base64,AA\">"
+        );
+        assert_eq!(
+            stripped,
+            vec![
+                "removed onclick from <p>",
+                "unwrapped <font>",
+                "removed <script>",
+                "removed unsafe href from <a>",
+                "removed unsafe style from <img>",
+            ]
+        );
+        let (result, stripped) = sanitize(
+            "<img src=\"a.png\" data-href=\"javascript:alert(1)\"><img data-href=\"data:image/png;base64,AA\">",
+            &allowlist,
+        );
+        assert_eq!(result, "<img src=\"a.png\"><img>");
+        assert_eq!(stripped, vec!["removed unsafe data-href from <img>"; 2]);
+        let clean = "<p class=\"x\">a<br></p>";
+        assert_eq!(sanitize(clean, &allowlist), (clean.to_string(), Vec::new()));
+    }
+
+    #[test]
+    fn test_allowlist_in_storage() {
+        let root = Path::new("/memory/sanitize");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        storage.create_dir(&root.join(".ahriknow")).unwrap();
+        storage
+            .write(
+                &allowlist_path(root),
+                br#"{"tags": ["p"], "attributes": {}, "schemes": []}"#,
+            )
+            .unwrap();
+        assert_eq!(allowlist(&root.join("a.ahtml")).tags, vec!["p"]);
+        assert_eq!(allowlist(root).tags, vec!["p"]);
+        storage::unmount(root);
+    }
+}
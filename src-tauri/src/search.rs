@@ -0,0 +1,267 @@
+use std::collections::{BTreeMap, HashMap};
+
+use kuchiki::traits::TendrilSink;
+use serde_json::json;
+
+use crate::entity;
+
+/// Format version of the emitted index.
+pub const VERSION: i32 = 1;
+
+const TITLE: u32 = 10;
+const HEADING: u32 = 5;
+const BODY: u32 = 1;
+/// Characters of body text kept for showing a result.
+const EXCERPT: usize = 160;
+
+/// Search page script, tokenizing queries like `tokens` and reading the index from
+/// `assets/search-index.js` so that it also works from `file://`.
+pub const SCRIPT: &str = r#"(function () {
+  var data = window.SEARCH_INDEX;
+  var keys = Object.keys(data.index);
+  function isCjk(c) {
+    var n = c.codePointAt(0);
+    return (n >= 0x3040 && n <= 0x30ff) || (n >= 0x3400 && n <= 0x4dbf) || (n >= 0x4e00 && n <= 0x9fff)
+      || (n >= 0xac00 && n <= 0xd7af) || (n >= 0xf900 && n <= 0xfaff);
+  }
+  function tokens(text) {
+    var result = [], word = "", run = [];
+    function flushWord() {
+      if (Array.from(word).length > 1 || /\p{N}/u.test(word)) result.push(word);
+      word = "";
+    }
+    function flushRun() {
+      if (run.length === 1) result.push(run[0]);
+      for (var i = 0; i + 1 < run.length; i++) result.push(run[i] + run[i + 1]);
+      run = [];
+    }
+    Array.from(text.toLowerCase()).forEach(function (c) {
+      if (isCjk(c)) { flushWord(); run.push(c); }
+      else if (/[\p{Alphabetic}\p{N}]/u.test(c)) { flushRun(); word += c; }
+      else { flushWord(); flushRun(); }
+    });
+    flushWord();
+    flushRun();
+    return result;
+  }
+  function search(query) {
+    var total = null;
+    var all = tokens(query);
+    all.forEach(function (token, i) {
+      var scores = {};
+      // the last word may still be being typed, so it also matches as a prefix
+      var prefix = i === all.length - 1 && !isCjk(token);
+      keys.forEach(function (key) {
+        if (key === token || (prefix && key.indexOf(token) === 0)) {
+          data.index[key].forEach(function (p) { scores[p[0]] = (scores[p[0]] || 0) + p[1]; });
+        }
+      });
+      if (total === null) { total = scores; return; }
+      var next = {};
+      Object.keys(total).forEach(function (doc) {
+        if (doc in scores) next[doc] = total[doc] + scores[doc];
+      });
+      total = next;
+    });
+    return Object.keys(total || {})
+      .map(function (doc) { return [data.docs[doc], total[doc]]; })
+      .sort(function (a, b) { return b[1] - a[1]; })
+      .map(function (r) { return r[0]; });
+  }
+  var input = document.getElementById("search-input");
+  var results = document.getElementById("search-results");
+  function update() {
+    results.innerHTML = "";
+    search(input.value).slice(0, 50).forEach(function (doc) {
+      var li = document.createElement("li");
+      var a = document.createElement("a");
+      a.href = doc.u;
+      a.textContent = doc.t;
+      var p = document.createElement("p");
+      p.textContent = doc.e;
+      li.appendChild(a);
+      li.appendChild(p);
+      results.appendChild(li);
+    });
+    history.replaceState(null, "", "?q=" + encodeURIComponent(input.value));
+  }
+  input.value = new URLSearchParams(location.search).get("q") || "";
+  input.addEventListener("input", update);
+  update();
+})();
+"#;
+
+/// Han, kana and hangul are written without spaces between words.
+pub fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3040}'..='\u{30ff}'
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}'
+        | '\u{f900}'..='\u{faff}')
+}
+
+/// Splits text into lowercase search tokens: words for alphabetic scripts and overlapping
+/// character pairs for CJK runs, which needs no dictionary and matches any substring of
+/// two or more characters. The search page tokenizes queries the same way.
+pub fn tokens(text: &str) -> Vec<String> {
+    fn flush_word(word: &mut String, result: &mut Vec<String>) {
+        if word.chars().count() > 1 || word.chars().any(|c| c.is_numeric()) {
+            result.push(word.clone());
+        }
+        word.clear();
+    }
+    fn flush_run(run: &mut Vec<char>, result: &mut Vec<String>) {
+        if run.len() == 1 {
+            result.push(run[0].to_string());
+        }
+        for pair in run.windows(2) {
+            result.push(pair.iter().collect());
+        }
+        run.clear();
+    }
+    let mut result = Vec::new();
+    let mut word = String::new();
+    let mut run = Vec::new();
+    // lowercased as a whole like `toLowerCase`, which treats a final sigma differently
+    for c in text.to_lowercase().chars() {
+        if is_cjk(c) {
+            flush_word(&mut word, &mut result);
+            run.push(c);
+        } else if c.is_alphanumeric() {
+            flush_run(&mut run, &mut result);
+            word.push(c);
+        } else {
+            flush_word(&mut word, &mut result);
+            flush_run(&mut run, &mut result);
+        }
+    }
+    flush_word(&mut word, &mut result);
+    flush_run(&mut run, &mut result);
+    result
+}
+
+/// Text content of an HTML fragment with whitespace collapsed.
+pub fn text(html: &str) -> String {
+    let document = kuchiki::parse_html().one(format!("<body>{}", html));
+    document
+        .text_contents()
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .join(" ")
+}
+
+/// Index of a set of pages, serialized as compact JSON:
+/// `{"version", "docs": [{"t": title, "u": url, "h": [[heading, anchor]], "e": excerpt}],
+/// "index": {token: [[doc, score], ...]}}`.
+#[derive(Default)]
+pub struct SearchIndex {
+    docs: Vec<serde_json::Value>,
+    index: BTreeMap<String, Vec<(usize, u32)>>,
+}
+
+impl SearchIndex {
+    pub fn add(&mut self, url: &str, title: &str, headings: &[entity::Heading], html: &str) {
+        let body = text(html);
+        let mut scores: HashMap<String, u32> = HashMap::new();
+        for (text, weight) in std::iter::once((title, TITLE))
+            .chain(headings.iter().map(|h| (h.text.as_str(), HEADING)))
+            .chain(std::iter::once((body.as_str(), BODY)))
+        {
+            for token in tokens(text) {
+                *scores.entry(token).or_insert(0) += weight;
+            }
+        }
+        let doc = self.docs.len();
+        for (token, score) in scores {
+            self.index.entry(token).or_default().push((doc, score));
+        }
+        let excerpt: String = body.chars().take(EXCERPT).collect();
+        self.docs.push(json!({
+            "t": title,
+            "u": url,
+            "h": headings.iter().map(|h| json!([h.text, h.slug])).collect::<Vec<_>>(),
+            "e": excerpt,
+        }));
+    }
+
+    pub fn to_json(&self) -> String {
+        let index: serde_json::Map<String, serde_json::Value> = self
+            .index
+            .iter()
+            .map(|(token, postings)| {
+                let mut postings = postings.clone();
+                postings.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+                (token.clone(), json!(postings))
+            })
+            .collect();
+        json!({
+            "version": VERSION,
+            "docs": self.docs,
+            "index": index,
+        })
+        .to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokens() {
+        assert_eq!(
+            tokens("Hello, World! 搜索引擎 a 2 中"),
+            vec!["hello", "world", "搜索", "索引", "引擎", "2", "中"]
+        );
+        assert_eq!(tokens("Rust语言v2"), vec!["rust", "语言", "v2"]);
+    }
+
+    /// The search page must split queries exactly like the index, so its tokenizer is run
+    /// under Node on the same samples. Skipped when Node is not installed.
+    #[test]
+    fn test_script_tokens() {
+        let samples = [
+            "Hello, World! 搜索引擎 a 2 中",
+            "Rust语言v2",
+            "ひらがなとカタカナ 한국어 검색",
+            "ΟΔΟΣ Straße İstanbul",
+            "हिन्दी ٣ ² x",
+            "café_naïve 1.5 e\u{301}",
+        ];
+        let start = SCRIPT.find("  function isCjk").unwrap();
+        let end = SCRIPT.find("  function search").unwrap();
+        let program = format!(
+            "{}console.log(JSON.stringify({}.map(tokens)));",
+            &SCRIPT[start..end],
+            serde_json::to_string(&samples).unwrap()
+        );
+        let output = match std::process::Command::new("node")
+            .arg("-e")
+            .arg(program)
+            .output()
+        {
+            Ok(output) => output,
+            Err(_) => return,
+        };
+        assert!(
+            output.status.success(),
+            "{}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        let script: Vec<Vec<String>> = serde_json::from_slice(&output.stdout).unwrap();
+        for (sample, tokens_js) in samples.iter().zip(script) {
+            assert_eq!(tokens(sample), tokens_js, "{}", sample);
+        }
+    }
+
+    #[test]
+    fn test_index() {
+        let mut index = SearchIndex::default();
+        index.add("a.html", "Guide", &[], "<p>guide to <b>搜索</b></p>");
+        assert_eq!(
+            index.to_json(),
+            r#"{"version":1,"docs":[{"t":"Guide","u":"a.html","h":[],"e":"guide to 搜索"}],"index":{"guide":[[0,11]],"to":[[0,1]],"搜索":[[0,1]]}}"#
+        );
+    }
+}
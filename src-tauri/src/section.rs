@@ -0,0 +1,216 @@
+use crate::{entity, outline};
+
+fn lines(content: &str) -> Vec<String> {
+    content
+        .split_inclusive('\n')
+        .map(|l| l.to_string())
+        .collect()
+}
+
+/// Joins lines `start..=end` (1-based), making sure the chunk ends with a line break.
+fn chunk(lines: &[String], start: i32, end: i32) -> String {
+    let mut result: String = lines[(start - 1) as usize..end as usize].concat();
+    if !result.is_empty() && !result.ends_with('\n') {
+        result.push('\n');
+    }
+    result
+}
+
+fn find(content: &str, line: i32) -> Result<(Vec<entity::Heading>, usize), String> {
+    let headings = outline::markdown(content);
+    match headings.iter().position(|h| h.line == line) {
+        Some(i) => Ok((headings, i)),
+        None => Err(format!("no heading at line {}", line)),
+    }
+}
+
+/// Swaps the section at `line` with its previous (`offset < 0`) or next sibling.
+pub fn move_by(content: &str, line: i32, offset: i32) -> Result<String, String> {
+    let (headings, i) = find(content, line)?;
+    let level = headings[i].level;
+    let sibling = if offset < 0 {
+        headings[..i].iter().rev().find(|h| h.level <= level)
+    } else {
+        headings[i + 1..].iter().find(|h| h.level <= level)
+    };
+    let sibling = match sibling {
+        Some(sibling) if sibling.level == level => sibling,
+        _ => return Err("section has no sibling in that direction".to_string()),
+    };
+    let (first, second) = if offset < 0 {
+        (sibling, &headings[i])
+    } else {
+        (&headings[i], sibling)
+    };
+    let lines = lines(content);
+    Ok(chunk(&lines, 1, first.line - 1)
+        + &chunk(&lines, second.line, second.end)
+        + &chunk(&lines, first.line, first.end)
+        + &lines[second.end as usize..].concat())
+}
+
+/// Promotes (`delta < 0`) or demotes every heading in the section at `line`.
+pub fn shift(content: &str, line: i32, delta: i32) -> Result<String, String> {
+    let (headings, i) = find(content, line)?;
+    let end = headings[i].end;
+    let section: Vec<&entity::Heading> = headings[i..].iter().filter(|h| h.line <= end).collect();
+    if section
+        .iter()
+        .any(|h| h.level + delta < 1 || h.level + delta > 6)
+    {
+        return Err("heading level out of range".to_string());
+    }
+    let mut lines = lines(content);
+    for heading in section.into_iter().rev() {
+        let index = (heading.line - 1) as usize;
+        let level = (heading.level + delta) as usize;
+        let (text, count) = if lines[index].trim().starts_with('#') {
+            (atx_text(&lines[index]), 1)
+        } else {
+            // a setext heading may span several lines above its `===` / `---` underline
+            let count = lines[index..]
+                .iter()
+                .position(|line| underline(line))
+                .ok_or_else(|| format!("no setext underline for line {}", heading.line))?;
+            let text: Vec<&str> = lines[index..index + count]
+                .iter()
+                .map(|line| line.trim())
+                .collect();
+            (text.join(" "), count + 1)
+        };
+        let text = format!("{} {}", "#".repeat(level), text)
+            .trim_end()
+            .to_string()
+            + "\n";
+        lines.splice(index..index + count, vec![text]);
+    }
+    Ok(lines.concat())
+}
+
+/// Text of an ATX heading line without the opening and the optional closing `#` sequence,
+/// which only counts as closing when whitespace precedes it.
+fn atx_text(line: &str) -> String {
+    let text = line.trim().trim_start_matches('#').trim();
+    let stripped = text.trim_end_matches('#');
+    if stripped.is_empty() {
+        String::new()
+    } else if stripped.ends_with(|c: char| c.is_whitespace()) {
+        stripped.trim_end().to_string()
+    } else {
+        text.to_string()
+    }
+}
+
+fn underline(line: &str) -> bool {
+    let indent = line.len() - line.trim_start_matches(' ').len();
+    let line = line.trim();
+    indent < 4
+        && !line.is_empty()
+        && (line.chars().all(|c| c == '=') || line.chars().all(|c| c == '-'))
+}
+
+/// Removes the section at `line`, returning the remaining content, the section and its heading.
+pub fn take(content: &str, line: i32) -> Result<(String, String, entity::Heading), String> {
+    let (mut headings, i) = find(content, line)?;
+    let heading = headings.swap_remove(i);
+    let lines = lines(content);
+    let section = chunk(&lines, heading.line, heading.end);
+    let rest = chunk(&lines, 1, heading.line - 1) + &lines[heading.end as usize..].concat();
+    Ok((rest, section, heading))
+}
+
+/// Inserts `section` into `target`, nested below the heading at `target_line` or appended at the end.
+pub fn insert(target: &str, section: &str, target_line: Option<i32>) -> Result<String, String> {
+    let line = match target_line {
+        None => {
+            let mut result = target.to_string();
+            if !result.is_empty() && !result.ends_with('\n') {
+                result.push('\n');
+            }
+            return Ok(result + section);
+        }
+        Some(line) => line,
+    };
+    let (headings, i) = find(target, line)?;
+    let parent = &headings[i];
+    let level = outline::markdown(section).first().map_or(1, |h| h.level);
+    let section = shift(section, 1, parent.level + 1 - level)?;
+    let lines = lines(target);
+    Ok(chunk(&lines, 1, parent.end) + &section + &lines[parent.end as usize..].concat())
+}
+
+/// Replaces the section at `line` with a link to the document at the relative path `link`,
+/// returning the new content and the section with its headings moved up so that it starts
+/// at level 1.
+pub fn extract(content: &str, line: i32, link: &str) -> Result<(String, String), String> {
+    let (_, section, heading) = take(content, line)?;
+    let section = shift(&section, 1, 1 - heading.level)?;
+    let lines = lines(content);
+    let result = chunk(&lines, 1, heading.line - 1)
+        + &format!("[{}]({})\n\n", label(&heading.text), encode(link))
+        + &lines[heading.end as usize..].concat();
+    Ok((result, section))
+}
+
+/// Escapes what would end a Markdown link label early.
+fn label(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('[', "\\[")
+        .replace(']', "\\]")
+        .replace('(', "\\(")
+        .replace(')', "\\)")
+}
+
+/// Encodes what would end a Markdown link destination early.
+fn encode(href: &str) -> String {
+    href.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('(', "%28")
+        .replace(')', "%29")
+        .replace('#', "%23")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_move() {
+        let content = "# A\na\n## A1\n# B\nb\n";
+        assert_eq!(move_by(content, 4, -1).unwrap(), "# B\nb\n# A\na\n## A1\n");
+        assert_eq!(move_by(content, 1, 1).unwrap(), "# B\nb\n# A\na\n## A1\n");
+        assert!(move_by(content, 3, 1).is_err());
+    }
+
+    #[test]
+    fn test_shift() {
+        let content = "# A\n## A1\nText\n----\n# B\n";
+        assert_eq!(
+            shift(content, 1, 1).unwrap(),
+            "## A\n### A1\n### Text\n# B\n"
+        );
+        assert!(shift(content, 1, -1).is_err());
+        assert_eq!(
+            shift("# C#\n## Done ##\n### #\n", 1, 1).unwrap(),
+            "## C#\n### Done\n####\n"
+        );
+        assert_eq!(
+            shift("# A\nMulti\nline\n---\nbody\n", 1, 1).unwrap(),
+            "## A\n### Multi line\nbody\n"
+        );
+    }
+
+    #[test]
+    fn test_extract() {
+        let content = "# A\n## A1\nbody\n# B\n";
+        let (rest, section) = extract(content, 2, "A1.md").unwrap();
+        assert_eq!(rest, "# A\n[A1](A1.md)\n\n# B\n");
+        assert_eq!(section, "# A1\nbody\n");
+        let content = "# A\n## [Draft] (1)\nbody\n";
+        let (rest, _) = extract(content, 2, "Draft (1)#.md").unwrap();
+        assert_eq!(
+            rest,
+            "# A\n[\\[Draft\\] \\(1\\)](Draft%20%281%29%23.md)\n\n"
+        );
+    }
+}
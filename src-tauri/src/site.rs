@@ -0,0 +1,567 @@
+use std::{
+    collections::HashMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use crate::{
+    entity,
+    export::{self, Page, Target},
+    highlight::{self, Highlighter},
+    links::{self, Document},
+    outline, render,
+    search::{self, SearchIndex},
+    storage,
+};
+
+const STYLE: &str = r#"
+:root { --bg: #ffffff; --fg: #24292f; --muted: #57606a; --border: #d0d7de; --side: #f6f8fa; --link: #0969da; --code: #f6f8fa; }
+.theme-dark { --bg: #0d1117; --fg: #c9d1d9; --muted: #8b949e; --border: #30363d; --side: #161b22; --link: #58a6ff; --code: #161b22; }
+* { box-sizing: border-box; }
+body { margin: 0; display: flex; background: var(--bg); color: var(--fg); font: 16px/1.6 -apple-system, "Segoe UI", "PingFang SC", "Microsoft YaHei", sans-serif; }
+a { color: var(--link); text-decoration: none; }
+a:hover { text-decoration: underline; }
+.sidebar { position: sticky; top: 0; width: 280px; height: 100vh; overflow: auto; padding: 16px; background: var(--side); border-right: 1px solid var(--border); flex-shrink: 0; }
+.sidebar .site-title { display: block; font-weight: 600; font-size: 18px; margin-bottom: 12px; color: var(--fg); }
+.sidebar ul { list-style: none; margin: 0; padding-left: 14px; }
+.sidebar > ul { padding-left: 0; }
+.sidebar li { margin: 2px 0; }
+.sidebar .folder > span { color: var(--muted); font-weight: 600; }
+.sidebar a.active { font-weight: 600; color: var(--fg); }
+main { flex: 1; min-width: 0; max-width: 960px; padding: 24px 40px; }
+.breadcrumbs { color: var(--muted); font-size: 14px; margin-bottom: 16px; }
+.breadcrumbs span + span::before { content: " / "; }
+article img { max-width: 100%; }
+article pre { padding: 12px; overflow: auto; background: var(--code); border-radius: 6px; }
+article code { font-family: ui-monospace, Consolas, monospace; }
+article table { border-collapse: collapse; }
+article th, article td { border: 1px solid var(--border); padding: 4px 12px; }
+article blockquote { margin: 0; padding: 0 16px; color: var(--muted); border-left: 4px solid var(--border); }
+.pager { display: flex; justify-content: space-between; margin-top: 48px; padding-top: 16px; border-top: 1px solid var(--border); }
+.pager .next { margin-left: auto; }
+.search input { width: 100%; margin-bottom: 12px; padding: 4px 8px; color: var(--fg); background: var(--bg); border: 1px solid var(--border); border-radius: 4px; }
+#search-input { width: 100%; padding: 8px 12px; font-size: 16px; color: var(--fg); background: var(--bg); border: 1px solid var(--border); border-radius: 6px; }
+#search-results { list-style: none; padding: 0; }
+#search-results p { margin: 4px 0 16px; color: var(--muted); font-size: 14px; }
+"#;
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Output path of a document below the site root, joined with `/`.
+fn html_path(rel: &str) -> String {
+    match rel.rsplit_once('.') {
+        Some((stem, _)) => format!("{}.html", stem),
+        None => format!("{}.html", rel),
+    }
+}
+
+/// Pages the export writes itself.
+const RESERVED: [&str; 1] = ["search.html"];
+
+/// Output path of every page. A document whose plain output path is already taken, by an
+/// earlier document such as `a.md` for `a.ahtml` or by a generated page, keeps its
+/// extension in the name instead (`a.ahtml.html`), with a warning.
+fn outputs(pages: &[Page], warnings: &mut Vec<String>) -> HashMap<PathBuf, String> {
+    let mut taken: Vec<String> = RESERVED.iter().map(|name| name.to_string()).collect();
+    let mut result = HashMap::new();
+    for page in pages {
+        let plain = html_path(&page.rel);
+        let mut output = plain.clone();
+        let mut n = 1;
+        while taken.iter().any(|t| t.eq_ignore_ascii_case(&output)) {
+            output = if n == 1 {
+                format!("{}.html", page.rel)
+            } else {
+                format!("{}-{}.html", page.rel, n)
+            };
+            n += 1;
+        }
+        if output != plain {
+            warnings.push(format!(
+                "{}: {} is taken, exported as {}",
+                page.source.display(),
+                plain,
+                output
+            ));
+        }
+        taken.push(output.clone());
+        result.insert(page.source.clone(), output);
+    }
+    result
+}
+
+fn encode(href: &str) -> String {
+    href.replace('%', "%25")
+        .replace(' ', "%20")
+        .replace('#', "%23")
+        .replace('?', "%3F")
+}
+
+/// Exports the documents below `folder` as a static HTML site in `output`.
+pub struct Site<'a> {
+    /// Workspace root, for `/` links and embeds.
+    pub root: &'a Path,
+    pub folder: &'a Path,
+    pub output: &'a Path,
+    pub files: &'a HashMap<String, Document>,
+    pub options: &'a entity::SiteOptions,
+}
+
+impl<'a> Site<'a> {
+    pub fn export(&self) -> Result<entity::Export, String> {
+        if self.output.starts_with(self.folder) {
+            return Err("the output folder cannot be inside the exported folder".to_string());
+        }
+        let tree = export::tree(self.folder)?;
+        let pages = export::pages(self.folder, &tree, self.files);
+        let title = if self.options.title.is_empty() {
+            self.folder
+                .file_name()
+                .map(|name| name.to_string_lossy().to_string())
+                .unwrap_or_default()
+        } else {
+            self.options.title.clone()
+        };
+        let mut report = entity::Export {
+            output: self.output.to_str().unwrap().to_string(),
+            pages: 0,
+            assets: 0,
+            warnings: Vec::new(),
+        };
+        self.assets(&mut report)?;
+        let outputs = outputs(&pages, &mut report.warnings);
+
+        let highlighter = Highlighter::new(Some(&highlight::theme_dir(self.root)), None);
+        let mut copied = Vec::new();
+        let mut search = SearchIndex::default();
+        for (i, page) in pages.iter().enumerate() {
+            let content =
+                match export::prepare(self.root, &page.source, self.files, &mut report.warnings) {
+                    Ok(content) => content,
+                    Err(e) => {
+                        report
+                            .warnings
+                            .push(format!("{}: {}", page.source.display(), e));
+                        continue;
+                    }
+                };
+            let html =
+                render::document(page.source.to_str().unwrap(), &content, Some(&highlighter));
+            let html = self.links(page, &html, &outputs, &mut copied, &mut report);
+            let headings = outline::headings(page.source.to_str().unwrap(), &content);
+            search.add(&outputs[&page.source], &page.title, &headings, &html);
+            let prev = if i > 0 { pages.get(i - 1) } else { None };
+            let neighbours = (prev, pages.get(i + 1));
+            let html = self.page(&title, &tree, &outputs, page, &html, neighbours);
+            let path = self.output.join(&outputs[&page.source]);
+            fs::create_dir_all(path.parent().unwrap()).map_err(|e| e.to_string())?;
+            fs::write(&path, html).map_err(|e| e.to_string())?;
+            report.pages += 1;
+        }
+        report.assets = copied.len() as i32;
+
+        let index = search.to_json();
+        let write =
+            |path: PathBuf, content: String| fs::write(path, content).map_err(|e| e.to_string());
+        write(self.output.join("search-index.json"), index.clone())?;
+        write(
+            self.output.join("assets").join("search-index.js"),
+            format!("window.SEARCH_INDEX = {};\n", index),
+        )?;
+        write(
+            self.output.join("assets").join("search.js"),
+            search::SCRIPT.to_string(),
+        )?;
+        write(
+            self.output.join("search.html"),
+            self.search(&title, &tree, &outputs),
+        )?;
+
+        if !outputs.values().any(|output| output == "index.html") {
+            let html = self.index(&title, &tree, &outputs);
+            fs::write(self.output.join("index.html"), html).map_err(|e| e.to_string())?;
+        }
+        Ok(report)
+    }
+
+    /// Writes the stylesheets: the site theme, code highlighting and the custom one.
+    fn assets(&self, report: &mut entity::Export) -> Result<(), String> {
+        let dir = self.output.join("assets");
+        fs::create_dir_all(&dir).map_err(|e| e.to_string())?;
+        fs::write(dir.join("style.css"), STYLE.trim_start()).map_err(|e| e.to_string())?;
+        let highlighter = Highlighter::new(Some(&highlight::theme_dir(self.root)), None);
+        let theme = if self.options.highlight.is_empty() {
+            highlight::DEFAULT_THEME
+        } else {
+            self.options.highlight.as_str()
+        };
+        match highlighter.css(theme) {
+            Ok(css) => fs::write(dir.join("highlight.css"), css).map_err(|e| e.to_string())?,
+            Err(e) => report.warnings.push(e),
+        }
+        if !self.options.css.is_empty() {
+            if let Err(e) = fs::copy(&self.options.css, dir.join("custom.css")) {
+                report.warnings.push(format!("{}: {}", self.options.css, e));
+            }
+        }
+        Ok(())
+    }
+
+    /// Points links to documents at their pages and copies the assets the page uses.
+    fn links(
+        &self,
+        page: &Page,
+        html: &str,
+        outputs: &HashMap<PathBuf, String>,
+        copied: &mut Vec<PathBuf>,
+        report: &mut entity::Export,
+    ) -> String {
+        let from = page.source.parent().unwrap_or(self.folder);
+        export::rewrite(html, |_, href| {
+            let target = export::target(self.root, &page.source, href);
+            let path = match &target {
+                Target::Document(path, _) | Target::Asset(path) => path,
+                Target::Missing => {
+                    report.warnings.push(format!(
+                        "{}: broken link {}",
+                        page.source.display(),
+                        href
+                    ));
+                    return None;
+                }
+                Target::Unchanged => return None,
+            };
+            if !path.starts_with(self.folder) {
+                report.warnings.push(format!(
+                    "{}: {} is outside the exported folder",
+                    page.source.display(),
+                    href
+                ));
+                return None;
+            }
+            let rel = links::relative(from, path);
+            match &target {
+                Target::Document(_, anchor) => {
+                    let output = match outputs.get(path) {
+                        Some(output) => links::relative(from, &self.folder.join(output)),
+                        None => html_path(&rel),
+                    };
+                    if anchor.is_empty() {
+                        Some(encode(&output))
+                    } else {
+                        Some(format!("{}#{}", encode(&output), anchor))
+                    }
+                }
+                _ => {
+                    let output = links::relative(self.folder, path);
+                    if outputs.values().any(|o| o.eq_ignore_ascii_case(&output))
+                        || RESERVED.contains(&output.as_str())
+                    {
+                        report.warnings.push(format!(
+                            "{}: {} would overwrite a page",
+                            page.source.display(),
+                            href
+                        ));
+                        return None;
+                    }
+                    if !copied.contains(path) {
+                        let to = self.output.join(links::relative(self.folder, path));
+                        let result = storage::for_path(path).read(path).and_then(|data| {
+                            fs::create_dir_all(to.parent().unwrap())?;
+                            fs::write(&to, data)
+                        });
+                        match result {
+                            Ok(_) => copied.push(path.clone()),
+                            Err(e) => report.warnings.push(format!("{}: {}", path.display(), e)),
+                        }
+                    }
+                    Some(encode(&rel))
+                }
+            }
+        })
+    }
+
+    fn head(&self, title: &str, prefix: &str) -> String {
+        let custom = if self.options.css.is_empty() {
+            "".to_string()
+        } else {
+            format!(
+                "<link rel=\"stylesheet\" href=\"{}assets/custom.css\">\n",
+                prefix
+            )
+        };
+        format!(
+            "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n\
+             <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+             <title>{}</title>\n\
+             <link rel=\"stylesheet\" href=\"{}assets/style.css\">\n\
+             <link rel=\"stylesheet\" href=\"{}assets/highlight.css\">\n{}</head>\n",
+            escape(title),
+            prefix,
+            prefix,
+            custom
+        )
+    }
+
+    fn sidebar(
+        &self,
+        title: &str,
+        tree: &[entity::FileTree],
+        outputs: &HashMap<PathBuf, String>,
+        current: &str,
+        prefix: &str,
+    ) -> String {
+        format!(
+            "<nav class=\"sidebar\">\n<a class=\"site-title\" href=\"{}index.html\">{}</a>\n\
+             <form class=\"search\" action=\"{}search.html\"><input name=\"q\" placeholder=\"Search\"></form>\n\
+             {}</nav>\n",
+            prefix,
+            escape(title),
+            prefix,
+            self.nav(tree, outputs, current, prefix)
+        )
+    }
+
+    fn nav(
+        &self,
+        tree: &[entity::FileTree],
+        outputs: &HashMap<PathBuf, String>,
+        current: &str,
+        prefix: &str,
+    ) -> String {
+        let mut result = "<ul>\n".to_string();
+        for node in tree {
+            match &node.children {
+                Some(children) => result.push_str(&format!(
+                    "<li class=\"folder\"><span>{}</span>\n{}</li>\n",
+                    escape(&node.name),
+                    self.nav(children, outputs, current, prefix)
+                )),
+                None => {
+                    let source = Path::new(&node.path);
+                    let rel = match outputs.get(source) {
+                        Some(output) => output.clone(),
+                        None => html_path(&links::relative(self.folder, source)),
+                    };
+                    let class = if rel == current {
+                        " class=\"active\""
+                    } else {
+                        ""
+                    };
+                    result.push_str(&format!(
+                        "<li><a href=\"{}{}\"{}>{}</a></li>\n",
+                        prefix,
+                        encode(&rel),
+                        class,
+                        escape(&export::title(source, self.files.get(&node.path)))
+                    ));
+                }
+            }
+        }
+        result.push_str("</ul>\n");
+        result
+    }
+
+    fn page(
+        &self,
+        title: &str,
+        tree: &[entity::FileTree],
+        outputs: &HashMap<PathBuf, String>,
+        page: &Page,
+        html: &str,
+        (prev, next): (Option<&Page>, Option<&Page>),
+    ) -> String {
+        let current = &outputs[&page.source];
+        let prefix = "../".repeat(current.matches('/').count());
+        let mut breadcrumbs = format!(
+            "<span><a href=\"{}index.html\">{}</a></span>",
+            prefix,
+            escape(title)
+        );
+        for folder in page
+            .rel
+            .split('/')
+            .rev()
+            .skip(1)
+            .collect::<Vec<&str>>()
+            .iter()
+            .rev()
+        {
+            breadcrumbs.push_str(&format!("<span>{}</span>", escape(folder)));
+        }
+        breadcrumbs.push_str(&format!("<span>{}</span>", escape(&page.title)));
+        let pager = |page: Option<&Page>, class: &str, arrow: &str| match page {
+            Some(page) => format!(
+                "<a class=\"{}\" href=\"{}{}\">{}</a>",
+                class,
+                prefix,
+                encode(&outputs[&page.source]),
+                if class == "prev" {
+                    format!("{} {}", arrow, escape(&page.title))
+                } else {
+                    format!("{} {}", escape(&page.title), arrow)
+                }
+            ),
+            None => "".to_string(),
+        };
+        format!(
+            "{}<body class=\"theme-{}\">\n{}<main>\n<nav class=\"breadcrumbs\">{}</nav>\n\
+             <article>\n{}</article>\n<nav class=\"pager\">{}{}</nav>\n</main>\n</body>\n</html>\n",
+            self.head(&format!("{} - {}", page.title, title), &prefix),
+            escape(&self.options.theme),
+            self.sidebar(title, tree, outputs, current, &prefix),
+            breadcrumbs,
+            html,
+            pager(prev, "prev", "←"),
+            pager(next, "next", "→")
+        )
+    }
+
+    /// Landing page listing every document, used when the folder has no `index` document.
+    fn index(
+        &self,
+        title: &str,
+        tree: &[entity::FileTree],
+        outputs: &HashMap<PathBuf, String>,
+    ) -> String {
+        format!(
+            "{}<body class=\"theme-{}\">\n{}<main>\n<article>\n<h1>{}</h1>\n{}</article>\n</main>\n</body>\n</html>\n",
+            self.head(title, ""),
+            escape(&self.options.theme),
+            self.sidebar(title, tree, outputs, "index.html", ""),
+            escape(title),
+            self.nav(tree, outputs, "", "")
+        )
+    }
+
+    fn search(
+        &self,
+        title: &str,
+        tree: &[entity::FileTree],
+        outputs: &HashMap<PathBuf, String>,
+    ) -> String {
+        format!(
+            "{}<body class=\"theme-{}\">\n{}<main>\n<article>\n<h1>Search</h1>\n\
+             <input id=\"search-input\" type=\"search\" placeholder=\"Search\" autofocus>\n\
+             <ul id=\"search-results\"></ul>\n</article>\n</main>\n\
+             <script src=\"assets/search-index.js\"></script>\n\
+             <script src=\"assets/search.js\"></script>\n</body>\n</html>\n",
+            self.head(&format!("Search - {}", title), ""),
+            escape(&self.options.theme),
+            self.sidebar(title, tree, outputs, "search.html", ""),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::TempDir;
+
+    #[test]
+    fn test_export() {
+        let temp = TempDir::new("site");
+        let root = temp.join("site");
+        let output = temp.join("site-out");
+        fs::create_dir_all(root.join("docs/img")).unwrap();
+        let files = [
+            (
+                "a.md",
+                "# Alpha\n\nsee [[Beta#Two]] and ![pic](docs/img/p.png)\n",
+            ),
+            (
+                "docs/Beta.md",
+                "# Beta\n\n## Two\n\n[back](../a.md) [gone](x.md)\n",
+            ),
+        ];
+        let mut index = HashMap::new();
+        for (name, content) in files {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            index.insert(
+                path.to_str().unwrap().to_string(),
+                Document::new(&path, content),
+            );
+        }
+        fs::write(root.join("docs/img/p.png"), "png").unwrap();
+        let options = entity::SiteOptions {
+            title: "Docs".to_string(),
+            theme: "light".to_string(),
+            highlight: "".to_string(),
+            css: "".to_string(),
+        };
+        let report = Site {
+            root: &root,
+            folder: &root,
+            output: &output,
+            files: &index,
+            options: &options,
+        }
+        .export()
+        .unwrap();
+        assert_eq!((report.pages, report.assets), (2, 1));
+        assert_eq!(report.warnings.len(), 1);
+        assert!(output.join("docs/img/p.png").is_file());
+        assert!(output.join("index.html").is_file());
+        assert!(output.join("search.html").is_file());
+        let index = fs::read_to_string(output.join("search-index.json")).unwrap();
+        assert!(index.contains("\"alpha\":[[1,16]]"));
+        let beta = fs::read_to_string(output.join("docs/Beta.html")).unwrap();
+        assert!(beta.contains("<a href=\"../a.html\">back</a>"));
+        assert!(beta.contains("<a class=\"next\" href=\"../a.html\">Alpha →</a>"));
+        assert!(beta.contains("<a href=\"../docs/Beta.html\" class=\"active\">Beta</a>"));
+        let alpha = fs::read_to_string(output.join("a.html")).unwrap();
+        assert!(alpha.contains("<a href=\"docs/Beta.html#two\">Beta#Two</a>"));
+        assert!(alpha.contains("<img alt=\"pic\" src=\"docs/img/p.png\">"));
+    }
+
+    #[test]
+    fn test_collisions() {
+        let temp = TempDir::new("site-collisions");
+        let root = temp.join("site");
+        let output = temp.join("site-out");
+        fs::create_dir_all(&root).unwrap();
+        let files = [
+            ("a.ahtml", "<p>html</p>"),
+            ("a.md", "# A\n"),
+            ("search.md", "# Search\n\n[html](a.ahtml)\n"),
+        ];
+        let mut index = HashMap::new();
+        for (name, content) in files {
+            let path = root.join(name);
+            fs::write(&path, content).unwrap();
+            index.insert(
+                path.to_str().unwrap().to_string(),
+                Document::new(&path, content),
+            );
+        }
+        let options = entity::SiteOptions {
+            title: "Docs".to_string(),
+            theme: "light".to_string(),
+            highlight: "".to_string(),
+            css: "".to_string(),
+        };
+        let report = Site {
+            root: &root,
+            folder: &root,
+            output: &output,
+            files: &index,
+            options: &options,
+        }
+        .export()
+        .unwrap();
+        assert_eq!(report.pages, 3);
+        assert_eq!(report.warnings.len(), 2);
+        assert!(report.warnings[0].ends_with("a.md: a.html is taken, exported as a.md.html"));
+        let search = fs::read_to_string(output.join("search.html")).unwrap();
+        assert!(search.contains("id=\"search-input\""));
+        let page = fs::read_to_string(output.join("search.md.html")).unwrap();
+        assert!(page.contains("<a href=\"a.html\">html</a>"));
+        assert!(page.contains("<a href=\"a.md.html\">A</a>"));
+    }
+}
@@ -0,0 +1,688 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    io::{Error, ErrorKind, Result},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use hotwatch::{Event, Hotwatch};
+use once_cell::sync::Lazy;
+
+use crate::entity;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Stat {
+    pub is_dir: bool,
+    pub size: u64,
+    pub modified: SystemTime,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub name: String,
+    pub path: PathBuf,
+    pub stat: Stat,
+}
+
+/// A change below a watched folder, with whether the path was a folder when it was seen.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+    Create(PathBuf, bool),
+    Write(PathBuf, bool),
+    Remove(PathBuf, bool),
+    Rename(PathBuf, PathBuf, bool),
+}
+
+impl Change {
+    /// The `file-system-changed` message for the change: folders, and the `.md`, `.ahtml`
+    /// and `.json` files the app keeps track of.
+    pub fn message(&self) -> Option<entity::Message> {
+        fn tracked(path: &Path) -> Option<&str> {
+            path.extension()
+                .and_then(|ext| ext.to_str())
+                .filter(|ext| ["md", "ahtml", "json"].contains(ext))
+        }
+        let (type_, path, path2) = match self {
+            Change::Create(path, true) => (1, path, None),
+            Change::Create(path, false) => (2, path, None),
+            Change::Write(path, true) => (3, path, None),
+            Change::Write(path, false) => (4, path, None),
+            Change::Remove(path, true) => (-1, path, None),
+            Change::Remove(path, false) => (-2, path, None),
+            Change::Rename(from, to, true) => (5, from, Some(to)),
+            Change::Rename(from, to, false) => (6, from, Some(to)),
+        };
+        if type_ % 2 == 0 {
+            let ext = tracked(path)?;
+            if path2.map_or(false, |to| tracked(to) != Some(ext)) {
+                return None;
+            }
+        }
+        Some(entity::Message {
+            type_,
+            path: Box::new(path.to_str().unwrap().to_string()),
+            path2: Box::new(path2.map_or("".to_string(), |to| to.to_str().unwrap().to_string())),
+        })
+    }
+}
+
+pub type Handler = Box<dyn Fn(Change) + Send + 'static>;
+
+/// Where a workspace keeps its files. Paths are absolute, below the folder the backend is
+/// mounted at.
+pub trait Storage: Send + Sync {
+    /// The files and folders directly inside `path`, in name order.
+    fn list(&self, path: &Path) -> Result<Vec<Entry>>;
+    fn stat(&self, path: &Path) -> Result<Stat>;
+    fn read(&self, path: &Path) -> Result<Vec<u8>>;
+    /// Creates or replaces a file, whose folder must exist.
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()>;
+    fn create_dir(&self, path: &Path) -> Result<()>;
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+    /// Deletes a file, or a folder with everything inside it.
+    fn delete(&self, path: &Path) -> Result<()>;
+    /// Calls `handler` for every change below `path` until `unwatch`.
+    fn watch(&self, path: &Path, handler: Handler) -> Result<()>;
+    fn unwatch(&self, path: &Path) -> Result<()>;
+
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        String::from_utf8(self.read(path)?).map_err(|e| Error::new(ErrorKind::InvalidData, e))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.stat(path).is_ok()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.stat(path).map_or(false, |stat| stat.is_dir)
+    }
+
+    fn is_file(&self, path: &Path) -> bool {
+        self.stat(path).map_or(false, |stat| !stat.is_dir)
+    }
+
+    /// Copies a file, replacing `to` if it exists.
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.write(to, &self.read(from)?)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        if self.is_dir(path) {
+            return Ok(());
+        }
+        if let Some(parent) = path.parent() {
+            self.create_dir_all(parent)?;
+        }
+        self.create_dir(path)
+    }
+}
+
+/// The local disk.
+#[derive(Default)]
+pub struct Local {
+    hotwatch: Mutex<Option<Hotwatch>>,
+}
+
+impl Storage for Local {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        let mut result = Vec::new();
+        for entry in fs::read_dir(path)? {
+            let entry = entry?;
+            let path = entry.path();
+            result.push(Entry {
+                name: entry.file_name().to_string_lossy().to_string(),
+                stat: self.stat(&path)?,
+                path,
+            });
+        }
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        let metadata = fs::metadata(path)?;
+        Ok(Stat {
+            is_dir: metadata.is_dir(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        fs::read(path)
+    }
+
+    fn read_to_string(&self, path: &Path) -> Result<String> {
+        fs::read_to_string(path)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        fs::write(path, data)
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        fs::create_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::rename(from, to)
+    }
+
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::copy(from, to).map(|_| ())
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        if fs::metadata(path)?.is_dir() {
+            fs::remove_dir_all(path)
+        } else {
+            fs::remove_file(path)
+        }
+    }
+
+    fn watch(&self, path: &Path, handler: Handler) -> Result<()> {
+        let error = |e: hotwatch::Error| Error::new(ErrorKind::Other, e.to_string());
+        let mut hotwatch = self.hotwatch.lock().unwrap();
+        if hotwatch.is_none() {
+            *hotwatch = Some(Hotwatch::new().map_err(error)?);
+        }
+        hotwatch
+            .as_mut()
+            .unwrap()
+            .watch(path, move |event: Event| {
+                let change = match event {
+                    Event::Create(path) => Change::Create(path.clone(), path.is_dir()),
+                    Event::Write(path) => Change::Write(path.clone(), path.is_dir()),
+                    Event::Remove(path) => Change::Remove(path.clone(), path.is_dir()),
+                    Event::Rename(from, to) => Change::Rename(from, to.clone(), to.is_dir()),
+                    _ => return,
+                };
+                handler(change);
+            })
+            .map_err(error)
+    }
+
+    fn unwatch(&self, path: &Path) -> Result<()> {
+        match self.hotwatch.lock().unwrap().as_mut() {
+            Some(hotwatch) => hotwatch
+                .unwatch(path)
+                .map_err(|e| Error::new(ErrorKind::Other, e.to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What the files below a watched folder looked like to a backend without change
+/// notifications: whether each one is a folder, and a signature that changes with its content.
+pub type Snapshot = BTreeMap<PathBuf, (bool, String)>;
+
+/// The changes from one snapshot to the next. Renames show as the old path removed and the
+/// new one created.
+pub fn diff(previous: &Snapshot, current: &Snapshot) -> Vec<Change> {
+    let mut changes: Vec<Change> = previous
+        .iter()
+        .filter(|(path, _)| !current.contains_key(*path))
+        .map(|(path, (is_dir, _))| Change::Remove(path.clone(), *is_dir))
+        .collect();
+    for (path, (is_dir, signature)) in current {
+        match previous.get(path) {
+            None => changes.push(Change::Create(path.clone(), *is_dir)),
+            Some((was_dir, old)) if !is_dir && (*was_dir || old != signature) => {
+                changes.push(Change::Write(path.clone(), false))
+            }
+            _ => {}
+        }
+    }
+    changes
+}
+
+/// Watches for backends that can only be listed, by taking a snapshot of the watched folder
+/// every `interval` and reporting the differences.
+pub struct Poller {
+    interval: Duration,
+    watches: Mutex<HashMap<PathBuf, Arc<Mutex<Watch>>>>,
+}
+
+struct Watch {
+    stopped: bool,
+    previous: Snapshot,
+    snapshot: Box<dyn Fn() -> Result<Snapshot> + Send>,
+    handler: Handler,
+}
+
+impl Watch {
+    fn poll(&mut self) {
+        // the backend is tried again on the next round when it cannot be reached
+        let current = match (self.snapshot)() {
+            Ok(current) => current,
+            Err(_) => return,
+        };
+        for change in diff(&self.previous, &current) {
+            (self.handler)(change);
+        }
+        self.previous = current;
+    }
+}
+
+impl Poller {
+    pub fn new(interval: Duration) -> Poller {
+        Poller {
+            interval,
+            watches: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn watch<F>(&self, path: &Path, snapshot: F, handler: Handler) -> Result<()>
+    where
+        F: Fn() -> Result<Snapshot> + Send + 'static,
+    {
+        let watch = Arc::new(Mutex::new(Watch {
+            stopped: false,
+            previous: snapshot()?,
+            snapshot: Box::new(snapshot),
+            handler,
+        }));
+        if let Some(old) = self
+            .watches
+            .lock()
+            .unwrap()
+            .insert(path.to_path_buf(), watch.clone())
+        {
+            old.lock().unwrap().stopped = true;
+        }
+        let interval = self.interval;
+        thread::spawn(move || loop {
+            thread::sleep(interval);
+            let mut watch = watch.lock().unwrap();
+            if watch.stopped {
+                break;
+            }
+            watch.poll();
+        });
+        Ok(())
+    }
+
+    /// Looks for changes below the watched `path` right away instead of at the next round.
+    pub fn poll(&self, path: &Path) {
+        let watch = match self.watches.lock().unwrap().get(path) {
+            Some(watch) => watch.clone(),
+            None => return,
+        };
+        watch.lock().unwrap().poll();
+    }
+
+    pub fn unwatch(&self, path: &Path) {
+        if let Some(watch) = self.watches.lock().unwrap().remove(path) {
+            watch.lock().unwrap().stopped = true;
+        }
+    }
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        for watch in self.watches.lock().unwrap().values() {
+            watch.lock().unwrap().stopped = true;
+        }
+    }
+}
+
+enum Node {
+    Dir(SystemTime),
+    File(Vec<u8>, SystemTime),
+}
+
+/// Files kept in memory, for tests and scratch workspaces.
+pub struct Memory {
+    nodes: Mutex<BTreeMap<PathBuf, Node>>,
+    handlers: Mutex<Vec<(PathBuf, Arc<Mutex<Handler>>)>>,
+}
+
+impl Memory {
+    /// An empty storage holding only the folder `root`.
+    pub fn new(root: &Path) -> Memory {
+        let mut nodes = BTreeMap::new();
+        nodes.insert(root.to_path_buf(), Node::Dir(SystemTime::now()));
+        Memory {
+            nodes: Mutex::new(nodes),
+            handlers: Mutex::new(Vec::new()),
+        }
+    }
+
+    fn emit(&self, change: Change) {
+        let path = match &change {
+            Change::Create(path, _)
+            | Change::Write(path, _)
+            | Change::Remove(path, _)
+            | Change::Rename(path, _, _) => path.clone(),
+        };
+        // handlers run without the list locked, so they may use the storage themselves
+        let handlers: Vec<_> = self
+            .handlers
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(root, _)| path.starts_with(root))
+            .map(|(_, handler)| handler.clone())
+            .collect();
+        for handler in handlers {
+            (handler.lock().unwrap())(change.clone());
+        }
+    }
+
+    fn parent_is_dir(nodes: &BTreeMap<PathBuf, Node>, path: &Path) -> Result<()> {
+        match path.parent().and_then(|parent| nodes.get(parent)) {
+            Some(Node::Dir(_)) => Ok(()),
+            _ => Err(Error::new(
+                ErrorKind::NotFound,
+                format!(
+                    "{}: no such folder",
+                    path.parent().unwrap_or(path).display()
+                ),
+            )),
+        }
+    }
+}
+
+fn not_found(path: &Path) -> Error {
+    Error::new(
+        ErrorKind::NotFound,
+        format!("{}: no such file or folder", path.display()),
+    )
+}
+
+impl Storage for Memory {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        let nodes = self.nodes.lock().unwrap();
+        match nodes.get(path) {
+            Some(Node::Dir(_)) => {}
+            Some(Node::File(..)) => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("{}: not a folder", path.display()),
+                ))
+            }
+            None => return Err(not_found(path)),
+        }
+        Ok(nodes
+            .iter()
+            .filter(|(child, _)| child.parent() == Some(path))
+            .map(|(child, node)| Entry {
+                name: child.file_name().unwrap().to_string_lossy().to_string(),
+                path: child.clone(),
+                stat: stat(node),
+            })
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        self.nodes
+            .lock()
+            .unwrap()
+            .get(path)
+            .map(stat)
+            .ok_or_else(|| not_found(path))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        match self.nodes.lock().unwrap().get(path) {
+            Some(Node::File(data, _)) => Ok(data.clone()),
+            Some(Node::Dir(_)) => Err(Error::new(
+                ErrorKind::Other,
+                format!("{}: is a folder", path.display()),
+            )),
+            None => Err(not_found(path)),
+        }
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let created = {
+            let mut nodes = self.nodes.lock().unwrap();
+            Memory::parent_is_dir(&nodes, path)?;
+            if let Some(Node::Dir(_)) = nodes.get(path) {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("{}: is a folder", path.display()),
+                ));
+            }
+            nodes
+                .insert(
+                    path.to_path_buf(),
+                    Node::File(data.to_vec(), SystemTime::now()),
+                )
+                .is_none()
+        };
+        self.emit(if created {
+            Change::Create(path.to_path_buf(), false)
+        } else {
+            Change::Write(path.to_path_buf(), false)
+        });
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        {
+            let mut nodes = self.nodes.lock().unwrap();
+            Memory::parent_is_dir(&nodes, path)?;
+            if nodes.contains_key(path) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{}: already exists", path.display()),
+                ));
+            }
+            nodes.insert(path.to_path_buf(), Node::Dir(SystemTime::now()));
+        }
+        self.emit(Change::Create(path.to_path_buf(), true));
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        let is_dir = {
+            let mut nodes = self.nodes.lock().unwrap();
+            let is_dir = match nodes.get(from) {
+                Some(node) => stat(node).is_dir,
+                None => return Err(not_found(from)),
+            };
+            Memory::parent_is_dir(&nodes, to)?;
+            if to.starts_with(from) && to != from {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!("cannot move {} into itself", from.display()),
+                ));
+            }
+            if nodes.get(to).map_or(false, |node| stat(node).is_dir) {
+                return Err(Error::new(
+                    ErrorKind::AlreadyExists,
+                    format!("{}: already exists", to.display()),
+                ));
+            }
+            let moved: Vec<PathBuf> = nodes
+                .keys()
+                .filter(|path| path.starts_with(from))
+                .cloned()
+                .collect();
+            for path in moved {
+                let node = nodes.remove(&path).unwrap();
+                nodes.insert(to.join(path.strip_prefix(from).unwrap()), node);
+            }
+            is_dir
+        };
+        self.emit(Change::Rename(from.to_path_buf(), to.to_path_buf(), is_dir));
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        let is_dir = {
+            let mut nodes = self.nodes.lock().unwrap();
+            let is_dir = match nodes.get(path) {
+                Some(node) => stat(node).is_dir,
+                None => return Err(not_found(path)),
+            };
+            nodes.retain(|child, _| !child.starts_with(path));
+            is_dir
+        };
+        self.emit(Change::Remove(path.to_path_buf(), is_dir));
+        Ok(())
+    }
+
+    fn watch(&self, path: &Path, handler: Handler) -> Result<()> {
+        self.handlers
+            .lock()
+            .unwrap()
+            .push((path.to_path_buf(), Arc::new(Mutex::new(handler))));
+        Ok(())
+    }
+
+    fn unwatch(&self, path: &Path) -> Result<()> {
+        self.handlers
+            .lock()
+            .unwrap()
+            .retain(|(root, _)| root != path);
+        Ok(())
+    }
+}
+
+fn stat(node: &Node) -> Stat {
+    match node {
+        Node::Dir(modified) => Stat {
+            is_dir: true,
+            size: 0,
+            modified: *modified,
+        },
+        Node::File(data, modified) => Stat {
+            is_dir: false,
+            size: data.len() as u64,
+            modified: *modified,
+        },
+    }
+}
+
+static LOCAL: Lazy<Arc<Local>> = Lazy::new(|| Arc::new(Local::default()));
+
+/// Backends by the folder they are mounted at.
+type Mounts = Vec<(PathBuf, Arc<dyn Storage>)>;
+
+static MOUNTS: Lazy<RwLock<Mounts>> = Lazy::new(|| RwLock::new(Vec::new()));
+
+/// Serves the files below `root` from `storage` instead of the local disk.
+pub fn mount(root: &Path, storage: Arc<dyn Storage>) {
+    let mut mounts = MOUNTS.write().unwrap();
+    mounts.retain(|(path, _)| path != root);
+    mounts.push((root.to_path_buf(), storage));
+}
+
+pub fn unmount(root: &Path) {
+    MOUNTS.write().unwrap().retain(|(path, _)| path != root);
+}
+
+/// The storage of the workspace `path` belongs to: the backend mounted at its closest
+/// ancestor, or the local disk.
+pub fn for_path(path: &Path) -> Arc<dyn Storage> {
+    MOUNTS
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|(root, _)| path.starts_with(root))
+        .max_by_key(|(root, _)| root.components().count())
+        .map_or_else(
+            || LOCAL.clone() as Arc<dyn Storage>,
+            |(_, storage)| storage.clone(),
+        )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_memory() {
+        let root = Path::new("/memory/ws");
+        let storage = Memory::new(root);
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = changes.clone();
+        storage
+            .watch(
+                root,
+                Box::new(move |change| seen.lock().unwrap().push(change)),
+            )
+            .unwrap();
+        storage.create_dir(&root.join("docs")).unwrap();
+        storage.write(&root.join("docs/a.md"), b"# A").unwrap();
+        storage.write(&root.join("docs/a.md"), b"# A!").unwrap();
+        assert!(storage.write(&root.join("nope/b.md"), b"").is_err());
+        storage
+            .rename(&root.join("docs"), &root.join("guide"))
+            .unwrap();
+        assert_eq!(
+            storage.read_to_string(&root.join("guide/a.md")).unwrap(),
+            "# A!"
+        );
+        let names: Vec<String> = storage
+            .list(root)
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect();
+        assert_eq!(names, vec!["guide"]);
+        storage.delete(&root.join("guide")).unwrap();
+        assert!(!storage.exists(&root.join("guide/a.md")));
+
+        let messages: Vec<(i32, String)> = changes
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|change| change.message())
+            .map(|message| (message.type_, message.path.to_string()))
+            .collect();
+        assert_eq!(
+            messages,
+            vec![
+                (1, "/memory/ws/docs".to_string()),
+                (2, "/memory/ws/docs/a.md".to_string()),
+                (4, "/memory/ws/docs/a.md".to_string()),
+                (5, "/memory/ws/docs".to_string()),
+                (-1, "/memory/ws/guide".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_mount() {
+        let root = Path::new("/memory/mounted");
+        mount(root, Arc::new(Memory::new(root)));
+        for_path(root)
+            .write(&root.join("a.md"), b"---\ntags: [x]\n---\n")
+            .unwrap();
+        let tree = crate::api::read_dir(root, 0).unwrap();
+        assert_eq!(tree.len(), 1);
+        assert!(tree[0].front_matter.is_some());
+        // the front matter read before is only reused while the document is unchanged
+        for_path(root)
+            .write(&root.join("a.md"), b"---\ntags: [y, z]\n---\n")
+            .unwrap();
+        let tree = crate::api::read_dir(root, 0).unwrap();
+        assert_eq!(tree[0].front_matter.as_ref().unwrap().data["tags"][1], "z");
+        unmount(root);
+        assert!(!for_path(root).exists(&root.join("a.md")));
+    }
+
+    #[test]
+    fn test_handler_uses_storage() {
+        let root = Path::new("/memory/reentrant");
+        let storage = Arc::new(Memory::new(root));
+        let inner = storage.clone();
+        storage
+            .watch(
+                root,
+                Box::new(move |change| {
+                    if let Change::Write(path, _) = change {
+                        inner.read_to_string(&path).unwrap();
+                    }
+                }),
+            )
+            .unwrap();
+        storage.write(&root.join("a.md"), b"# A").unwrap();
+    }
+}
@@ -0,0 +1,239 @@
+use std::{collections::HashMap, ops::Deref, ops::Range, path::Path};
+
+use once_cell::sync::Lazy;
+use regex::Regex;
+use serde_json::{Map, Value};
+
+use crate::{entity, frontmatter, index::Index, outline, storage};
+
+// no `(` lead-in, which would turn anchors like `](#installation)` into tags
+static TAG: Lazy<Regex> = Lazy::new(|| Regex::new(r"(?:^|[\s\[,])#([\p{L}\p{N}_/\-]+)").unwrap());
+
+/// A whole tag name, nested parts included.
+static NAME: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"^[\p{L}\p{N}_\-]+(?:/[\p{L}\p{N}_\-]+)*$").unwrap());
+
+/// Finds inline `#tag` references in a Markdown body, skipping code.
+///
+/// Returns the byte range of each tag name (without the `#`) and the name itself.
+pub fn inline(body: &str) -> Vec<(Range<usize>, String)> {
+    let mut result = Vec::new();
+    for (offset, line) in outline::prose(body) {
+        for cap in TAG.captures_iter(line) {
+            let m = cap.get(1).unwrap();
+            if outline::in_code_span(line, m.start()) {
+                continue;
+            }
+            let name = m.as_str().trim_end_matches('/');
+            if name.is_empty() || name.chars().all(|c| c.is_ascii_digit()) {
+                continue;
+            }
+            let start = offset + m.start();
+            result.push((start..start + name.len(), name.to_string()));
+        }
+    }
+    result
+}
+
+/// Reads the `tags` front matter field, accepting a list or a comma separated string.
+pub fn declared(front_matter: &entity::FrontMatter) -> Vec<String> {
+    frontmatter::strings(&front_matter.data, "tags")
+        .into_iter()
+        .map(|tag| tag.trim_start_matches('#').to_string())
+        .filter(|tag| !tag.is_empty())
+        .collect()
+}
+
+/// Collects the distinct tags of a document from its front matter and body.
+pub fn extract(content: &str) -> Vec<String> {
+    let mut result = match frontmatter::parse(content) {
+        Some(front_matter) => declared(&front_matter),
+        None => Vec::new(),
+    };
+    for (_, name) in inline(frontmatter::body(content)) {
+        result.push(name);
+    }
+    result.sort();
+    result.dedup();
+    result
+}
+
+/// Returns the new name for `tag` when `from` is renamed to `to`, including nested tags.
+fn renamed(tag: &str, from: &str, to: &str) -> Option<String> {
+    if tag == from {
+        Some(to.to_string())
+    } else {
+        tag.strip_prefix(from)
+            .filter(|rest| rest.starts_with('/'))
+            .map(|rest| format!("{}{}", to, rest))
+    }
+}
+
+/// Renames the tag `from` to `to` in both the front matter and the body of `content`.
+pub fn rename(content: &str, from: &str, to: &str) -> Result<String, String> {
+    let mut content = content.to_string();
+    if let Some(front_matter) = frontmatter::parse(&content) {
+        let tags = declared(&front_matter);
+        if tags.iter().any(|tag| renamed(tag, from, to).is_some()) {
+            let mut result: Vec<String> = Vec::new();
+            for tag in tags {
+                let tag = renamed(&tag, from, to).unwrap_or(tag);
+                if !result.contains(&tag) {
+                    result.push(tag);
+                }
+            }
+            let value = match front_matter.data.get("tags") {
+                Some(Value::String(_)) => Value::String(result.join(", ")),
+                _ => Value::Array(result.into_iter().map(Value::String).collect()),
+            };
+            let mut fields = Map::new();
+            fields.insert("tags".to_string(), value);
+            content = frontmatter::update(&content, fields)?;
+        }
+    }
+    let offset = content.len() - frontmatter::body(&content).len();
+    let mut body = frontmatter::body(&content).to_string();
+    for (range, name) in inline(&body).into_iter().rev() {
+        if let Some(name) = renamed(&name, from, to) {
+            body.replace_range(range, &name);
+        }
+    }
+    content.replace_range(offset.., &body);
+    Ok(content)
+}
+
+/// Tags of every Markdown document in the watched workspace.
+#[derive(Clone)]
+pub struct TagIndex {
+    index: Index<Vec<String>>,
+}
+
+impl Default for TagIndex {
+    fn default() -> Self {
+        TagIndex {
+            index: Index::new(|path, content| {
+                if path.extension().map_or(false, |ext| ext == "md") {
+                    Some(extract(content))
+                } else {
+                    None
+                }
+            }),
+        }
+    }
+}
+
+impl Deref for TagIndex {
+    type Target = Index<Vec<String>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.index
+    }
+}
+
+impl TagIndex {
+    pub fn tags(&self) -> Vec<entity::Tag> {
+        let mut result: Vec<entity::Tag> = self.read(|files| {
+            let mut counts: HashMap<&str, i32> = HashMap::new();
+            for tags in files.values() {
+                for tag in tags {
+                    *counts.entry(tag).or_insert(0) += 1;
+                }
+            }
+            counts
+                .into_iter()
+                .map(|(name, count)| entity::Tag {
+                    name: name.to_string(),
+                    count,
+                })
+                .collect()
+        });
+        result.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.name.cmp(&b.name)));
+        result
+    }
+
+    /// Documents tagged with `tag` or one of its nested tags.
+    pub fn files(&self, tag: &str) -> Vec<String> {
+        let mut result: Vec<String> = self.read(|files| {
+            files
+                .iter()
+                .filter(|(_, tags)| tags.iter().any(|t| renamed(t, tag, tag).is_some()))
+                .map(|(file, _)| file.clone())
+                .collect()
+        });
+        result.sort();
+        result
+    }
+
+    /// Renames a tag in every document that uses it, returning the changed documents.
+    pub fn rename(&self, from: &str, to: &str) -> Result<Vec<String>, String> {
+        if !NAME.is_match(to) || to.chars().all(|c| c.is_ascii_digit()) {
+            return Err(format!("{} is not a valid tag name", to));
+        }
+        let mut result = Vec::new();
+        for file in self.files(from) {
+            let path = Path::new(&file);
+            let storage = storage::for_path(path);
+            let content = storage.read_to_string(path).map_err(|e| e.to_string())?;
+            let updated = rename(&content, from, to)?;
+            if updated != content {
+                storage
+                    .write(path, updated.as_bytes())
+                    .map_err(|e| e.to_string())?;
+                result.push(file.clone());
+            }
+            self.update(Path::new(&file));
+        }
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_extract() {
+        let content = "---\ntags: [api]\n---\n# Title #not\n\nSee #guide and #guide/setup.\n`#code` #123\n```\n#fenced\n```\n";
+        assert_eq!(extract(content), vec!["api", "guide", "guide/setup", "not"]);
+    }
+
+    #[test]
+    fn test_rename() {
+        let content = "---\ntags: [api, guide]\n---\n#guide/setup and #guide\n";
+        assert_eq!(
+            rename(content, "guide", "manual").unwrap(),
+            "---\ntags:\n- api\n- manual\n---\n#manual/setup and #manual\n"
+        );
+        // anchors in link destinations are not tags
+        let content = "See [Install](#install) and [#install]\n";
+        assert_eq!(extract(content), vec!["install"]);
+        assert_eq!(
+            rename(content, "install", "setup").unwrap(),
+            "See [Install](#install) and [#setup]\n"
+        );
+    }
+
+    #[test]
+    fn test_rename_in_storage() {
+        let root = Path::new("/memory/tags");
+        storage::mount(root, std::sync::Arc::new(storage::Memory::new(root)));
+        let storage = storage::for_path(root);
+        storage
+            .write(&root.join("a.md"), b"#guide and #api\n")
+            .unwrap();
+        let index = TagIndex::default();
+        index.build(root);
+        for to in ["", "two words", "#manual", "a//b", "123"] {
+            assert!(index.rename("guide", to).is_err());
+        }
+        assert_eq!(
+            index.rename("guide", "manual").unwrap(),
+            vec!["/memory/tags/a.md"]
+        );
+        assert_eq!(
+            storage.read_to_string(&root.join("a.md")).unwrap(),
+            "#manual and #api\n"
+        );
+        storage::unmount(root);
+    }
+}
@@ -0,0 +1,784 @@
+use std::{
+    collections::HashMap,
+    io::{Error, ErrorKind, Read, Result},
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+use crate::storage::{self, Entry, Handler, Poller, Snapshot, Stat, Storage};
+
+/// Characters left as they are in a segment of a URL path.
+const SEGMENT: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'.')
+    .remove(b'_')
+    .remove(b'~');
+
+/// How often a watched share is listed again to find the changes made on it.
+pub const POLL: Duration = Duration::from_secs(10);
+
+const PROPFIND: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<propfind xmlns="DAV:"><prop><resourcetype/><getcontentlength/><getlastmodified/><getetag/></prop></propfind>"#;
+
+/// A file or folder as listed by PROPFIND.
+struct Resource {
+    path: PathBuf,
+    stat: Stat,
+    etag: Option<String>,
+}
+
+struct Client {
+    root: PathBuf,
+    // the URL of the share, without the trailing slash
+    url: String,
+    // the decoded path of `url`, for the hrefs of PROPFIND responses
+    base: String,
+    auth: Option<String>,
+    agent: ureq::Agent,
+}
+
+impl Client {
+    fn url(&self, path: &Path) -> Result<String> {
+        let rel = path.strip_prefix(&self.root).map_err(|_| {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("{} is not on {}", path.display(), self.url),
+            )
+        })?;
+        let mut url = self.url.clone();
+        for component in rel.components() {
+            url.push('/');
+            url.extend(utf8_percent_encode(
+                &component.as_os_str().to_string_lossy(),
+                SEGMENT,
+            ));
+        }
+        Ok(url)
+    }
+
+    fn request(&self, method: &str, url: &str) -> ureq::Request {
+        let request = self.agent.request(method, url);
+        match &self.auth {
+            Some(auth) => request.set("Authorization", auth),
+            None => request,
+        }
+    }
+
+    /// The resource at `path`, and with a `depth` of 1 the ones directly inside it.
+    fn propfind(&self, path: &Path, depth: &str) -> Result<Vec<Resource>> {
+        let mut url = self.url(path)?;
+        if depth != "0" {
+            url.push('/');
+        }
+        let response = self
+            .request("PROPFIND", &url)
+            .set("Depth", depth)
+            .set("Content-Type", "application/xml; charset=utf-8")
+            .send_string(PROPFIND)
+            .map_err(|e| error("PROPFIND", path, e))?;
+        let body = response.into_string()?;
+        self.parse(&body)
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("PROPFIND {}", e)))
+    }
+
+    fn parse(&self, xml: &str) -> std::result::Result<Vec<Resource>, String> {
+        let document = roxmltree::Document::parse(xml).map_err(|e| e.to_string())?;
+        let dav = |node: &roxmltree::Node, name: &str| node.has_tag_name(("DAV:", name));
+        let mut result = Vec::new();
+        for response in document.descendants().filter(|n| dav(n, "response")) {
+            let path = match response
+                .children()
+                .find(|n| dav(n, "href"))
+                .and_then(|href| href.text())
+                .and_then(|href| self.path(href))
+            {
+                Some(path) => path,
+                None => continue,
+            };
+            let prop = response
+                .children()
+                .filter(|n| dav(n, "propstat"))
+                .filter(|propstat| {
+                    propstat
+                        .children()
+                        .find(|n| dav(n, "status"))
+                        .and_then(|status| status.text())
+                        .map_or(false, |status| status.contains(" 200 "))
+                })
+                .find_map(|propstat| propstat.children().find(|n| dav(n, "prop")));
+            let prop = match prop {
+                Some(prop) => prop,
+                None => continue,
+            };
+            let text = |name: &str| {
+                prop.children()
+                    .find(|n| dav(n, name))
+                    .and_then(|n| n.text())
+                    .map(|text| text.trim().to_string())
+            };
+            let is_dir = prop
+                .children()
+                .find(|n| dav(n, "resourcetype"))
+                .map_or(false, |resourcetype| {
+                    resourcetype.children().any(|n| dav(&n, "collection"))
+                });
+            // a server clock running ahead would give times in the future
+            let modified = text("getlastmodified")
+                .and_then(|date| http_date(&date))
+                .unwrap_or(UNIX_EPOCH)
+                .min(SystemTime::now());
+            result.push(Resource {
+                path,
+                stat: Stat {
+                    is_dir,
+                    size: text("getcontentlength").map_or(0, |size| size.parse().unwrap_or(0)),
+                    modified,
+                },
+                etag: text("getetag"),
+            });
+        }
+        Ok(result)
+    }
+
+    /// The path of an href, which is either a full URL or an absolute path on the server.
+    fn path(&self, href: &str) -> Option<PathBuf> {
+        let href = match href.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+            None => href,
+        };
+        let href = percent_decode_str(href).decode_utf8_lossy();
+        let rest = href.trim_end_matches('/').strip_prefix(&self.base)?;
+        if !rest.is_empty() && !rest.starts_with('/') {
+            return None;
+        }
+        Some(
+            rest.split('/')
+                .filter(|part| !part.is_empty())
+                .fold(self.root.clone(), |path, part| path.join(part)),
+        )
+    }
+
+    fn snapshot(&self, dir: &Path, snapshot: &mut Snapshot) -> Result<()> {
+        for resource in self.propfind(dir, "1")? {
+            if resource.path == dir {
+                continue;
+            }
+            let signature = resource.etag.unwrap_or_else(|| {
+                format!(
+                    "{}-{:?}",
+                    resource.stat.size,
+                    resource.stat.modified.duration_since(UNIX_EPOCH)
+                )
+            });
+            snapshot.insert(resource.path.clone(), (resource.stat.is_dir, signature));
+            if resource.stat.is_dir {
+                self.snapshot(&resource.path, snapshot)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn error(method: &str, path: &Path, error: ureq::Error) -> Error {
+    match error {
+        ureq::Error::Status(code, response) => {
+            let kind = match code {
+                // 409 is what a missing parent folder gives
+                404 | 409 => ErrorKind::NotFound,
+                401 | 403 => ErrorKind::PermissionDenied,
+                405 | 412 => ErrorKind::AlreadyExists,
+                _ => ErrorKind::Other,
+            };
+            Error::new(
+                kind,
+                format!(
+                    "{} {}: {} {}",
+                    method,
+                    path.display(),
+                    code,
+                    response.status_text()
+                ),
+            )
+        }
+        ureq::Error::Transport(e) => Error::new(ErrorKind::Other, e.to_string()),
+    }
+}
+
+/// Parses an HTTP date like `Sun, 06 Nov 1994 08:49:37 GMT`.
+fn http_date(value: &str) -> Option<SystemTime> {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let parts: Vec<&str> = value.split_whitespace().collect();
+    if parts.len() != 6 {
+        return None;
+    }
+    let day: i64 = parts[1].parse().ok()?;
+    let month = MONTHS.iter().position(|month| *month == parts[2])? as i64 + 1;
+    let year: i64 = parts[3].parse().ok()?;
+    let time = parts[4]
+        .split(':')
+        .map(|part| part.parse().ok())
+        .collect::<Option<Vec<i64>>>()?;
+    if time.len() != 3 {
+        return None;
+    }
+    // days since 1970-01-01 of the proleptic Gregorian calendar
+    let y = if month <= 2 { year - 1 } else { year };
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let doy = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let days = era * 146097 + yoe * 365 + yoe / 4 - yoe / 100 + doy - 719468;
+    let secs = days * 86400 + time[0] * 3600 + time[1] * 60 + time[2];
+    Some(UNIX_EPOCH + Duration::from_secs(u64::try_from(secs).ok()?))
+}
+
+/// A share on a WebDAV server, mounted at its URL: the workspace of `https://host/dav` has
+/// its files at paths like `https://host/dav/guide/setup.md`.
+///
+/// Writing a file only succeeds while it still has the ETag it was last read or written
+/// with, and a file with no ETag yet only while it does not exist, so that a change saved by
+/// someone else in the meantime is not overwritten. Changes are found by listing the watched
+/// folder again every `POLL`.
+pub struct WebDav {
+    client: Arc<Client>,
+    etags: Mutex<HashMap<PathBuf, String>>,
+    poller: Poller,
+}
+
+impl WebDav {
+    /// The share at `url`, signing in with basic authentication unless `username` is empty.
+    pub fn new(url: &str, username: &str, password: &str) -> WebDav {
+        let url = url.trim_end_matches('/').to_string();
+        let base = match url.split_once("://") {
+            Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]),
+            None => "",
+        };
+        let base = percent_decode_str(base).decode_utf8_lossy().to_string();
+        WebDav {
+            client: Arc::new(Client {
+                root: PathBuf::from(&url),
+                auth: if username.is_empty() {
+                    None
+                } else {
+                    Some(format!(
+                        "Basic {}",
+                        base64::encode(format!("{}:{}", username, password))
+                    ))
+                },
+                agent: ureq::AgentBuilder::new()
+                    .timeout(Duration::from_secs(30))
+                    .build(),
+                url,
+                base,
+            }),
+            etags: Mutex::new(HashMap::new()),
+            poller: Poller::new(POLL),
+        }
+    }
+
+    pub fn with_interval(mut self, interval: Duration) -> WebDav {
+        self.poller = Poller::new(interval);
+        self
+    }
+
+    /// The folder the share is mounted at.
+    pub fn root(&self) -> &Path {
+        &self.client.root
+    }
+
+    /// Forgets the ETags of `path` and everything inside it, or moves them to `to`.
+    fn forget(&self, path: &Path, to: Option<&Path>) {
+        let mut etags = self.etags.lock().unwrap();
+        let moved: Vec<PathBuf> = etags
+            .keys()
+            .filter(|known| known.starts_with(path))
+            .cloned()
+            .collect();
+        for known in moved {
+            let etag = etags.remove(&known).unwrap();
+            if let Some(to) = to {
+                etags.insert(to.join(known.strip_prefix(path).unwrap()), etag);
+            }
+        }
+    }
+
+    /// Asks the server for the current ETag of the file at `path`.
+    fn refresh(&self, path: &Path) {
+        let etag = self
+            .client
+            .propfind(path, "0")
+            .ok()
+            .and_then(|resources| resources.into_iter().next())
+            .filter(|resource| !resource.stat.is_dir)
+            .and_then(|resource| resource.etag);
+        let mut etags = self.etags.lock().unwrap();
+        match etag {
+            Some(etag) => etags.insert(path.to_path_buf(), etag),
+            None => etags.remove(path),
+        };
+    }
+
+    fn transfer(&self, method: &str, from: &Path, to: &Path, overwrite: bool) -> Result<()> {
+        self.client
+            .request(method, &self.client.url(from)?)
+            .set("Destination", &self.client.url(to)?)
+            .set("Overwrite", if overwrite { "T" } else { "F" })
+            .call()
+            .map_err(|e| error(method, from, e))?;
+        Ok(())
+    }
+}
+
+impl Storage for WebDav {
+    fn list(&self, path: &Path) -> Result<Vec<Entry>> {
+        let resources = self.client.propfind(path, "1")?;
+        if resources
+            .iter()
+            .any(|resource| resource.path == path && !resource.stat.is_dir)
+        {
+            return Err(Error::new(
+                ErrorKind::Other,
+                format!("{}: not a folder", path.display()),
+            ));
+        }
+        let mut result: Vec<Entry> = resources
+            .into_iter()
+            .filter(|resource| resource.path != path)
+            .map(|resource| Entry {
+                name: resource
+                    .path
+                    .file_name()
+                    .unwrap()
+                    .to_string_lossy()
+                    .to_string(),
+                path: resource.path,
+                stat: resource.stat,
+            })
+            .collect();
+        result.sort_by(|a, b| a.name.cmp(&b.name));
+        Ok(result)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Stat> {
+        self.client
+            .propfind(path, "0")?
+            .into_iter()
+            .next()
+            .map(|resource| resource.stat)
+            .ok_or_else(|| Error::from(ErrorKind::NotFound))
+    }
+
+    fn read(&self, path: &Path) -> Result<Vec<u8>> {
+        let response = self
+            .client
+            .request("GET", &self.client.url(path)?)
+            .call()
+            .map_err(|e| error("GET", path, e))?;
+        let mut etags = self.etags.lock().unwrap();
+        match response.header("ETag") {
+            Some(etag) => etags.insert(path.to_path_buf(), etag.to_string()),
+            None => etags.remove(path),
+        };
+        drop(etags);
+        let mut data = Vec::new();
+        response.into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn write(&self, path: &Path, data: &[u8]) -> Result<()> {
+        let mut request = self.client.request("PUT", &self.client.url(path)?);
+        // a file without a known ETag must not exist yet, so nothing unseen is overwritten
+        let known = self.etags.lock().unwrap().get(path).cloned();
+        request = match &known {
+            Some(etag) => request.set("If-Match", etag),
+            None => request.set("If-None-Match", "*"),
+        };
+        let response = match request.send_bytes(data) {
+            Ok(response) => response,
+            Err(ureq::Error::Status(412, _)) => {
+                return Err(Error::new(
+                    ErrorKind::Other,
+                    format!(
+                        "{}: {} on the server since it was opened, reload it before saving",
+                        path.display(),
+                        if known.is_some() {
+                            "changed"
+                        } else {
+                            "created"
+                        }
+                    ),
+                ))
+            }
+            Err(e) => return Err(error("PUT", path, e)),
+        };
+        // not every server answers a PUT with the new ETag
+        match response.header("ETag") {
+            Some(etag) => {
+                let mut etags = self.etags.lock().unwrap();
+                etags.insert(path.to_path_buf(), etag.to_string());
+            }
+            None => self.refresh(path),
+        }
+        Ok(())
+    }
+
+    fn create_dir(&self, path: &Path) -> Result<()> {
+        let url = format!("{}/", self.client.url(path)?);
+        self.client
+            .request("MKCOL", &url)
+            .call()
+            .map_err(|e| error("MKCOL", path, e))?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        self.transfer("MOVE", from, to, false)?;
+        self.forget(from, Some(to));
+        Ok(())
+    }
+
+    /// Copies on the server, without the file being read.
+    fn copy(&self, from: &Path, to: &Path) -> Result<()> {
+        self.transfer("COPY", from, to, true)?;
+        self.forget(to, None);
+        // the copy is ours, so saving it next must not be taken for a conflict
+        self.refresh(to);
+        Ok(())
+    }
+
+    fn delete(&self, path: &Path) -> Result<()> {
+        self.client
+            .request("DELETE", &self.client.url(path)?)
+            .call()
+            .map_err(|e| error("DELETE", path, e))?;
+        self.forget(path, None);
+        Ok(())
+    }
+
+    fn watch(&self, path: &Path, handler: Handler) -> Result<()> {
+        let client = self.client.clone();
+        let root = path.to_path_buf();
+        self.poller.watch(
+            path,
+            move || {
+                let mut snapshot = Snapshot::new();
+                client.snapshot(&root, &mut snapshot)?;
+                Ok(snapshot)
+            },
+            handler,
+        )
+    }
+
+    fn unwatch(&self, path: &Path) -> Result<()> {
+        self.poller.unwatch(path);
+        Ok(())
+    }
+}
+
+/// Mounts the share at `url` and returns the folder to open it as a workspace with.
+pub fn connect(url: &str, username: &str, password: &str) -> Result<PathBuf> {
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("{} is not an http or https URL", url),
+        ));
+    }
+    let webdav = WebDav::new(url, username, password);
+    let root = webdav.root().to_path_buf();
+    if !webdav.stat(&root)?.is_dir {
+        return Err(Error::new(
+            ErrorKind::Other,
+            format!("{} is not a folder", url),
+        ));
+    }
+    storage::mount(&root, Arc::new(webdav));
+    Ok(root)
+}
+
+/// Unmounts the share at `url`, forgetting its credentials.
+pub fn disconnect(url: &str) {
+    storage::unmount(Path::new(url.trim_end_matches('/')));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader, Write},
+        net::{TcpListener, TcpStream},
+        thread,
+    };
+
+    use super::*;
+    use crate::{
+        links::LinkIndex,
+        storage::{self, Change, Memory},
+    };
+
+    /// A WebDAV server on a free local port, serving `store` below `/dav`.
+    fn serve(store: Arc<Memory>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        thread::spawn(move || {
+            for stream in listener.incoming().flatten() {
+                let _ = handle(&store, stream);
+            }
+        });
+        format!("http://127.0.0.1:{}/dav", port)
+    }
+
+    fn handle(store: &Memory, mut stream: TcpStream) -> Result<()> {
+        let mut reader = BufReader::new(stream.try_clone()?);
+        let mut line = String::new();
+        reader.read_line(&mut line)?;
+        let mut parts = line.split_whitespace();
+        let method = parts.next().unwrap_or("").to_string();
+        let target = parts.next().unwrap_or("").to_string();
+        let mut headers = HashMap::new();
+        loop {
+            let mut line = String::new();
+            reader.read_line(&mut line)?;
+            match line.trim_end().split_once(':') {
+                Some((name, value)) => {
+                    headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string())
+                }
+                None => break,
+            };
+        }
+        let length = headers
+            .get("content-length")
+            .map_or(0, |l| l.parse().unwrap());
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body)?;
+
+        let local = |target: &str| {
+            let target = &target[target.find("/dav").unwrap_or(0)..];
+            PathBuf::from(
+                percent_decode_str(target)
+                    .decode_utf8_lossy()
+                    .trim_end_matches('/'),
+            )
+        };
+        let path = local(&target);
+        let etag = |path: &Path| {
+            store.stat(path).ok().map(|stat| {
+                format!(
+                    "\"{}-{}\"",
+                    stat.size,
+                    stat.modified.duration_since(UNIX_EPOCH).unwrap().as_nanos()
+                )
+            })
+        };
+        let auth = format!("Basic {}", base64::encode("ahri:secret"));
+        let (status, mut extra, content) = if headers.get("authorization") != Some(&auth) {
+            ("401 Unauthorized", String::new(), Vec::new())
+        } else {
+            match method.as_str() {
+                "PROPFIND" => match store.stat(&path) {
+                    Ok(stat) => {
+                        let mut resources = vec![(path.clone(), stat)];
+                        if headers.get("depth").map(String::as_str) == Some("1") && stat.is_dir {
+                            for entry in store.list(&path)? {
+                                resources.push((entry.path, entry.stat));
+                            }
+                        }
+                        let mut xml = String::from(r#"<d:multistatus xmlns:d="DAV:">"#);
+                        for (path, stat) in resources {
+                            let href: Vec<String> = path
+                                .to_str()
+                                .unwrap()
+                                .split('/')
+                                .map(|part| utf8_percent_encode(part, SEGMENT).to_string())
+                                .collect();
+                            xml.push_str(&format!(
+                                "<d:response><d:href>{}{}</d:href><d:propstat><d:prop>\
+                                 <d:resourcetype>{}</d:resourcetype>\
+                                 <d:getcontentlength>{}</d:getcontentlength>\
+                                 <d:getlastmodified>Mon, 12 Jan 2026 10:00:00 GMT</d:getlastmodified>\
+                                 <d:getetag>{}</d:getetag></d:prop>\
+                                 <d:status>HTTP/1.1 200 OK</d:status></d:propstat></d:response>",
+                                href.join("/"),
+                                if stat.is_dir { "/" } else { "" },
+                                if stat.is_dir { "<d:collection/>" } else { "" },
+                                stat.size,
+                                etag(&path).unwrap()
+                            ));
+                        }
+                        xml.push_str("</d:multistatus>");
+                        ("207 Multi-Status", String::new(), xml.into_bytes())
+                    }
+                    Err(_) => ("404 Not Found", String::new(), Vec::new()),
+                },
+                "GET" => match store.read(&path) {
+                    Ok(data) => (
+                        "200 OK",
+                        format!("ETag: {}\r\n", etag(&path).unwrap()),
+                        data,
+                    ),
+                    Err(_) => ("404 Not Found", String::new(), Vec::new()),
+                },
+                "PUT"
+                    if headers
+                        .get("if-match")
+                        .map_or(false, |m| Some(m) != etag(&path).as_ref())
+                        || headers.get("if-none-match").map(String::as_str) == Some("*")
+                            && store.exists(&path) =>
+                {
+                    ("412 Precondition Failed", String::new(), Vec::new())
+                }
+                "PUT" => match store.write(&path, &body) {
+                    Ok(_) => ("201 Created", String::new(), Vec::new()),
+                    Err(_) => ("409 Conflict", String::new(), Vec::new()),
+                },
+                "MKCOL" if store.exists(&path) => {
+                    ("405 Method Not Allowed", String::new(), Vec::new())
+                }
+                "MKCOL" => match store.create_dir(&path) {
+                    Ok(_) => ("201 Created", String::new(), Vec::new()),
+                    Err(_) => ("409 Conflict", String::new(), Vec::new()),
+                },
+                "DELETE" => match store.delete(&path) {
+                    Ok(_) => ("204 No Content", String::new(), Vec::new()),
+                    Err(_) => ("404 Not Found", String::new(), Vec::new()),
+                },
+                "MOVE" | "COPY" => {
+                    let to = local(&headers["destination"]);
+                    if headers.get("overwrite").map(String::as_str) == Some("F")
+                        && store.exists(&to)
+                    {
+                        ("412 Precondition Failed", String::new(), Vec::new())
+                    } else {
+                        let result = if method == "MOVE" {
+                            store.rename(&path, &to)
+                        } else {
+                            store.copy(&path, &to)
+                        };
+                        match result {
+                            Ok(_) => ("201 Created", String::new(), Vec::new()),
+                            Err(_) => ("409 Conflict", String::new(), Vec::new()),
+                        }
+                    }
+                }
+                _ => ("405 Method Not Allowed", String::new(), Vec::new()),
+            }
+        };
+        extra.push_str(&format!("Content-Length: {}\r\n", content.len()));
+        write!(
+            stream,
+            "HTTP/1.1 {}\r\nConnection: close\r\n{}\r\n",
+            status, extra
+        )?;
+        stream.write_all(&content)
+    }
+
+    #[test]
+    fn test_http_date() {
+        assert_eq!(
+            http_date("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(UNIX_EPOCH + Duration::from_secs(784111777))
+        );
+        assert_eq!(http_date("yesterday"), None);
+    }
+
+    #[test]
+    fn test_webdav() {
+        let store = Arc::new(Memory::new(Path::new("/dav")));
+        let url = serve(store.clone());
+        assert!(WebDav::new(&url, "ahri", "wrong")
+            .stat(Path::new(&url))
+            .is_err());
+        // changes are polled by the test itself
+        let webdav = WebDav::new(&url, "ahri", "secret").with_interval(Duration::from_secs(3600));
+        let root = webdav.root().to_path_buf();
+        assert_eq!(root, PathBuf::from(&url));
+        assert!(webdav.stat(&root).unwrap().is_dir);
+
+        webdav.create_dir(&root.join("guide")).unwrap();
+        assert!(webdav.create_dir(&root.join("guide")).is_err());
+        let a = root.join("guide/read me.md");
+        webdav.write(&a, b"# A").unwrap();
+        let entries = webdav.list(&root.join("guide")).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].name, "read me.md");
+        assert_eq!(entries[0].path, a);
+        assert_eq!((entries[0].stat.is_dir, entries[0].stat.size), (false, 3));
+        assert_eq!(webdav.read_to_string(&a).unwrap(), "# A");
+
+        // someone else saves the document in the meantime
+        store
+            .write(Path::new("/dav/guide/read me.md"), b"# Theirs")
+            .unwrap();
+        let error = webdav.write(&a, b"# Mine").unwrap_err();
+        assert!(error.to_string().contains("changed on the server"));
+        assert_eq!(webdav.read_to_string(&a).unwrap(), "# Theirs");
+        webdav.write(&a, b"# Mine").unwrap();
+        webdav.write(&a, b"# Mine!").unwrap();
+        // a file created by someone else is not overwritten before it has been read
+        let c = root.join("c.md");
+        store.write(Path::new("/dav/c.md"), b"# C").unwrap();
+        let error = webdav.write(&c, b"# Mine").unwrap_err();
+        assert!(error.to_string().contains("created on the server"));
+        webdav.read(&c).unwrap();
+        webdav.write(&c, b"# Mine").unwrap();
+
+        let changes = Arc::new(Mutex::new(Vec::new()));
+        let seen = changes.clone();
+        webdav
+            .watch(
+                &root,
+                Box::new(move |change| seen.lock().unwrap().push(change)),
+            )
+            .unwrap();
+        store.write(Path::new("/dav/guide/b.md"), b"# B").unwrap();
+        webdav.poller.poll(&root);
+        webdav.unwatch(&root).unwrap();
+        assert_eq!(
+            *changes.lock().unwrap(),
+            vec![Change::Create(root.join("guide/b.md"), false)]
+        );
+
+        webdav.copy(&a, &root.join("a.md")).unwrap();
+        webdav
+            .rename(&root.join("guide"), &root.join("docs"))
+            .unwrap();
+        assert!(webdav.is_file(&root.join("docs/read me.md")));
+        webdav.delete(&root.join("docs")).unwrap();
+        assert!(!webdav.exists(&root.join("docs")));
+        assert_eq!(store.read(Path::new("/dav/a.md")).unwrap(), b"# Mine!");
+        // the copy can be saved without reading it first
+        webdav.write(&root.join("a.md"), b"# Copy").unwrap();
+        assert_eq!(store.read(Path::new("/dav/a.md")).unwrap(), b"# Copy");
+    }
+
+    #[test]
+    fn test_links() {
+        let store = Arc::new(Memory::new(Path::new("/dav")));
+        let url = serve(store.clone());
+        let webdav = WebDav::new(&url, "ahri", "secret").with_interval(Duration::from_secs(3600));
+        let root = webdav.root().to_path_buf();
+        storage::mount(&root, Arc::new(webdav));
+        store.create_dir(Path::new("/dav/docs")).unwrap();
+        store
+            .write(
+                Path::new("/dav/docs/a.md"),
+                b"[b](../b.md) [[b]] [c](./c.md)\n",
+            )
+            .unwrap();
+        store.write(Path::new("/dav/b.md"), b"# B\n").unwrap();
+        let index = LinkIndex::default();
+        index.build(&root);
+        let source = root.join("docs/a.md");
+        let targets: Vec<String> = index
+            .outgoing(source.to_str().unwrap())
+            .into_iter()
+            .map(|link| link.target)
+            .collect();
+        let b = format!("{}/b.md", url);
+        assert_eq!(targets, vec![b.clone(), String::new(), b.clone()]);
+        assert_eq!(index.backlinks(&b).len(), 2);
+        storage::unmount(&root);
+    }
+}
@@ -10,6 +10,7 @@ export interface FileTree {
     name: string
     path: string
     updated: number
+    front_matter: FrontMatter | null
     children: FileTree[]
 }
 
@@ -22,6 +23,11 @@ export interface DocFile {
     changed: boolean
 }
 
+export interface FrontMatter {
+    type_: number
+    data: { [x: string]: any }
+}
+
 export interface Response<T> {
     code: number
     msg: string
@@ -31,4 +37,75 @@ export interface Response<T> {
 export interface ProjectConfig {
     token: string
     project: string
+    author: string
+    language: string
+    cover: string
+}
+
+export interface AhtmlHeader {
+    version: number
+    title: string
+    tags: string[]
+    created: number
+    updated: number
+    editor: string
+}
+
+export interface Allowlist {
+    tags: string[]
+    attributes: { [tag: string]: string[] }
+    schemes: string[]
+}
+
+export interface SiteOptions {
+    title: string
+    theme: "light" | "dark"
+    highlight: string
+    css: string
+}
+
+export interface PdfOptions {
+    font: string
+    bold_font: string
+    mono_font: string
+    paper: "A4" | "Letter"
+}
+
+export interface Export {
+    output: string
+    pages: number
+    assets: number
+    warnings: string[]
+}
+
+export interface Migration {
+    source: string
+    target: string
+    kind: "obsidian" | "notion"
+    documents: number
+    attachments: number
+    links: number
+    renamed: string[]
+    warnings: string[]
+}
+
+export interface Scaffold {
+    created: string[]
+    skipped: string[]
+}
+
+export interface Backup {
+    archive: string
+    folder: string
+    files: number
+    size: number
+}
+
+export interface Bucket {
+    endpoint: string
+    region: string
+    bucket: string
+    prefix: string
+    access_key: string
+    secret_key: string
 }